@@ -0,0 +1,88 @@
+/* pushes a short stream through the C API and checks what two receivers read */
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "rust_proj.h"
+
+#define CHECK(cond)                                                         \
+    do {                                                                    \
+        if (!(cond)) {                                                      \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #cond);                                                 \
+            exit(1);                                                        \
+        }                                                                   \
+    } while (0)
+
+#define FRAMES 12
+#define TIMEOUT_MS 1000
+
+/* video every 40ms with a key frame every 160ms, audio in between */
+static void push_frames(RpDispatcher *dispatcher)
+{
+    for (int i = 0; i < FRAMES; i++) {
+        uint64_t pts = (uint64_t)i * 20;
+        uint32_t media_type = i % 2 == 0 ? RP_MEDIA_VIDEO : RP_MEDIA_AUDIO;
+        bool key_frame = media_type == RP_MEDIA_VIDEO && pts % 160 == 0;
+        uint8_t payload[64];
+        size_t size = 16 + (size_t)i;
+        memset(payload, i, size);
+        CHECK(rp_dispatcher_push(dispatcher, media_type, pts, pts, key_frame,
+                                 payload, size) == RP_OK);
+    }
+}
+
+int main(void)
+{
+    uint8_t buffer[64];
+    RpFrameInfo info;
+
+    RpDispatcher *dispatcher = rp_dispatcher_new(400, 50);
+    CHECK(dispatcher != NULL);
+    CHECK(rp_dispatcher_start(dispatcher) == RP_OK);
+    RpReceiver *all = rp_receiver_attach(dispatcher, 0, false);
+    RpReceiver *keys = rp_receiver_attach(dispatcher, 0, true);
+    CHECK(all != NULL && keys != NULL);
+
+    CHECK(rp_dispatcher_push(NULL, RP_MEDIA_VIDEO, 0, 0, true, NULL, 0) ==
+          RP_ERR_INVALID);
+    CHECK(rp_dispatcher_push(dispatcher, 42, 0, 0, true, NULL, 0) ==
+          RP_ERR_INVALID);
+    push_frames(dispatcher);
+
+    /* every frame in order, the payload copied into the buffer */
+    for (int i = 0; i < FRAMES; i++) {
+        int ret = rp_receiver_read_timeout(all, RP_MEDIA_AV, buffer,
+                                           sizeof(buffer), &info, TIMEOUT_MS);
+        CHECK(ret == RP_OK);
+        CHECK(info.pts == (uint64_t)i * 20);
+        CHECK(info.media_type ==
+              (i % 2 == 0 ? RP_MEDIA_VIDEO : RP_MEDIA_AUDIO));
+        CHECK(info.size == 16 + (size_t)i);
+        CHECK(buffer[0] == i && buffer[info.size - 1] == i);
+    }
+    CHECK(rp_receiver_read_timeout(all, RP_MEDIA_AV, buffer, sizeof(buffer),
+                                   &info, 50) == RP_ERR_TIMEOUT);
+
+    /* a frame that does not fit is kept for the next read */
+    CHECK(rp_receiver_read_timeout(keys, RP_MEDIA_VIDEO, buffer, 4, &info,
+                                   TIMEOUT_MS) == RP_ERR_BUFFER_TOO_SMALL);
+    CHECK(info.pts == 0 && info.key_frame && info.size == 16);
+    CHECK(rp_receiver_read(keys, RP_MEDIA_VIDEO, buffer, sizeof(buffer),
+                           &info) == RP_OK);
+    CHECK(info.pts == 0 && buffer[15] == 0);
+    CHECK(rp_receiver_read_timeout(keys, RP_MEDIA_VIDEO, buffer,
+                                   sizeof(buffer), &info, TIMEOUT_MS) == RP_OK);
+    CHECK(info.pts == 160 && info.key_frame);
+
+    CHECK(rp_receiver_stop(keys) == RP_OK);
+    CHECK(rp_receiver_read(keys, RP_MEDIA_VIDEO, buffer, sizeof(buffer),
+                           &info) == RP_ERR_STOPPED);
+
+    rp_receiver_detach(all);
+    rp_receiver_detach(keys);
+    CHECK(rp_dispatcher_stop(dispatcher) == RP_OK);
+    rp_dispatcher_free(dispatcher);
+    printf("c api test passed\n");
+    return 0;
+}
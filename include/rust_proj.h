@@ -0,0 +1,111 @@
+/*
+ * dispatcher C API
+ *
+ * a dispatcher buffers the frames pushed into it and hands them to every attached
+ * receiver. create one with rp_dispatcher_new, start its dispatch thread before
+ * blocking reads, push frames from one thread and read each receiver from its own.
+ *
+ * media types are the RP_MEDIA_* values, RP_MEDIA_AV reads every track of a
+ * receiver in buffer order. calls return RP_OK or one of the RP_ERR_* codes:
+ *   RP_ERR_INVALID           a null handle or pointer, or an unknown media type
+ *   RP_ERR_TIMEOUT           rp_receiver_read_timeout got no frame in time
+ *   RP_ERR_STOPPED           the receiver was stopped or its dispatcher is gone
+ *   RP_ERR_BUFFER_TOO_SMALL  info->size holds the payload size, the frame is kept
+ *                            for the next read of the same type
+ *
+ * rp_receiver_attach takes the tracks to read as a mask of 1 << RP_MEDIA_* bits,
+ * 0 for audio and video.
+ *
+ * rp_receiver_detach frees the receiver and rp_dispatcher_free the dispatcher,
+ * detach every receiver of a dispatcher before freeing it.
+ */
+
+
+#ifndef RUST_PROJ_H
+#define RUST_PROJ_H
+
+/* generated from src/ffi.rs by src/bin/gen_header.rs, do not edit */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#define RP_MEDIA_AV 0
+
+#define RP_MEDIA_AUDIO 1
+
+#define RP_MEDIA_VIDEO 2
+
+#define RP_MEDIA_SUBTITLE 3
+
+#define RP_MEDIA_METADATA 4
+
+#define RP_MEDIA_DATA 5
+
+#define RP_OK 0
+
+#define RP_ERR_INVALID -1
+
+#define RP_ERR_TIMEOUT -2
+
+#define RP_ERR_STOPPED -3
+
+#define RP_ERR_BUFFER_TOO_SMALL -4
+
+typedef struct RpDispatcher RpDispatcher;
+
+typedef struct RpReceiver RpReceiver;
+
+typedef struct RpFrameInfo {
+  uint32_t media_type;
+  uint32_t track_id;
+  uint64_t pts;
+  uint64_t dts;
+  bool key_frame;
+  size_t size;
+} RpFrameInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+struct RpDispatcher *rp_dispatcher_new(uint32_t max_capacity, uint32_t capacity_increment);
+
+void rp_dispatcher_free(struct RpDispatcher *handle);
+
+int32_t rp_dispatcher_start(struct RpDispatcher *handle);
+
+int32_t rp_dispatcher_stop(struct RpDispatcher *handle);
+
+int32_t rp_dispatcher_push(struct RpDispatcher *handle,
+                           uint32_t media_type,
+                           uint64_t pts,
+                           uint64_t dts,
+                           bool key_frame,
+                           const uint8_t *data,
+                           size_t size);
+
+struct RpReceiver *rp_receiver_attach(struct RpDispatcher *handle, uint32_t tracks, bool key_only);
+
+void rp_receiver_detach(struct RpReceiver *handle);
+
+int32_t rp_receiver_stop(struct RpReceiver *handle);
+
+int32_t rp_receiver_read(struct RpReceiver *handle,
+                         uint32_t media_type,
+                         uint8_t *buffer,
+                         size_t capacity,
+                         struct RpFrameInfo *info);
+
+int32_t rp_receiver_read_timeout(struct RpReceiver *handle,
+                                 uint32_t media_type,
+                                 uint8_t *buffer,
+                                 size_t capacity,
+                                 struct RpFrameInfo *info,
+                                 uint32_t timeout_ms);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* RUST_PROJ_H */
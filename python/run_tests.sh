@@ -0,0 +1,10 @@
+#!/bin/sh
+# builds the wheel, installs it into a scratch venv and runs the python scenarios
+set -e
+cd "$(dirname "$0")/.."
+python3 -m venv target/python-venv
+. target/python-venv/bin/activate
+pip install -q maturin pytest
+maturin build --release --out target/wheels
+pip install -q --force-reinstall target/wheels/rust_proj-*.whl
+pytest python/tests "$@"
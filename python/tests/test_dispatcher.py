@@ -0,0 +1,141 @@
+# scenarios for the python bindings, run with python/run_tests.sh
+import threading
+import time
+
+import rust_proj
+from rust_proj import AUDIO, AV, VIDEO, Dispatcher, MediaData, SyntheticStream
+
+SPS = bytes([0x67, 0x42, 0x00, 0x1E, 0x95, 0xA8, 0x28, 0x0F, 0x64])
+PPS = bytes([0x68, 0xCE, 0x38, 0x80])
+START_CODE = b"\x00\x00\x00\x01"
+
+
+def gops(count, gop_size=4, video_interval=3):
+    stream = SyntheticStream(gop_size, video_interval, 10)
+    return stream.frames(count * gop_size * video_interval)
+
+
+def started(frames, **receivers):
+    dispatcher = Dispatcher()
+    attached = {name: dispatcher.attach(**options) for name, options in receivers.items()}
+    dispatcher.start()
+    dispatcher.push_all(frames)
+    return dispatcher, attached
+
+
+def pts(frames):
+    return [frame.pts for frame in frames]
+
+
+def test_receivers_get_the_tracks_they_read():
+    frames = gops(2)
+    dispatcher, receivers = started(frames, mixed={}, video={}, audio={})
+    try:
+        mixed = receivers["mixed"].read_all(AV)
+        video = receivers["video"].read_all(VIDEO)
+        audio = receivers["audio"].read_all(AUDIO)
+    finally:
+        dispatcher.stop()
+
+    assert pts(mixed) == pts(frames)
+    assert pts(video) == [frame.pts for frame in frames if frame.media_type == VIDEO]
+    assert pts(audio) == [frame.pts for frame in frames if frame.media_type == AUDIO]
+    assert all(frame.media_type == VIDEO for frame in video)
+    assert all(frame.media_type == AUDIO for frame in audio)
+
+
+def test_key_only_receiver_reads_one_frame_per_gop():
+    frames = gops(3)
+    dispatcher, receivers = started(frames, key={"key_only": True}, video={})
+    try:
+        key = receivers["key"].read_all(VIDEO)
+        video = receivers["video"].read_all(VIDEO)
+    finally:
+        dispatcher.stop()
+
+    assert receivers["key"].key_only
+    assert all(frame.key_frame for frame in key)
+    assert pts(key) == [frame.pts for frame in frames if frame.key_frame]
+    assert len(video) == 3 * 4
+
+
+def test_frames_expose_their_payload_as_bytes_and_memoryview():
+    dispatcher, receivers = started(
+        [MediaData(VIDEO, 40, b"\x65frame", key_frame=True, dts=20, track_id=1)], reader={}
+    )
+    try:
+        frame = receivers["reader"].read(VIDEO, timeout=1.0)
+    finally:
+        dispatcher.stop()
+
+    assert (frame.pts, frame.dts, frame.track_id, frame.key_frame) == (40, 20, 1, True)
+    assert bytes(frame) == frame.payload == b"\x65frame"
+    assert len(frame) == 6
+    view = memoryview(frame)
+    assert view.readonly
+    assert view[0] == 0x65
+    assert view.tobytes() == b"\x65frame"
+
+
+def test_blocking_read_releases_the_gil():
+    dispatcher = Dispatcher()
+    receiver = dispatcher.attach()
+    dispatcher.start()
+    got = []
+    reader = threading.Thread(target=lambda: got.append(receiver.read(VIDEO)))
+    reader.start()
+    try:
+        # the push only gets the GIL while the reader waits without it
+        time.sleep(0.1)
+        dispatcher.push(MediaData(VIDEO, 0, b"\x65", key_frame=True))
+        reader.join(2.0)
+    finally:
+        dispatcher.stop()
+
+    assert not reader.is_alive()
+    assert pts(got) == [0]
+
+
+def test_read_times_out_and_stop_wakes_the_reader():
+    dispatcher = Dispatcher()
+    receiver = dispatcher.attach()
+    dispatcher.start()
+    try:
+        assert receiver.read(VIDEO, timeout=0.05) is None
+        got = []
+        reader = threading.Thread(target=lambda: got.append(receiver.read(VIDEO)))
+        reader.start()
+        time.sleep(0.1)
+        receiver.stop()
+        reader.join(2.0)
+    finally:
+        dispatcher.stop()
+
+    assert not reader.is_alive()
+    assert got == [None]
+
+
+def test_file_source_feeds_the_dispatcher(tmp_path):
+    path = tmp_path / "stream.h264"
+    with open(path, "wb") as output:
+        for index in range(8):
+            if index % 4 == 0:
+                output.write(START_CODE + SPS + START_CODE + PPS)
+                output.write(START_CODE + b"\x65\x88\x84" + bytes([index]))
+            else:
+                output.write(START_CODE + b"\x41\x9a\x02" + bytes([index]))
+
+    dispatcher = Dispatcher()
+    receiver = dispatcher.attach()
+    dispatcher.start()
+    source = rust_proj.FileSource(str(path), dispatcher, fast=True)
+    source.start()
+    try:
+        frames = receiver.read_all(VIDEO, timeout=0.5)
+    finally:
+        source.stop()
+        dispatcher.stop()
+
+    assert len(frames) == 8
+    assert [frame.key_frame for frame in frames] == [True, False, False, False] * 2
+    assert pts(frames) == sorted(pts(frames))
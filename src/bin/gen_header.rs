@@ -0,0 +1,33 @@
+// writes the C header for src/ffi.rs to include/rust_proj.h, or to the path given.
+// run it after changing the C API: cargo run --features header --bin gen_header
+use std::{env, path::PathBuf, process};
+
+fn main() {
+    let crate_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let output = env::args()
+        .nth(1)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| crate_dir.join("include").join("rust_proj.h"));
+
+    let config = match cbindgen::Config::from_file(crate_dir.join("cbindgen.toml")) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("can not read cbindgen.toml: {}", err);
+            process::exit(1);
+        }
+    };
+    match cbindgen::Builder::new()
+        .with_src(crate_dir.join("src").join("ffi.rs"))
+        .with_config(config)
+        .generate()
+    {
+        Ok(bindings) => {
+            bindings.write_to_file(&output);
+            println!("wrote {}", output.display());
+        }
+        Err(err) => {
+            eprintln!("can not generate the C header: {}", err);
+            process::exit(1);
+        }
+    }
+}
@@ -0,0 +1,62 @@
+// replays a dispatcher trace written by a SessionCapture against a fresh dispatcher
+// and lists the reads that came out differently
+use rust_proj::dispatcher::{capture, dispatcher::Dispatcher};
+use std::{env, path::Path, process};
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!(
+            "usage: {} <trace> [max_capacity capacity_increment]",
+            args[0]
+        );
+        process::exit(2);
+    }
+    let capacity = |index: usize, default: u32| {
+        args.get(index)
+            .map_or(Some(default), |arg| arg.parse().ok())
+            .unwrap_or_else(|| {
+                eprintln!("invalid capacity: {}", args[index]);
+                process::exit(2);
+            })
+    };
+    let dispatcher = Dispatcher::new(capacity(2, 400), capacity(3, 50));
+
+    let records = match capture::read_trace(Path::new(&args[1])) {
+        Ok(records) => records,
+        Err(err) => {
+            eprintln!("can not read trace {}: {}", args[1], err);
+            process::exit(1);
+        }
+    };
+    let report = capture::replay(&records, &dispatcher);
+
+    println!("events: {}", report.events);
+    let mut receivers: Vec<_> = report.reads.iter().collect();
+    receivers.sort();
+    for (recv_id, reads) in receivers {
+        let divergences = report
+            .divergences
+            .iter()
+            .filter(|divergence| divergence.recv_id == *recv_id)
+            .count();
+        println!(
+            "receiver {}: {} reads, {} divergences",
+            recv_id, reads, divergences
+        );
+    }
+    for divergence in report.divergences.iter() {
+        println!(
+            "event {} at {}us, receiver {} reading {:?}: expected {:?}, got {:?}",
+            divergence.event,
+            divergence.time,
+            divergence.recv_id,
+            divergence.media_type,
+            divergence.expected,
+            divergence.actual
+        );
+    }
+    if !report.is_clean() {
+        process::exit(1);
+    }
+}
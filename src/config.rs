@@ -0,0 +1,725 @@
+// declarative pipeline setup from a TOML or YAML file: the dispatchers, the sources
+// feeding them, the receivers and sinks reading them and the log levels
+use crate::dispatcher::{
+    dispatcher::{Dispatcher, OverflowPolicy, MAX_RECEIVERS},
+    receiver::Receiver,
+};
+use crate::format::fmp4::{Fmp4Writer, FragmentMode};
+use crate::source::{
+    file_source::{FileFormat, FileSource, PaceMode},
+    synthetic::{SyntheticSource, SyntheticStream},
+};
+use crate::utils::{
+    buffer::MediaType,
+    macros::{self, LogLevel},
+};
+use crate::{info, warn};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+pub enum ConfigError {
+    // the file could not be read
+    IO(String),
+    // not TOML or YAML, or not shaped like a PipelineConfig
+    PARSE(String),
+    // well formed but inconsistent, one message per problem
+    INVALID(Vec<String>),
+    // a part of the graph could not be created
+    BUILD(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::IO(message) | ConfigError::PARSE(message) => write!(f, "{}", message),
+            ConfigError::INVALID(problems) => {
+                write!(f, "invalid config:")?;
+                for problem in problems {
+                    write!(f, "\n  {}", problem)?;
+                }
+                Ok(())
+            }
+            ConfigError::BUILD(message) => write!(f, "can not build the pipeline: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub logging: LoggingConfig,
+    pub dispatchers: Vec<DispatcherConfig>,
+    pub sources: Vec<SourceConfig>,
+    pub receivers: Vec<ReceiverConfig>,
+    pub sinks: Vec<SinkConfig>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String,
+    // module path below src, "dispatcher" or "dispatcher::receiver", to its level
+    pub modules: BTreeMap<String, String>,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        LoggingConfig {
+            level: "debug".to_string(),
+            modules: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DispatcherConfig {
+    pub name: String,
+    pub max_capacity: u32,
+    pub capacity_increment: u32,
+    // grow, drop_oldest or drop_new
+    pub overflow: String,
+    // payload bytes kept in memory before spilling to disk
+    pub memory_budget: Option<usize>,
+}
+
+impl Default for DispatcherConfig {
+    fn default() -> Self {
+        DispatcherConfig {
+            name: String::new(),
+            max_capacity: 400,
+            capacity_increment: 50,
+            overflow: "grow".to_string(),
+            memory_budget: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum SourceConfig {
+    #[serde(rename = "synthetic")]
+    SYNTHETIC(SyntheticConfig),
+    #[serde(rename = "file")]
+    FILE(FileConfig),
+}
+
+impl SourceConfig {
+    pub fn dispatcher(&self) -> &str {
+        match self {
+            SourceConfig::SYNTHETIC(config) => &config.dispatcher,
+            SourceConfig::FILE(config) => &config.dispatcher,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SyntheticConfig {
+    pub dispatcher: String,
+    // 0 until the pipeline stops
+    pub frames: u32,
+    pub fps: u32,
+    pub audio_per_video: u32,
+    pub gop: u32,
+    pub payload: usize,
+    // realtime or fast
+    pub pace: String,
+}
+
+impl Default for SyntheticConfig {
+    fn default() -> Self {
+        SyntheticConfig {
+            dispatcher: String::new(),
+            frames: 0,
+            fps: 25,
+            audio_per_video: 2,
+            gop: 30,
+            payload: 4,
+            pace: "realtime".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub dispatcher: String,
+    pub path: PathBuf,
+    // realtime or fast
+    pub pace: String,
+    #[serde(rename = "loop")]
+    pub looping: bool,
+    pub speed: f64,
+    // frame rate of raw H.264 files
+    pub fps: u32,
+}
+
+impl Default for FileConfig {
+    fn default() -> Self {
+        FileConfig {
+            dispatcher: String::new(),
+            path: PathBuf::new(),
+            pace: "realtime".to_string(),
+            looping: false,
+            speed: 1.0,
+            fps: 25,
+        }
+    }
+}
+
+// "live" to start at the newest key frame, or a pts: the frames before it are
+// skipped and video starts at the first key frame at or after it
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(untagged)]
+pub enum StartConfig {
+    NAMED(String),
+    PTS(u64),
+}
+
+impl Default for StartConfig {
+    fn default() -> Self {
+        StartConfig::NAMED("live".to_string())
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReceiverConfig {
+    pub name: String,
+    pub dispatcher: String,
+    // av, audio, video, subtitle, metadata or data, av when not given
+    pub media_type: Option<String>,
+    pub key_only: bool,
+    pub start: StartConfig,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "type", deny_unknown_fields)]
+pub enum SinkConfig {
+    #[serde(rename = "fmp4")]
+    FMP4(Fmp4SinkConfig),
+}
+
+impl SinkConfig {
+    pub fn dispatcher(&self) -> &str {
+        match self {
+            SinkConfig::FMP4(config) => &config.dispatcher,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Fmp4SinkConfig {
+    pub dispatcher: String,
+    pub path: PathBuf,
+    // the tracks written, all of them when not given
+    pub media_type: Option<String>,
+    pub key_only: bool,
+    pub start: StartConfig,
+    // cut fragments by duration instead of at key frames
+    pub fragment_ms: Option<u64>,
+}
+
+fn overflow_policy(name: &str) -> Option<OverflowPolicy> {
+    match name {
+        "grow" => Some(OverflowPolicy::GROW),
+        "drop_oldest" => Some(OverflowPolicy::EVICT),
+        "drop_new" => Some(OverflowPolicy::DROP),
+        _ => None,
+    }
+}
+
+fn pace_mode(name: &str) -> Option<PaceMode> {
+    match name {
+        "realtime" => Some(PaceMode::REALTIME),
+        "fast" => Some(PaceMode::FAST),
+        _ => None,
+    }
+}
+
+fn media_type(name: &Option<String>) -> Option<MediaType> {
+    name.as_deref()
+        .map_or(Some(MediaType::AV), MediaType::from_name)
+}
+
+// none for live
+fn start_pts(start: &StartConfig) -> Result<Option<u64>, String> {
+    match start {
+        StartConfig::NAMED(name) if name == "live" => Ok(None),
+        StartConfig::NAMED(name) => Err(format!(
+            "unknown start {:?}, expected \"live\" or a pts",
+            name
+        )),
+        StartConfig::PTS(pts) => Ok(Some(*pts)),
+    }
+}
+
+fn label(section: &str, index: usize, name: &str) -> String {
+    match name {
+        "" => format!("{}[{}]", section, index),
+        name => format!("{}[{}] {:?}", section, index, name),
+    }
+}
+
+impl PipelineConfig {
+    // by the extension, .toml, .yaml or .yml
+    pub fn load(path: &Path) -> Result<PipelineConfig, ConfigError> {
+        let text = fs::read_to_string(path)
+            .map_err(|err| ConfigError::IO(format!("can not read {:?}: {}", path, err)))?;
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let config = match extension.as_deref() {
+            Some("toml") => toml::from_str(&text).map_err(|err| err.to_string()),
+            Some("yaml") | Some("yml") => {
+                serde_yaml::from_str(&text).map_err(|err| err.to_string())
+            }
+            _ => {
+                return Err(ConfigError::PARSE(format!(
+                    "{:?}: unknown config format, expected .toml, .yaml or .yml",
+                    path
+                )))
+            }
+        };
+        let config: PipelineConfig =
+            config.map_err(|err| ConfigError::PARSE(format!("{:?}: {}", path, err)))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_toml(text: &str) -> Result<PipelineConfig, ConfigError> {
+        let config: PipelineConfig =
+            toml::from_str(text).map_err(|err| ConfigError::PARSE(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_yaml(text: &str) -> Result<PipelineConfig, ConfigError> {
+        let config: PipelineConfig =
+            serde_yaml::from_str(text).map_err(|err| ConfigError::PARSE(err.to_string()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    // every problem, not only the first one
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let mut problems = Vec::new();
+
+        if LogLevel::from_name(&self.logging.level).is_none() {
+            problems.push(format!(
+                "logging: unknown level {:?}, expected debug, info, warn, error, fatal or off",
+                self.logging.level
+            ));
+        }
+        for (module, level) in self.logging.modules.iter() {
+            if LogLevel::from_name(level).is_none() {
+                problems.push(format!(
+                    "logging.modules {:?}: unknown level {:?}",
+                    module, level
+                ));
+            }
+        }
+
+        if self.dispatchers.is_empty() {
+            problems.push("dispatchers: at least one is needed".to_string());
+        }
+        let mut dispatchers = HashSet::new();
+        for (index, config) in self.dispatchers.iter().enumerate() {
+            let label = label("dispatchers", index, &config.name);
+            if config.name.is_empty() {
+                problems.push(format!("{}: missing name", label));
+            } else if !dispatchers.insert(config.name.as_str()) {
+                problems.push(format!("{}: duplicate name", label));
+            }
+            if config.max_capacity == 0 {
+                problems.push(format!("{}: max_capacity must be at least 1", label));
+            }
+            if overflow_policy(&config.overflow).is_none() {
+                problems.push(format!(
+                    "{}: unknown overflow {:?}, expected grow, drop_oldest or drop_new",
+                    label, config.overflow
+                ));
+            }
+            if config.memory_budget == Some(0) {
+                problems.push(format!("{}: memory_budget must be at least 1", label));
+            }
+        }
+        let check_dispatcher = |label: &str, name: &str, problems: &mut Vec<String>| {
+            if name.is_empty() {
+                problems.push(format!("{}: missing dispatcher", label));
+            } else if !dispatchers.contains(name) {
+                problems.push(format!("{}: unknown dispatcher {:?}", label, name));
+            }
+        };
+
+        for (index, source) in self.sources.iter().enumerate() {
+            let label = label("sources", index, "");
+            check_dispatcher(&label, source.dispatcher(), &mut problems);
+            let pace = match source {
+                SourceConfig::SYNTHETIC(config) => {
+                    if config.fps == 0 {
+                        problems.push(format!("{}: fps must be at least 1", label));
+                    }
+                    if config.gop == 0 {
+                        problems.push(format!("{}: gop must be at least 1", label));
+                    }
+                    &config.pace
+                }
+                SourceConfig::FILE(config) => {
+                    if FileFormat::from_path(&config.path).is_none() {
+                        problems.push(format!(
+                            "{}: unknown file format {:?}, expected .flv, .ts, .m2ts, .h264, .264 or .aac",
+                            label, config.path
+                        ));
+                    } else if !config.path.is_file() {
+                        problems.push(format!("{}: no such file {:?}", label, config.path));
+                    }
+                    if config.speed <= 0.0 {
+                        problems.push(format!("{}: speed must be above 0", label));
+                    }
+                    &config.pace
+                }
+            };
+            if pace_mode(pace).is_none() {
+                problems.push(format!(
+                    "{}: unknown pace {:?}, expected realtime or fast",
+                    label, pace
+                ));
+            }
+        }
+
+        let mut readers: HashMap<&str, usize> = HashMap::new();
+        let mut receivers = HashSet::new();
+        for (index, config) in self.receivers.iter().enumerate() {
+            let label = label("receivers", index, &config.name);
+            if config.name.is_empty() {
+                problems.push(format!("{}: missing name", label));
+            } else if !receivers.insert(config.name.as_str()) {
+                problems.push(format!("{}: duplicate name", label));
+            }
+            check_dispatcher(&label, &config.dispatcher, &mut problems);
+            *readers.entry(config.dispatcher.as_str()).or_default() += 1;
+            if media_type(&config.media_type).is_none() {
+                problems.push(format!(
+                    "{}: unknown media_type {:?}",
+                    label,
+                    config.media_type.as_deref().unwrap_or_default()
+                ));
+            }
+            if let Err(problem) = start_pts(&config.start) {
+                problems.push(format!("{}: {}", label, problem));
+            }
+        }
+
+        for (index, sink) in self.sinks.iter().enumerate() {
+            let label = label("sinks", index, "");
+            check_dispatcher(&label, sink.dispatcher(), &mut problems);
+            *readers.entry(sink.dispatcher()).or_default() += 1;
+            match sink {
+                SinkConfig::FMP4(config) => {
+                    if config.path.as_os_str().is_empty() {
+                        problems.push(format!("{}: missing path", label));
+                    }
+                    match media_type(&config.media_type) {
+                        None => problems.push(format!(
+                            "{}: unknown media_type {:?}",
+                            label,
+                            config.media_type.as_deref().unwrap_or_default()
+                        )),
+                        // fmp4 only has audio and video tracks
+                        Some(MediaType::SUBTITLE | MediaType::METADATA | MediaType::DATA) => {
+                            problems.push(format!(
+                                "{}: media_type {:?} can not be written to fmp4, expected av, audio or video",
+                                label,
+                                config.media_type.as_deref().unwrap_or_default()
+                            ))
+                        }
+                        Some(_) => (),
+                    }
+                    if let Err(problem) = start_pts(&config.start) {
+                        problems.push(format!("{}: {}", label, problem));
+                    }
+                    if config.fragment_ms == Some(0) {
+                        problems.push(format!("{}: fragment_ms must be at least 1", label));
+                    }
+                }
+            }
+        }
+
+        let mut readers: Vec<_> = readers.into_iter().collect();
+        readers.sort();
+        for (dispatcher, count) in readers {
+            if count > MAX_RECEIVERS {
+                problems.push(format!(
+                    "dispatcher {:?}: {} receivers and sinks, at most {}",
+                    dispatcher, count, MAX_RECEIVERS
+                ));
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(ConfigError::INVALID(problems))
+        }
+    }
+
+    // sets the log levels and creates the graph, nothing runs before Pipeline::start
+    pub fn build(&self) -> Result<Pipeline, ConfigError> {
+        self.validate()?;
+        self.apply_logging();
+
+        let mut pipeline = Pipeline {
+            dispatchers: Vec::new(),
+            receivers: Vec::new(),
+            synthetic_sources: Vec::new(),
+            file_sources: Vec::new(),
+            sinks: Vec::new(),
+        };
+        for config in self.dispatchers.iter() {
+            let dispatcher = Dispatcher::new(config.max_capacity, config.capacity_increment);
+            {
+                let mut dispatcher = dispatcher.lock().unwrap();
+                dispatcher.set_overflow_policy(overflow_policy(&config.overflow).unwrap());
+                dispatcher.set_memory_budget(config.memory_budget);
+            }
+            pipeline.dispatchers.push((config.name.clone(), dispatcher));
+        }
+
+        // readers first, so that they see the sources from their first key frame
+        for config in self.receivers.iter() {
+            let media_type = media_type(&config.media_type).unwrap();
+            // the extra tracks are only delivered to receivers subscribed to them
+            let tracks = match media_type {
+                MediaType::AV | MediaType::AUDIO | MediaType::VIDEO => None,
+                media_type => Some(vec![MediaType::AUDIO, MediaType::VIDEO, media_type]),
+            };
+            let receiver = pipeline.attach(
+                &config.dispatcher,
+                tracks,
+                config.key_only,
+                start_pts(&config.start).unwrap(),
+            );
+            pipeline.receivers.push(PipelineReceiver {
+                name: config.name.clone(),
+                media_type,
+                receiver,
+            });
+        }
+
+        for (index, sink) in self.sinks.iter().enumerate() {
+            match sink {
+                SinkConfig::FMP4(config) => {
+                    let file = File::create(&config.path).map_err(|err| {
+                        ConfigError::BUILD(format!(
+                            "sinks[{}]: can not create {:?}: {}",
+                            index, config.path, err
+                        ))
+                    })?;
+                    let tracks = match media_type(&config.media_type).unwrap() {
+                        MediaType::AV => None,
+                        media_type => Some(vec![media_type]),
+                    };
+                    let receiver = pipeline.attach(
+                        &config.dispatcher,
+                        tracks,
+                        config.key_only,
+                        start_pts(&config.start).unwrap(),
+                    );
+                    let mode = config
+                        .fragment_ms
+                        .map_or(FragmentMode::GOP, FragmentMode::DURATION);
+                    pipeline
+                        .sinks
+                        .push(Fmp4Writer::with_receiver(receiver, Box::new(file), mode));
+                }
+            }
+        }
+
+        for (index, source) in self.sources.iter().enumerate() {
+            let dispatcher = pipeline.dispatcher(source.dispatcher()).unwrap();
+            match source {
+                SourceConfig::SYNTHETIC(config) => {
+                    let mut stream = SyntheticStream::new(
+                        config.gop,
+                        config.audio_per_video.saturating_add(1),
+                        (1000 / config.fps as u64).max(1),
+                    );
+                    stream.payload_size = config.payload;
+                    let mut source = SyntheticSource::new(stream, dispatcher);
+                    source.set_frame_count(config.frames);
+                    source.set_pace_mode(pace_mode(&config.pace).unwrap());
+                    pipeline.synthetic_sources.push(source);
+                }
+                SourceConfig::FILE(config) => {
+                    let mut source =
+                        FileSource::new(&config.path, dispatcher).ok_or_else(|| {
+                            ConfigError::BUILD(format!(
+                                "sources[{}]: can not open {:?}",
+                                index, config.path
+                            ))
+                        })?;
+                    source.set_pace_mode(pace_mode(&config.pace).unwrap());
+                    source.set_loop(config.looping);
+                    source.set_speed(config.speed);
+                    source.set_fps(config.fps);
+                    pipeline.file_sources.push(source);
+                }
+            }
+        }
+        info!(
+            "pipeline built: {} dispatchers, {} sources, {} receivers, {} sinks",
+            pipeline.dispatchers.len(),
+            pipeline.synthetic_sources.len() + pipeline.file_sources.len(),
+            pipeline.receivers.len(),
+            pipeline.sinks.len()
+        );
+        Ok(pipeline)
+    }
+
+    fn apply_logging(&self) {
+        macros::set_log_level(LogLevel::from_name(&self.logging.level).unwrap());
+        macros::clear_module_levels();
+        for (module, level) in self.logging.modules.iter() {
+            macros::set_module_level(module, LogLevel::from_name(level).unwrap());
+        }
+    }
+}
+
+pub struct PipelineReceiver {
+    pub name: String,
+    // what the owner of the pipeline reads from it
+    pub media_type: MediaType,
+    pub receiver: Arc<Receiver>,
+}
+
+// the graph built from a PipelineConfig
+pub struct Pipeline {
+    dispatchers: Vec<(String, Arc<Mutex<Dispatcher>>)>,
+    receivers: Vec<PipelineReceiver>,
+    synthetic_sources: Vec<SyntheticSource>,
+    file_sources: Vec<FileSource>,
+    sinks: Vec<Fmp4Writer>,
+}
+
+impl Pipeline {
+    fn attach(
+        &self,
+        dispatcher: &str,
+        tracks: Option<Vec<MediaType>>,
+        key_only: bool,
+        start: Option<u64>,
+    ) -> Arc<Receiver> {
+        let dispatcher = self.dispatcher(dispatcher).unwrap();
+        let receiver = Arc::new(Receiver::new());
+        if let Some(tracks) = tracks {
+            receiver.set_tracks(&tracks);
+        }
+        match start {
+            Some(pts) => dispatcher
+                .lock()
+                .unwrap()
+                .attach_receiver_after(receiver.clone(), pts),
+            None => dispatcher.lock().unwrap().attach_receiver(receiver.clone()),
+        }
+        receiver.set_dispatcher(dispatcher);
+        receiver.set_key_mode(key_only);
+        receiver.set_start_pts(start);
+        receiver
+    }
+
+    pub fn dispatcher(&self, name: &str) -> Option<Arc<Mutex<Dispatcher>>> {
+        self.dispatchers
+            .iter()
+            .find(|(other, _)| other == name)
+            .map(|(_, dispatcher)| dispatcher.clone())
+    }
+
+    pub fn dispatcher_names(&self) -> Vec<String> {
+        self.dispatchers
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    pub fn receiver(&self, name: &str) -> Option<Arc<Receiver>> {
+        self.receivers
+            .iter()
+            .find(|receiver| receiver.name == name)
+            .map(|receiver| receiver.receiver.clone())
+    }
+
+    // in config order
+    pub fn receivers(&self) -> &[PipelineReceiver] {
+        &self.receivers
+    }
+
+    pub fn start(&mut self) {
+        info!("start pipeline");
+        for (_, dispatcher) in self.dispatchers.iter() {
+            dispatcher.lock().unwrap().start_dispatch();
+        }
+        for sink in self.sinks.iter_mut() {
+            sink.start_write();
+        }
+        for source in self.synthetic_sources.iter_mut() {
+            source.start_ingest();
+        }
+        for source in self.file_sources.iter_mut() {
+            source.start_ingest();
+        }
+    }
+
+    // false once every source is done, the sinks and receivers may still read
+    pub fn is_ingesting(&self) -> bool {
+        self.synthetic_sources
+            .iter()
+            .any(|source| source.is_running())
+            || self.file_sources.iter().any(|source| source.is_running())
+    }
+
+    pub fn stop_ingest(&mut self) {
+        for source in self.synthetic_sources.iter_mut() {
+            source.stop_ingest();
+        }
+        for source in self.file_sources.iter_mut() {
+            source.stop_ingest();
+        }
+    }
+
+    pub fn stop(&mut self) {
+        info!("stop pipeline");
+        self.stop_ingest();
+        for sink in self.sinks.iter_mut() {
+            sink.stop_write();
+        }
+        for receiver in self.receivers.iter() {
+            receiver.receiver.notify_read_stop();
+        }
+        for (name, dispatcher) in self.dispatchers.iter() {
+            let dispatcher = dispatcher.lock().unwrap();
+            if dispatcher.dropped_frames() > 0 {
+                warn!(
+                    "dispatcher {}: {} frames dropped on overflow",
+                    name,
+                    dispatcher.dropped_frames()
+                );
+            }
+        }
+        for (_, dispatcher) in self.dispatchers.iter() {
+            dispatcher.lock().unwrap().stop_dispatch();
+        }
+    }
+}
@@ -0,0 +1,445 @@
+use super::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::ipc::{media_type_code, media_type_from_code};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    Identity,
+};
+use crate::{error, info, warn};
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    path::Path,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// a trace is the magic and a version byte followed by one record per event:
+// kind u8, microseconds since the previous event u32, then by kind
+//   input:  frame
+//   attach: receiver, 1 and pts u64 for attach_receiver_after or 0
+//   notify: receiver, media type
+//   read:   receiver, media type, 1 and frame or 0 when nothing was read
+//   detach: receiver id u32
+// receiver: id u32, flags (bit 0 key only), track mask u8
+// frame:    media type, flags (bit 0 key frame, bit 1 discontinuity), track id u32,
+//           pts u64, dts u64, payload length u32
+// payloads are not kept, a replay feeds zeroed ones of the same length
+const MAGIC: &[u8; 4] = b"RPTR";
+const VERSION: u8 = 1;
+
+const KIND_INPUT: u8 = 0;
+const KIND_ATTACH: u8 = 1;
+const KIND_NOTIFY: u8 = 2;
+const KIND_READ: u8 = 3;
+const KIND_DETACH: u8 = 4;
+
+const FLAG_KEY_FRAME: u8 = 0x01;
+const FLAG_DISCONTINUITY: u8 = 0x02;
+const FLAG_KEY_ONLY: u8 = 0x01;
+
+// receiver ids of the replay start at 1, this one is never attached
+const UNKNOWN_RECEIVER: u32 = u32::MAX;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub media_type: MediaType,
+    pub track_id: u32,
+    pub pts: u64,
+    pub dts: u64,
+    pub key_frame: bool,
+    pub discontinuity: bool,
+    pub len: u32,
+}
+
+impl TraceFrame {
+    pub fn new(data: &MediaData) -> Self {
+        let len = data.buff.lock().unwrap().data().lock().unwrap().len();
+        TraceFrame {
+            media_type: data.media_type,
+            track_id: data.track_id,
+            pts: data.pts,
+            dts: data.dts,
+            key_frame: data.key_frame,
+            discontinuity: data.discontinuity,
+            len: len as u32,
+        }
+    }
+
+    fn media_data(&self) -> Arc<Mutex<MediaData>> {
+        let data = MediaData {
+            key_frame: self.key_frame,
+            pts: self.pts,
+            dts: self.dts,
+            media_type: self.media_type,
+            track_id: self.track_id,
+            discontinuity: self.discontinuity,
+            ..Default::default()
+        };
+        data.buff
+            .lock()
+            .unwrap()
+            .replace(vec![0; self.len as usize]);
+        Arc::new(Mutex::new(data))
+    }
+}
+
+// the receiver settings the dispatcher looks at, taken at every event since they
+// can change while attached
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceReceiver {
+    pub recv_id: u32,
+    pub key_only: bool,
+    pub tracks: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceEvent {
+    INPUT(TraceFrame),
+    ATTACH(TraceReceiver, Option<u64>),
+    NOTIFY(TraceReceiver, MediaType),
+    READ(TraceReceiver, MediaType, Option<TraceFrame>),
+    DETACH(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceRecord {
+    // microseconds since the capture started
+    pub time: u64,
+    pub event: TraceEvent,
+}
+
+// records the calls into a dispatcher, see Dispatcher::set_capture
+#[derive(Debug)]
+pub struct SessionCapture {
+    output: BufWriter<File>,
+    // clock time of the last record
+    last: Option<Duration>,
+    events: u64,
+}
+
+impl SessionCapture {
+    pub fn new(path: &Path) -> Option<Self> {
+        let file = match File::create(path) {
+            Ok(file) => file,
+            Err(err) => {
+                error!("can not create trace {:?}: {}", path, err);
+                return None;
+            }
+        };
+        let mut output = BufWriter::new(file);
+        let header = output
+            .write_all(MAGIC)
+            .and_then(|_| output.write_all(&[VERSION]));
+        if let Err(err) = header {
+            error!("can not write trace {:?}: {}", path, err);
+            return None;
+        }
+        info!("capturing dispatcher session to {:?}", path);
+        Some(SessionCapture {
+            output,
+            last: None,
+            events: 0,
+        })
+    }
+
+    pub fn events(&self) -> u64 {
+        self.events
+    }
+
+    // now is the dispatcher's clock, the first record is at time 0
+    pub fn record(&mut self, event: &TraceEvent, now: Duration) -> io::Result<()> {
+        let elapsed = now
+            .saturating_sub(self.last.unwrap_or(now))
+            .as_micros()
+            .min(u32::MAX as u128) as u32;
+        self.last = Some(now);
+
+        let mut out = Vec::with_capacity(48);
+        let kind = match event {
+            TraceEvent::INPUT(_) => KIND_INPUT,
+            TraceEvent::ATTACH(..) => KIND_ATTACH,
+            TraceEvent::NOTIFY(..) => KIND_NOTIFY,
+            TraceEvent::READ(..) => KIND_READ,
+            TraceEvent::DETACH(_) => KIND_DETACH,
+        };
+        out.push(kind);
+        out.extend_from_slice(&elapsed.to_be_bytes());
+        match event {
+            TraceEvent::INPUT(frame) => encode_frame(&mut out, frame),
+            TraceEvent::ATTACH(receiver, after) => {
+                encode_receiver(&mut out, receiver);
+                match after {
+                    Some(pts) => {
+                        out.push(1);
+                        out.extend_from_slice(&pts.to_be_bytes());
+                    }
+                    None => out.push(0),
+                }
+            }
+            TraceEvent::NOTIFY(receiver, media_type) => {
+                encode_receiver(&mut out, receiver);
+                out.push(media_type_code(*media_type));
+            }
+            TraceEvent::READ(receiver, media_type, frame) => {
+                encode_receiver(&mut out, receiver);
+                out.push(media_type_code(*media_type));
+                match frame {
+                    Some(frame) => {
+                        out.push(1);
+                        encode_frame(&mut out, frame);
+                    }
+                    None => out.push(0),
+                }
+            }
+            TraceEvent::DETACH(recv_id) => out.extend_from_slice(&recv_id.to_be_bytes()),
+        }
+        self.output.write_all(&out)?;
+        self.events += 1;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+impl Drop for SessionCapture {
+    fn drop(&mut self) {
+        let _ = self.output.flush();
+    }
+}
+
+fn encode_frame(out: &mut Vec<u8>, frame: &TraceFrame) {
+    out.push(media_type_code(frame.media_type));
+    let mut flags = 0;
+    if frame.key_frame {
+        flags |= FLAG_KEY_FRAME;
+    }
+    if frame.discontinuity {
+        flags |= FLAG_DISCONTINUITY;
+    }
+    out.push(flags);
+    out.extend_from_slice(&frame.track_id.to_be_bytes());
+    out.extend_from_slice(&frame.pts.to_be_bytes());
+    out.extend_from_slice(&frame.dts.to_be_bytes());
+    out.extend_from_slice(&frame.len.to_be_bytes());
+}
+
+fn encode_receiver(out: &mut Vec<u8>, receiver: &TraceReceiver) {
+    out.extend_from_slice(&receiver.recv_id.to_be_bytes());
+    out.push(if receiver.key_only { FLAG_KEY_ONLY } else { 0 });
+    out.push(receiver.tracks as u8);
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+fn read_u8(input: &mut impl Read) -> io::Result<u8> {
+    let mut value = [0u8; 1];
+    input.read_exact(&mut value)?;
+    Ok(value[0])
+}
+
+fn read_u32(input: &mut impl Read) -> io::Result<u32> {
+    let mut value = [0u8; 4];
+    input.read_exact(&mut value)?;
+    Ok(u32::from_be_bytes(value))
+}
+
+fn read_u64(input: &mut impl Read) -> io::Result<u64> {
+    let mut value = [0u8; 8];
+    input.read_exact(&mut value)?;
+    Ok(u64::from_be_bytes(value))
+}
+
+fn read_media_type(input: &mut impl Read) -> io::Result<MediaType> {
+    media_type_from_code(read_u8(input)?).ok_or_else(|| invalid("invalid media type"))
+}
+
+fn decode_frame(input: &mut impl Read) -> io::Result<TraceFrame> {
+    let media_type = read_media_type(input)?;
+    let flags = read_u8(input)?;
+    Ok(TraceFrame {
+        media_type,
+        track_id: read_u32(input)?,
+        pts: read_u64(input)?,
+        dts: read_u64(input)?,
+        key_frame: flags & FLAG_KEY_FRAME != 0,
+        discontinuity: flags & FLAG_DISCONTINUITY != 0,
+        len: read_u32(input)?,
+    })
+}
+
+fn decode_receiver(input: &mut impl Read) -> io::Result<TraceReceiver> {
+    Ok(TraceReceiver {
+        recv_id: read_u32(input)?,
+        key_only: read_u8(input)? & FLAG_KEY_ONLY != 0,
+        tracks: read_u8(input)? as u32,
+    })
+}
+
+// None at the end of the trace
+fn decode_record(input: &mut impl Read, time: &mut u64) -> io::Result<Option<TraceRecord>> {
+    let mut kind = [0u8; 1];
+    if input.read(&mut kind)? == 0 {
+        return Ok(None);
+    }
+    *time += read_u32(input)? as u64;
+    let event = match kind[0] {
+        KIND_INPUT => TraceEvent::INPUT(decode_frame(input)?),
+        KIND_ATTACH => {
+            let receiver = decode_receiver(input)?;
+            let after = match read_u8(input)? {
+                0 => None,
+                _ => Some(read_u64(input)?),
+            };
+            TraceEvent::ATTACH(receiver, after)
+        }
+        KIND_NOTIFY => TraceEvent::NOTIFY(decode_receiver(input)?, read_media_type(input)?),
+        KIND_READ => {
+            let receiver = decode_receiver(input)?;
+            let media_type = read_media_type(input)?;
+            let frame = match read_u8(input)? {
+                0 => None,
+                _ => Some(decode_frame(input)?),
+            };
+            TraceEvent::READ(receiver, media_type, frame)
+        }
+        KIND_DETACH => TraceEvent::DETACH(read_u32(input)?),
+        _ => return Err(invalid("invalid trace record")),
+    };
+    Ok(Some(TraceRecord { time: *time, event }))
+}
+
+pub fn read_trace(path: &Path) -> io::Result<Vec<TraceRecord>> {
+    let mut input = BufReader::new(File::open(path)?);
+    let mut magic = [0u8; 4];
+    input.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a dispatcher trace"));
+    }
+    if read_u8(&mut input)? != VERSION {
+        return Err(invalid("unsupported trace version"));
+    }
+
+    let mut records = Vec::new();
+    let mut time = 0;
+    while let Some(record) = decode_record(&mut input, &mut time)? {
+        records.push(record);
+    }
+    Ok(records)
+}
+
+// a read whose outcome differs from the captured one
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Divergence {
+    // position of the read in the trace
+    pub event: usize,
+    pub time: u64,
+    pub recv_id: u32,
+    pub media_type: MediaType,
+    pub expected: Option<TraceFrame>,
+    pub actual: Option<TraceFrame>,
+}
+
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub events: usize,
+    // reads per captured receiver id
+    pub reads: HashMap<u32, usize>,
+    pub divergences: Vec<Divergence>,
+}
+
+impl ReplayReport {
+    pub fn is_clean(&self) -> bool {
+        self.divergences.is_empty()
+    }
+}
+
+// drives the dispatcher with the captured calls on the calling thread and compares
+// every read with the captured one. the dispatcher should be fresh and set up like
+// the captured one, e.g. with the same capacities and timestamp normalizer
+pub fn replay(records: &[TraceRecord], dispatcher: &Arc<Mutex<Dispatcher>>) -> ReplayReport {
+    let mut report = ReplayReport {
+        events: records.len(),
+        ..Default::default()
+    };
+    let mut receivers: HashMap<u32, Arc<Receiver>> = HashMap::new();
+    let replay_receiver = |receivers: &HashMap<u32, Arc<Receiver>>, traced: &TraceReceiver| {
+        let receiver = receivers.get(&traced.recv_id)?;
+        apply(receiver, traced);
+        Some(receiver.get_id())
+    };
+
+    for (event, record) in records.iter().enumerate() {
+        match &record.event {
+            TraceEvent::INPUT(frame) => {
+                dispatcher.lock().unwrap().input_data(frame.media_data());
+            }
+            TraceEvent::ATTACH(traced, after) => {
+                let receiver = Arc::new(Receiver::new());
+                apply(&receiver, traced);
+                let mut dispatcher = dispatcher.lock().unwrap();
+                match after {
+                    Some(pts) => dispatcher.attach_receiver_after(receiver.clone(), *pts),
+                    None => dispatcher.attach_receiver(receiver.clone()),
+                }
+                receivers.insert(traced.recv_id, receiver);
+            }
+            TraceEvent::NOTIFY(traced, media_type) => {
+                let recv_id = replay_receiver(&receivers, traced).unwrap_or(UNKNOWN_RECEIVER);
+                dispatcher
+                    .lock()
+                    .unwrap()
+                    .notify_read_ready(recv_id, *media_type);
+            }
+            TraceEvent::READ(traced, media_type, expected) => {
+                let recv_id = replay_receiver(&receivers, traced).unwrap_or(UNKNOWN_RECEIVER);
+                let (_, data) = dispatcher
+                    .lock()
+                    .unwrap()
+                    .read_buffer_data(recv_id, *media_type);
+                let actual = data.map(|data| TraceFrame::new(&data.lock().unwrap()));
+                *report.reads.entry(traced.recv_id).or_default() += 1;
+                if actual != *expected {
+                    warn!(
+                        "replay diverged at event {}, receiver: {}, expected: {:?}, actual: {:?}",
+                        event, traced.recv_id, expected, actual
+                    );
+                    report.divergences.push(Divergence {
+                        event,
+                        time: record.time,
+                        recv_id: traced.recv_id,
+                        media_type: *media_type,
+                        expected: *expected,
+                        actual,
+                    });
+                }
+            }
+            TraceEvent::DETACH(recv_id) => {
+                let recv_id = receivers
+                    .remove(recv_id)
+                    .map_or(UNKNOWN_RECEIVER, |receiver| receiver.get_id());
+                dispatcher.lock().unwrap().detach_receiver(recv_id);
+            }
+        }
+    }
+    info!(
+        "replayed {} events, {} divergences",
+        report.events,
+        report.divergences.len()
+    );
+    report
+}
+
+fn apply(receiver: &Receiver, traced: &TraceReceiver) {
+    let tracks: Vec<MediaType> = MediaType::TRACKS
+        .iter()
+        .filter(|track| traced.tracks & track.track_bit() != 0)
+        .copied()
+        .collect();
+    receiver.set_tracks(&tracks);
+    receiver.set_key_mode(traced.key_only);
+}
@@ -1,27 +1,41 @@
 use super::{
-    receiver::{self, Receiver},
+    capture::{SessionCapture, TraceEvent, TraceFrame, TraceReceiver},
+    dump::{self, DumpFormat},
+    dvr::DvrStore,
+    normalizer::{TimestampCorrections, TimestampNormalizer},
+    receiver::Receiver,
+    spill::{SpillFile, SpillSlot},
     // DispatcherReceiver,
 };
 use crate::utils::{
-    buffer::{MediaData, MediaType},
+    buffer::{Buffer, MediaData, MediaType, TRACK_COUNT},
+    clock::{SharedClock, SystemClock},
+    codec::CodecConfig,
+    sync::{self, ReadyMasks, Signal},
     Identity,
 };
 use crate::{debug, error, fatal, info, warn};
+use serde_json::{json, Value};
 use std::{
     collections::{HashMap, LinkedList, VecDeque},
-    ops::{Deref, DerefMut},
+    io,
     sync::{
-        atomic::{AtomicBool, AtomicU16, AtomicU32, Ordering},
-        Arc, Condvar, Mutex, MutexGuard, RwLock, Weak,
+        atomic::{AtomicU16, AtomicU32, Ordering},
+        Arc, Mutex, RwLock, Weak,
     },
-    thread::{self, JoinHandle, Thread},
 };
-const INVALID_INDEX: u32 = std::u32::MAX;
+const INVALID_INDEX: u32 = u32::MAX;
+// read indices are the bits of a sample's u16 reserve flag
+pub const MAX_RECEIVERS: usize = 16;
+// key frame pts kept per video track for the alignment check
+const KEY_HISTORY: usize = 8;
 
 #[derive(Debug)]
 struct DataNotifier {
-    pub audio_index: u32,
-    pub video_index: u32,
+    // the next buffer index to read, per track slot
+    pub indices: [u32; TRACK_COUNT],
+    // the tracks the receiver has read, as a mask of track bits
+    reads: u32,
 
     block: bool,
     read_index: u32,
@@ -31,8 +45,8 @@ struct DataNotifier {
 impl DataNotifier {
     fn new() -> Arc<Mutex<Self>> {
         Arc::new(Mutex::new(DataNotifier {
-            audio_index: INVALID_INDEX,
-            video_index: INVALID_INDEX,
+            indices: [INVALID_INDEX; TRACK_COUNT],
+            reads: 0,
 
             block: false,
             read_index: INVALID_INDEX,
@@ -45,13 +59,33 @@ impl DataNotifier {
     }
 
     pub fn is_mix_receiver(&self) -> bool {
-        let receiver = self.receiver.lock().unwrap().upgrade().unwrap().clone();
-        receiver.is_mix_read()
+        let receiver = self.receiver.lock().unwrap().upgrade();
+        receiver.is_some_and(|receiver| receiver.is_mix_read())
     }
 
     pub fn is_key_receiver(&self) -> bool {
-        let receiver = self.receiver.lock().unwrap().upgrade().unwrap().clone();
-        receiver.is_key_read()
+        let receiver = self.receiver.lock().unwrap().upgrade();
+        receiver.is_some_and(|receiver| receiver.is_key_read())
+    }
+
+    // the tracks the receiver subscribed to, as a mask of track bits
+    pub fn track_mask(&self) -> u32 {
+        let receiver = self.receiver.lock().unwrap().upgrade();
+        receiver.map_or(0, |receiver| receiver.track_mask())
+    }
+
+    pub fn is_subscribed(&self, media_type: MediaType) -> bool {
+        self.track_mask() & media_type.track_bit() != 0
+    }
+
+    // the tracks whose frames the receiver waits on: the ones it reads, all of the
+    // subscribed ones until its first read
+    pub fn read_mask(&self) -> u32 {
+        let track_mask = self.track_mask();
+        match self.reads & track_mask {
+            0 => track_mask,
+            reads => reads,
+        }
     }
 
     pub fn notify_data_receiver(&self, media_type: MediaType) {
@@ -61,34 +95,33 @@ impl DataNotifier {
             warn!("receiver is none");
             return;
         }
-        match media_type {
-            MediaType::AUDIO => receiver.unwrap().on_audio_data(),
-            MediaType::VIDEO => receiver.unwrap().on_video_data(),
-            MediaType::AV => receiver.unwrap().on_media_data(),
-        }
+        receiver.unwrap().on_data(media_type);
+    }
+
+    pub fn index(&self, media_type: MediaType) -> u32 {
+        self.indices[media_type.slot()]
+    }
+
+    pub fn set_index(&mut self, media_type: MediaType, index: u32) {
+        self.indices[media_type.slot()] = index;
     }
 
     pub fn get_receiver_read_index(&self, media_type: MediaType) -> u32 {
         match media_type {
-            MediaType::AUDIO => self.audio_index,
-            MediaType::VIDEO => self.video_index,
+            // a key-only mixed read follows the key frames of the video alone
+            MediaType::AV if self.is_key_receiver() => self.index(MediaType::VIDEO),
+            // the earliest position over the subscribed tracks
             MediaType::AV => {
-                if self.audio_index != INVALID_INDEX && self.video_index != INVALID_INDEX {
-                    if self.audio_index <= self.video_index {
-                        self.audio_index
-                    } else {
-                        self.video_index
-                    }
-                } else if self.audio_index == INVALID_INDEX && self.video_index == INVALID_INDEX {
-                    INVALID_INDEX
-                } else {
-                    if self.audio_index == INVALID_INDEX {
-                        self.video_index
-                    } else {
-                        self.audio_index
-                    }
-                }
+                let track_mask = self.track_mask();
+                MediaType::TRACKS
+                    .iter()
+                    .filter(|track| track_mask & track.track_bit() != 0)
+                    .map(|track| self.index(*track))
+                    .filter(|index| *index != INVALID_INDEX)
+                    .min()
+                    .unwrap_or(INVALID_INDEX)
             }
+            media_type => self.index(media_type),
         }
     }
 
@@ -101,11 +134,26 @@ impl DataNotifier {
     }
 }
 
+// what input_data does once max_capacity frames are buffered
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    // keep everything the slowest receiver has not read
+    #[default]
+    GROW,
+    // at each key frame drop the oldest GOPs past max_capacity, receivers reading
+    // them skip to the oldest kept key frame
+    EVICT,
+    // drop incoming frames, once dropping until the next key frame
+    DROP,
+}
+
 #[derive(Default, Debug)]
 struct DataSample {
     reserve_flag: AtomicU16,
     media_data: Arc<Mutex<MediaData>>,
     seq: u64,
+    // the payload is in the spill file, not in media_data
+    spilled: Option<SpillSlot>,
 }
 
 impl DataSample {
@@ -114,49 +162,70 @@ impl DataSample {
             reserve_flag: AtomicU16::new(0),
             media_data: data.clone(),
             seq: 0,
+            spilled: None,
         }
     }
 }
 
 #[derive(Default, Debug)]
 struct DispatcherInner {
-    running: bool,
-    notify_mutex: Arc<Mutex<()>>,
+    running: Arc<sync::AtomicBool>,
     notifiers: Arc<RwLock<HashMap<u32, Arc<Mutex<DataNotifier>>>>>,
-    data_condvar: Arc<Condvar>,
+    data_signal: Arc<Signal>,
 
-    continue_notify: Arc<AtomicBool>,
-    recv_ref: AtomicU32,
-    data_ref: AtomicU32,
+    // recv_ref and data_ref per read index
+    refs: ReadyMasks<MAX_RECEIVERS>,
     circular_buffer: Arc<RwLock<VecDeque<DataSample>>>,
     key_index: Arc<RwLock<LinkedList<u32>>>,
 }
 
-#[derive(Default, Debug)]
+#[derive(Debug)]
 pub struct Dispatcher {
     id: u32,
+    // timestamps the trace and times the receivers' read timeouts
+    clock: SharedClock,
     inner: Arc<Mutex<DispatcherInner>>,
     writing: bool,
-    audio_activate: bool,
-    video_activate: bool,
-    evaluating: bool,
+    // per track slot, the next input of the track moves waiting receivers to it
+    activate: [bool; TRACK_COUNT],
     waiting_key_frame: bool,
     read_flag: i16,
     base_count: u32,
-    video_frames: u32,
-    audio_frames: u32,
+    frames: [u32; TRACK_COUNT],
     max_capacity: u32,
-    base_capacity: u32,
-    double_capacity: u32,
+    // taken by the constructors, the buffer grows by what its VecDeque allocates
+    #[allow(dead_code)]
     capacity_increment: u32,
 
-    notify_thread: Option<JoinHandle<()>>,
+    notify_thread: Option<sync::thread::JoinHandle<()>>,
 
     gop: AtomicU32,
     data_mode: MediaType,
 
-    last_audio_index: u32,
-    last_video_index: u32,
+    last_index: [u32; TRACK_COUNT],
+
+    configs: [Option<CodecConfig>; TRACK_COUNT],
+
+    // recent key frame pts per video track id
+    key_pts: HashMap<u32, VecDeque<u64>>,
+
+    normalizer: Option<TimestampNormalizer>,
+    dvr: Option<Arc<Mutex<DvrStore>>>,
+
+    // payload bytes kept in memory before older payloads are spilled to disk
+    memory_budget: Option<usize>,
+    resident_bytes: usize,
+    spilled_frames: usize,
+    spill: Option<SpillFile>,
+    // set once a pass could not get under the budget, so that is only logged once
+    over_budget: bool,
+
+    capture: Option<SessionCapture>,
+
+    overflow_policy: OverflowPolicy,
+    // DROP dropped the last input, the next kept one is a key frame
+    dropping: bool,
+    dropped_frames: u64,
 }
 
 impl Identity for Dispatcher {
@@ -167,28 +236,44 @@ impl Identity for Dispatcher {
 
 impl Dispatcher {
     pub fn new(max_capacity: u32, capacity_increment: u32) -> Arc<Mutex<Self>> {
+        Self::with_clock(max_capacity, capacity_increment, SystemClock::shared())
+    }
+
+    pub fn with_clock(
+        max_capacity: u32,
+        capacity_increment: u32,
+        clock: SharedClock,
+    ) -> Arc<Mutex<Self>> {
         Arc::new(
             Dispatcher {
                 id: 1,
+                clock,
                 inner: Arc::new(Mutex::new(DispatcherInner::default())),
                 writing: false,
-                video_activate: false,
-                audio_activate: false,
-                evaluating: false,
+                activate: [false; TRACK_COUNT],
                 waiting_key_frame: true,
                 read_flag: 0,
                 base_count: 0,
-                video_frames: 0,
-                audio_frames: 0,
+                frames: [0; TRACK_COUNT],
                 max_capacity,
-                base_capacity: 50,
-                double_capacity: 100,
                 capacity_increment,
                 notify_thread: None,
                 gop: AtomicU32::new(0),
                 data_mode: MediaType::AV,
-                last_audio_index: INVALID_INDEX,
-                last_video_index: INVALID_INDEX,
+                last_index: [INVALID_INDEX; TRACK_COUNT],
+                configs: Default::default(),
+                key_pts: HashMap::new(),
+                normalizer: None,
+                dvr: None,
+                memory_budget: None,
+                resident_bytes: 0,
+                spilled_frames: 0,
+                spill: None,
+                over_budget: false,
+                capture: None,
+                overflow_policy: OverflowPolicy::GROW,
+                dropping: false,
+                dropped_frames: 0,
             }
             .into(),
         )
@@ -196,63 +281,65 @@ impl Dispatcher {
 
     pub fn start_dispatch(&mut self) {
         let inner = self.inner.clone();
-        inner.lock().unwrap().running = true;
-        let mtx = inner.lock().unwrap().notify_mutex.clone();
-        let condvar = inner.lock().unwrap().data_condvar.clone();
+        let running = inner.lock().unwrap().running.clone();
+        running.store(true, sync::Ordering::Release);
+        let data_signal = inner.lock().unwrap().data_signal.clone();
+
+        self.notify_thread = Some(sync::thread::spawn(move || {
+            sync::run_signaled(&running, &data_signal, || Self::notify_receivers(&inner));
+        }));
+        debug!("dispatch started");
+    }
+
+    // runs one notify pass on the calling thread, for driving an unstarted dispatcher step by step
+    pub fn dispatch_once(&self) {
+        Self::notify_receivers(&self.inner);
+    }
+
+    fn notify_receivers(inner: &Arc<Mutex<DispatcherInner>>) {
         let notifiers = inner.lock().unwrap().notifiers.clone();
-        let continue_notify = inner.lock().unwrap().continue_notify.clone();
-
-        self.notify_thread = Some(thread::spawn(move || {
-            while inner.lock().unwrap().running {
-                warn!("in notify thread");
-                let data_ref = inner.lock().unwrap().data_ref.load(Ordering::Relaxed);
-                let recv_ref = inner.lock().unwrap().recv_ref.load(Ordering::Relaxed);
-                let notify_ref = data_ref & recv_ref;
-                fatal!(
-                    "data: {}, recv: {}, notify: {}",
-                    data_ref,
-                    recv_ref,
-                    notify_ref
-                );
-                for (recv_id, notifier) in notifiers.read().unwrap().iter() {
-                    let read_index = notifier.lock().unwrap().get_read_index();
-                    if 0x0001 << (read_index * 2) & notify_ref != 0
-                        || 0x0001 << (read_index * 2 + 1) & notify_ref != 0
-                    {
-                        let mixed = notifier.lock().unwrap().is_mix_receiver();
-                        if mixed {
-                            notifier.lock().unwrap().notify_data_receiver(MediaType::AV);
-                        } else {
-                            if 0x0001 << (read_index * 2) & notify_ref != 0 {
-                                notifier
-                                    .lock()
-                                    .unwrap()
-                                    .notify_data_receiver(MediaType::AUDIO);
-                            } else if 0x0001 << (read_index * 2 + 1) & notify_ref != 0 {
-                                notifier
-                                    .lock()
-                                    .unwrap()
-                                    .notify_data_receiver(MediaType::VIDEO);
-                            }
-                        }
-                    }
+        for (recv_id, notifier) in notifiers.read().unwrap().iter() {
+            let read_index = notifier.lock().unwrap().get_read_index() as usize;
+            if read_index >= MAX_RECEIVERS {
+                continue;
+            }
+            let (data_ref, recv_ref, notify_ref) = {
+                let inner = inner.lock().unwrap();
+                (
+                    inner.refs.data(read_index),
+                    inner.refs.recv(read_index),
+                    inner.refs.ready(read_index),
+                )
+            };
+            fatal!(
+                "recv_id: {}, data: {}, recv: {}, notify: {}",
+                recv_id,
+                data_ref,
+                recv_ref,
+                notify_ref
+            );
+            if notify_ref == 0 {
+                continue;
+            }
+            let mixed = notifier.lock().unwrap().is_mix_receiver();
+            if mixed {
+                notifier.lock().unwrap().notify_data_receiver(MediaType::AV);
+                continue;
+            }
+            // every ready track, a read of one must not wait for another to be read
+            for track in MediaType::TRACKS {
+                if notify_ref & track.track_bit() != 0 {
+                    notifier.lock().unwrap().notify_data_receiver(track);
                 }
-                warn!("before wait");
-                let _unused = condvar.wait(mtx.lock().unwrap());
-                warn!("after wait");
-                continue_notify.store(false, Ordering::Relaxed);
             }
-        }));
-        debug!("dispatch started");
+        }
     }
 
     pub fn stop_dispatch(&mut self) {
-        let mut inner = self.inner.clone();
-        if inner.lock().unwrap().running == true {
-            inner.lock().unwrap().running = false;
-            inner.lock().unwrap().continue_notify = Arc::new(AtomicBool::new(true));
-
-            inner.lock().unwrap().data_condvar.notify_all();
+        let inner = self.inner.clone();
+        let running = inner.lock().unwrap().running.clone();
+        if running.swap(false, sync::Ordering::AcqRel) {
+            inner.lock().unwrap().data_signal.notify();
             self.notify_thread
                 .take()
                 .unwrap()
@@ -266,18 +353,29 @@ impl Dispatcher {
         if !self.writing {
             self.writing = true;
         }
+        if self.capture.is_some() {
+            let frame = TraceFrame::new(&data.lock().unwrap());
+            self.record(TraceEvent::INPUT(frame));
+        }
         let inner = self.inner.clone();
         let pts = data.lock().unwrap().pts;
         let buff = data.lock().unwrap().buff.clone();
         let key_frame = data.lock().unwrap().key_frame;
-        let media_type = data.lock().unwrap().media_type;
+        let media_type = match data.lock().unwrap().media_type {
+            // not a track of its own, counted as video
+            MediaType::AV => MediaType::VIDEO,
+            media_type => media_type,
+        };
         info!(
             "input data, pts: {}, key_frame: {}, media_type: {:?}",
             pts, key_frame, media_type
         );
 
+        // every audio frame is a starting point when there is no video to wait for
+        let sync_point =
+            key_frame || (self.data_mode == MediaType::AUDIO && media_type == MediaType::AUDIO);
         if self.waiting_key_frame {
-            if key_frame {
+            if sync_point {
                 info!("got the first key frame");
                 self.flush_buffer();
                 self.base_count += 1;
@@ -288,37 +386,72 @@ impl Dispatcher {
             }
         }
 
+        if self.overflow_policy == OverflowPolicy::DROP {
+            let len = inner.lock().unwrap().circular_buffer.read().unwrap().len() as u32;
+            // key frames are kept, they free the GOPs that were read
+            if !sync_point && (self.dropping || len >= self.max_capacity) {
+                if !self.dropping {
+                    warn!("buffer full at {} frames, dropping until a key frame", len);
+                }
+                self.dropping = true;
+                self.dropped_frames += 1;
+                return;
+            }
+            self.dropping = false;
+        }
+
+        // the caller's frame may be shared with other dispatchers or reused by its
+        // producer, so the normalizer rewrites a copy that shares the payload
+        let data = match self.normalizer.as_mut() {
+            Some(normalizer) => {
+                let mut normalized = data.lock().unwrap().clone();
+                normalizer.normalize(&mut normalized);
+                Arc::new(Mutex::new(normalized))
+            }
+            None => data,
+        };
+
         let data_sample = DataSample::new(data.clone());
 
-        let mut circular_buffer = inner.lock().unwrap().circular_buffer.clone();
+        let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
 
         circular_buffer.write().unwrap().push_back(data_sample);
+        self.resident_bytes += payload_len(&buff);
 
         let buffer_len = circular_buffer.read().unwrap().len() as u32;
 
-        let data = data.lock().unwrap();
-        if key_frame {
+        if key_frame && media_type == MediaType::VIDEO {
+            let (track_id, pts) = {
+                let data = data.lock().unwrap();
+                (data.track_id, data.pts)
+            };
+            let key_pts = self.key_pts.entry(track_id).or_default();
+            key_pts.push_back(pts);
+            if key_pts.len() > KEY_HISTORY {
+                key_pts.pop_front();
+            }
+        }
+        if sync_point {
             fatal!("input key frame, cur_len: {}", buffer_len);
             self.erase_old_gop();
-            self.audio_activate = true;
-            self.video_activate = true;
+            if self.overflow_policy == OverflowPolicy::EVICT {
+                self.drop_overflow();
+            }
+            self.activate = [true; TRACK_COUNT];
         }
+        let buffer_len = circular_buffer.read().unwrap().len() as u32;
 
-        if media_type == MediaType::AUDIO {
-            self.last_audio_index = buffer_len - 1;
-            self.activate_data_ref(MediaType::AUDIO, false);
-            self.audio_frames += 1;
-        } else {
-            self.last_video_index = buffer_len - 1;
-            self.activate_data_ref(MediaType::VIDEO, key_frame);
-            self.video_frames += 1;
-        }
+        let slot = media_type.slot();
+        self.last_index[slot] = buffer_len - 1;
+        self.activate_data_ref(media_type, key_frame && media_type == MediaType::VIDEO);
+        self.frames[slot] += 1;
 
-        if self.audio_activate && media_type == MediaType::AUDIO {
-            self.activate_receiver_index(buffer_len - 1, MediaType::AUDIO);
+        // only video waits for a key frame, the other tracks start at any frame
+        if self.activate[slot] && media_type != MediaType::VIDEO {
+            self.activate_receiver_index(buffer_len - 1, media_type);
         }
 
-        if key_frame {
+        if sync_point {
             inner
                 .lock()
                 .unwrap()
@@ -326,23 +459,271 @@ impl Dispatcher {
                 .write()
                 .unwrap()
                 .push_back(circular_buffer.read().unwrap().len() as u32 - 1);
+        }
+        if key_frame && media_type == MediaType::VIDEO && self.activate[slot] {
+            self.activate_receiver_index(buffer_len - 1, MediaType::VIDEO);
+        }
+        self.enforce_budget();
+        inner.lock().unwrap().data_signal.notify();
+        debug!("input done");
+    }
 
-            if (self.video_activate) {
-                self.activate_receiver_index(buffer_len - 1, MediaType::VIDEO);
-            }
+    // MediaType::AUDIO for an audio-only input: audio frames then open the key frame
+    // gate and bound the buffer the way key frames do
+    pub fn set_data_mode(&mut self, data_mode: MediaType) {
+        info!("set data mode: {:?}", data_mode);
+        self.data_mode = data_mode;
+    }
+
+    pub fn data_mode(&self) -> MediaType {
+        self.data_mode
+    }
+
+    // drops the buffered frames and waits for a key frame again, for an input that
+    // ended or restarted its timeline
+    pub fn flush(&mut self) {
+        info!("flush dispatcher");
+        self.flush_buffer();
+        self.inner.lock().unwrap().refs.clear_data();
+    }
+
+    // rewrites the timestamps of accepted frames before they are buffered, None turns it off
+    pub fn set_timestamp_normalizer(&mut self, normalizer: Option<TimestampNormalizer>) {
+        self.normalizer = normalizer;
+    }
+
+    pub fn timestamp_corrections(&self) -> Option<TimestampCorrections> {
+        self.normalizer
+            .as_ref()
+            .map(|normalizer| normalizer.corrections())
+    }
+
+    pub fn set_codec_config(&mut self, config: CodecConfig) {
+        info!("set codec config: {:?}", config.codec);
+        match config.media_type() {
+            MediaType::AV => warn!("unknown codec, config ignored"),
+            media_type => self.configs[media_type.slot()] = Some(config),
         }
-        inner
-            .lock()
+    }
+
+    pub fn get_codec_config(&self, media_type: MediaType) -> Option<CodecConfig> {
+        match media_type {
+            MediaType::AV => None,
+            media_type => self.configs[media_type.slot()].clone(),
+        }
+    }
+
+    pub fn receiver_count(&self) -> usize {
+        let notifiers = self.inner.lock().unwrap().notifiers.clone();
+        let count = notifiers.read().unwrap().len();
+        count
+    }
+
+    // the video track ids seen since the last key frame gate
+    pub fn video_tracks(&self) -> Vec<u32> {
+        let mut tracks: Vec<u32> = self.key_pts.keys().copied().collect();
+        tracks.sort();
+        tracks
+    }
+
+    // whether two video tracks have put their key frames at the same pts so far, so
+    // a reader can move from one to the other at a key frame without a gap or overlap
+    pub fn is_gop_aligned(&self, track_id: u32, other: u32) -> bool {
+        let (keys, other_keys) = match (self.key_pts.get(&track_id), self.key_pts.get(&other)) {
+            (Some(keys), Some(other_keys)) => (keys, other_keys),
+            _ => return false,
+        };
+        // compare over the span both histories cover, one of them may be a key ahead
+        let from = (*keys.front().unwrap()).max(*other_keys.front().unwrap());
+        let to = (*keys.back().unwrap()).min(*other_keys.back().unwrap());
+        let span = |keys: &VecDeque<u64>| -> Vec<u64> {
+            keys.iter()
+                .copied()
+                .filter(|pts| (from..=to).contains(pts))
+                .collect()
+        };
+        let common = span(keys);
+        !common.is_empty() && common == span(other_keys)
+    }
+
+    // frames currently held in the buffer, all tracks together
+    pub fn buffered_frames(&self) -> u32 {
+        self.frames.iter().sum()
+    }
+
+    // GOPs leaving the buffer are written to the store, None turns it off
+    pub fn set_dvr_store(&mut self, dvr: Option<Arc<Mutex<DvrStore>>>) {
+        self.dvr = dvr;
+    }
+
+    pub fn dvr_store(&self) -> Option<Arc<Mutex<DvrStore>>> {
+        self.dvr.clone()
+    }
+
+    // above this many payload bytes in memory the oldest payloads that a receiver
+    // still has to read are moved to a temp file, and read back when they are read;
+    // None keeps everything in memory. a frame a reader still holds the data of is
+    // never spilled, so the budget can be exceeded while readers keep frames
+    pub fn set_memory_budget(&mut self, budget: Option<usize>) {
+        info!("set memory budget: {:?}", budget);
+        self.memory_budget = budget;
+        self.enforce_budget();
+    }
+
+    pub fn memory_budget(&self) -> Option<usize> {
+        self.memory_budget
+    }
+
+    // payload bytes of the buffered frames that are in memory
+    pub fn resident_bytes(&self) -> usize {
+        self.resident_bytes
+    }
+
+    pub fn spilled_frames(&self) -> usize {
+        self.spilled_frames
+    }
+
+    // what input_data does once max_capacity frames are buffered, GROW by default.
+    // applies from the next frame on, frames dropped so far stay counted
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        info!("set overflow policy: {:?}", policy);
+        self.overflow_policy = policy;
+    }
+
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
+
+    // frames the overflow policy dropped, incoming or buffered
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
+    }
+
+    pub fn clock(&self) -> SharedClock {
+        self.clock.clone()
+    }
+
+    // records the calls of the ingest and the receivers for a later replay, see
+    // capture::replay; None stops recording
+    pub fn set_capture(&mut self, capture: Option<SessionCapture>) {
+        self.capture = capture;
+    }
+
+    pub fn is_capturing(&self) -> bool {
+        self.capture.is_some()
+    }
+
+    // a snapshot of the buffer and the receiver cursors for inspecting a stalled
+    // receiver: every sample with its reserve_flag, each notifier's indices with the
+    // data_ref and recv_ref masks of its read index, and key_index. indices that are
+    // not set are null
+    pub fn dump_state(&self) -> Value {
+        let inner = self.inner.lock().unwrap();
+        let index_value = |index: u32| match index {
+            INVALID_INDEX => Value::Null,
+            index => json!(index),
+        };
+        let per_track = |values: &dyn Fn(MediaType) -> Value| {
+            MediaType::TRACKS
+                .iter()
+                .map(|track| (dump::track_name(*track), values(*track)))
+                .collect::<serde_json::Map<String, Value>>()
+        };
+
+        let samples: Vec<Value> = inner
+            .circular_buffer
+            .read()
             .unwrap()
-            .continue_notify
-            .store(true, Ordering::Relaxed);
-        inner.lock().unwrap().data_condvar.notify_all();
-        debug!("input done");
+            .iter()
+            .enumerate()
+            .map(|(index, sample)| {
+                let data = sample.media_data.lock().unwrap();
+                json!({
+                    "index": index,
+                    "seq": sample.seq,
+                    "media_type": dump::track_name(data.media_type),
+                    "track_id": data.track_id,
+                    "pts": data.pts,
+                    "dts": data.dts,
+                    "key_frame": data.key_frame,
+                    "reserve_flag": sample.reserve_flag.load(Ordering::Relaxed),
+                    "spilled": sample.spilled.is_some(),
+                })
+            })
+            .collect();
+
+        let mut receivers: Vec<Value> = inner
+            .notifiers
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(recv_id, notifier)| {
+                let notifier = notifier.lock().unwrap();
+                let read_index = notifier.get_read_index();
+                let refs = |mask: &dyn Fn(usize) -> u32| {
+                    if (read_index as usize) < MAX_RECEIVERS {
+                        json!(mask(read_index as usize))
+                    } else {
+                        Value::Null
+                    }
+                };
+                // a receiver dropped without detaching has no settings left
+                let receiver = notifier.receiver.lock().unwrap().upgrade();
+                json!({
+                    "recv_id": recv_id,
+                    "read_index": index_value(read_index),
+                    "key_only": receiver.as_ref().map(|receiver| receiver.is_key_read()),
+                    "mix_read": receiver.as_ref().map(|receiver| receiver.is_mix_read()),
+                    "tracks": receiver.as_ref().map(|receiver| receiver.track_mask()),
+                    "indices": per_track(&|track| index_value(notifier.index(track))),
+                    "data_ref": refs(&|index| inner.refs.data(index)),
+                    "recv_ref": refs(&|index| inner.refs.recv(index)),
+                })
+            })
+            .collect();
+        receivers.sort_by_key(|receiver| receiver["recv_id"].as_u64());
+
+        json!({
+            "id": self.id,
+            "running": inner.running.load(sync::Ordering::Relaxed),
+            "waiting_key_frame": self.waiting_key_frame,
+            "read_flag": self.read_flag as u16,
+            "frames": per_track(&|track| json!(self.frames[track.slot()])),
+            "last_index": per_track(&|track| index_value(self.last_index[track.slot()])),
+            "key_index": inner.key_index.read().unwrap().iter().collect::<Vec<_>>(),
+            "resident_bytes": self.resident_bytes,
+            "spilled_frames": self.spilled_frames,
+            "samples": samples,
+            "receivers": receivers,
+        })
+    }
+
+    pub fn dump_state_as(&self, format: DumpFormat) -> String {
+        dump::render(&self.dump_state(), format)
     }
 
     pub fn attach_receiver(&mut self, receiver: Arc<Receiver>) {
+        self.attach(receiver, None);
+    }
+
+    // starts the receiver at the first key frame after pts instead of the last one,
+    // for a reader coming from the frames already moved to disk
+    pub fn attach_receiver_after(&mut self, receiver: Arc<Receiver>, pts: u64) {
+        self.attach(receiver, Some(pts));
+    }
+
+    fn attach(&mut self, receiver: Arc<Receiver>, after: Option<u64>) {
         debug!("attach in");
         let receiver = receiver.clone();
+        receiver.set_clock(self.clock.clone());
+        if self.capture.is_some() {
+            let traced = TraceReceiver {
+                recv_id: receiver.get_id(),
+                key_only: receiver.is_key_read(),
+                tracks: receiver.track_mask(),
+            };
+            self.record(TraceEvent::ATTACH(traced, after));
+        }
         receiver.notify_read_start();
 
         if self.read_flag == 0xffffu16 as i16 {
@@ -366,7 +747,7 @@ impl Dispatcher {
 
         let usable_ref = !self.read_flag & (-(!self.read_flag));
 
-        if (usable_ref & usable_ref - 1) != 0 {
+        if (usable_ref & (usable_ref - 1)) != 0 {
             error!("usable_ref: {} invalid", usable_ref);
             return;
         }
@@ -387,61 +768,127 @@ impl Dispatcher {
         let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
 
         if circular_buffer.read().unwrap().is_empty() {
-            notifier.audio_index = INVALID_INDEX;
-            notifier.video_index = INVALID_INDEX;
+            notifier.indices = [INVALID_INDEX; TRACK_COUNT];
             drop(notifier);
-            self.set_receiver_data_ref(receiver.get_read_index(), MediaType::AUDIO, false);
-            self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, false);
-            self.video_activate = true;
-            self.audio_activate = true;
+            self.set_receiver_data_ref(receiver.get_read_index(), MediaType::AV, false);
+            self.activate = [true; TRACK_COUNT];
             debug!("attach done");
             return;
         }
 
+        let others = MediaType::TRACKS
+            .into_iter()
+            .filter(|track| *track != MediaType::VIDEO);
         if self.data_mode == MediaType::AUDIO {
-            notifier.audio_index = (circular_buffer.read().unwrap().len() - 1) as u32;
-            notifier.video_index = INVALID_INDEX;
+            notifier.indices = [INVALID_INDEX; TRACK_COUNT];
+            notifier.set_index(
+                MediaType::AUDIO,
+                (circular_buffer.read().unwrap().len() - 1) as u32,
+            );
             drop(notifier);
+            self.set_receiver_data_ref(receiver.get_read_index(), MediaType::AV, false);
             self.set_receiver_data_ref(receiver.get_read_index(), MediaType::AUDIO, true);
-            self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, false);
         } else {
             let key_index = inner.lock().unwrap().key_index.clone();
             if !key_index.read().unwrap().is_empty() {
-                let back = *key_index.read().unwrap().back().unwrap();
-                let temp_index = self.available_audio_index(back);
-                if temp_index == back {
-                    notifier.audio_index = INVALID_INDEX;
-                } else {
-                    notifier.audio_index = temp_index;
+                // video starts at the last key frame, the other tracks at their first
+                // frame after it; with several renditions at the first of the key
+                // frames that share its pts, so every rendition starts with a key frame
+                let back = {
+                    let key_index = key_index.read().unwrap();
+                    let last_pts = sample_pts(&circular_buffer, *key_index.back().unwrap());
+                    let next = after.and_then(|after| {
+                        key_index
+                            .iter()
+                            .find(|key| sample_pts(&circular_buffer, **key) > after)
+                    });
+                    match next {
+                        Some(next) => *next,
+                        None => *key_index
+                            .iter()
+                            .rev()
+                            .take_while(|key| sample_pts(&circular_buffer, **key) == last_pts)
+                            .last()
+                            .unwrap(),
+                    }
+                };
+                let mut available = Vec::new();
+                for track in others {
+                    let temp_index = self.available_index(back, track);
+                    if temp_index == back {
+                        notifier.set_index(track, INVALID_INDEX);
+                    } else {
+                        notifier.set_index(track, temp_index);
+                    }
+                    available.push((track, temp_index != INVALID_INDEX));
                 }
 
-                notifier.video_index = back;
+                notifier.set_index(MediaType::VIDEO, back);
                 drop(notifier);
-                self.set_receiver_data_ref(
-                    receiver.get_read_index(),
-                    MediaType::AUDIO,
-                    temp_index != INVALID_INDEX,
-                );
-                self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, true);
-                if self.last_audio_index == INVALID_INDEX {
-                    self.audio_activate = true;
+                for (track, available) in available {
+                    self.set_receiver_data_ref(receiver.get_read_index(), track, available);
+                    if self.last_index[track.slot()] == INVALID_INDEX {
+                        self.activate[track.slot()] = true;
+                    }
                 }
+                self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, true);
             } else {
-                notifier.audio_index = self.find_last_index(MediaType::AUDIO);
-                notifier.video_index = INVALID_INDEX;
+                for track in others.clone() {
+                    notifier.set_index(track, self.find_last_index(track));
+                }
+                notifier.set_index(MediaType::VIDEO, INVALID_INDEX);
                 drop(notifier);
-                self.set_receiver_data_ref(receiver.get_read_index(), MediaType::AUDIO, true);
-                self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, false);
-                if self.last_audio_index == INVALID_INDEX {
-                    self.audio_activate = true;
+                for track in others {
+                    self.set_receiver_data_ref(receiver.get_read_index(), track, true);
+                    if self.last_index[track.slot()] == INVALID_INDEX {
+                        self.activate[track.slot()] = true;
+                    }
                 }
+                self.set_receiver_data_ref(receiver.get_read_index(), MediaType::VIDEO, false);
             }
         }
         debug!("attach done");
     }
 
+    pub fn detach_receiver(&mut self, recv_id: u32) {
+        debug!("detach in");
+        if self.capture.is_some() {
+            self.record(TraceEvent::DETACH(recv_id));
+        }
+        let inner = self.inner.clone();
+        let notifier = inner
+            .lock()
+            .unwrap()
+            .notifiers
+            .write()
+            .unwrap()
+            .remove(&recv_id);
+        if notifier.is_none() {
+            warn!("receiver {} not attached", recv_id);
+            return;
+        }
+
+        let read_index = notifier.unwrap().lock().unwrap().get_read_index();
+        self.clear_data_bit(read_index, MediaType::AV);
+        self.clear_read_bit(read_index, MediaType::AV);
+
+        // release the read index so a later receiver starts with clean flags
+        let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
+        for sample in circular_buffer.read().unwrap().iter() {
+            sample
+                .reserve_flag
+                .fetch_and(!(0x1 << read_index), Ordering::Relaxed);
+        }
+        self.read_flag &= !(0x1 << read_index);
+        debug!("detach done, read_flag: {}", self.read_flag);
+    }
+
     pub fn notify_read_ready(&mut self, recv_id: u32, media_type: MediaType) {
         info!("notify read ready");
+        if self.capture.is_some() {
+            let traced = self.trace_receiver(recv_id);
+            self.record(TraceEvent::NOTIFY(traced, media_type));
+        }
         let inner = self.inner.clone();
 
         let notifier = inner
@@ -451,78 +898,89 @@ impl Dispatcher {
             .read()
             .unwrap()
             .get(&recv_id)
-            .unwrap()
-            .clone();
+            .cloned();
+        if notifier.is_none() {
+            warn!("receiver {} not attached", recv_id);
+            return;
+        }
+        let notifier = notifier.unwrap();
 
         let read_index = notifier.lock().unwrap().get_read_index();
-        if media_type == MediaType::AV {
-            self.set_receiver_read_ref(read_index, MediaType::AUDIO, true);
-            self.set_receiver_read_ref(read_index, MediaType::VIDEO, true);
-        } else {
-            self.set_receiver_read_ref(read_index, media_type, true);
-        }
+        self.set_receiver_read_ref(read_index, media_type, true);
 
-        let mut data_available = false;
+        let data_available;
         {
             let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
 
+            // a mixed read goes on from the earliest subscribed track, a key-only one
+            // from the video cursor
+            let current = notifier.lock().unwrap().get_receiver_read_index(media_type);
+            let key_receiver = notifier.lock().unwrap().is_key_receiver();
+            let track_mask = notifier.lock().unwrap().track_mask();
+            notifier.lock().unwrap().reads |= match media_type {
+                MediaType::AV if key_receiver => MediaType::VIDEO.track_bit(),
+                media_type => media_type.track_bit(),
+            };
+            let index = self.find_receiver_next_index(
+                current,
+                media_type,
+                read_index,
+                key_receiver,
+                track_mask,
+                circular_buffer.clone(),
+            );
             match media_type {
-                MediaType::AUDIO => {
-                    let audio_index = notifier.lock().unwrap().audio_index;
-                    let index = self.find_receiver_next_index(
-                        audio_index,
-                        media_type,
-                        read_index,
-                        notifier.lock().unwrap().is_key_receiver(),
-                        circular_buffer.clone(),
-                    );
-                    notifier.lock().unwrap().audio_index = index;
-                    data_available = !self.is_read(read_index, index, circular_buffer.clone());
+                MediaType::AV if key_receiver => {
+                    notifier.lock().unwrap().set_index(MediaType::VIDEO, index)
                 }
-                MediaType::VIDEO | MediaType::AV => {
-                    let video_index = notifier.lock().unwrap().video_index;
-                    let index = self.find_receiver_next_index(
-                        video_index,
-                        media_type,
-                        read_index,
-                        notifier.lock().unwrap().is_key_receiver(),
-                        circular_buffer.clone(),
-                    );
-                    notifier.lock().unwrap().video_index = index;
-                    data_available = !self.is_read(read_index, index, circular_buffer.clone());
+                MediaType::AV if index != current => {
+                    let mut notifier = notifier.lock().unwrap();
+                    for track in MediaType::TRACKS {
+                        if track_mask & track.track_bit() != 0 {
+                            notifier.set_index(track, index);
+                        }
+                    }
                 }
+                MediaType::AV => (),
+                media_type => notifier.lock().unwrap().set_index(media_type, index),
             }
+            data_available = !self.is_read(read_index, index, circular_buffer.clone());
 
-            let video_index = notifier.lock().unwrap().video_index;
-            let audio_index = notifier.lock().unwrap().audio_index;
+            let indices = notifier.lock().unwrap().indices;
             info!(
-                "notify read ready done, type: {:?}, audio: {}, video: {}, data_available: {:?}",
-                media_type, audio_index, video_index, data_available
+                "notify read ready done, type: {:?}, indices: {:?}, data_available: {:?}",
+                media_type, indices, data_available
             );
         }
 
-        if media_type == MediaType::AV {
-            self.set_receiver_data_ref(read_index, MediaType::AUDIO, data_available);
-            self.set_receiver_data_ref(read_index, MediaType::VIDEO, data_available);
-        } else {
-            self.set_receiver_data_ref(read_index, media_type, data_available);
-        }
+        self.set_receiver_data_ref(read_index, media_type, data_available);
 
         if !data_available {
             return;
         }
-        inner
-            .lock()
-            .unwrap()
-            .continue_notify
-            .store(true, Ordering::Relaxed);
-        inner.lock().unwrap().data_condvar.notify_one();
+        inner.lock().unwrap().data_signal.notify();
     }
 
     pub fn read_buffer_data(
         &mut self,
         recv_id: u32,
         media_type: MediaType,
+    ) -> (bool, Option<Arc<Mutex<MediaData>>>) {
+        let (ret, data) = self.read_sample(recv_id, media_type);
+        if self.capture.is_some() {
+            let traced = self.trace_receiver(recv_id);
+            let frame = data
+                .as_ref()
+                .map(|data| TraceFrame::new(&data.lock().unwrap()));
+            self.record(TraceEvent::READ(traced, media_type, frame));
+        }
+        (ret, data)
+    }
+
+    fn read_sample(
+        &mut self,
+        recv_id: u32,
+        media_type: MediaType,
     ) -> (bool, Option<Arc<Mutex<MediaData>>>) {
         debug!("read buffer data in");
         let inner = self.inner.clone();
@@ -558,7 +1016,7 @@ impl Dispatcher {
         }
 
         if notifier.lock().unwrap().is_key_receiver()
-            && media_type != MediaType::AUDIO
+            && matches!(media_type, MediaType::VIDEO | MediaType::AV)
             && !circular_buffer
                 .read()
                 .unwrap()
@@ -619,33 +1077,67 @@ impl Dispatcher {
             circular_buffer.clone(),
         );
 
-        let data = circular_buffer
-            .read()
-            .unwrap()
-            .get(index as usize)
-            .unwrap()
-            .media_data
-            .clone();
+        let (data, spilled) = {
+            let mut circular_buffer = circular_buffer.write().unwrap();
+            let sample = circular_buffer.get_mut(index as usize).unwrap();
+            (sample.media_data.clone(), sample.spilled.take())
+        };
+        if let Some(slot) = spilled {
+            if let Err(err) = self.reload(&data, slot) {
+                // the slot stays, the frame may still be read back by another receiver
+                error!("reload failed: {}", err);
+                circular_buffer.write().unwrap()[index as usize].spilled = Some(slot);
+                return (false, None);
+            }
+            self.spilled_frames -= 1;
+            self.clear_spill();
+            self.enforce_budget();
+        }
 
         debug!("read buffer data out");
         (true, Some(data.clone()))
     }
 
+    // AV clears the bits of every track
     pub fn clear_data_bit(&mut self, read_index: u32, media_type: MediaType) {
-        if media_type != MediaType::AV {
-            self.set_receiver_data_ref(read_index, media_type, false);
-        } else {
-            self.set_receiver_data_ref(read_index, MediaType::VIDEO, false);
-            self.set_receiver_data_ref(read_index, MediaType::AUDIO, false);
-        }
+        self.set_receiver_data_ref(read_index, media_type, false);
     }
 
     pub fn clear_read_bit(&mut self, read_index: u32, media_type: MediaType) {
-        if media_type != MediaType::AV {
-            self.set_receiver_read_ref(read_index, media_type, false);
-        } else {
-            self.set_receiver_read_ref(read_index, MediaType::VIDEO, false);
-            self.set_receiver_read_ref(read_index, MediaType::AUDIO, false);
+        self.set_receiver_read_ref(read_index, media_type, false);
+    }
+
+    // the receiver settings as the dispatcher sees them, empty for an unknown receiver
+    fn trace_receiver(&self, recv_id: u32) -> TraceReceiver {
+        let notifier = self
+            .inner
+            .lock()
+            .unwrap()
+            .notifiers
+            .read()
+            .unwrap()
+            .get(&recv_id)
+            .cloned();
+        let (key_only, tracks) = match notifier {
+            Some(notifier) => {
+                let notifier = notifier.lock().unwrap();
+                (notifier.is_key_receiver(), notifier.track_mask())
+            }
+            None => (false, 0),
+        };
+        TraceReceiver {
+            recv_id,
+            key_only,
+            tracks,
+        }
+    }
+
+    fn record(&mut self, event: TraceEvent) {
+        if let Some(capture) = self.capture.as_mut() {
+            if let Err(err) = capture.record(&event, self.clock.now()) {
+                error!("trace write failed, capture stopped: {}", err);
+                self.capture = None;
+            }
         }
     }
 
@@ -663,19 +1155,19 @@ impl Dispatcher {
         inner.lock().unwrap().key_index.write().unwrap().clear();
 
         self.gop.store(0, Ordering::Relaxed);
-        self.audio_frames = 0;
-        self.video_frames = 0;
+        self.frames = [0; TRACK_COUNT];
+        self.key_pts.clear();
+        self.resident_bytes = 0;
+        self.spilled_frames = 0;
+        self.clear_spill();
         let notifiers = inner.lock().unwrap().notifiers.clone();
 
-        for (recv_id, notifier) in notifiers.read().unwrap().iter() {
-            notifier.lock().unwrap().audio_index = INVALID_INDEX;
-            notifier.lock().unwrap().video_index = INVALID_INDEX;
+        for notifier in notifiers.read().unwrap().values() {
+            notifier.lock().unwrap().indices = [INVALID_INDEX; TRACK_COUNT];
         }
 
-        self.video_activate = true;
-        self.audio_activate = true;
-        self.last_audio_index = INVALID_INDEX;
-        self.last_video_index = INVALID_INDEX;
+        self.activate = [true; TRACK_COUNT];
+        self.last_index = [INVALID_INDEX; TRACK_COUNT];
         self.waiting_key_frame = true;
     }
 
@@ -683,92 +1175,299 @@ impl Dispatcher {
         let inner = self.inner.clone();
         let key_index = inner.lock().unwrap().key_index.clone();
         let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
-        let notifiers = inner.lock().unwrap().notifiers.clone();
 
+        let skipped = self.skipped_flags();
         let mut cnt = 0;
-        let mut next_key = 0;
+        let mut unread = None;
         for key in key_index.read().unwrap().iter() {
-            if circular_buffer
-                .read()
-                .unwrap()
-                .get(*key as usize)
-                .unwrap()
-                .reserve_flag
-                .fetch_xor(self.read_flag as u16, Ordering::Relaxed)
-                != 0
-            {
-                next_key = *key;
+            // stop at the first key frame that some receiver has not read yet
+            let read = self.is_sample_read(
+                circular_buffer.read().unwrap().get(*key as usize).unwrap(),
+                &skipped,
+            );
+            if !read {
+                unread = Some(*key);
                 break;
             } else {
                 cnt += 1;
             }
         }
 
-        while cnt != 0 {
-            key_index.write().unwrap().pop_front();
+        let newest = circular_buffer.read().unwrap().len() as u32 - 1;
+        let mut next_key = match unread {
+            Some(key) => key,
+            // every key frame was read, the GOPs before the incoming key frame go once
+            // all of their frames were
+            None => {
+                let read = circular_buffer
+                    .read()
+                    .unwrap()
+                    .iter()
+                    .take(newest as usize)
+                    .all(|sample| self.is_sample_read(sample, &skipped));
+                if read {
+                    newest
+                } else {
+                    0
+                }
+            }
+        };
+
+        // keep the key frames the other renditions have at the same pts
+        let next_pts = match next_key {
+            0 => sample_pts(&circular_buffer, newest),
+            next_key => sample_pts(&circular_buffer, next_key),
+        };
+        while cnt > 0 {
+            let key = *key_index.read().unwrap().iter().nth(cnt - 1).unwrap();
+            if sample_pts(&circular_buffer, key) != next_pts {
+                break;
+            }
+            if next_key > 0 {
+                next_key = key;
+            }
             cnt -= 1;
         }
 
+        // drops the key frames before next_key along with their GOPs
         if next_key > 0 {
-            for i in 0..next_key {
-                let media_type = circular_buffer
-                    .read()
-                    .unwrap()
-                    .front()
-                    .unwrap()
-                    .media_data
-                    .lock()
-                    .unwrap()
-                    .media_type;
-                if media_type == MediaType::AUDIO {
-                    self.audio_frames -= 1;
-                } else if media_type == MediaType::VIDEO {
-                    self.video_frames -= 1;
-                }
+            self.evict_front(next_key);
+        }
+    }
 
-                circular_buffer.write().unwrap().pop_front();
+    // per track, the read flags of the receivers that do not wait on its frames
+    fn skipped_flags(&self) -> [u16; TRACK_COUNT] {
+        let mut skipped = [0; TRACK_COUNT];
+        let notifiers = self.inner.lock().unwrap().notifiers.clone();
+        for notifier in notifiers.read().unwrap().values() {
+            let notifier = notifier.lock().unwrap();
+            let read_mask = notifier.read_mask();
+            for track in MediaType::TRACKS {
+                if read_mask & track.track_bit() == 0 {
+                    skipped[track.slot()] |= 0x1 << notifier.get_read_index();
+                }
             }
+        }
+        skipped
+    }
+
+    // read by every receiver that waits on the frame
+    fn is_sample_read(&self, sample: &DataSample, skipped: &[u16; TRACK_COUNT]) -> bool {
+        let slot = match sample.media_data.lock().unwrap().media_type {
+            MediaType::AV => MediaType::VIDEO.slot(),
+            media_type => media_type.slot(),
+        };
+        let reserve_flag = sample.reserve_flag.load(Ordering::Relaxed) | skipped[slot];
+        reserve_flag ^ self.read_flag as u16 == 0
+    }
+
+    // drops the count oldest samples, receivers whose position was dropped restart at
+    // the oldest kept key frame
+    fn evict_front(&mut self, count: u32) {
+        let inner = self.inner.clone();
+        let key_index = inner.lock().unwrap().key_index.clone();
+        let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
+        let notifiers = inner.lock().unwrap().notifiers.clone();
 
-            for key in key_index.write().unwrap().iter_mut() {
-                *key -= next_key;
+        let mut evicted = Vec::new();
+        for _ in 0..count {
+            let media_type = circular_buffer
+                .read()
+                .unwrap()
+                .front()
+                .unwrap()
+                .media_data
+                .lock()
+                .unwrap()
+                .media_type;
+            if media_type != MediaType::AV {
+                self.frames[media_type.slot()] -= 1;
             }
-            fatal!(
-                "next_key: {}, cur_len: {}",
-                next_key,
-                circular_buffer.read().unwrap().len()
-            );
-            self.last_audio_index -= next_key;
-            self.last_video_index -= next_key;
-            for (recv_id, notifier) in notifiers.write().unwrap().iter_mut() {
-                if notifier.lock().unwrap().video_index != INVALID_INDEX {
-                    notifier.lock().unwrap().video_index -= next_key;
+
+            let sample = circular_buffer.write().unwrap().pop_front().unwrap();
+            match sample.spilled {
+                Some(slot) => {
+                    self.spilled_frames -= 1;
+                    // the store needs the payload back, otherwise it just goes
+                    if self.dvr.is_some() {
+                        match self.reload(&sample.media_data, slot) {
+                            Ok(()) => self.resident_bytes -= slot.len(),
+                            Err(err) => error!("reload failed, stored without payload: {}", err),
+                        }
+                    }
+                }
+                None => {
+                    let buff = sample.media_data.lock().unwrap().buff.clone();
+                    self.resident_bytes -= payload_len(&buff);
                 }
+            }
+            evicted.push(sample.media_data);
+        }
+        if let Some(dvr) = self.dvr.as_ref() {
+            dvr.lock().unwrap().append(&evicted);
+        }
+        self.clear_spill();
 
-                if notifier.lock().unwrap().audio_index != INVALID_INDEX {
-                    notifier.lock().unwrap().audio_index -= next_key;
+        let mut key_index = key_index.write().unwrap();
+        while key_index.front().is_some_and(|key| *key < count) {
+            key_index.pop_front();
+        }
+        for key in key_index.iter_mut() {
+            *key -= count;
+        }
+        drop(key_index);
+        fatal!(
+            "evicted: {}, cur_len: {}",
+            count,
+            circular_buffer.read().unwrap().len()
+        );
+        for last_index in self.last_index.iter_mut() {
+            *last_index = shift_index(*last_index, count, INVALID_INDEX);
+        }
+        for notifier in notifiers.write().unwrap().values() {
+            // receivers whose position was erased restart at the oldest kept key frame
+            let mut notifier = notifier.lock().unwrap();
+            for index in notifier.indices.iter_mut() {
+                *index = shift_index(*index, count, 0);
+            }
+            fatal!("after erase, indices: {:?}", notifier.indices);
+        }
+    }
+
+    // evicts up to the oldest key frame that leaves at most max_capacity frames, at
+    // worst up to the key frame just written
+    fn drop_overflow(&mut self) {
+        let inner = self.inner.clone();
+        let len = inner.lock().unwrap().circular_buffer.read().unwrap().len() as u32;
+        if len <= self.max_capacity {
+            return;
+        }
+        let next_key = inner
+            .lock()
+            .unwrap()
+            .key_index
+            .read()
+            .unwrap()
+            .iter()
+            .copied()
+            .find(|key| *key > 0 && len - key <= self.max_capacity)
+            .unwrap_or(len - 1);
+        warn!(
+            "buffer over {} frames, dropping the oldest {}",
+            self.max_capacity, next_key
+        );
+        self.dropped_frames += next_key as u64;
+        self.evict_front(next_key);
+    }
+
+    fn enforce_budget(&mut self) {
+        let budget = match self.memory_budget {
+            Some(budget) if self.resident_bytes > budget => budget,
+            _ => {
+                self.over_budget = false;
+                return;
+            }
+        };
+        if self.spill.is_none() {
+            match SpillFile::new() {
+                Ok(spill) => self.spill = Some(spill),
+                Err(err) => {
+                    error!("can not create the spill file: {}", err);
+                    return;
                 }
+            }
+        }
 
-                {
-                    let video_index = notifier.lock().unwrap().video_index;
-                    let audio_index = notifier.lock().unwrap().audio_index;
-                    fatal!(
-                        "after erase, video: {}, audio: {}",
-                        video_index,
-                        audio_index
-                    );
+        let circular_buffer = self.inner.lock().unwrap().circular_buffer.clone();
+        let mut circular_buffer = circular_buffer.write().unwrap();
+        let spill = self.spill.as_mut().unwrap();
+        // oldest first, never the frame just written
+        let len = circular_buffer.len().saturating_sub(1);
+        for sample in circular_buffer.iter_mut().take(len) {
+            if self.resident_bytes <= budget {
+                break;
+            }
+            // only frames some receiver still has to read, and that no reader holds, nor
+            // a copy sharing the payload like the producer's frame the normalizer copied
+            if sample.spilled.is_some()
+                || sample.reserve_flag.load(Ordering::Relaxed) ^ self.read_flag as u16 == 0
+                || Arc::strong_count(&sample.media_data) > 1
+                || Arc::strong_count(&sample.media_data.lock().unwrap().buff) > 1
+            {
+                continue;
+            }
+            let buff = sample.media_data.lock().unwrap().buff.clone();
+            let data = buff.lock().unwrap().data();
+            let mut payload = data.lock().unwrap();
+            match spill.write(&payload) {
+                Ok(slot) => {
+                    self.resident_bytes -= payload.len();
+                    self.spilled_frames += 1;
+                    *payload = Vec::new();
+                    sample.spilled = Some(slot);
+                }
+                Err(err) => {
+                    error!("spill failed: {}", err);
+                    return;
                 }
             }
         }
+        debug!(
+            "after spill, resident: {}, spilled: {}",
+            self.resident_bytes, self.spilled_frames
+        );
+        let over_budget = self.resident_bytes > budget;
+        if over_budget && !self.over_budget {
+            // what is left is held by readers or producers, already read or the newest frame
+            warn!(
+                "{} bytes in memory over the budget of {}, nothing more can be spilled",
+                self.resident_bytes, budget
+            );
+        }
+        self.over_budget = over_budget;
+    }
+
+    fn reload(&mut self, media_data: &Arc<Mutex<MediaData>>, slot: SpillSlot) -> io::Result<()> {
+        let spill = self
+            .spill
+            .as_mut()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "no spill file"))?;
+        let payload = spill.read(slot)?;
+        let buff = media_data.lock().unwrap().buff.clone();
+        let data = buff.lock().unwrap().data();
+        *data.lock().unwrap() = payload;
+        self.resident_bytes += slot.len();
+        Ok(())
+    }
+
+    // the spill file starts over once nothing in it is needed
+    fn clear_spill(&mut self) {
+        if self.spilled_frames != 0 {
+            return;
+        }
+        if let Some(spill) = self.spill.as_mut().filter(|spill| !spill.is_empty()) {
+            if let Err(err) = spill.clear() {
+                warn!("can not clear the spill file: {}", err);
+            }
+        }
     }
 
     fn activate_data_ref(&mut self, media_type: MediaType, key_frame: bool) {
-        let mut bit_ref = 0x0000;
         let inner = self.inner.clone();
         let notifiers = inner.lock().unwrap().notifiers.clone();
-        for (recv_id, notifier) in notifiers.read().unwrap().iter() {
+        for notifier in notifiers.read().unwrap().values() {
             let index = notifier.lock().unwrap().get_read_index();
-            if media_type == MediaType::AUDIO {
-                bit_ref |= 0x1 << (index * 2);
+            if index as usize >= MAX_RECEIVERS
+                || !notifier.lock().unwrap().is_subscribed(media_type)
+            {
+                continue;
+            }
+            if media_type != MediaType::VIDEO {
+                inner
+                    .lock()
+                    .unwrap()
+                    .refs
+                    .set_data(index as usize, media_type.track_bit(), true);
                 continue;
             }
             let key_receiver = notifier.lock().unwrap().is_key_receiver();
@@ -776,21 +1475,17 @@ impl Dispatcher {
                 && key_receiver
                 && inner.lock().unwrap().key_index.read().unwrap().is_empty()
             {
-                notifier.lock().unwrap().video_index =
-                    inner.lock().unwrap().circular_buffer.read().unwrap().len() as u32 - 1;
+                let last = inner.lock().unwrap().circular_buffer.read().unwrap().len() as u32 - 1;
+                notifier.lock().unwrap().set_index(MediaType::VIDEO, last);
             }
-            if !key_receiver || key_frame && key_receiver {
-                if index != INVALID_INDEX {
-                    bit_ref |= 0x1 << (index * 2 + 1);
-                }
+            if !key_receiver || key_frame {
+                inner
+                    .lock()
+                    .unwrap()
+                    .refs
+                    .set_data(index as usize, media_type.track_bit(), true);
             }
         }
-
-        inner
-            .lock()
-            .unwrap()
-            .data_ref
-            .fetch_or(bit_ref, Ordering::Relaxed);
     }
 
     fn activate_receiver_index(&mut self, index: u32, media_type: MediaType) {
@@ -799,49 +1494,27 @@ impl Dispatcher {
         let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
         for (recv_id, notifier) in notifiers.read().unwrap().iter() {
             let mut notifier = notifier.lock().unwrap();
+            if !notifier.is_subscribed(media_type) {
+                continue;
+            }
 
-            if media_type == MediaType::VIDEO {
-                if notifier.video_index != index
-                    && (notifier.video_index == INVALID_INDEX
-                        || self.is_read(
-                            notifier.get_read_index(),
-                            notifier.video_index,
-                            circular_buffer.clone(),
-                        ))
-                {
-                    notifier.video_index = index;
-                    fatal!(
-                        "recv_id: {}, read_index: {}, activate video: {}",
-                        recv_id,
-                        notifier.get_read_index(),
-                        notifier.video_index
-                    );
-                }
-            } else {
-                if notifier.audio_index != index
-                    && (notifier.audio_index == INVALID_INDEX
-                        || self.is_read(
-                            notifier.get_read_index(),
-                            notifier.audio_index,
-                            circular_buffer.clone(),
-                        ))
-                {
-                    notifier.audio_index = index;
-                    fatal!(
-                        "recv_id: {}, read_index: {}, activate audio: {}",
-                        recv_id,
-                        notifier.get_read_index(),
-                        notifier.audio_index
-                    );
-                }
+            let current = notifier.index(media_type);
+            if current != index
+                && (current == INVALID_INDEX
+                    || self.is_read(notifier.get_read_index(), current, circular_buffer.clone()))
+            {
+                notifier.set_index(media_type, index);
+                fatal!(
+                    "recv_id: {}, read_index: {}, activate {:?}: {}",
+                    recv_id,
+                    notifier.get_read_index(),
+                    media_type,
+                    index
+                );
             }
         }
 
-        if media_type == MediaType::VIDEO {
-            self.video_activate = false;
-        } else {
-            self.audio_activate = false;
-        }
+        self.activate[media_type.slot()] = false;
     }
 
     fn updata_receiver_read_index(
@@ -854,109 +1527,72 @@ impl Dispatcher {
         debug!("trace");
         let read_index = notifier.lock().unwrap().get_read_index();
         let key_receiver = notifier.lock().unwrap().is_key_receiver();
+        let track_mask = notifier.lock().unwrap().track_mask();
 
         let next_index = self.find_receiver_next_index(
             index,
             media_type,
             read_index,
             key_receiver,
+            track_mask,
             circular_buffer,
         );
 
         if index == next_index {
             return;
         }
+        let mut notifier = notifier.lock().unwrap();
         match media_type {
-            MediaType::AUDIO => notifier.lock().unwrap().audio_index = next_index,
-            MediaType::VIDEO => notifier.lock().unwrap().video_index = next_index,
+            // a mixed read moves every subscribed track along
             MediaType::AV => {
-                notifier.lock().unwrap().video_index = next_index;
-                notifier.lock().unwrap().audio_index = next_index
+                for track in MediaType::TRACKS {
+                    if track_mask & track.track_bit() != 0 {
+                        notifier.set_index(track, next_index);
+                    }
+                }
             }
+            media_type => notifier.set_index(media_type, next_index),
         }
-        let audio_index = notifier.lock().unwrap().audio_index;
-        let video_index = notifier.lock().unwrap().video_index;
         info!(
-            "after update type: {:?}, audio_index: {}, video_index: {}",
-            media_type, audio_index, video_index
+            "after update type: {:?}, indices: {:?}",
+            media_type, notifier.indices
         );
     }
 
     fn set_receiver_data_ref(&mut self, read_index: u32, media_type: MediaType, ready: bool) {
         let inner = self.inner.clone();
 
-        let index = read_index;
-
-        if index == INVALID_INDEX {
+        if read_index as usize >= MAX_RECEIVERS {
             return;
         }
-        let mut bit_ref = 0;
-        if media_type == MediaType::AUDIO {
-            let audio_bit = index * 2;
-            bit_ref = 0x1 << audio_bit;
-        } else if media_type == MediaType::VIDEO {
-            let video_bit = index * 2 + 1;
-            bit_ref = 0x1 << video_bit;
-        }
-
-        if ready {
-            inner
-                .lock()
-                .unwrap()
-                .data_ref
-                .fetch_or(bit_ref, Ordering::Relaxed);
-        } else {
-            bit_ref = !bit_ref;
-            inner
-                .lock()
-                .unwrap()
-                .data_ref
-                .fetch_and(bit_ref, Ordering::Relaxed);
-        }
+        let inner = inner.lock().unwrap();
+        inner
+            .refs
+            .set_data(read_index as usize, media_type.track_bit(), ready);
         info!(
             "after set ref, data: {}",
-            inner.lock().unwrap().data_ref.load(Ordering::Relaxed)
+            inner.refs.data(read_index as usize)
         );
     }
 
     fn set_receiver_read_ref(&mut self, read_index: u32, media_type: MediaType, ready: bool) {
-        let mut index = read_index;
-
         let inner = self.inner.clone();
 
-        if index == INVALID_INDEX {
+        if read_index as usize >= MAX_RECEIVERS {
             return;
         }
-        let mut bit_ref = 0;
-        if media_type == MediaType::AUDIO {
-            let audio_bit = index * 2;
-            bit_ref = 0x1 << audio_bit;
-        } else if media_type == MediaType::VIDEO {
-            let video_bit = index * 2 + 1;
-            bit_ref = 0x1 << video_bit;
-        }
-
-        if ready {
-            inner
-                .lock()
-                .unwrap()
-                .recv_ref
-                .fetch_or(bit_ref, Ordering::Relaxed);
-        } else {
-            bit_ref = !bit_ref;
-            inner
-                .lock()
-                .unwrap()
-                .recv_ref
-                .fetch_and(bit_ref, Ordering::Relaxed);
-        }
+        let inner = inner.lock().unwrap();
+        inner
+            .refs
+            .set_recv(read_index as usize, media_type.track_bit(), ready);
         info!(
             "after set ref, recv: {}",
-            inner.lock().unwrap().recv_ref.load(Ordering::Relaxed)
+            inner.refs.recv(read_index as usize)
         );
     }
 
-    fn available_audio_index(&self, index: u32) -> u32 {
+    // the first frame of the track after index, or index if there is none
+    fn available_index(&self, index: u32, media_type: MediaType) -> u32 {
         let inner = self.inner.clone();
         let circular_buffer = inner.lock().unwrap().circular_buffer.clone();
         if index == INVALID_INDEX || (index + 1) as usize >= circular_buffer.read().unwrap().len() {
@@ -970,13 +1606,13 @@ impl Dispatcher {
                     .lock()
                     .unwrap()
                     .media_type
-                    == MediaType::AUDIO
+                    == media_type
             {
                 return i as u32;
             }
         }
 
-        return index;
+        index
     }
 
     fn find_receiver_next_index(
@@ -985,6 +1621,7 @@ impl Dispatcher {
         media_type: MediaType,
         read_index: u32,
         key_receiver: bool,
+        track_mask: u32,
         circular_buffer: Arc<RwLock<VecDeque<DataSample>>>,
     ) -> u32 {
         debug!("trace");
@@ -996,7 +1633,24 @@ impl Dispatcher {
             return index;
         }
         if media_type == MediaType::AV && !key_receiver {
-            return index + 1;
+            // the next frame of a subscribed track, the ones in between count as read
+            let len = circular_buffer.read().unwrap().len() as u32;
+            for i in index + 1..len {
+                let track = circular_buffer.read().unwrap()[i as usize]
+                    .media_data
+                    .lock()
+                    .unwrap()
+                    .media_type;
+                if track_mask & track.track_bit() != 0 {
+                    for j in index + 1..i {
+                        circular_buffer.read().unwrap()[j as usize]
+                            .reserve_flag
+                            .fetch_or(0x1 << read_index, Ordering::Relaxed);
+                    }
+                    return i;
+                }
+            }
+            return index;
         }
 
         let mut m_type = media_type;
@@ -1037,7 +1691,7 @@ impl Dispatcher {
         }
 
         debug!("trace");
-        return index;
+        index
     }
 
     fn find_last_index(&self, media_type: MediaType) -> u32 {
@@ -1053,11 +1707,7 @@ impl Dispatcher {
             return INVALID_INDEX;
         }
 
-        if media_type == MediaType::AUDIO {
-            return self.last_audio_index;
-        } else {
-            return self.last_video_index;
-        }
+        self.last_index[media_type.slot()]
     }
 
     fn is_read(
@@ -1067,7 +1717,7 @@ impl Dispatcher {
         circular_buffer: Arc<RwLock<VecDeque<DataSample>>>,
     ) -> bool {
         if index as usize >= circular_buffer.read().unwrap().len() {
-            return true;
+            true
         } else {
             self.is_data_read(
                 read_index,
@@ -1083,3 +1733,25 @@ impl Dispatcher {
         (data.reserve_flag.load(Ordering::Relaxed) & (0x0001 << read_index)) != 0
     }
 }
+
+fn payload_len(buff: &Mutex<Buffer>) -> usize {
+    buff.lock().unwrap().data().lock().unwrap().len()
+}
+
+fn sample_pts(circular_buffer: &RwLock<VecDeque<DataSample>>, index: u32) -> u64 {
+    circular_buffer.read().unwrap()[index as usize]
+        .media_data
+        .lock()
+        .unwrap()
+        .pts
+}
+
+fn shift_index(index: u32, erased: u32, erased_index: u32) -> u32 {
+    if index == INVALID_INDEX {
+        INVALID_INDEX
+    } else if index < erased {
+        erased_index
+    } else {
+        index - erased
+    }
+}
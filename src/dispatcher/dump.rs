@@ -0,0 +1,186 @@
+use crate::utils::buffer::MediaType;
+use serde_json::Value;
+use std::fmt::Write;
+
+// how Dispatcher::dump_state_as renders the state
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DumpFormat {
+    JSON,
+    // graphviz, the buffer as a chain of samples with the receivers pointing into it
+    DOT,
+    // one line per sample with a column per receiver
+    ASCII,
+}
+
+pub fn track_name(media_type: MediaType) -> String {
+    format!("{:?}", media_type).to_lowercase()
+}
+
+pub fn render(state: &Value, format: DumpFormat) -> String {
+    match format {
+        DumpFormat::JSON => serde_json::to_string_pretty(state).unwrap_or_default(),
+        DumpFormat::DOT => dot(state),
+        DumpFormat::ASCII => timeline(state),
+    }
+}
+
+fn items(state: &Value, key: &str) -> Vec<Value> {
+    state[key].as_array().cloned().unwrap_or_default()
+}
+
+fn is_key_index(state: &Value, index: u64) -> bool {
+    items(state, "key_index")
+        .iter()
+        .any(|key| key.as_u64() == Some(index))
+}
+
+// the tracks whose cursor is at the sample, by their first letter
+fn cursors(receiver: &Value, index: u64) -> String {
+    MediaType::TRACKS
+        .iter()
+        .map(|track| track_name(*track))
+        .filter(|name| receiver["indices"][name].as_u64() == Some(index))
+        .map(|name| name[..1].to_string())
+        .collect()
+}
+
+fn is_read(sample: &Value, receiver: &Value) -> bool {
+    match receiver["read_index"].as_u64() {
+        Some(read_index) if read_index < 16 => {
+            sample["reserve_flag"].as_u64().unwrap_or(0) & (1 << read_index) != 0
+        }
+        _ => false,
+    }
+}
+
+fn mask(value: &Value) -> String {
+    value
+        .as_u64()
+        .map_or("-".to_string(), |mask| format!("{:#04x}", mask))
+}
+
+fn timeline(state: &Value) -> String {
+    let receivers = items(state, "receivers");
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "dispatcher {}, read_flag {}, waiting_key_frame {}, key_index {}",
+        state["id"],
+        mask(&state["read_flag"]),
+        state["waiting_key_frame"],
+        state["key_index"]
+    );
+    for (column, receiver) in receivers.iter().enumerate() {
+        let _ = writeln!(
+            out,
+            "r{}: recv {}, read index {}, key only {}, tracks {}, data_ref {}, recv_ref {}",
+            column,
+            receiver["recv_id"],
+            receiver["read_index"],
+            receiver["key_only"],
+            mask(&receiver["tracks"]),
+            mask(&receiver["data_ref"]),
+            mask(&receiver["recv_ref"]),
+        );
+    }
+    let _ = writeln!(
+        out,
+        "x read, . unread, a/v/s/m/d the receiver's audio/video/subtitle/metadata/data cursor, * in key_index"
+    );
+
+    let _ = write!(
+        out,
+        "{:>6} {:<9} {:>5} {:>12} {:<3}",
+        "index", "type", "track", "pts", "key"
+    );
+    for column in 0..receivers.len() {
+        let _ = write!(out, " {:<6}", format!("r{}", column));
+    }
+    out.push('\n');
+    for sample in items(state, "samples") {
+        let index = sample["index"].as_u64().unwrap_or(0);
+        let key = match (sample["key_frame"].as_bool(), is_key_index(state, index)) {
+            (Some(true), true) => "K*",
+            (Some(true), false) => "K",
+            (_, true) => "*",
+            _ => "",
+        };
+        let _ = write!(
+            out,
+            "{:>6} {:<9} {:>5} {:>12} {:<3}",
+            index,
+            sample["media_type"].as_str().unwrap_or("?"),
+            sample["track_id"].to_string(),
+            sample["pts"].to_string(),
+            key
+        );
+        for receiver in receivers.iter() {
+            let read = if is_read(&sample, receiver) { "x" } else { "." };
+            let _ = write!(
+                out,
+                " {:<6}",
+                format!("{}{}", read, cursors(receiver, index))
+            );
+        }
+        if sample["spilled"].as_bool() == Some(true) {
+            out.push_str(" spilled");
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn dot(state: &Value) -> String {
+    let samples = items(state, "samples");
+    let mut out = String::new();
+    let _ = writeln!(out, "digraph dispatcher {{");
+    let _ = writeln!(out, "    rankdir=LR;");
+    let _ = writeln!(out, "    node [shape=record, fontname=monospace];");
+    for sample in samples.iter() {
+        let index = sample["index"].as_u64().unwrap_or(0);
+        let mut style = String::new();
+        if sample["key_frame"].as_bool() == Some(true) {
+            style.push_str(", style=filled, fillcolor=lightgrey");
+        }
+        if is_key_index(state, index) {
+            style.push_str(", penwidth=2");
+        }
+        let _ = writeln!(
+            out,
+            "    s{} [label=\"{}|{} {}|pts {}|reserve {}\"{}];",
+            index,
+            index,
+            sample["media_type"].as_str().unwrap_or("?"),
+            sample["track_id"],
+            sample["pts"],
+            mask(&sample["reserve_flag"]),
+            style
+        );
+    }
+    for pair in samples.windows(2) {
+        let _ = writeln!(out, "    s{} -> s{};", pair[0]["index"], pair[1]["index"]);
+    }
+    for receiver in items(state, "receivers") {
+        let recv_id = &receiver["recv_id"];
+        let _ = writeln!(
+            out,
+            "    r{} [shape=box, label=\"recv {}\\nread index {}\\ndata_ref {}\\nrecv_ref {}\"];",
+            recv_id,
+            recv_id,
+            receiver["read_index"],
+            mask(&receiver["data_ref"]),
+            mask(&receiver["recv_ref"])
+        );
+        for track in MediaType::TRACKS.iter().map(|track| track_name(*track)) {
+            if let Some(index) = receiver["indices"][&track].as_u64() {
+                let _ = writeln!(
+                    out,
+                    "    r{} -> s{} [label=\"{}\", style=dashed];",
+                    recv_id, index, track
+                );
+            }
+        }
+    }
+    let _ = writeln!(out, "}}");
+    out
+}
@@ -0,0 +1,472 @@
+use super::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::{ipc, Packet};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    Identity,
+};
+use crate::{error, info, warn};
+use std::{
+    collections::VecDeque,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[derive(Debug)]
+struct Segment {
+    seq: u64,
+    first_pts: u64,
+    last_pts: u64,
+    len: u64,
+    // pts and byte offset of the segment's key frames
+    keys: Vec<(u64, u64)>,
+}
+
+// a position in the store, see DvrStore::seek
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DvrPosition {
+    seq: u64,
+    offset: u64,
+}
+
+// keeps the GOPs the dispatcher evicts from its buffer on disk. frames go to
+// <seq>.seg as a track id followed by an ipc data message, the key frames of a
+// segment are listed in <seq>.idx as pts and byte offset. a segment is closed at
+// a key frame once it spans the segment duration, segments older than the
+// retention are removed. segments left in the directory by an earlier store are
+// loaded from their index files.
+#[derive(Debug)]
+pub struct DvrStore {
+    dir: PathBuf,
+    segment_duration: u64,
+    retention: u64,
+    segments: VecDeque<Segment>,
+    next_seq: u64,
+    has_video: bool,
+    output: Option<(BufWriter<File>, BufWriter<File>)>,
+}
+
+// a segment file kept open between reads, seeks only when the offset jumps
+#[derive(Debug)]
+struct SegmentReader {
+    seq: u64,
+    offset: u64,
+    input: BufReader<File>,
+}
+
+impl SegmentReader {
+    fn open(path: &Path, seq: u64, offset: u64) -> io::Result<Self> {
+        let mut input = BufReader::new(File::open(path)?);
+        input.seek(SeekFrom::Start(offset))?;
+        Ok(SegmentReader { seq, offset, input })
+    }
+
+    // the frame at the offset, the reader is left right after it
+    fn read_record(&mut self, offset: u64) -> io::Result<MediaData> {
+        if offset != self.offset {
+            self.input.seek(SeekFrom::Start(offset))?;
+        }
+        // a failed read leaves the reader anywhere, the next one seeks
+        self.offset = u64::MAX;
+        let mut track_id = [0u8; 4];
+        self.input.read_exact(&mut track_id)?;
+        let mut len = [0u8; 4];
+        self.input.read_exact(&mut len)?;
+        let len = u32::from_be_bytes(len);
+        let mut body = vec![0u8; len as usize];
+        self.input.read_exact(&mut body)?;
+        match ipc::decode(&body) {
+            Some(Packet::DATA(mut data)) => {
+                data.track_id = u32::from_be_bytes(track_id);
+                self.offset = offset + 8 + len as u64;
+                Ok(data)
+            }
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "invalid dvr record",
+            )),
+        }
+    }
+}
+
+impl DvrStore {
+    // durations are in ms of pts
+    pub fn new(dir: &Path, segment_duration: u64, retention: u64) -> Option<Self> {
+        if let Err(err) = fs::create_dir_all(dir) {
+            error!("can not create dvr dir {:?}: {}", dir, err);
+            return None;
+        }
+        let mut store = DvrStore {
+            dir: dir.to_path_buf(),
+            segment_duration: segment_duration.max(1),
+            retention,
+            segments: VecDeque::new(),
+            next_seq: 0,
+            has_video: false,
+            output: None,
+        };
+        if let Err(err) = store.load() {
+            warn!("can not load dvr dir {:?}: {}", dir, err);
+        }
+        store.expire();
+        Some(store)
+    }
+
+    fn load(&mut self) -> io::Result<()> {
+        let mut seqs: Vec<u64> = fs::read_dir(&self.dir)?
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+                if path.extension()? != "idx" {
+                    return None;
+                }
+                path.file_stem()?.to_str()?.parse().ok()
+            })
+            .collect();
+        seqs.sort_unstable();
+        for seq in seqs {
+            match self.load_segment(seq) {
+                Ok(Some(segment)) => {
+                    info!(
+                        "dvr segment {} loaded, pts {}..{}",
+                        seq, segment.first_pts, segment.last_pts
+                    );
+                    self.segments.push_back(segment);
+                }
+                Ok(None) => {
+                    info!("dvr segment {} is empty, removed", seq);
+                    for extension in ["seg", "idx"] {
+                        let _ = fs::remove_file(self.segment_path(seq, extension));
+                    }
+                }
+                Err(err) => warn!("can not load dvr segment {}: {}", seq, err),
+            }
+            self.next_seq = seq + 1;
+        }
+        Ok(())
+    }
+
+    // the key frames come from the index, the frames after the last one give the
+    // last pts and the end of the last complete record
+    fn load_segment(&mut self, seq: u64) -> io::Result<Option<Segment>> {
+        let mut index = Vec::new();
+        File::open(self.segment_path(seq, "idx"))?.read_to_end(&mut index)?;
+        let keys: Vec<(u64, u64)> = index
+            .chunks_exact(16)
+            .map(|entry| {
+                (
+                    u64::from_be_bytes(entry[..8].try_into().unwrap()),
+                    u64::from_be_bytes(entry[8..].try_into().unwrap()),
+                )
+            })
+            .collect();
+        let (first_pts, offset) = match (keys.first(), keys.last()) {
+            (Some((first_pts, _)), Some((_, offset))) => (*first_pts, *offset),
+            _ => return Ok(None),
+        };
+        let mut input = SegmentReader::open(&self.segment_path(seq, "seg"), seq, offset)?;
+        let mut segment = Segment {
+            seq,
+            first_pts,
+            last_pts: first_pts,
+            len: offset,
+            keys,
+        };
+        while let Ok(data) = input.read_record(segment.len) {
+            self.has_video |= data.media_type == MediaType::VIDEO;
+            segment.last_pts = segment.last_pts.max(data.pts);
+            segment.len = input.offset;
+        }
+        // a key frame cut off by the end of the file is not there
+        let len = segment.len;
+        segment.keys.retain(|(_, offset)| *offset < len);
+        if segment.keys.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(segment))
+    }
+
+    // ten second segments kept for an hour
+    pub fn with_defaults(dir: &Path) -> Option<Self> {
+        Self::new(
+            dir,
+            Duration::from_secs(10).as_millis() as u64,
+            Duration::from_secs(3600).as_millis() as u64,
+        )
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    // the pts span on disk
+    pub fn range(&self) -> Option<(u64, u64)> {
+        Some((
+            self.segments.front()?.first_pts,
+            self.segments.back()?.last_pts,
+        ))
+    }
+
+    fn segment_path(&self, seq: u64, extension: &str) -> PathBuf {
+        self.dir.join(format!("{:08}.{}", seq, extension))
+    }
+
+    // takes the frames of evicted GOPs in buffer order
+    pub fn append(&mut self, frames: &[Arc<Mutex<MediaData>>]) {
+        for data in frames {
+            let data = data.lock().unwrap();
+            if let Err(err) = self.write_frame(&data) {
+                error!("dvr write failed: {}", err);
+                self.output = None;
+                return;
+            }
+        }
+        self.expire();
+    }
+
+    fn write_frame(&mut self, data: &MediaData) -> io::Result<()> {
+        self.has_video |= data.media_type == MediaType::VIDEO;
+        // segments start at a key frame, any audio frame is one without video
+        let key = if self.has_video {
+            data.key_frame && data.media_type == MediaType::VIDEO
+        } else {
+            data.media_type == MediaType::AUDIO
+        };
+        let roll = self.segments.back().is_none_or(|segment| {
+            key && data.pts.saturating_sub(segment.first_pts) >= self.segment_duration
+        });
+        if roll || self.output.is_none() {
+            self.open_segment(data.pts)?;
+        }
+
+        let segment = self.segments.back_mut().unwrap();
+        let (output, index) = self.output.as_mut().unwrap();
+        let offset = segment.len;
+        let mut record = data.track_id.to_be_bytes().to_vec();
+        let body = ipc::encode_data(data);
+        record.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        record.extend_from_slice(&body);
+        output.write_all(&record)?;
+        // every video key frame, the segment start only for audio
+        if offset == 0 || (key && self.has_video) {
+            index.write_all(&data.pts.to_be_bytes())?;
+            index.write_all(&offset.to_be_bytes())?;
+            segment.keys.push((data.pts, offset));
+        }
+        segment.len += record.len() as u64;
+        segment.last_pts = segment.last_pts.max(data.pts);
+        Ok(())
+    }
+
+    fn open_segment(&mut self, pts: u64) -> io::Result<()> {
+        if let Some((mut output, mut index)) = self.output.take() {
+            output.flush()?;
+            index.flush()?;
+        }
+        let seq = self.next_seq;
+        let create = |path: PathBuf| {
+            OpenOptions::new()
+                .create(true)
+                .write(true)
+                .truncate(true)
+                .open(path)
+        };
+        let output = BufWriter::new(create(self.segment_path(seq, "seg"))?);
+        let index = BufWriter::new(create(self.segment_path(seq, "idx"))?);
+        info!("dvr segment {} opened at pts {}", seq, pts);
+        self.output = Some((output, index));
+        self.segments.push_back(Segment {
+            seq,
+            first_pts: pts,
+            last_pts: pts,
+            len: 0,
+            keys: Vec::new(),
+        });
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    fn expire(&mut self) {
+        let newest = match self.segments.back() {
+            Some(segment) => segment.last_pts,
+            None => return,
+        };
+        while self.segments.len() > 1
+            && newest.saturating_sub(self.segments.front().unwrap().last_pts) > self.retention
+        {
+            let segment = self.segments.pop_front().unwrap();
+            info!("dvr segment {} expired", segment.seq);
+            for extension in ["seg", "idx"] {
+                let _ = fs::remove_file(self.segment_path(segment.seq, extension));
+            }
+        }
+    }
+
+    // the last key frame at or before pts, the oldest one for a pts before the store
+    pub fn seek(&self, pts: u64) -> Option<DvrPosition> {
+        let mut found = None;
+        for segment in self.segments.iter() {
+            for (key_pts, offset) in segment.keys.iter() {
+                if *key_pts > pts && found.is_some() {
+                    return found;
+                }
+                found = Some(DvrPosition {
+                    seq: segment.seq,
+                    offset: *offset,
+                });
+            }
+        }
+        found
+    }
+
+    // the frame at the position and the position after it, None at the end. the
+    // reader is kept for the next read while it stays in the segment
+    fn read_at(
+        &mut self,
+        position: DvrPosition,
+        input: &mut Option<SegmentReader>,
+    ) -> Option<(MediaData, DvrPosition)> {
+        let mut position = position;
+        let seq = loop {
+            // a position whose segment expired continues at the oldest one
+            let segment = self
+                .segments
+                .iter()
+                .find(|segment| segment.seq >= position.seq)?;
+            if segment.seq != position.seq {
+                position = DvrPosition {
+                    seq: segment.seq,
+                    offset: 0,
+                };
+            }
+            if position.offset < segment.len {
+                break segment.seq;
+            }
+            position.seq += 1;
+            position.offset = 0;
+        };
+
+        // the open segment is still buffered
+        if self.segments.back().map(|segment| segment.seq) == Some(seq) {
+            if let Some((output, _)) = self.output.as_mut() {
+                if let Err(err) = output.flush() {
+                    warn!("dvr flush failed: {}", err);
+                    return None;
+                }
+            }
+        }
+        let reader = match input.take().filter(|input| input.seq == seq) {
+            Some(reader) => reader,
+            None => {
+                match SegmentReader::open(&self.segment_path(seq, "seg"), seq, position.offset) {
+                    Ok(reader) => reader,
+                    Err(err) => {
+                        warn!("dvr read failed: {}", err);
+                        return None;
+                    }
+                }
+            }
+        };
+        let reader = input.insert(reader);
+        match reader.read_record(position.offset) {
+            Ok(data) => Some((
+                data,
+                DvrPosition {
+                    seq,
+                    offset: reader.offset,
+                },
+            )),
+            Err(err) => {
+                warn!("dvr read failed: {}", err);
+                None
+            }
+        }
+    }
+}
+
+// reads a stream from a past pts: first the frames on disk, then, once caught up,
+// the receiver is attached to the dispatcher right after the last frame read, so
+// the live buffer takes over without a gap or a repeated frame
+pub struct DvrPlayback {
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    store: Arc<Mutex<DvrStore>>,
+    receiver: Arc<Receiver>,
+    position: Option<DvrPosition>,
+    input: Option<SegmentReader>,
+    last_pts: Option<u64>,
+}
+
+impl DvrPlayback {
+    // the receiver must not be attached yet
+    pub fn new(
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        store: Arc<Mutex<DvrStore>>,
+        receiver: Arc<Receiver>,
+        pts: u64,
+    ) -> Self {
+        let position = store.lock().unwrap().seek(pts);
+        info!(
+            "dvr playback from pts {}, receiver: {}, position: {:?}",
+            pts,
+            receiver.get_id(),
+            position
+        );
+        let mut playback = DvrPlayback {
+            dispatcher: dispatcher.clone(),
+            store,
+            receiver,
+            position,
+            input: None,
+            last_pts: None,
+        };
+        if playback.position.is_none() {
+            playback.go_live(&mut dispatcher.lock().unwrap());
+        }
+        playback
+    }
+
+    pub fn is_live(&self) -> bool {
+        self.position.is_none()
+    }
+
+    pub fn receiver(&self) -> Arc<Receiver> {
+        self.receiver.clone()
+    }
+
+    pub fn request_read(&mut self, media_type: MediaType) -> (bool, Option<Arc<Mutex<MediaData>>>) {
+        while let Some(position) = self.position {
+            // the dispatcher lock keeps more GOPs from moving to disk meanwhile
+            let dispatcher = self.dispatcher.clone();
+            let mut dispatcher = dispatcher.lock().unwrap();
+            let next = self
+                .store
+                .lock()
+                .unwrap()
+                .read_at(position, &mut self.input);
+            match next {
+                Some((data, next)) => {
+                    drop(dispatcher);
+                    self.position = Some(next);
+                    self.last_pts = Some(data.pts);
+                    if media_type == MediaType::AV || data.media_type == media_type {
+                        return (true, Some(Arc::new(Mutex::new(data))));
+                    }
+                }
+                None => self.go_live(&mut dispatcher),
+            }
+        }
+        self.receiver.request_read(media_type)
+    }
+
+    fn go_live(&mut self, dispatcher: &mut Dispatcher) {
+        info!("dvr playback live after pts {:?}", self.last_pts);
+        match self.last_pts {
+            Some(pts) => dispatcher.attach_receiver_after(self.receiver.clone(), pts),
+            None => dispatcher.attach_receiver(self.receiver.clone()),
+        }
+        self.receiver.set_dispatcher(self.dispatcher.clone());
+        self.position = None;
+        self.input = None;
+    }
+}
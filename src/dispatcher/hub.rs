@@ -0,0 +1,299 @@
+use super::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::utils::{
+    buffer::MediaData,
+    clock::{SharedClock, SystemClock},
+    codec::CodecConfig,
+    Identity,
+};
+use crate::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, Weak,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+const DEFAULT_GRACE_PERIOD: Duration = Duration::from_secs(10);
+const REAP_INTERVAL: Duration = Duration::from_millis(100);
+
+type Streams = Arc<Mutex<HashMap<String, Stream>>>;
+
+struct Stream {
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    subscribers: Vec<Weak<Receiver>>,
+    input_frames: Arc<AtomicU64>,
+    publishing: bool,
+    dispatching: bool,
+    // clock time the stream lost its publisher, or was created without one
+    idle_since: Option<Duration>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamStats {
+    pub name: String,
+    pub receivers: usize,
+    pub buffered_frames: u32,
+    pub input_frames: u64,
+    pub publishing: bool,
+    pub idle_for: Option<Duration>,
+}
+
+// keeps one dispatcher per stream key. a stream comes into being with its first
+// publisher or subscriber, subscribers can wait for a publisher that has not
+// appeared yet, and a stream left without a publisher for the grace period is
+// torn down together with its subscribers.
+pub struct StreamHub {
+    streams: Streams,
+    max_capacity: u32,
+    capacity_increment: u32,
+    grace: Duration,
+    clock: SharedClock,
+    reaper_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl StreamHub {
+    pub fn new(max_capacity: u32, capacity_increment: u32) -> Self {
+        Self::with_clock(max_capacity, capacity_increment, SystemClock::shared())
+    }
+
+    pub fn with_clock(max_capacity: u32, capacity_increment: u32, clock: SharedClock) -> Self {
+        StreamHub {
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            max_capacity,
+            capacity_increment,
+            grace: DEFAULT_GRACE_PERIOD,
+            clock,
+            reaper_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    // takes effect for the reaper on its next start
+    pub fn set_grace_period(&mut self, grace: Duration) {
+        self.grace = grace;
+    }
+
+    pub fn grace_period(&self) -> Duration {
+        self.grace
+    }
+
+    pub fn dispatcher(&self, name: &str) -> Option<Arc<Mutex<Dispatcher>>> {
+        let streams = self.streams.lock().unwrap();
+        streams.get(name).map(|stream| stream.dispatcher.clone())
+    }
+
+    // None while another publisher holds the stream
+    pub fn publish(&self, name: &str) -> Option<Publisher> {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = self.entry(&mut streams, name);
+        if stream.publishing {
+            warn!("stream {} already has a publisher", name);
+            return None;
+        }
+        if !stream.dispatching {
+            stream.dispatcher.lock().unwrap().start_dispatch();
+            stream.dispatching = true;
+        }
+        stream.publishing = true;
+        stream.idle_since = None;
+        info!("publish stream: {}", name);
+
+        Some(Publisher {
+            name: name.to_string(),
+            dispatcher: stream.dispatcher.clone(),
+            input_frames: stream.input_frames.clone(),
+            streams: Arc::downgrade(&self.streams),
+            clock: self.clock.clone(),
+        })
+    }
+
+    pub fn subscribe(&self, name: &str, receiver: Arc<Receiver>) {
+        let mut streams = self.streams.lock().unwrap();
+        let stream = self.entry(&mut streams, name);
+        stream
+            .dispatcher
+            .lock()
+            .unwrap()
+            .attach_receiver(receiver.clone());
+        receiver.set_dispatcher(stream.dispatcher.clone());
+        stream
+            .subscribers
+            .retain(|subscriber| subscriber.strong_count() > 0);
+        stream.subscribers.push(Arc::downgrade(&receiver));
+        info!(
+            "subscribe stream: {}, receiver: {}",
+            name,
+            receiver.get_id()
+        );
+    }
+
+    fn entry<'a>(&self, streams: &'a mut HashMap<String, Stream>, name: &str) -> &'a mut Stream {
+        streams.entry(name.to_string()).or_insert_with(|| {
+            info!("create stream: {}", name);
+            Stream {
+                dispatcher: Dispatcher::with_clock(
+                    self.max_capacity,
+                    self.capacity_increment,
+                    self.clock.clone(),
+                ),
+                subscribers: Vec::new(),
+                input_frames: Arc::new(AtomicU64::new(0)),
+                publishing: false,
+                dispatching: false,
+                idle_since: Some(self.clock.now()),
+            }
+        })
+    }
+
+    // sorted by name
+    pub fn streams(&self) -> Vec<StreamStats> {
+        let now = self.clock.now();
+        let streams = self.streams.lock().unwrap();
+        let mut stats: Vec<StreamStats> = streams
+            .iter()
+            .map(|(name, stream)| {
+                let dispatcher = stream.dispatcher.lock().unwrap();
+                StreamStats {
+                    name: name.clone(),
+                    receivers: dispatcher.receiver_count(),
+                    buffered_frames: dispatcher.buffered_frames(),
+                    input_frames: stream.input_frames.load(Ordering::Relaxed),
+                    publishing: stream.publishing,
+                    idle_for: stream.idle_since.map(|since| now.saturating_sub(since)),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.name.cmp(&b.name));
+        stats
+    }
+
+    // tears down the streams idle for at least the grace period, returns their names
+    pub fn reap(&self) -> Vec<String> {
+        reap(&self.streams, self.grace, &self.clock)
+    }
+
+    pub fn start_reaper(&mut self) {
+        info!("start stream reaper, grace: {:?}", self.grace);
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let streams = self.streams.clone();
+        let grace = self.grace;
+        let clock = self.clock.clone();
+
+        self.reaper_thread = Some(thread::spawn(move || {
+            while *running.lock().unwrap() {
+                reap(&streams, grace, &clock);
+                thread::sleep(REAP_INTERVAL);
+            }
+            info!("stream reaper done");
+        }));
+    }
+
+    pub fn stop_reaper(&mut self) {
+        debug!("stop stream reaper begin");
+        *self.running.lock().unwrap() = false;
+        if let Some(reaper_thread) = self.reaper_thread.take() {
+            reaper_thread
+                .join()
+                .expect("can not join the reaper thread");
+        }
+        debug!("stop stream reaper end");
+    }
+}
+
+impl Drop for StreamHub {
+    fn drop(&mut self) {
+        self.stop_reaper();
+        let names: Vec<String> = self.streams.lock().unwrap().keys().cloned().collect();
+        for name in names {
+            let stream = self.streams.lock().unwrap().remove(&name);
+            if let Some(stream) = stream {
+                teardown(&name, stream);
+            }
+        }
+    }
+}
+
+fn reap(streams: &Streams, grace: Duration, clock: &SharedClock) -> Vec<String> {
+    let now = clock.now();
+    let expired: Vec<(String, Stream)> = {
+        let mut streams = streams.lock().unwrap();
+        let names: Vec<String> = streams
+            .iter()
+            .filter(|(_, stream)| {
+                matches!(stream.idle_since, Some(since) if now.saturating_sub(since) >= grace)
+            })
+            .map(|(name, _)| name.clone())
+            .collect();
+        names
+            .into_iter()
+            .filter_map(|name| streams.remove(&name).map(|stream| (name, stream)))
+            .collect()
+    };
+    // detaching locks each dispatcher, so the streams lock is released by now
+    expired
+        .into_iter()
+        .map(|(name, stream)| {
+            teardown(&name, stream);
+            name
+        })
+        .collect()
+}
+
+fn teardown(name: &str, stream: Stream) {
+    info!("tear down stream: {}", name);
+    for subscriber in stream.subscribers.iter().filter_map(Weak::upgrade) {
+        subscriber.detach();
+    }
+    stream.dispatcher.lock().unwrap().stop_dispatch();
+}
+
+// the write side of a hub stream, dropping it starts the stream's grace period
+pub struct Publisher {
+    name: String,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    input_frames: Arc<AtomicU64>,
+    streams: Weak<Mutex<HashMap<String, Stream>>>,
+    clock: SharedClock,
+}
+
+impl Publisher {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn dispatcher(&self) -> Arc<Mutex<Dispatcher>> {
+        self.dispatcher.clone()
+    }
+
+    pub fn input_data(&self, data: Arc<Mutex<MediaData>>) {
+        self.dispatcher.lock().unwrap().input_data(data);
+        self.input_frames.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn set_codec_config(&self, config: CodecConfig) {
+        self.dispatcher.lock().unwrap().set_codec_config(config);
+    }
+}
+
+impl Drop for Publisher {
+    fn drop(&mut self) {
+        info!("unpublish stream: {}", self.name);
+        let streams = match self.streams.upgrade() {
+            Some(streams) => streams,
+            None => return,
+        };
+        let mut streams = streams.lock().unwrap();
+        // the stream may have been torn down and created again by someone else
+        if let Some(stream) = streams.get_mut(&self.name) {
+            if Arc::ptr_eq(&stream.dispatcher, &self.dispatcher) {
+                stream.publishing = false;
+                stream.idle_since = Some(self.clock.now());
+            }
+        }
+    }
+}
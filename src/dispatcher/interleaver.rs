@@ -0,0 +1,102 @@
+use crate::utils::buffer::{MediaData, MediaType};
+use std::{
+    collections::VecDeque,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+struct Pending {
+    data: Arc<Mutex<MediaData>>,
+    dts: u64,
+    media_type: MediaType,
+    arrived: Duration,
+}
+
+// puts the frames of a mixed read back in dts order across tracks. each track is
+// expected in dts order already, so once every subscribed track has a frame waiting
+// the earliest of them is safe to hand out. a track that goes quiet holds the others
+// back until the window is full or the oldest frame has waited max_wait. times are
+// those of the reading receiver's clock.
+pub struct Interleaver {
+    window: usize,
+    max_wait: Duration,
+    // track bits of the tracks to wait for, audio and video by default
+    tracks: u32,
+    pending: VecDeque<Pending>,
+}
+
+impl Interleaver {
+    pub fn new(window: usize, max_wait: Duration) -> Self {
+        Interleaver {
+            window: window.max(1),
+            max_wait,
+            tracks: MediaType::AUDIO.track_bit() | MediaType::VIDEO.track_bit(),
+            pending: VecDeque::new(),
+        }
+    }
+
+    pub fn set_tracks(&mut self, tracks: u32) {
+        self.tracks = tracks;
+    }
+
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+
+    pub fn push(&mut self, data: Arc<Mutex<MediaData>>, now: Duration) {
+        let (dts, media_type) = {
+            let data = data.lock().unwrap();
+            (data.dts, data.media_type)
+        };
+        // after the frames with the same dts, so a track keeps its own order
+        let position = self
+            .pending
+            .iter()
+            .rposition(|pending| pending.dts <= dts)
+            .map_or(0, |position| position + 1);
+        self.pending.insert(
+            position,
+            Pending {
+                data,
+                dts,
+                media_type,
+                arrived: now,
+            },
+        );
+    }
+
+    // the earliest frame once it can not be overtaken any more, or right away with drain
+    pub fn pop(&mut self, now: Duration, drain: bool) -> Option<Arc<Mutex<MediaData>>> {
+        if drain || self.is_ready(now) {
+            self.pending.pop_front().map(|pending| pending.data)
+        } else {
+            None
+        }
+    }
+
+    // how long until the oldest frame reaches max_wait
+    pub fn wait_time(&self, now: Duration) -> Option<Duration> {
+        self.pending
+            .iter()
+            .map(|pending| pending.arrived)
+            .min()
+            .map(|arrived| self.max_wait.saturating_sub(now.saturating_sub(arrived)))
+    }
+
+    fn is_ready(&self, now: Duration) -> bool {
+        if self.pending.is_empty() {
+            return false;
+        }
+        let waiting = self
+            .pending
+            .iter()
+            .fold(0, |mask, pending| mask | pending.media_type.track_bit());
+        waiting & self.tracks == self.tracks
+            || self.pending.len() >= self.window
+            || self.wait_time(now) == Some(Duration::ZERO)
+    }
+}
@@ -1,2 +1,13 @@
+pub mod capture;
+#[allow(clippy::module_inception)]
 pub mod dispatcher;
+pub mod dump;
+pub mod dvr;
+pub mod hub;
+pub mod interleaver;
+pub mod normalizer;
 pub mod receiver;
+pub mod relay;
+pub mod rendition;
+pub mod scenario;
+pub mod spill;
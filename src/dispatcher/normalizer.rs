@@ -0,0 +1,114 @@
+use crate::utils::buffer::{MediaData, MediaType, TRACK_COUNT};
+use crate::warn;
+
+// a 33-bit 90kHz MPEG clock wraps after this many ms
+pub const MPEG_ROLLOVER: u64 = (1 << 33) / 90;
+pub const DEFAULT_JUMP_THRESHOLD: u64 = 1000;
+
+// what to do about a jump that is not a rollover
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum JumpAction {
+    // close the jump, the output carries on one frame after the last
+    #[default]
+    REBASE,
+    // keep forward gaps and restart backward jumps one frame after the last output,
+    // either way the first frame after the jump is marked as a discontinuity
+    DISCONTINUITY,
+}
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TimestampCorrections {
+    pub rollovers: u64,
+    pub backward_jumps: u64,
+    pub forward_gaps: u64,
+    // small steps back held at the last output
+    pub clamped: u64,
+}
+
+#[derive(Default, Debug)]
+struct TrackState {
+    last_in: Option<u64>,
+    last_out: u64,
+    offset: i64,
+    frame_duration: u64,
+}
+
+// rewrites ingest timestamps so each track's dts is continuous and monotonic and
+// the stream starts at zero. jumps are measured on dts so that B-frame reordering
+// of pts is not one, pts keeps its distance to dts.
+#[derive(Default, Debug)]
+pub struct TimestampNormalizer {
+    threshold: u64,
+    action: JumpAction,
+    base: Option<u64>,
+    tracks: [TrackState; TRACK_COUNT],
+    corrections: TimestampCorrections,
+}
+
+impl TimestampNormalizer {
+    pub fn new() -> Self {
+        Self::with_threshold(DEFAULT_JUMP_THRESHOLD, JumpAction::REBASE)
+    }
+
+    // steps between frames of a track larger than the threshold, in ms, are jumps
+    pub fn with_threshold(threshold: u64, action: JumpAction) -> Self {
+        TimestampNormalizer {
+            threshold,
+            action,
+            ..Default::default()
+        }
+    }
+
+    pub fn corrections(&self) -> TimestampCorrections {
+        self.corrections
+    }
+
+    pub fn normalize(&mut self, data: &mut MediaData) {
+        let base = *self.base.get_or_insert(data.dts);
+        let track = match data.media_type {
+            MediaType::AV => &mut self.tracks[MediaType::VIDEO.slot()],
+            media_type => &mut self.tracks[media_type.slot()],
+        };
+        let corrections = &mut self.corrections;
+        let dts = data.dts;
+        let mut discontinuity = false;
+
+        match track.last_in {
+            None => track.offset = -(base as i64),
+            Some(last_in) => {
+                let delta = dts as i64 - last_in as i64;
+                let restart = track.last_out as i64 + track.frame_duration as i64 - dts as i64;
+                if delta < 0 && (dts + MPEG_ROLLOVER).abs_diff(last_in) <= self.threshold {
+                    corrections.rollovers += 1;
+                    track.offset += MPEG_ROLLOVER as i64;
+                } else if delta < -(self.threshold as i64) {
+                    warn!("dts jumped back: {} -> {}", last_in, dts);
+                    corrections.backward_jumps += 1;
+                    track.offset = restart;
+                    discontinuity = self.action == JumpAction::DISCONTINUITY;
+                } else if delta > self.threshold as i64 {
+                    warn!("dts jumped forward: {} -> {}", last_in, dts);
+                    corrections.forward_gaps += 1;
+                    match self.action {
+                        JumpAction::REBASE => track.offset = restart,
+                        JumpAction::DISCONTINUITY => discontinuity = true,
+                    }
+                } else if delta > 0 {
+                    track.frame_duration = delta as u64;
+                }
+            }
+        }
+
+        let mut out = (dts as i64 + track.offset).max(0) as u64;
+        if track.last_in.is_some() && out < track.last_out {
+            corrections.clamped += 1;
+            out = track.last_out;
+        }
+        let composition_time = data.pts as i64 - dts as i64;
+        data.dts = out;
+        data.pts = (out as i64 + composition_time).max(0) as u64;
+        data.discontinuity |= discontinuity;
+        track.last_in = Some(dts);
+        track.last_out = out;
+    }
+}
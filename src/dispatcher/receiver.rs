@@ -1,38 +1,52 @@
 use crate::utils::{
-    buffer::{MediaData, MediaType},
+    buffer::{MediaData, MediaType, TRACK_COUNT},
+    clock::{SharedClock, SystemClock},
+    codec::CodecConfig,
+    sync::{self, AtomicBool, Ordering},
     Identity,
 };
-use crate::{debug, error, fatal, info, warn};
+use crate::{debug, fatal, info, warn};
 use std::{
-    sync::{
-        atomic::{AtomicBool, Ordering},
-        Arc, Condvar, Mutex, RwLock, Weak,
-    },
-    thread, time,
+    sync::{atomic::AtomicU32, Arc, Mutex, Weak},
+    time,
 };
 
-use super::dispatcher::{self, Dispatcher};
+use super::dispatcher::Dispatcher;
+use super::interleaver::Interleaver;
+use super::rendition::Renditions;
 // use super::DispatcherReceiver;
 
+static NEXT_RECEIVER_ID: AtomicU32 = AtomicU32::new(1);
+
+// one read slot per track plus one for mixed reads, see MediaType::slot
+const READ_SLOTS: usize = TRACK_COUNT + 1;
+
+// the requesting flags are only read and written under `mutex`, which orders them
+// against the condvar waits; a waiter consumes its flag when it wakes up, so data
+// signaled while the read is in progress stays pending for the next request_read
+
 pub struct Receiver {
     id: u32,
     read_index: Mutex<u32>,
-    requesting_media: AtomicBool,
-    requesting_audio: AtomicBool,
-    requesting_video: AtomicBool,
+    requesting: [AtomicBool; READ_SLOTS],
+    first: [AtomicBool; READ_SLOTS],
 
-    first_audio: AtomicBool,
-    first_video: AtomicBool,
-    first_mix: AtomicBool,
+    mutex: sync::Mutex<()>,
+    stopped: AtomicBool,
+    notify: [sync::Condvar; READ_SLOTS],
 
-    mutex: Mutex<()>,
-    notify_audio: Condvar,
-    notify_video: Condvar,
-    notify_data: Condvar,
+    // the tracks a mixed read delivers, as a mask of track bits
+    tracks: AtomicU32,
 
     mix_read: AtomicBool,
     key_only: AtomicBool,
     dispatcher: Mutex<Weak<Mutex<Dispatcher>>>,
+    interleaver: Mutex<Option<Interleaver>>,
+    renditions: Mutex<Renditions>,
+    // start pts, and whether video still waits for a key frame at or after it
+    start: Mutex<Option<(u64, bool)>>,
+    // the dispatcher's clock once attached, for the read timeouts
+    clock: Mutex<SharedClock>,
 }
 
 impl Identity for Receiver {
@@ -41,33 +55,64 @@ impl Identity for Receiver {
     }
 }
 
+impl Default for Receiver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Receiver {
     pub fn new() -> Self {
         Receiver {
-            id: 0,
+            id: NEXT_RECEIVER_ID.fetch_add(1, Ordering::Relaxed),
             read_index: Mutex::new(0),
-            requesting_media: AtomicBool::new(false),
-            requesting_audio: AtomicBool::new(false),
-            requesting_video: AtomicBool::new(false),
-            first_audio: AtomicBool::new(true),
-            first_video: AtomicBool::new(true),
-            first_mix: AtomicBool::new(true),
-            mutex: Mutex::new(()),
-            notify_audio: Condvar::new(),
-            notify_video: Condvar::new(),
-            notify_data: Condvar::new(),
+            requesting: std::array::from_fn(|_| AtomicBool::new(false)),
+            first: std::array::from_fn(|_| AtomicBool::new(true)),
+            mutex: sync::Mutex::new(()),
+            stopped: AtomicBool::new(false),
+            notify: std::array::from_fn(|_| sync::Condvar::new()),
+            tracks: AtomicU32::new(MediaType::AUDIO.track_bit() | MediaType::VIDEO.track_bit()),
             mix_read: AtomicBool::new(false),
             key_only: AtomicBool::new(false),
             dispatcher: Mutex::new(Weak::new()),
+            interleaver: Mutex::new(None),
+            renditions: Mutex::new(Renditions::new()),
+            start: Mutex::new(None),
+            clock: Mutex::new(SystemClock::shared()),
         }
     }
 
     pub fn is_mix_read(&self) -> bool {
-        self.mix_read.load(Ordering::Relaxed)
+        self.mix_read.load(Ordering::Acquire)
     }
 
     pub fn is_key_read(&self) -> bool {
-        self.key_only.load(Ordering::Relaxed)
+        self.key_only.load(Ordering::Acquire)
+    }
+
+    // set by notify_read_stop and detach, reads then return without data
+    pub fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Relaxed)
+    }
+
+    // audio and video by default; the subtitle, metadata and data tracks are only
+    // delivered to receivers that subscribe to them, also for reads of that track alone
+    pub fn set_tracks(&self, tracks: &[MediaType]) {
+        let mask = tracks
+            .iter()
+            .fold(0, |mask, track| mask | track.track_bit());
+        self.tracks.store(mask, Ordering::Relaxed);
+        if let Some(interleaver) = self.interleaver.lock().unwrap().as_mut() {
+            interleaver.set_tracks(mask);
+        }
+    }
+
+    pub fn is_subscribed(&self, media_type: MediaType) -> bool {
+        self.track_mask() & media_type.track_bit() != 0
+    }
+
+    pub fn track_mask(&self) -> u32 {
+        self.tracks.load(Ordering::Relaxed)
     }
 
     pub fn set_read_index(&self, index: u32) {
@@ -79,7 +124,7 @@ impl Receiver {
     }
 
     pub fn set_key_mode(&self, enable: bool) {
-        self.key_only.store(enable, Ordering::Relaxed);
+        self.key_only.store(enable, Ordering::Release);
         if enable {
             let dispatcher = self.dispatcher.lock().unwrap().upgrade();
             if dispatcher.is_none() {
@@ -101,54 +146,233 @@ impl Receiver {
         *dispatcher = binding;
     }
 
+    pub fn set_clock(&self, clock: SharedClock) {
+        *self.clock.lock().unwrap() = clock;
+    }
+
+    // false once detached or the dispatcher is gone
+    pub fn is_attached(&self) -> bool {
+        self.dispatcher.lock().unwrap().strong_count() > 0
+    }
+
+    pub fn get_codec_config(&self, media_type: MediaType) -> Option<CodecConfig> {
+        let dispatcher = self.dispatcher.lock().unwrap().upgrade()?;
+        let config = dispatcher.lock().unwrap().get_codec_config(media_type);
+        config
+    }
+
+    // the data mode of the dispatcher, None once detached
+    pub fn data_mode(&self) -> Option<MediaType> {
+        let dispatcher = self.dispatcher.lock().unwrap().upgrade()?;
+        let data_mode = dispatcher.lock().unwrap().data_mode();
+        Some(data_mode)
+    }
+
+    // mixed reads come back in dts order across the subscribed tracks, holding at
+    // most `window` frames for up to `max_wait` of the receiver's clock; a window of
+    // 0 turns it off
+    pub fn set_interleave(&self, window: usize, max_wait: time::Duration) {
+        *self.interleaver.lock().unwrap() = match window {
+            0 => None,
+            window => {
+                let mut interleaver = Interleaver::new(window, max_wait);
+                interleaver.set_tracks(self.track_mask());
+                Some(interleaver)
+            }
+        };
+    }
+
+    // follows one rendition of the media type, by the frames' track id. the first
+    // selection applies right away, a later one switches at the next key frame of
+    // the new rendition while staying attached
+    pub fn select_track(&self, media_type: MediaType, track_id: u32) {
+        self.renditions.lock().unwrap().select(media_type, track_id);
+    }
+
+    pub fn selected_track(&self, media_type: MediaType) -> Option<u32> {
+        self.renditions.lock().unwrap().selected(media_type)
+    }
+
+    // skips the frames before pts and video until a key frame at or after it, for a
+    // receiver attached before the data it should start at came in
+    pub fn set_start_pts(&self, pts: Option<u64>) {
+        *self.start.lock().unwrap() = pts.map(|pts| (pts, true));
+    }
+
     pub fn request_read(&self, media_type: MediaType) -> (bool, Option<Arc<Mutex<MediaData>>>) {
+        if media_type == MediaType::AV && self.interleaver.lock().unwrap().is_some() {
+            return self.interleaved_read();
+        }
+        self.read_data(media_type)
+    }
+
+    fn read_data(&self, media_type: MediaType) -> (bool, Option<Arc<Mutex<MediaData>>>) {
         debug!("request_read");
+        loop {
+            let dispatcher = self.prepare_read(media_type);
+            if dispatcher.is_none() {
+                return (false, None);
+            }
+            let dispatcher = dispatcher.unwrap();
+
+            fatal!("request_read, type: {:?}", media_type);
+            self.wait_data(media_type);
+            fatal!("request_read type: {:?} done", media_type);
+
+            let (ret, data) = self.finish_read(&dispatcher, media_type);
+            if self.accept(&dispatcher, &data) {
+                return (ret, data);
+            }
+        }
+    }
+
+    // like request_read, but returns right away when no data has been signaled
+    pub fn try_read(&self, media_type: MediaType) -> (bool, Option<Arc<Mutex<MediaData>>>) {
+        loop {
+            let dispatcher = self.prepare_read(media_type);
+            if dispatcher.is_none() {
+                return (false, None);
+            }
+            let dispatcher = dispatcher.unwrap();
+
+            if !self.poll_data(media_type) {
+                return (false, None);
+            }
+
+            let (ret, data) = self.finish_read(&dispatcher, media_type);
+            if self.accept(&dispatcher, &data) {
+                return (ret, data);
+            }
+        }
+    }
+
+    // the next frame, waiting up to the timeout if there is one. None once reading
+    // stopped, the receiver was detached or the timeout passed
+    pub fn read_frame(
+        &self,
+        media_type: MediaType,
+        timeout: Option<time::Duration>,
+    ) -> Option<Arc<Mutex<MediaData>>> {
+        let clock = self.clock.lock().unwrap().clone();
+        let deadline = timeout.map(|timeout| clock.now() + timeout);
+        loop {
+            if self.is_stopped() || !self.is_attached() {
+                return None;
+            }
+            let (_, data) = match deadline {
+                Some(_) => self.try_read(media_type),
+                None => self.request_read(media_type),
+            };
+            if data.is_some() {
+                return data;
+            }
+            if let Some(deadline) = deadline {
+                if clock.now() >= deadline {
+                    return None;
+                }
+                clock.sleep(time::Duration::from_millis(1));
+            }
+        }
+    }
+
+    // false for frames of a rendition the receiver does not follow and for frames
+    // before the start pts
+    fn accept(
+        &self,
+        dispatcher: &Arc<Mutex<Dispatcher>>,
+        data: &Option<Arc<Mutex<MediaData>>>,
+    ) -> bool {
+        let data = match data {
+            Some(data) => data.lock().unwrap(),
+            None => return true,
+        };
+        if let Some((start, waiting_key)) = self.start.lock().unwrap().as_mut() {
+            if data.pts < *start {
+                return false;
+            }
+            if data.media_type == MediaType::VIDEO && *waiting_key {
+                if !data.key_frame {
+                    return false;
+                }
+                *waiting_key = false;
+            }
+        }
+        self.renditions
+            .lock()
+            .unwrap()
+            .accept(&data, |track_id, other| {
+                dispatcher.lock().unwrap().is_gop_aligned(track_id, other)
+            })
+    }
+
+    fn interleaved_read(&self) -> (bool, Option<Arc<Mutex<MediaData>>>) {
+        let clock = self.clock.lock().unwrap().clone();
+        loop {
+            let now = clock.now();
+            let stopped = self.stopped.load(Ordering::Relaxed);
+            let waiting = {
+                let mut interleaver = self.interleaver.lock().unwrap();
+                let interleaver = match interleaver.as_mut() {
+                    Some(interleaver) => interleaver,
+                    None => return self.read_data(MediaType::AV),
+                };
+                // what is held back goes out as it is once reading stops
+                if let Some(data) = interleaver.pop(now, stopped) {
+                    return (true, Some(data));
+                }
+                !interleaver.is_empty()
+            };
+
+            // block only while nothing is held back, otherwise poll until max_wait
+            let (ret, data) = if waiting {
+                self.try_read(MediaType::AV)
+            } else {
+                self.read_data(MediaType::AV)
+            };
+            match data {
+                Some(data) => {
+                    if let Some(interleaver) = self.interleaver.lock().unwrap().as_mut() {
+                        interleaver.push(data, clock.now());
+                    }
+                }
+                None if !waiting => return (ret, None),
+                None => clock.sleep(time::Duration::from_millis(1)),
+            }
+        }
+    }
+
+    pub fn detach(&self) {
         let dispatcher = self.dispatcher.lock().unwrap().upgrade();
-        if dispatcher.is_none() {
-            return (false, None);
+        if let Some(dispatcher) = dispatcher {
+            dispatcher.lock().unwrap().detach_receiver(self.get_id());
         }
+        *self.dispatcher.lock().unwrap() = Weak::new();
+        self.notify_read_stop();
+    }
 
-        let dispatcher = dispatcher.unwrap();
+    fn prepare_read(&self, media_type: MediaType) -> Option<Arc<Mutex<Dispatcher>>> {
+        let dispatcher = self.dispatcher.lock().unwrap().upgrade()?;
 
-        if self.first_mix.load(Ordering::Relaxed) == true && media_type == MediaType::AV {
-            dispatcher
-                .lock()
-                .unwrap()
-                .notify_read_ready(self.get_id(), media_type);
-            self.mix_read.store(true, Ordering::Relaxed);
-            self.first_mix.store(false, Ordering::Relaxed);
-        } else if self.first_audio.load(Ordering::Relaxed) && media_type == MediaType::AUDIO {
-            dispatcher
-                .lock()
-                .unwrap()
-                .notify_read_ready(self.get_id(), media_type);
-            self.first_audio.store(false, Ordering::Relaxed);
-        } else if self.first_video.load(Ordering::Relaxed) && media_type == MediaType::VIDEO {
+        let first = &self.first[media_type.slot()];
+        if first.load(Ordering::Relaxed) {
+            if media_type == MediaType::AV {
+                // set before the notify pass can see the receiver's data bits
+                self.mix_read.store(true, Ordering::Release);
+            }
             dispatcher
                 .lock()
                 .unwrap()
                 .notify_read_ready(self.get_id(), media_type);
-            self.first_video.store(false, Ordering::Relaxed);
+            first.store(false, Ordering::Relaxed);
         }
+        Some(dispatcher)
+    }
 
-        {
-            let lock = self.mutex.lock().unwrap();
-            fatal!("request_read, type: {:?}", media_type);
-            match media_type {
-                // wait utill the pred is false
-                MediaType::AUDIO => self
-                    .notify_audio
-                    .wait_while(lock, |_| !self.requesting_audio.load(Ordering::Relaxed)),
-                MediaType::VIDEO => self
-                    .notify_video
-                    .wait_while(lock, |_| !self.requesting_video.load(Ordering::Relaxed)),
-                MediaType::AV => self
-                    .notify_data
-                    .wait_while(lock, |_| !self.requesting_media.load(Ordering::Relaxed)),
-            };
-        }
-        fatal!("request_read type: {:?} done", media_type);
-
+    fn finish_read(
+        &self,
+        dispatcher: &Arc<Mutex<Dispatcher>>,
+        media_type: MediaType,
+    ) -> (bool, Option<Arc<Mutex<MediaData>>>) {
         dispatcher
             .lock()
             .unwrap()
@@ -164,14 +388,6 @@ impl Receiver {
             .read_buffer_data(self.get_id(), media_type);
 
         info!("read buffer data out, ret: {:?}", ret);
-        {
-            let _lock = self.mutex.lock().unwrap();
-            match media_type {
-                MediaType::AUDIO => self.requesting_audio.store(false, Ordering::Release),
-                MediaType::VIDEO => self.requesting_video.store(false, Ordering::Release),
-                MediaType::AV => self.requesting_media.store(false, Ordering::Release),
-            };
-        }
 
         dispatcher
             .lock()
@@ -182,55 +398,73 @@ impl Receiver {
         (ret, x.clone())
     }
 
+    // blocks until data of this type is signaled or reading is stopped, consuming the signal
+    pub fn wait_data(&self, media_type: MediaType) {
+        let mut lock = self.mutex.lock().unwrap();
+        // wait utill the pred is false
+        while !self.requesting(media_type).load(Ordering::Relaxed)
+            && !self.stopped.load(Ordering::Relaxed)
+        {
+            lock = self.notifier(media_type).wait(lock).unwrap();
+        }
+        self.requesting(media_type).store(false, Ordering::Relaxed);
+    }
+
+    // consumes a pending signal without blocking, false when there is none
+    pub fn poll_data(&self, media_type: MediaType) -> bool {
+        let _lock = self.mutex.lock().unwrap();
+        let signaled = self.requesting(media_type).swap(false, Ordering::Relaxed);
+        signaled || self.stopped.load(Ordering::Relaxed)
+    }
+
+    fn requesting(&self, media_type: MediaType) -> &AtomicBool {
+        &self.requesting[media_type.slot()]
+    }
+
+    fn notifier(&self, media_type: MediaType) -> &sync::Condvar {
+        &self.notify[media_type.slot()]
+    }
+
     pub fn notify_read_start(&self) {
-        self.first_audio.store(true, Ordering::Relaxed);
-        self.first_video.store(true, Ordering::Relaxed);
-        self.first_mix.store(true, Ordering::Relaxed);
+        {
+            let _lk = self.mutex.lock().unwrap();
+            self.stopped.store(false, Ordering::Relaxed);
+        }
+        for first in self.first.iter() {
+            first.store(true, Ordering::Relaxed);
+        }
     }
 
     pub fn notify_read_stop(&self) {
         info!("trace");
         let _lk = self.mutex.lock().unwrap();
-        self.requesting_audio.store(true, Ordering::Relaxed);
-        self.requesting_video.store(true, Ordering::Relaxed);
-        self.requesting_media.store(true, Ordering::Relaxed);
-        self.notify_audio.notify_all();
-        self.notify_video.notify_all();
-        self.notify_data.notify_all();
+        self.stopped.store(true, Ordering::Relaxed);
+        for notify in self.notify.iter() {
+            notify.notify_all();
+        }
     }
 
-    pub fn on_media_data(&self) {
+    pub fn on_data(&self, media_type: MediaType) {
+        debug!("trace");
         let _lk = self.mutex.lock().unwrap();
-        if self.requesting_media.load(Ordering::Relaxed) {
-            warn!("requesting media");
+        if self.requesting(media_type).load(Ordering::Relaxed) {
+            warn!("requesting {:?}", media_type);
             return;
         }
 
-        self.requesting_media.store(true, Ordering::Relaxed);
-        self.notify_data.notify_one();
+        self.requesting(media_type).store(true, Ordering::Relaxed);
+        self.notifier(media_type).notify_one();
     }
 
-    pub fn on_audio_data(&self) {
-        let _lk = self.mutex.lock().unwrap();
-        if self.requesting_audio.load(Ordering::Relaxed) {
-            warn!("requesting audio");
-            return;
-        }
+    pub fn on_media_data(&self) {
+        self.on_data(MediaType::AV);
+    }
 
-        self.requesting_audio.store(true, Ordering::Relaxed);
-        self.notify_audio.notify_one();
+    pub fn on_audio_data(&self) {
+        self.on_data(MediaType::AUDIO);
     }
 
     pub fn on_video_data(&self) {
-        debug!("trace");
-        let _lk = self.mutex.lock().unwrap();
-        if self.requesting_video.load(Ordering::Relaxed) {
-            warn!("requesting video");
-            return;
-        }
-
-        debug!("trace");
-        self.requesting_video.store(true, Ordering::Relaxed);
-        self.notify_video.notify_one();
+        self.on_data(MediaType::VIDEO);
     }
 }
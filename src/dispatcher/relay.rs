@@ -0,0 +1,124 @@
+use super::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::utils::{buffer::MediaType, codec::CodecConfig, Identity};
+use crate::{debug, info};
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+// forwards the frames an attached receiver reads into another dispatcher's input.
+// codec configs follow the frames, a discontinuity flushes the downstream before
+// the frame that carries it, and the end of the upstream flushes it too so a later
+// source starts again at a key frame.
+pub struct Relay {
+    receiver: Arc<Receiver>,
+    downstream: Arc<Mutex<Dispatcher>>,
+    media_type: MediaType,
+    frame_count: Arc<AtomicU64>,
+    relay_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl Relay {
+    // the receiver is already attached upstream, the relay ends when it is detached
+    pub fn new(
+        receiver: Arc<Receiver>,
+        downstream: Arc<Mutex<Dispatcher>>,
+        media_type: MediaType,
+    ) -> Self {
+        // an audio-only downstream never sees a key frame to start from
+        if media_type == MediaType::AUDIO {
+            downstream.lock().unwrap().set_data_mode(MediaType::AUDIO);
+        }
+        Relay {
+            receiver,
+            downstream,
+            media_type,
+            frame_count: Arc::new(AtomicU64::new(0)),
+            relay_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    // forwards only the key frames, for a key-frames-only derived stream
+    pub fn set_key_mode(&self, enable: bool) {
+        self.receiver.set_key_mode(enable);
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count.load(Ordering::Relaxed)
+    }
+
+    // false once the upstream has ended or the relay was stopped
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    pub fn start_relay(&mut self) {
+        info!(
+            "start relay: {:?}, receiver: {}",
+            self.media_type,
+            self.receiver.get_id()
+        );
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let receiver = self.receiver.clone();
+        let downstream = self.downstream.clone();
+        let media_type = self.media_type;
+        let frame_count = self.frame_count.clone();
+
+        self.relay_thread = Some(thread::spawn(move || {
+            let tracks = match media_type {
+                MediaType::AV => vec![MediaType::VIDEO, MediaType::AUDIO],
+                media_type => vec![media_type],
+            };
+            let mut sent_configs: Vec<Option<CodecConfig>> = vec![None; tracks.len()];
+
+            while *running.lock().unwrap() {
+                let (ret, out_data) = receiver.request_read(media_type);
+                if !receiver.is_attached() {
+                    if *running.lock().unwrap() {
+                        info!("relay upstream ended");
+                        downstream.lock().unwrap().flush();
+                    }
+                    break;
+                }
+                let data = match (ret, out_data) {
+                    (true, Some(data)) => data,
+                    _ => continue,
+                };
+
+                let mut downstream = downstream.lock().unwrap();
+                for (track, sent) in tracks.iter().zip(sent_configs.iter_mut()) {
+                    if let Some(config) = receiver.get_codec_config(*track) {
+                        if sent.as_ref() != Some(&config) {
+                            downstream.set_codec_config(config.clone());
+                            *sent = Some(config);
+                        }
+                    }
+                }
+                if data.lock().unwrap().discontinuity {
+                    downstream.flush();
+                }
+                // the frame is shared with the upstream buffer, not copied
+                downstream.input_data(data);
+                frame_count.fetch_add(1, Ordering::Relaxed);
+            }
+            *running.lock().unwrap() = false;
+            info!("relay done");
+        }));
+    }
+
+    pub fn stop_relay(&mut self) {
+        debug!("stop relay begin");
+        *self.running.lock().unwrap() = false;
+        self.receiver.notify_read_stop();
+        if let Some(relay_thread) = self.relay_thread.take() {
+            relay_thread.join().expect("can not join the relay thread");
+        }
+        debug!("stop relay end");
+    }
+}
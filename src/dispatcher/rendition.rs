@@ -0,0 +1,98 @@
+use crate::info;
+use crate::utils::buffer::{MediaData, MediaType, TRACK_COUNT};
+
+#[derive(Default, Debug)]
+struct Selection {
+    // None passes every track id
+    active: Option<u32>,
+    pending: Option<u32>,
+    // frames of the active track from this pts on give way to the pending one
+    switch_pts: Option<u64>,
+    last_pts: Option<u64>,
+}
+
+// the rendition a receiver follows per media type. a switch waits for a key frame
+// of the new rendition past what was delivered, audio and the other tracks switch
+// at any frame. when the dispatcher reports both video renditions as GOP aligned
+// the old one stops at its own key frame, so the new one takes over at that pts
+// even when its key frame comes second; the other tracks are taken as aligned.
+#[derive(Default, Debug)]
+pub struct Renditions {
+    selections: [Selection; TRACK_COUNT],
+}
+
+impl Renditions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn select(&mut self, media_type: MediaType, track_id: u32) {
+        let selection = &mut self.selections[track_slot(media_type)];
+        info!("select {:?} track: {}", media_type, track_id);
+        if selection.active.is_none() && selection.last_pts.is_none() {
+            selection.active = Some(track_id);
+        } else if selection.active == Some(track_id) {
+            selection.pending = None;
+            selection.switch_pts = None;
+        } else {
+            selection.pending = Some(track_id);
+            selection.switch_pts = None;
+        }
+    }
+
+    // the track being delivered, a pending switch shows once it happened
+    pub fn selected(&self, media_type: MediaType) -> Option<u32> {
+        self.selections[track_slot(media_type)].active
+    }
+
+    // whether the frame goes to the reader. aligned tells if two video tracks have
+    // their key frames at the same pts
+    pub fn accept(&mut self, data: &MediaData, aligned: impl Fn(u32, u32) -> bool) -> bool {
+        let video = track_slot(data.media_type) == MediaType::VIDEO.slot();
+        let selection = &mut self.selections[track_slot(data.media_type)];
+        let switch_point = data.key_frame || !video;
+
+        if let Some(pending) = selection.pending {
+            let past = match selection.switch_pts {
+                Some(switch_pts) => data.pts >= switch_pts,
+                None => selection
+                    .last_pts
+                    .is_none_or(|last_pts| data.pts > last_pts),
+            };
+            if data.track_id == pending && switch_point && past {
+                info!("switch track: {:?} -> {}", selection.active, pending);
+                selection.active = Some(pending);
+                selection.pending = None;
+                selection.switch_pts = None;
+                selection.last_pts = Some(data.pts);
+                return true;
+            }
+            if switch_point
+                && selection.switch_pts.is_none()
+                && selection.active == Some(data.track_id)
+                && (!video || aligned(data.track_id, pending))
+            {
+                selection.switch_pts = Some(data.pts);
+            }
+        }
+
+        if selection
+            .active
+            .is_some_and(|active| active != data.track_id)
+            || selection
+                .switch_pts
+                .is_some_and(|switch_pts| data.pts >= switch_pts)
+        {
+            return false;
+        }
+        selection.last_pts = Some(data.pts);
+        true
+    }
+}
+
+fn track_slot(media_type: MediaType) -> usize {
+    match media_type {
+        MediaType::AV => MediaType::VIDEO.slot(),
+        media_type => media_type.slot(),
+    }
+}
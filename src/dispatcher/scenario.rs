@@ -0,0 +1,165 @@
+use super::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::source::synthetic::SyntheticStream;
+use crate::utils::{buffer::MediaType, clock::VirtualClock};
+use crate::{debug, error};
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ReadFrame {
+    pub pts: u64,
+    pub media_type: MediaType,
+    pub key_frame: bool,
+}
+
+struct ScenarioReceiver {
+    receiver: Arc<Receiver>,
+    media_type: MediaType,
+    frames: Vec<ReadFrame>,
+}
+
+// drives an unstarted dispatcher on the calling thread, so every step is deterministic:
+// the notify pass that the dispatch thread would run is executed after each input and
+// before each read.
+pub struct Scenario {
+    clock: Arc<VirtualClock>,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    stream: SyntheticStream,
+    receivers: HashMap<String, ScenarioReceiver>,
+}
+
+impl Scenario {
+    pub fn new(stream: SyntheticStream) -> Self {
+        let clock = VirtualClock::shared();
+        Scenario {
+            dispatcher: Dispatcher::with_clock(400, 50, clock.clone()),
+            clock,
+            stream,
+            receivers: HashMap::new(),
+        }
+    }
+
+    // the dispatcher's clock, it only moves when advanced or slept on
+    pub fn clock(&self) -> Arc<VirtualClock> {
+        self.clock.clone()
+    }
+
+    pub fn dispatcher(&self) -> Arc<Mutex<Dispatcher>> {
+        self.dispatcher.clone()
+    }
+
+    pub fn attach(&mut self, name: &str, media_type: MediaType) -> &mut Self {
+        self.attach_receiver(name, media_type, false)
+    }
+
+    pub fn attach_key_only(&mut self, name: &str, media_type: MediaType) -> &mut Self {
+        self.attach_receiver(name, media_type, true)
+    }
+
+    // feeds the next `count` frames of the synthetic stream
+    pub fn input(&mut self, count: usize) -> &mut Self {
+        for data in self.stream.frames(count) {
+            self.dispatcher.lock().unwrap().input_data(data);
+            self.dispatcher.lock().unwrap().dispatch_once();
+        }
+        self
+    }
+
+    // performs up to `count` reads, stopping early once no data is signaled
+    pub fn read(&mut self, name: &str, count: usize) -> &mut Self {
+        for _ in 0..count {
+            if !self.read_one(name) {
+                break;
+            }
+        }
+        self
+    }
+
+    pub fn read_all(&mut self, name: &str) -> &mut Self {
+        while self.read_one(name) {}
+        self
+    }
+
+    // the receiver skips what comes before pts, see Receiver::set_start_pts
+    pub fn start_at(&mut self, name: &str, pts: u64) -> &mut Self {
+        match self.receivers.get(name) {
+            Some(entry) => entry.receiver.set_start_pts(Some(pts)),
+            None => error!("unknown receiver: {}", name),
+        }
+        self
+    }
+
+    pub fn detach(&mut self, name: &str) -> &mut Self {
+        match self.receivers.get(name) {
+            Some(entry) => entry.receiver.detach(),
+            None => error!("unknown receiver: {}", name),
+        }
+        self
+    }
+
+    pub fn frames(&self, name: &str) -> &[ReadFrame] {
+        self.receivers
+            .get(name)
+            .map_or(&[], |entry| entry.frames.as_slice())
+    }
+
+    pub fn pts(&self, name: &str) -> Vec<u64> {
+        self.frames(name).iter().map(|frame| frame.pts).collect()
+    }
+
+    pub fn assert_pts(&self, name: &str, expected: &[u64]) -> &Self {
+        assert_eq!(self.pts(name), expected, "frames read by {}", name);
+        self
+    }
+
+    fn attach_receiver(&mut self, name: &str, media_type: MediaType, key_only: bool) -> &mut Self {
+        let receiver = Arc::new(Receiver::new());
+        self.dispatcher
+            .lock()
+            .unwrap()
+            .attach_receiver(receiver.clone());
+        receiver.set_dispatcher(self.dispatcher.clone());
+        receiver.set_key_mode(key_only);
+        self.receivers.insert(
+            name.to_string(),
+            ScenarioReceiver {
+                receiver,
+                media_type,
+                frames: Vec::new(),
+            },
+        );
+        self
+    }
+
+    fn read_one(&mut self, name: &str) -> bool {
+        let entry = match self.receivers.get_mut(name) {
+            Some(entry) => entry,
+            None => {
+                error!("unknown receiver: {}", name);
+                return false;
+            }
+        };
+
+        let (mut ret, mut data) = entry.receiver.try_read(entry.media_type);
+        if !ret {
+            // the first read only registers the receiver, let the notify pass signal it
+            self.dispatcher.lock().unwrap().dispatch_once();
+            (ret, data) = entry.receiver.try_read(entry.media_type);
+        }
+        if !ret || data.is_none() {
+            return false;
+        }
+
+        let binding = data.unwrap();
+        let data = binding.lock().unwrap();
+        debug!("{} read pts: {}", name, data.pts);
+        entry.frames.push(ReadFrame {
+            pts: data.pts,
+            media_type: data.media_type,
+            key_frame: data.key_frame,
+        });
+        true
+    }
+}
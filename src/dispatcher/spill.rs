@@ -0,0 +1,93 @@
+use crate::info;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::PathBuf,
+    process,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+static NEXT_SPILL_ID: AtomicU32 = AtomicU32::new(1);
+
+// where a spilled payload went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpillSlot {
+    offset: u64,
+    len: u32,
+}
+
+impl SpillSlot {
+    pub fn len(&self) -> usize {
+        self.len as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+}
+
+// an append-only temp file for payloads moved out of memory, removed on drop.
+// space is only given back once nothing in it is needed any more, see clear
+#[derive(Debug)]
+pub struct SpillFile {
+    path: PathBuf,
+    file: File,
+    len: u64,
+}
+
+impl SpillFile {
+    pub fn new() -> io::Result<Self> {
+        let path = std::env::temp_dir().join(format!(
+            "rust_proj-{}-spill-{}",
+            process::id(),
+            NEXT_SPILL_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        let file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .write(true)
+            .truncate(true)
+            .open(&path)?;
+        info!("spill file: {:?}", path);
+        Ok(SpillFile { path, file, len: 0 })
+    }
+
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn write(&mut self, payload: &[u8]) -> io::Result<SpillSlot> {
+        self.file.seek(SeekFrom::Start(self.len))?;
+        self.file.write_all(payload)?;
+        let slot = SpillSlot {
+            offset: self.len,
+            len: payload.len() as u32,
+        };
+        self.len += payload.len() as u64;
+        Ok(slot)
+    }
+
+    pub fn read(&mut self, slot: SpillSlot) -> io::Result<Vec<u8>> {
+        let mut payload = vec![0u8; slot.len()];
+        self.file.seek(SeekFrom::Start(slot.offset))?;
+        self.file.read_exact(&mut payload)?;
+        Ok(payload)
+    }
+
+    // drops everything written so far
+    pub fn clear(&mut self) -> io::Result<()> {
+        self.file.set_len(0)?;
+        self.len = 0;
+        Ok(())
+    }
+}
+
+impl Drop for SpillFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
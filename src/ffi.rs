@@ -0,0 +1,290 @@
+// the C API, declared in include/rust_proj.h which src/bin/gen_header.rs generates
+// from this file. handles are boxed and owned by the caller until the matching
+// free/detach. every pointer must be null or valid for the call, a null handle is
+// rejected with RP_ERR_INVALID
+#![allow(clippy::missing_safety_doc)]
+
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::ipc::{media_type_code, media_type_from_code};
+use crate::utils::{
+    buffer::{MediaData, MediaType, TRACK_COUNT},
+    Identity,
+};
+use crate::{info, warn};
+use std::{
+    ptr, slice,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+pub const RP_MEDIA_AV: u32 = 0;
+pub const RP_MEDIA_AUDIO: u32 = 1;
+pub const RP_MEDIA_VIDEO: u32 = 2;
+pub const RP_MEDIA_SUBTITLE: u32 = 3;
+pub const RP_MEDIA_METADATA: u32 = 4;
+pub const RP_MEDIA_DATA: u32 = 5;
+
+pub const RP_OK: i32 = 0;
+pub const RP_ERR_INVALID: i32 = -1;
+pub const RP_ERR_TIMEOUT: i32 = -2;
+// the receiver was stopped or its dispatcher is gone
+pub const RP_ERR_STOPPED: i32 = -3;
+// the frame is kept for the next read, its size is in the frame info
+pub const RP_ERR_BUFFER_TOO_SMALL: i32 = -4;
+
+pub struct RpDispatcher {
+    dispatcher: Arc<Mutex<Dispatcher>>,
+}
+
+pub struct RpReceiver {
+    receiver: Arc<Receiver>,
+    // per media type read, a frame that did not fit the caller's buffer
+    pending: Mutex<[Option<Arc<Mutex<MediaData>>>; TRACK_COUNT + 1]>,
+}
+
+#[repr(C)]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct RpFrameInfo {
+    pub media_type: u32,
+    pub track_id: u32,
+    pub pts: u64,
+    pub dts: u64,
+    pub key_frame: bool,
+    // payload bytes
+    pub size: usize,
+}
+
+fn from_code(code: u32) -> Option<MediaType> {
+    media_type_from_code(u8::try_from(code).ok()?)
+}
+
+#[no_mangle]
+pub extern "C" fn rp_dispatcher_new(
+    max_capacity: u32,
+    capacity_increment: u32,
+) -> *mut RpDispatcher {
+    Box::into_raw(Box::new(RpDispatcher {
+        dispatcher: Dispatcher::new(max_capacity, capacity_increment),
+    }))
+}
+
+// stops the dispatch thread if it still runs, attached receivers stop reading
+#[no_mangle]
+pub unsafe extern "C" fn rp_dispatcher_free(handle: *mut RpDispatcher) {
+    if handle.is_null() {
+        return;
+    }
+    let handle = Box::from_raw(handle);
+    handle.dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rp_dispatcher_start(handle: *mut RpDispatcher) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => {
+            handle.dispatcher.lock().unwrap().start_dispatch();
+            RP_OK
+        }
+        None => RP_ERR_INVALID,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn rp_dispatcher_stop(handle: *mut RpDispatcher) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => {
+            handle.dispatcher.lock().unwrap().stop_dispatch();
+            RP_OK
+        }
+        None => RP_ERR_INVALID,
+    }
+}
+
+// copies the payload, data may be null for an empty one
+#[no_mangle]
+pub unsafe extern "C" fn rp_dispatcher_push(
+    handle: *mut RpDispatcher,
+    media_type: u32,
+    pts: u64,
+    dts: u64,
+    key_frame: bool,
+    data: *const u8,
+    size: usize,
+) -> i32 {
+    let (handle, media_type) = match (handle.as_ref(), from_code(media_type)) {
+        (Some(handle), Some(media_type)) => (handle, media_type),
+        _ => return RP_ERR_INVALID,
+    };
+    if data.is_null() && size > 0 {
+        return RP_ERR_INVALID;
+    }
+    let payload = match size {
+        0 => Vec::new(),
+        size => slice::from_raw_parts(data, size).to_vec(),
+    };
+    let frame = MediaData {
+        key_frame,
+        pts,
+        dts,
+        media_type,
+        ..Default::default()
+    };
+    frame.buff.lock().unwrap().replace(payload);
+    handle
+        .dispatcher
+        .lock()
+        .unwrap()
+        .input_data(Arc::new(Mutex::new(frame)));
+    RP_OK
+}
+
+// tracks is a mask of 1 << RP_MEDIA_* bits, 1 << RP_MEDIA_AV for every track and 0
+// for audio and video. null for a null dispatcher, an unknown bit in tracks or when
+// all read indices are taken
+#[no_mangle]
+pub unsafe extern "C" fn rp_receiver_attach(
+    handle: *mut RpDispatcher,
+    tracks: u32,
+    key_only: bool,
+) -> *mut RpReceiver {
+    let handle = match handle.as_ref() {
+        Some(handle) => handle,
+        None => return ptr::null_mut(),
+    };
+    if tracks >> (RP_MEDIA_DATA + 1) != 0 {
+        warn!("unknown track in mask {:#x}", tracks);
+        return ptr::null_mut();
+    }
+    let receiver = Arc::new(Receiver::new());
+    if tracks != 0 {
+        let tracks: Vec<MediaType> = (RP_MEDIA_AV..=RP_MEDIA_DATA)
+            .filter(|code| tracks & (1 << code) != 0)
+            .filter_map(from_code)
+            .collect();
+        receiver.set_tracks(&tracks);
+    }
+    let mut dispatcher = handle.dispatcher.lock().unwrap();
+    let count = dispatcher.receiver_count();
+    dispatcher.attach_receiver(receiver.clone());
+    if dispatcher.receiver_count() == count {
+        warn!("can not attach receiver {}", receiver.get_id());
+        return ptr::null_mut();
+    }
+    drop(dispatcher);
+    receiver.set_dispatcher(handle.dispatcher.clone());
+    receiver.set_key_mode(key_only);
+    info!("receiver {} attached over the C API", receiver.get_id());
+    Box::into_raw(Box::new(RpReceiver {
+        receiver,
+        pending: Mutex::new(Default::default()),
+    }))
+}
+
+// detaches and frees the receiver, no read may be in progress on it
+#[no_mangle]
+pub unsafe extern "C" fn rp_receiver_detach(handle: *mut RpReceiver) {
+    if handle.is_null() {
+        return;
+    }
+    Box::from_raw(handle).receiver.detach();
+}
+
+// wakes reads blocked on the receiver, they and later reads return RP_ERR_STOPPED
+#[no_mangle]
+pub unsafe extern "C" fn rp_receiver_stop(handle: *mut RpReceiver) -> i32 {
+    match handle.as_ref() {
+        Some(handle) => {
+            handle.receiver.notify_read_stop();
+            RP_OK
+        }
+        None => RP_ERR_INVALID,
+    }
+}
+
+// waits for the next frame of the type, RP_MEDIA_AV for every track, and copies its
+// payload into buffer. info may be null. a frame held back by
+// RP_ERR_BUFFER_TOO_SMALL comes again on the next read of the same type
+#[no_mangle]
+pub unsafe extern "C" fn rp_receiver_read(
+    handle: *mut RpReceiver,
+    media_type: u32,
+    buffer: *mut u8,
+    capacity: usize,
+    info: *mut RpFrameInfo,
+) -> i32 {
+    read(handle, media_type, buffer, capacity, info, None)
+}
+
+// like rp_receiver_read, RP_ERR_TIMEOUT when nothing came within timeout_ms
+#[no_mangle]
+pub unsafe extern "C" fn rp_receiver_read_timeout(
+    handle: *mut RpReceiver,
+    media_type: u32,
+    buffer: *mut u8,
+    capacity: usize,
+    info: *mut RpFrameInfo,
+    timeout_ms: u32,
+) -> i32 {
+    let timeout = Duration::from_millis(timeout_ms as u64);
+    read(handle, media_type, buffer, capacity, info, Some(timeout))
+}
+
+unsafe fn read(
+    handle: *mut RpReceiver,
+    media_type: u32,
+    buffer: *mut u8,
+    capacity: usize,
+    info: *mut RpFrameInfo,
+    timeout: Option<Duration>,
+) -> i32 {
+    let (handle, media_type) = match (handle.as_ref(), from_code(media_type)) {
+        (Some(handle), Some(media_type)) => (handle, media_type),
+        _ => return RP_ERR_INVALID,
+    };
+    if buffer.is_null() && capacity > 0 {
+        return RP_ERR_INVALID;
+    }
+
+    let pending = handle.pending.lock().unwrap()[media_type.slot()].take();
+    let data = match pending {
+        Some(data) => data,
+        None => match next_frame(&handle.receiver, media_type, timeout) {
+            Ok(data) => data,
+            Err(code) => return code,
+        },
+    };
+
+    let frame = data.lock().unwrap();
+    let payload = frame.payload();
+    if let Some(info) = info.as_mut() {
+        *info = RpFrameInfo {
+            media_type: media_type_code(frame.media_type) as u32,
+            track_id: frame.track_id,
+            pts: frame.pts,
+            dts: frame.dts,
+            key_frame: frame.key_frame,
+            size: payload.len(),
+        };
+    }
+    if payload.len() > capacity {
+        drop(frame);
+        handle.pending.lock().unwrap()[media_type.slot()] = Some(data);
+        return RP_ERR_BUFFER_TOO_SMALL;
+    }
+    if !payload.is_empty() {
+        ptr::copy_nonoverlapping(payload.as_ptr(), buffer, payload.len());
+    }
+    RP_OK
+}
+
+fn next_frame(
+    receiver: &Receiver,
+    media_type: MediaType,
+    timeout: Option<Duration>,
+) -> Result<Arc<Mutex<MediaData>>, i32> {
+    match receiver.read_frame(media_type, timeout) {
+        Some(data) => Ok(data),
+        None if receiver.is_stopped() || !receiver.is_attached() => Err(RP_ERR_STOPPED),
+        None => Err(RP_ERR_TIMEOUT),
+    }
+}
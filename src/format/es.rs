@@ -0,0 +1,273 @@
+use super::{media_data, Demuxer, Packet};
+use crate::utils::{
+    buffer::MediaType,
+    codec::{
+        h264_config, nalus_to_avcc, split_annexb, AdtsHeader, CodecConfig, H264_NAL_AUD,
+        H264_NAL_IDR, H264_NAL_PPS, H264_NAL_SEI, H264_NAL_SLICE, H264_NAL_SPS,
+    },
+};
+use crate::{error, info, warn};
+use std::{
+    collections::VecDeque,
+    io::{ErrorKind, Read},
+};
+
+// converts Annex-B access units to length-prefixed payloads, tracking SPS/PPS
+#[derive(Default)]
+pub struct AnnexbParser {
+    sps: Vec<u8>,
+    pps: Vec<u8>,
+    config_changed: bool,
+}
+
+impl AnnexbParser {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // returns the new codec config if this access unit changed it, the payload and the key flag
+    pub fn parse(&mut self, nalus: &[&[u8]]) -> (Option<CodecConfig>, Vec<u8>, bool) {
+        let mut key_frame = false;
+        let mut kept = Vec::new();
+        for nalu in nalus.iter().filter(|nalu| !nalu.is_empty()) {
+            match nalu[0] & 0x1f {
+                H264_NAL_AUD => continue,
+                H264_NAL_SPS if self.sps != *nalu => {
+                    self.sps = nalu.to_vec();
+                    self.config_changed = true;
+                }
+                H264_NAL_PPS if self.pps != *nalu => {
+                    self.pps = nalu.to_vec();
+                    self.config_changed = true;
+                }
+                H264_NAL_IDR => key_frame = true,
+                _ => {}
+            }
+            kept.push(*nalu);
+        }
+
+        let mut config = None;
+        if self.config_changed && !self.sps.is_empty() && !self.pps.is_empty() {
+            config = h264_config(&self.sps, &self.pps);
+            if config.is_none() {
+                error!("invalid sps");
+            }
+            self.config_changed = false;
+        }
+        (config, nalus_to_avcc(&kept), key_frame)
+    }
+}
+
+// group Annex-B NAL units into access units
+pub fn split_access_units(data: &[u8]) -> Vec<Vec<&[u8]>> {
+    let mut units = Vec::new();
+    let mut current: Vec<&[u8]> = Vec::new();
+    let mut has_slice = false;
+    for nalu in split_annexb(data)
+        .into_iter()
+        .filter(|nalu| !nalu.is_empty())
+    {
+        let nal_type = nalu[0] & 0x1f;
+        let is_slice = nal_type == H264_NAL_SLICE || nal_type == H264_NAL_IDR;
+        let first_slice = is_slice && nalu.len() > 1 && nalu[1] & 0x80 != 0;
+        let starts_unit = match nal_type {
+            H264_NAL_AUD | H264_NAL_SPS | H264_NAL_PPS | H264_NAL_SEI => has_slice,
+            _ => has_slice && first_slice,
+        };
+        if starts_unit {
+            units.push(std::mem::take(&mut current));
+            has_slice = false;
+        }
+        has_slice |= is_slice;
+        current.push(nalu);
+    }
+    if !current.is_empty() {
+        units.push(current);
+    }
+    units
+}
+
+// bytes asked from the input at a time
+const READ_CHUNK: usize = 64 * 1024;
+
+// raw H.264 Annex-B elementary stream, timestamps derived from a fixed frame rate
+pub struct H264Demuxer {
+    input: Box<dyn Read + Send>,
+    fps: u32,
+    // read but not yet split, starts at the access unit that may still grow
+    data: Vec<u8>,
+    eof: bool,
+    frames: u64,
+    parser: AnnexbParser,
+    packets: VecDeque<Packet>,
+}
+
+impl H264Demuxer {
+    pub fn new(input: Box<dyn Read + Send>, fps: u32) -> Self {
+        H264Demuxer {
+            input,
+            fps: fps.max(1),
+            data: Vec::new(),
+            eof: false,
+            frames: 0,
+            parser: AnnexbParser::new(),
+            packets: VecDeque::new(),
+        }
+    }
+
+    fn read_more(&mut self) {
+        let mut chunk = vec![0u8; READ_CHUNK];
+        match self.input.read(&mut chunk) {
+            Ok(0) => self.eof = true,
+            Ok(len) => self.data.extend_from_slice(&chunk[..len]),
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(e) => {
+                error!("read h264 stream failed: {}", e);
+                self.eof = true;
+            }
+        }
+    }
+
+    // queues the access units known to be complete: all of them at the end of the
+    // stream, otherwise the ones followed by the start of another
+    fn split(&mut self) {
+        let data = std::mem::take(&mut self.data);
+        let units = split_access_units(&data);
+        let complete = if self.eof {
+            units.len()
+        } else {
+            units.len().saturating_sub(1)
+        };
+        for unit in units.iter().take(complete) {
+            let (config, payload, key_frame) = self.parser.parse(unit);
+            if let Some(config) = config {
+                self.packets.push_back(Packet::CONFIG(config));
+            }
+            let pts = self.frames * 1000 / self.fps as u64;
+            self.frames += 1;
+            self.packets.push_back(Packet::DATA(media_data(
+                MediaType::VIDEO,
+                pts,
+                pts,
+                key_frame,
+                payload,
+            )));
+        }
+        // keep the last unit from its start code on, or everything while no NAL unit
+        // has started yet
+        if let Some(unit) = units.get(complete) {
+            let start = unit[0].as_ptr() as usize - data.as_ptr() as usize - 3;
+            self.data = data[start..].to_vec();
+        } else if !self.eof && units.is_empty() {
+            self.data = data;
+        }
+    }
+}
+
+impl Demuxer for H264Demuxer {
+    fn read_packet(&mut self) -> Option<Packet> {
+        while self.packets.is_empty() && !(self.eof && self.data.is_empty()) {
+            self.read_more();
+            self.split();
+        }
+        if self.eof && self.packets.is_empty() {
+            info!("h264 stream done, frames: {}", self.frames);
+        }
+        self.packets.pop_front()
+    }
+}
+
+// raw AAC elementary stream with ADTS headers
+pub struct AdtsDemuxer {
+    input: Box<dyn Read + Send>,
+    // read ahead, starts at the next header
+    pending: Vec<u8>,
+    frames: u64,
+    header: Option<AdtsHeader>,
+    // between a bad header and the next sync word
+    resyncing: bool,
+    packets: VecDeque<Packet>,
+}
+
+impl AdtsDemuxer {
+    pub fn new(input: Box<dyn Read + Send>) -> Self {
+        AdtsDemuxer {
+            input,
+            pending: Vec::new(),
+            frames: 0,
+            header: None,
+            resyncing: false,
+            packets: VecDeque::new(),
+        }
+    }
+
+    // reads until at least len bytes are pending, false at the end of the stream
+    fn fill(&mut self, len: usize) -> bool {
+        while self.pending.len() < len {
+            let mut chunk = vec![0u8; len - self.pending.len()];
+            match self.input.read(&mut chunk) {
+                Ok(0) => return false,
+                Ok(read) => self.pending.extend_from_slice(&chunk[..read]),
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    error!("read aac stream failed: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    // the next frame, bytes that do not start a valid header are skipped
+    fn read_frame(&mut self) -> Option<(AdtsHeader, Vec<u8>)> {
+        loop {
+            if !self.fill(7) {
+                return None;
+            }
+            let Some(header) = AdtsHeader::parse(&self.pending) else {
+                if !self.resyncing {
+                    warn!("invalid adts header, looking for the next sync word");
+                    self.resyncing = true;
+                }
+                self.pending.remove(0);
+                continue;
+            };
+            self.resyncing = false;
+            if !self.fill(header.frame_len) {
+                warn!("truncated adts frame at the end of the stream");
+                return None;
+            }
+            let frame: Vec<u8> = self.pending.drain(..header.frame_len).collect();
+            return Some((header, frame[header.header_len..].to_vec()));
+        }
+    }
+}
+
+impl Demuxer for AdtsDemuxer {
+    fn read_packet(&mut self) -> Option<Packet> {
+        while self.packets.is_empty() {
+            let (header, payload) = self.read_frame()?;
+            let Some(config) = header.config() else {
+                warn!("unsupported adts header {:?}, frame skipped", header);
+                continue;
+            };
+            if self.header.map(|last| last.frequency_index) != Some(header.frequency_index)
+                || self.header.map(|last| last.channels) != Some(header.channels)
+            {
+                self.header = Some(header);
+                self.packets.push_back(Packet::CONFIG(config.clone()));
+            }
+
+            let pts = self.frames * 1024 * 1000 / config.sample_rate as u64;
+            self.frames += 1;
+            self.packets.push_back(Packet::DATA(media_data(
+                MediaType::AUDIO,
+                pts,
+                pts,
+                false,
+                payload,
+            )));
+        }
+        self.packets.pop_front()
+    }
+}
@@ -0,0 +1,216 @@
+use super::{media_data, Demuxer, Packet};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::{aac_config, h264_config_from_avcc, CodecConfig, CodecType},
+};
+use crate::{error, warn};
+use std::io::Read;
+
+pub const TAG_AUDIO: u8 = 8;
+pub const TAG_VIDEO: u8 = 9;
+pub const TAG_SCRIPT: u8 = 18;
+
+const SOUND_FORMAT_AAC: u8 = 10;
+const VIDEO_CODEC_AVC: u8 = 7;
+// AAC, 44 kHz, 16 bit, stereo: the rate and channel bits are ignored for AAC
+const AAC_SOUND_HEADER: u8 = 0xaf;
+
+pub struct FlvDemuxer {
+    input: Box<dyn Read + Send>,
+    header_read: bool,
+}
+
+impl FlvDemuxer {
+    pub fn new(input: Box<dyn Read + Send>) -> Self {
+        FlvDemuxer {
+            input,
+            header_read: false,
+        }
+    }
+
+    fn read_header(&mut self) -> bool {
+        let mut header = [0u8; 9];
+        if self.input.read_exact(&mut header).is_err() || &header[0..3] != b"FLV" {
+            error!("invalid flv header");
+            return false;
+        }
+        let offset = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) as usize;
+        // skip the rest of the header and PreviousTagSize0
+        let mut skip = vec![0u8; offset.saturating_sub(9) + 4];
+        self.input.read_exact(&mut skip).is_ok()
+    }
+
+    fn read_tag(&mut self) -> Option<(u8, u64, Vec<u8>)> {
+        let mut header = [0u8; 11];
+        self.input.read_exact(&mut header).ok()?;
+        let tag_type = header[0] & 0x1f;
+        let size = u32::from_be_bytes([0, header[1], header[2], header[3]]) as usize;
+        let timestamp = u32::from_be_bytes([header[7], header[4], header[5], header[6]]) as u64;
+        let mut body = vec![0u8; size + 4];
+        self.input.read_exact(&mut body).ok()?;
+        body.truncate(size);
+        Some((tag_type, timestamp, body))
+    }
+
+    fn parse_audio(&self, timestamp: u64, body: &[u8]) -> Option<Packet> {
+        if body.len() < 2 || body[0] >> 4 != SOUND_FORMAT_AAC {
+            warn!("unsupported audio tag");
+            return None;
+        }
+        if body[1] == 0 {
+            return aac_config(&body[2..]).map(Packet::CONFIG);
+        }
+        Some(Packet::DATA(media_data(
+            MediaType::AUDIO,
+            timestamp,
+            timestamp,
+            false,
+            body[2..].to_vec(),
+        )))
+    }
+
+    fn parse_video(&self, timestamp: u64, body: &[u8]) -> Option<Packet> {
+        if body.len() < 5 || body[0] & 0x0f != VIDEO_CODEC_AVC {
+            warn!("unsupported video tag");
+            return None;
+        }
+        let key_frame = body[0] >> 4 == 1;
+        let composition_time = (i32::from_be_bytes([body[2], body[3], body[4], 0]) >> 8) as i64;
+        match body[1] {
+            0 => h264_config_from_avcc(&body[5..]).map(Packet::CONFIG),
+            1 => Some(Packet::DATA(media_data(
+                MediaType::VIDEO,
+                (timestamp as i64 + composition_time).max(0) as u64,
+                timestamp,
+                key_frame,
+                body[5..].to_vec(),
+            ))),
+            _ => None,
+        }
+    }
+}
+
+impl Demuxer for FlvDemuxer {
+    fn read_packet(&mut self) -> Option<Packet> {
+        if !self.header_read {
+            if !self.read_header() {
+                return None;
+            }
+            self.header_read = true;
+        }
+
+        loop {
+            let (tag_type, timestamp, body) = self.read_tag()?;
+            let packet = match tag_type {
+                TAG_AUDIO => self.parse_audio(timestamp, &body),
+                TAG_VIDEO => self.parse_video(timestamp, &body),
+                _ => None,
+            };
+            if packet.is_some() {
+                return packet;
+            }
+        }
+    }
+}
+
+// writes a live FLV stream, timestamps start at zero from the first frame written
+// and carry on from the last one written across a discontinuity
+pub struct FlvMuxer {
+    audio_config: Option<CodecConfig>,
+    video_config: Option<CodecConfig>,
+    base_dts: Option<i64>,
+    last_dts: u64,
+}
+
+impl Default for FlvMuxer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FlvMuxer {
+    pub fn new() -> Self {
+        FlvMuxer {
+            audio_config: None,
+            video_config: None,
+            base_dts: None,
+            last_dts: 0,
+        }
+    }
+
+    // file header and PreviousTagSize0
+    pub fn header(&self, has_audio: bool, has_video: bool) -> Vec<u8> {
+        let flags = ((has_audio as u8) << 2) | has_video as u8;
+        vec![b'F', b'L', b'V', 1, flags, 0, 0, 0, 9, 0, 0, 0, 0]
+    }
+
+    // returns the sequence header tag when the config is new or changed
+    pub fn set_codec_config(&mut self, config: &CodecConfig) -> Option<Vec<u8>> {
+        let (current, tag_type, mut body) = match config.codec {
+            CodecType::H264 => (&mut self.video_config, TAG_VIDEO, vec![0x17, 0, 0, 0, 0]),
+            CodecType::AAC => (&mut self.audio_config, TAG_AUDIO, vec![AAC_SOUND_HEADER, 0]),
+            _ => {
+                warn!("unsupported flv codec: {:?}", config.codec);
+                return None;
+            }
+        };
+        if current.as_ref() == Some(config) {
+            return None;
+        }
+        *current = Some(config.clone());
+        body.extend_from_slice(&config.extra_data);
+        Some(tag(tag_type, self.last_dts, &body))
+    }
+
+    pub fn has_config(&self, media_type: MediaType) -> bool {
+        match media_type {
+            MediaType::AUDIO => self.audio_config.is_some(),
+            MediaType::VIDEO => self.video_config.is_some(),
+            MediaType::AV => self.audio_config.is_some() || self.video_config.is_some(),
+            // no flv tag for the other tracks
+            _ => false,
+        }
+    }
+
+    // None for frames of a track without a sequence header yet
+    pub fn push(&mut self, data: &MediaData) -> Option<Vec<u8>> {
+        if !self.has_config(data.media_type) {
+            return None;
+        }
+        if data.discontinuity && self.base_dts.is_some() {
+            self.base_dts = Some(data.dts as i64 - self.last_dts as i64);
+        }
+        let base_dts = *self.base_dts.get_or_insert(data.dts as i64);
+        let dts = (data.dts as i64 - base_dts).max(0) as u64;
+        self.last_dts = dts;
+        let payload = data.payload();
+        match data.media_type {
+            MediaType::AUDIO => {
+                let mut body = vec![AAC_SOUND_HEADER, 1];
+                body.extend_from_slice(&payload);
+                Some(tag(TAG_AUDIO, dts, &body))
+            }
+            MediaType::VIDEO => {
+                let frame_type = if data.key_frame { 0x10 } else { 0x20 };
+                let composition_time = (data.pts as i64 - data.dts as i64) as i32;
+                let mut body = vec![frame_type | VIDEO_CODEC_AVC, 1];
+                body.extend_from_slice(&composition_time.to_be_bytes()[1..]);
+                body.extend_from_slice(&payload);
+                Some(tag(TAG_VIDEO, dts, &body))
+            }
+            _ => None,
+        }
+    }
+}
+
+// a tag followed by its PreviousTagSize
+fn tag(tag_type: u8, timestamp: u64, body: &[u8]) -> Vec<u8> {
+    let timestamp = (timestamp as u32).to_be_bytes();
+    let size = (body.len() as u32).to_be_bytes();
+    let mut out = vec![tag_type, size[1], size[2], size[3]];
+    out.extend_from_slice(&[timestamp[1], timestamp[2], timestamp[3], timestamp[0]]);
+    out.extend_from_slice(&[0, 0, 0]);
+    out.extend_from_slice(body);
+    out.extend_from_slice(&(body.len() as u32 + 11).to_be_bytes());
+    out
+}
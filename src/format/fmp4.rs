@@ -0,0 +1,583 @@
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::{CodecConfig, CodecType},
+};
+use crate::{debug, error, info, warn};
+use std::{
+    io::Write,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+// all timestamps are carried in milliseconds
+const TIMESCALE: u32 = 1000;
+const MATRIX: [u32; 9] = [0x00010000, 0, 0, 0, 0x00010000, 0, 0, 0, 0x40000000];
+
+const SAMPLE_FLAGS_SYNC: u32 = 0x02000000;
+const SAMPLE_FLAGS_NON_SYNC: u32 = 0x01010000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FragmentMode {
+    // cut a fragment at every video key frame
+    GOP,
+    // cut a fragment once it spans the given duration in milliseconds
+    DURATION(u64),
+}
+
+#[derive(Debug)]
+struct Sample {
+    dts: u64,
+    pts: u64,
+    duration: u32,
+    key_frame: bool,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+struct Track {
+    track_id: u32,
+    config: CodecConfig,
+    samples: Vec<Sample>,
+    last_duration: u32,
+}
+
+impl Track {
+    fn default_duration(&self) -> u32 {
+        match self.config.codec {
+            CodecType::AAC if self.config.sample_rate != 0 => {
+                1024 * TIMESCALE / self.config.sample_rate
+            }
+            CodecType::OPUS => 20,
+            _ => 40,
+        }
+    }
+
+    fn push(&mut self, sample: Sample) {
+        if let Some(last) = self.samples.last_mut() {
+            if sample.dts > last.dts {
+                last.duration = (sample.dts - last.dts) as u32;
+                self.last_duration = last.duration;
+            }
+        }
+        self.samples.push(sample);
+    }
+
+    fn close(&mut self) {
+        let duration = if self.last_duration != 0 {
+            self.last_duration
+        } else {
+            self.default_duration()
+        };
+        if let Some(last) = self.samples.last_mut() {
+            if last.duration == 0 {
+                last.duration = duration;
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Fmp4Muxer {
+    mode: FragmentMode,
+    sequence: u32,
+    tracks: Vec<Track>,
+    fragment_start: Option<u64>,
+}
+
+impl Fmp4Muxer {
+    pub fn new(mode: FragmentMode) -> Self {
+        Fmp4Muxer {
+            mode,
+            sequence: 0,
+            tracks: Vec::new(),
+            fragment_start: None,
+        }
+    }
+
+    pub fn add_track(&mut self, config: CodecConfig) -> bool {
+        if config.media_type() == MediaType::AV || self.track_index(config.media_type()).is_some() {
+            error!("can not add track: {:?}", config.codec);
+            return false;
+        }
+        self.tracks.push(Track {
+            track_id: self.tracks.len() as u32 + 1,
+            config,
+            samples: Vec::new(),
+            last_duration: 0,
+        });
+        true
+    }
+
+    pub fn has_track(&self, media_type: MediaType) -> bool {
+        self.track_index(media_type).is_some()
+    }
+
+    pub fn init_segment(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+
+        let mut ftyp = Vec::new();
+        ftyp.extend_from_slice(b"iso6");
+        ftyp.extend_from_slice(&0u32.to_be_bytes());
+        for brand in [b"iso6", b"cmfc", b"isom", b"mp41"] {
+            ftyp.extend_from_slice(brand);
+        }
+        write_box(&mut out, b"ftyp", &ftyp);
+
+        let mut moov = Vec::new();
+        write_box(&mut moov, b"mvhd", &self.mvhd());
+        for track in self.tracks.iter() {
+            write_box(&mut moov, b"trak", &trak(track));
+        }
+        let mut mvex = Vec::new();
+        for track in self.tracks.iter() {
+            let mut trex = Vec::new();
+            trex.extend_from_slice(&track.track_id.to_be_bytes());
+            trex.extend_from_slice(&1u32.to_be_bytes());
+            trex.extend_from_slice(&[0u8; 12]);
+            write_full_box(&mut mvex, b"trex", 0, 0, &trex);
+        }
+        write_box(&mut moov, b"mvex", &mvex);
+        write_box(&mut out, b"moov", &moov);
+        out
+    }
+
+    // queue a frame, returns the previous fragment when this frame starts a new one
+    pub fn push(&mut self, data: &MediaData) -> Option<Vec<u8>> {
+        let index = match self.track_index(data.media_type) {
+            Some(index) => index,
+            None => {
+                warn!("no track for {:?}, frame dropped", data.media_type);
+                return None;
+            }
+        };
+
+        let mut fragment = None;
+        if self.should_cut(data) {
+            fragment = self.flush();
+        }
+        if self.fragment_start.is_none() {
+            self.fragment_start = Some(data.dts);
+        }
+
+        self.tracks[index].push(Sample {
+            dts: data.dts,
+            pts: data.pts,
+            duration: 0,
+            key_frame: data.key_frame,
+            data: data.payload(),
+        });
+        fragment
+    }
+
+    pub fn flush(&mut self) -> Option<Vec<u8>> {
+        if self.tracks.iter().all(|track| track.samples.is_empty()) {
+            return None;
+        }
+        for track in self.tracks.iter_mut() {
+            track.close();
+        }
+
+        self.sequence += 1;
+        // the moof size does not depend on the offsets, so build it twice
+        let moof_len = self.moof(0).len();
+        let moof = self.moof(moof_len as u32 + 8);
+
+        let mut mdat = Vec::new();
+        for track in self.tracks.iter_mut() {
+            for sample in track.samples.drain(..) {
+                mdat.extend_from_slice(&sample.data);
+            }
+        }
+
+        let mut out = moof;
+        write_box(&mut out, b"mdat", &mdat);
+        self.fragment_start = None;
+        debug!("fragment {} done, size: {}", self.sequence, out.len());
+        Some(out)
+    }
+
+    fn should_cut(&self, data: &MediaData) -> bool {
+        let start = match self.fragment_start {
+            Some(start) => start,
+            None => return false,
+        };
+        match self.mode {
+            FragmentMode::GOP if self.has_track(MediaType::VIDEO) => {
+                data.media_type == MediaType::VIDEO && data.key_frame
+            }
+            FragmentMode::GOP => data.dts >= start + TIMESCALE as u64,
+            FragmentMode::DURATION(duration) => data.dts >= start + duration,
+        }
+    }
+
+    fn track_index(&self, media_type: MediaType) -> Option<usize> {
+        self.tracks
+            .iter()
+            .position(|track| track.config.media_type() == media_type)
+    }
+
+    fn mvhd(&self) -> Vec<u8> {
+        let mut body = Vec::new();
+        body.extend_from_slice(&[0u8; 8]);
+        body.extend_from_slice(&TIMESCALE.to_be_bytes());
+        body.extend_from_slice(&0u32.to_be_bytes());
+        body.extend_from_slice(&0x00010000u32.to_be_bytes());
+        body.extend_from_slice(&0x0100u16.to_be_bytes());
+        body.extend_from_slice(&[0u8; 10]);
+        for value in MATRIX {
+            body.extend_from_slice(&value.to_be_bytes());
+        }
+        body.extend_from_slice(&[0u8; 24]);
+        body.extend_from_slice(&(self.tracks.len() as u32 + 1).to_be_bytes());
+        full_box_body(0, 0, &body)
+    }
+
+    fn moof(&self, mdat_offset: u32) -> Vec<u8> {
+        let mut moof = Vec::new();
+        write_full_box(&mut moof, b"mfhd", 0, 0, &self.sequence.to_be_bytes());
+
+        let mut data_offset = mdat_offset;
+        for track in self.tracks.iter().filter(|track| !track.samples.is_empty()) {
+            let mut traf = Vec::new();
+            // default-base-is-moof
+            write_full_box(
+                &mut traf,
+                b"tfhd",
+                0,
+                0x020000,
+                &track.track_id.to_be_bytes(),
+            );
+            write_full_box(
+                &mut traf,
+                b"tfdt",
+                1,
+                0,
+                &track.samples[0].dts.to_be_bytes(),
+            );
+
+            let mut trun = Vec::new();
+            trun.extend_from_slice(&(track.samples.len() as u32).to_be_bytes());
+            trun.extend_from_slice(&data_offset.to_be_bytes());
+            for sample in track.samples.iter() {
+                let flags = if sample.key_frame || track.config.media_type() == MediaType::AUDIO {
+                    SAMPLE_FLAGS_SYNC
+                } else {
+                    SAMPLE_FLAGS_NON_SYNC
+                };
+                trun.extend_from_slice(&sample.duration.to_be_bytes());
+                trun.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+                trun.extend_from_slice(&flags.to_be_bytes());
+                trun.extend_from_slice(
+                    &((sample.pts as i64 - sample.dts as i64) as i32).to_be_bytes(),
+                );
+                data_offset += sample.data.len() as u32;
+            }
+            // data-offset, duration, size, flags and composition offset present
+            write_full_box(&mut traf, b"trun", 1, 0x000f01, &trun);
+            write_box(&mut moof, b"traf", &traf);
+        }
+
+        let mut out = Vec::new();
+        write_box(&mut out, b"moof", &moof);
+        out
+    }
+}
+
+fn trak(track: &Track) -> Vec<u8> {
+    let config = &track.config;
+    let video = config.media_type() == MediaType::VIDEO;
+    let mut trak = Vec::new();
+
+    let mut tkhd = Vec::new();
+    tkhd.extend_from_slice(&[0u8; 8]);
+    tkhd.extend_from_slice(&track.track_id.to_be_bytes());
+    // reserved, duration, reserved, layer and alternate group
+    tkhd.extend_from_slice(&[0u8; 20]);
+    tkhd.extend_from_slice(&(if video { 0u16 } else { 0x0100u16 }).to_be_bytes());
+    tkhd.extend_from_slice(&[0u8; 2]);
+    for value in MATRIX {
+        tkhd.extend_from_slice(&value.to_be_bytes());
+    }
+    tkhd.extend_from_slice(&(config.width << 16).to_be_bytes());
+    tkhd.extend_from_slice(&(config.height << 16).to_be_bytes());
+    // enabled, in movie, in preview
+    write_full_box(&mut trak, b"tkhd", 0, 0x000003, &tkhd);
+
+    let mut mdia = Vec::new();
+    let mut mdhd = Vec::new();
+    mdhd.extend_from_slice(&[0u8; 8]);
+    mdhd.extend_from_slice(&TIMESCALE.to_be_bytes());
+    mdhd.extend_from_slice(&0u32.to_be_bytes());
+    // language "und"
+    mdhd.extend_from_slice(&0x55c4u16.to_be_bytes());
+    mdhd.extend_from_slice(&[0u8; 2]);
+    write_full_box(&mut mdia, b"mdhd", 0, 0, &mdhd);
+
+    let mut hdlr = vec![0u8; 4];
+    hdlr.extend_from_slice(if video { b"vide" } else { b"soun" });
+    hdlr.extend_from_slice(&[0u8; 12]);
+    hdlr.extend_from_slice(if video {
+        b"VideoHandler\0"
+    } else {
+        b"SoundHandler\0"
+    });
+    write_full_box(&mut mdia, b"hdlr", 0, 0, &hdlr);
+
+    let mut minf = Vec::new();
+    if video {
+        write_full_box(&mut minf, b"vmhd", 0, 1, &[0u8; 8]);
+    } else {
+        write_full_box(&mut minf, b"smhd", 0, 0, &[0u8; 4]);
+    }
+    let mut dref = 1u32.to_be_bytes().to_vec();
+    write_full_box(&mut dref, b"url ", 0, 1, &[]);
+    let mut dinf = Vec::new();
+    write_full_box(&mut dinf, b"dref", 0, 0, &dref);
+    write_box(&mut minf, b"dinf", &dinf);
+
+    let mut stbl = Vec::new();
+    let mut stsd = 1u32.to_be_bytes().to_vec();
+    sample_entry(&mut stsd, config);
+    write_full_box(&mut stbl, b"stsd", 0, 0, &stsd);
+    write_full_box(&mut stbl, b"stts", 0, 0, &[0u8; 4]);
+    write_full_box(&mut stbl, b"stsc", 0, 0, &[0u8; 4]);
+    write_full_box(&mut stbl, b"stsz", 0, 0, &[0u8; 8]);
+    write_full_box(&mut stbl, b"stco", 0, 0, &[0u8; 4]);
+    write_box(&mut minf, b"stbl", &stbl);
+    write_box(&mut mdia, b"minf", &minf);
+    write_box(&mut trak, b"mdia", &mdia);
+    trak
+}
+
+fn sample_entry(out: &mut Vec<u8>, config: &CodecConfig) {
+    let mut entry = vec![0u8; 6];
+    entry.extend_from_slice(&1u16.to_be_bytes());
+
+    match config.codec {
+        CodecType::H264 | CodecType::H265 => {
+            entry.extend_from_slice(&[0u8; 16]);
+            entry.extend_from_slice(&(config.width as u16).to_be_bytes());
+            entry.extend_from_slice(&(config.height as u16).to_be_bytes());
+            entry.extend_from_slice(&0x00480000u32.to_be_bytes());
+            entry.extend_from_slice(&0x00480000u32.to_be_bytes());
+            entry.extend_from_slice(&0u32.to_be_bytes());
+            entry.extend_from_slice(&1u16.to_be_bytes());
+            entry.extend_from_slice(&[0u8; 32]);
+            entry.extend_from_slice(&0x0018u16.to_be_bytes());
+            entry.extend_from_slice(&0xffffu16.to_be_bytes());
+            if config.codec == CodecType::H264 {
+                write_box(&mut entry, b"avcC", &config.extra_data);
+                write_box(out, b"avc1", &entry);
+            } else {
+                write_box(&mut entry, b"hvcC", &config.extra_data);
+                write_box(out, b"hvc1", &entry);
+            }
+        }
+        CodecType::AAC | CodecType::OPUS => {
+            let sample_rate = if config.codec == CodecType::OPUS {
+                48000
+            } else {
+                config.sample_rate
+            };
+            entry.extend_from_slice(&[0u8; 8]);
+            entry.extend_from_slice(&(config.channels as u16).to_be_bytes());
+            entry.extend_from_slice(&16u16.to_be_bytes());
+            entry.extend_from_slice(&[0u8; 4]);
+            entry.extend_from_slice(&(sample_rate << 16).to_be_bytes());
+            if config.codec == CodecType::AAC {
+                write_full_box(&mut entry, b"esds", 0, 0, &esds(config));
+                write_box(out, b"mp4a", &entry);
+            } else {
+                write_box(&mut entry, b"dOps", &dops(config));
+                write_box(out, b"Opus", &entry);
+            }
+        }
+        CodecType::UNKNOWN => error!("unknown codec"),
+    }
+}
+
+fn esds(config: &CodecConfig) -> Vec<u8> {
+    let mut decoder_specific = Vec::new();
+    descriptor(&mut decoder_specific, 0x05, &config.extra_data);
+
+    let mut decoder_config = vec![0x40, 0x15, 0, 0, 0];
+    decoder_config.extend_from_slice(&[0u8; 8]);
+    decoder_config.extend_from_slice(&decoder_specific);
+    let mut es = vec![0, 1, 0];
+    descriptor(&mut es, 0x04, &decoder_config);
+    descriptor(&mut es, 0x06, &[0x02]);
+
+    let mut out = Vec::new();
+    descriptor(&mut out, 0x03, &es);
+    out
+}
+
+fn dops(config: &CodecConfig) -> Vec<u8> {
+    // take pre-skip and input rate from an OpusHead when there is one
+    let head = &config.extra_data;
+    let (pre_skip, input_rate) = if head.len() >= 16 && head.starts_with(b"OpusHead") {
+        (
+            u16::from_le_bytes([head[10], head[11]]),
+            u32::from_le_bytes([head[12], head[13], head[14], head[15]]),
+        )
+    } else {
+        (0, config.sample_rate)
+    };
+    let mut out = vec![0, config.channels as u8];
+    out.extend_from_slice(&pre_skip.to_be_bytes());
+    out.extend_from_slice(&input_rate.to_be_bytes());
+    out.extend_from_slice(&0i16.to_be_bytes());
+    out.push(0);
+    out
+}
+
+fn descriptor(out: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    out.push(tag);
+    let len = body.len() as u32;
+    out.extend_from_slice(&[
+        0x80 | (len >> 21) as u8 & 0x7f,
+        0x80 | (len >> 14) as u8 & 0x7f,
+        0x80 | (len >> 7) as u8 & 0x7f,
+        len as u8 & 0x7f,
+    ]);
+    out.extend_from_slice(body);
+}
+
+fn full_box_body(version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut out = ((version as u32) << 24 | flags & 0x00ffffff)
+        .to_be_bytes()
+        .to_vec();
+    out.extend_from_slice(body);
+    out
+}
+
+fn write_full_box(out: &mut Vec<u8>, fourcc: &[u8; 4], version: u8, flags: u32, body: &[u8]) {
+    write_box(out, fourcc, &full_box_body(version, flags, body));
+}
+
+fn write_box(out: &mut Vec<u8>, fourcc: &[u8; 4], body: &[u8]) {
+    out.extend_from_slice(&(body.len() as u32 + 8).to_be_bytes());
+    out.extend_from_slice(fourcc);
+    out.extend_from_slice(body);
+}
+
+pub struct Fmp4Writer {
+    receiver: Arc<Receiver>,
+    mode: FragmentMode,
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+    write_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl Fmp4Writer {
+    pub fn new(
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        output: Box<dyn Write + Send>,
+        mode: FragmentMode,
+    ) -> Self {
+        let receiver = Arc::new(Receiver::new());
+        dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+        receiver.set_dispatcher(dispatcher.clone());
+        Self::with_receiver(receiver, output, mode)
+    }
+
+    // writes what an already attached receiver reads, for one set up with its own
+    // key mode, tracks or start position
+    pub fn with_receiver(
+        receiver: Arc<Receiver>,
+        output: Box<dyn Write + Send>,
+        mode: FragmentMode,
+    ) -> Self {
+        Fmp4Writer {
+            receiver,
+            mode,
+            output: Arc::new(Mutex::new(output)),
+            write_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    pub fn start_write(&mut self) {
+        info!("start fmp4 write");
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let receiver = self.receiver.clone();
+        let output = self.output.clone();
+        let mode = self.mode;
+
+        self.write_thread = Some(thread::spawn(move || {
+            let mut muxer: Option<Fmp4Muxer> = None;
+            // the tracks read before their codec config came, as track bits
+            let mut unconfigured = 0;
+            while *running.lock().unwrap() {
+                let (ret, out_data) = receiver.request_read(MediaType::AV);
+                if !receiver.is_attached() {
+                    info!("fmp4 receiver detached");
+                    break;
+                }
+                if !ret || !*running.lock().unwrap() {
+                    continue;
+                }
+                let binding = out_data.unwrap();
+                let data = binding.lock().unwrap();
+
+                if muxer.is_none() {
+                    // the init segment fixes the tracks, so it waits for the config of
+                    // every track read so far and of video unless the input has none
+                    if receiver.get_codec_config(data.media_type).is_none() {
+                        unconfigured |= data.media_type.track_bit();
+                    }
+                    let video_expected = receiver.is_subscribed(MediaType::VIDEO)
+                        && receiver.data_mode() != Some(MediaType::AUDIO);
+                    let mut new_muxer = Fmp4Muxer::new(mode);
+                    let mut missing = 0;
+                    for media_type in [MediaType::VIDEO, MediaType::AUDIO] {
+                        match receiver.get_codec_config(media_type) {
+                            Some(config) => {
+                                new_muxer.add_track(config);
+                            }
+                            None => missing |= media_type.track_bit(),
+                        }
+                    }
+                    if video_expected {
+                        unconfigured |= MediaType::VIDEO.track_bit();
+                    }
+                    if new_muxer.tracks.is_empty() || missing & unconfigured != 0 {
+                        debug!("no codec config yet, {:?} frame dropped", data.media_type);
+                        continue;
+                    }
+                    write_segment(&output, &new_muxer.init_segment());
+                    muxer = Some(new_muxer);
+                }
+
+                if let Some(fragment) = muxer.as_mut().unwrap().push(&data) {
+                    write_segment(&output, &fragment);
+                }
+            }
+
+            if let Some(fragment) = muxer.as_mut().and_then(|muxer| muxer.flush()) {
+                write_segment(&output, &fragment);
+            }
+            info!("fmp4 write done");
+        }));
+    }
+
+    pub fn stop_write(&mut self) {
+        debug!("stop fmp4 write begin");
+        *self.running.lock().unwrap() = false;
+        self.receiver.notify_read_stop();
+        if let Some(write_thread) = self.write_thread.take() {
+            write_thread.join().expect("can not join the write thread");
+        }
+        self.receiver.detach();
+        debug!("stop fmp4 write end");
+    }
+}
+
+fn write_segment(output: &Arc<Mutex<Box<dyn Write + Send>>>, segment: &[u8]) {
+    let mut output = output.lock().unwrap();
+    if let Err(e) = output.write_all(segment).and_then(|_| output.flush()) {
+        error!("write segment failed: {}", e);
+    }
+}
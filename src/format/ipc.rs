@@ -0,0 +1,167 @@
+use super::{media_data, Packet};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::{CodecConfig, CodecType},
+};
+use std::io::{self, Read, Write};
+
+// frames and codec configs exchanged between processes on the same host.
+// every message is a u32 BE length followed by the body:
+//   data:   kind 0, media type, flags (bit 0 key frame, bit 1 discontinuity), pts u64,
+//           dts u64, track id u32, payload
+//   config: kind 1, codec, width u32, height u32, sample rate u32, channels u32, extra data
+// version 1 had no track id
+pub const FORMAT_VERSION: u8 = 2;
+pub const KIND_DATA: u8 = 0;
+pub const KIND_CONFIG: u8 = 1;
+pub const DATA_HEADER_SIZE: usize = 23;
+pub const CONFIG_HEADER_SIZE: usize = 18;
+pub const MAX_MESSAGE_SIZE: usize = 64 << 20;
+
+const FLAG_KEY_FRAME: u8 = 0x01;
+const FLAG_DISCONTINUITY: u8 = 0x02;
+
+pub fn media_type_code(media_type: MediaType) -> u8 {
+    match media_type {
+        MediaType::AV => 0,
+        MediaType::AUDIO => 1,
+        MediaType::VIDEO => 2,
+        MediaType::SUBTITLE => 3,
+        MediaType::METADATA => 4,
+        MediaType::DATA => 5,
+    }
+}
+
+pub fn media_type_from_code(code: u8) -> Option<MediaType> {
+    match code {
+        0 => Some(MediaType::AV),
+        1 => Some(MediaType::AUDIO),
+        2 => Some(MediaType::VIDEO),
+        3 => Some(MediaType::SUBTITLE),
+        4 => Some(MediaType::METADATA),
+        5 => Some(MediaType::DATA),
+        _ => None,
+    }
+}
+
+fn codec_code(codec: CodecType) -> u8 {
+    match codec {
+        CodecType::UNKNOWN => 0,
+        CodecType::H264 => 1,
+        CodecType::H265 => 2,
+        CodecType::AAC => 3,
+        CodecType::OPUS => 4,
+    }
+}
+
+fn codec_from_code(code: u8) -> Option<CodecType> {
+    match code {
+        0 => Some(CodecType::UNKNOWN),
+        1 => Some(CodecType::H264),
+        2 => Some(CodecType::H265),
+        3 => Some(CodecType::AAC),
+        4 => Some(CodecType::OPUS),
+        _ => None,
+    }
+}
+
+// the fixed part of a data message, the payload follows it
+pub fn data_header(data: &MediaData) -> Vec<u8> {
+    let mut out = Vec::with_capacity(DATA_HEADER_SIZE);
+    out.push(KIND_DATA);
+    out.push(media_type_code(data.media_type));
+    let mut flags = 0;
+    if data.key_frame {
+        flags |= FLAG_KEY_FRAME;
+    }
+    if data.discontinuity {
+        flags |= FLAG_DISCONTINUITY;
+    }
+    out.push(flags);
+    out.extend_from_slice(&data.pts.to_be_bytes());
+    out.extend_from_slice(&data.dts.to_be_bytes());
+    out.extend_from_slice(&data.track_id.to_be_bytes());
+    out
+}
+
+pub fn encode_data(data: &MediaData) -> Vec<u8> {
+    let payload = data.payload();
+    let mut out = data_header(data);
+    out.extend_from_slice(&payload);
+    out
+}
+
+pub fn encode_config(config: &CodecConfig) -> Vec<u8> {
+    let mut out = Vec::with_capacity(CONFIG_HEADER_SIZE + config.extra_data.len());
+    out.push(KIND_CONFIG);
+    out.push(codec_code(config.codec));
+    for value in [
+        config.width,
+        config.height,
+        config.sample_rate,
+        config.channels,
+    ] {
+        out.extend_from_slice(&value.to_be_bytes());
+    }
+    out.extend_from_slice(&config.extra_data);
+    out
+}
+
+pub fn decode(body: &[u8]) -> Option<Packet> {
+    match *body.first()? {
+        KIND_DATA if body.len() >= DATA_HEADER_SIZE => {
+            let mut data = media_data(
+                media_type_from_code(body[1])?,
+                u64::from_be_bytes(body[3..11].try_into().ok()?),
+                u64::from_be_bytes(body[11..19].try_into().ok()?),
+                body[2] & FLAG_KEY_FRAME != 0,
+                body[DATA_HEADER_SIZE..].to_vec(),
+            );
+            data.discontinuity = body[2] & FLAG_DISCONTINUITY != 0;
+            data.track_id = u32::from_be_bytes(body[19..23].try_into().ok()?);
+            Some(Packet::DATA(data))
+        }
+        KIND_CONFIG if body.len() >= CONFIG_HEADER_SIZE => {
+            let field =
+                |i: usize| u32::from_be_bytes(body[2 + i * 4..6 + i * 4].try_into().unwrap());
+            Some(Packet::CONFIG(CodecConfig {
+                codec: codec_from_code(body[1])?,
+                width: field(0),
+                height: field(1),
+                sample_rate: field(2),
+                channels: field(3),
+                extra_data: body[CONFIG_HEADER_SIZE..].to_vec(),
+            }))
+        }
+        _ => None,
+    }
+}
+
+pub fn write_message(output: &mut impl Write, body: &[u8]) -> io::Result<()> {
+    output.write_all(&(body.len() as u32).to_be_bytes())?;
+    output.write_all(body)
+}
+
+// writes a data message without copying the payload into a body first
+pub fn write_data(output: &mut impl Write, data: &MediaData) -> io::Result<()> {
+    let buff = data.buff.lock().unwrap().data();
+    let payload = buff.lock().unwrap();
+    output.write_all(&((DATA_HEADER_SIZE + payload.len()) as u32).to_be_bytes())?;
+    output.write_all(&data_header(data))?;
+    output.write_all(&payload)
+}
+
+pub fn read_message(input: &mut impl Read) -> io::Result<Packet> {
+    let mut len = [0u8; 4];
+    input.read_exact(&mut len)?;
+    let len = u32::from_be_bytes(len) as usize;
+    if len > MAX_MESSAGE_SIZE {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    let mut body = vec![0u8; len];
+    input.read_exact(&mut body)?;
+    decode(&body).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "invalid message"))
+}
@@ -0,0 +1,40 @@
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::CodecConfig,
+};
+
+pub mod es;
+pub mod flv;
+pub mod fmp4;
+pub mod ipc;
+pub mod rtp;
+pub mod ts;
+
+#[derive(Debug)]
+pub enum Packet {
+    CONFIG(CodecConfig),
+    DATA(MediaData),
+}
+
+pub trait Demuxer: Send {
+    // returns None at the end of the stream
+    fn read_packet(&mut self) -> Option<Packet>;
+}
+
+pub(crate) fn media_data(
+    media_type: MediaType,
+    pts: u64,
+    dts: u64,
+    key_frame: bool,
+    payload: Vec<u8>,
+) -> MediaData {
+    let media_data = MediaData {
+        key_frame,
+        pts,
+        dts,
+        media_type,
+        ..Default::default()
+    };
+    media_data.buff.lock().unwrap().replace(payload);
+    media_data
+}
@@ -0,0 +1,462 @@
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::{avcc_parameter_sets, split_avcc, CodecConfig, CodecType, H264_NAL_AUD, H264_NAL_SPS},
+};
+use crate::{debug, error, info, warn};
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    io,
+    net::{SocketAddr, UdpSocket},
+    process,
+    sync::{Arc, Condvar, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+pub const RTP_HEADER_SIZE: usize = 12;
+pub const DEFAULT_MTU: usize = 1400;
+pub const VIDEO_PAYLOAD_TYPE: u8 = 96;
+pub const AUDIO_PAYLOAD_TYPE: u8 = 97;
+
+const FU_A: u8 = 28;
+// the 13 bit size field of an AAC-hbr AU header
+const AAC_MAX_AU_SIZE: usize = 0x1fff;
+const RTCP_SR: u8 = 200;
+const RTCP_SDES: u8 = 202;
+// seconds between 1900-01-01 and 1970-01-01
+const NTP_UNIX_OFFSET: u64 = 2208988800;
+
+pub struct RtpPacketizer {
+    codec: CodecType,
+    payload_type: u8,
+    ssrc: u32,
+    clock_rate: u32,
+    mtu: usize,
+    sequence: u16,
+    packet_count: u32,
+    octet_count: u32,
+    // random start of the rtp timestamps, RFC 3550 5.1
+    timestamp_offset: u32,
+    last_timestamp: u32,
+    // SPS and PPS sent ahead of key frames that do not carry their own
+    parameter_sets: Vec<Vec<u8>>,
+}
+
+impl RtpPacketizer {
+    pub fn new(config: &CodecConfig, payload_type: u8, ssrc: u32) -> Self {
+        RtpPacketizer {
+            codec: config.codec,
+            payload_type,
+            ssrc,
+            clock_rate: clock_rate(config),
+            mtu: DEFAULT_MTU,
+            sequence: random_u32() as u16,
+            packet_count: 0,
+            octet_count: 0,
+            timestamp_offset: random_u32(),
+            last_timestamp: 0,
+            parameter_sets: match config.codec {
+                CodecType::H264 => avcc_parameter_sets(&config.extra_data)
+                    .into_iter()
+                    .map(<[u8]>::to_vec)
+                    .collect(),
+                _ => Vec::new(),
+            },
+        }
+    }
+
+    pub fn set_mtu(&mut self, mtu: usize) {
+        // room for the header and the FU-A/AU headers
+        self.mtu = mtu.max(RTP_HEADER_SIZE + 8);
+    }
+
+    pub fn ssrc(&self) -> u32 {
+        self.ssrc
+    }
+
+    pub fn clock_rate(&self) -> u32 {
+        self.clock_rate
+    }
+
+    pub fn timestamp_offset(&self) -> u32 {
+        self.timestamp_offset
+    }
+
+    pub fn last_timestamp(&self) -> u32 {
+        self.last_timestamp
+    }
+
+    pub fn packetize(&mut self, data: &MediaData) -> Vec<Vec<u8>> {
+        let timestamp =
+            ((data.pts * self.clock_rate as u64 / 1000) as u32).wrapping_add(self.timestamp_offset);
+        self.last_timestamp = timestamp;
+        let payload = data.payload();
+        let payloads = match self.codec {
+            CodecType::H264 => {
+                let mut nalus = split_avcc(&payload);
+                let has_sps = nalus
+                    .iter()
+                    .any(|nalu| nalu.first().is_some_and(|byte| byte & 0x1f == H264_NAL_SPS));
+                if data.key_frame && !has_sps {
+                    // after an access unit delimiter, which has to come first
+                    let at = nalus
+                        .iter()
+                        .position(|nalu| {
+                            nalu.first().is_none_or(|byte| byte & 0x1f != H264_NAL_AUD)
+                        })
+                        .unwrap_or(nalus.len());
+                    nalus.splice(at..at, self.parameter_sets.iter().map(Vec::as_slice));
+                }
+                self.h264_payloads(&nalus)
+            }
+            CodecType::AAC => self.aac_payloads(&payload),
+            CodecType::OPUS => vec![(payload, true)],
+            _ => {
+                warn!("unsupported rtp codec: {:?}", self.codec);
+                Vec::new()
+            }
+        };
+
+        payloads
+            .into_iter()
+            .map(|(payload, marker)| self.packet(timestamp, marker, &payload))
+            .collect()
+    }
+
+    // RFC 3550 sender report followed by a CNAME item, `ntp` is the NTP time of `timestamp`
+    pub fn sender_report(&self, ntp: u64, timestamp: u32, cname: &str) -> Vec<u8> {
+        let mut out = vec![0x80, RTCP_SR, 0, 6];
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(&ntp.to_be_bytes());
+        out.extend_from_slice(&timestamp.to_be_bytes());
+        out.extend_from_slice(&self.packet_count.to_be_bytes());
+        out.extend_from_slice(&self.octet_count.to_be_bytes());
+
+        let cname = &cname.as_bytes()[..cname.len().min(255)];
+        let mut chunk = self.ssrc.to_be_bytes().to_vec();
+        chunk.extend_from_slice(&[1, cname.len() as u8]);
+        chunk.extend_from_slice(cname);
+        // item list end, padded to a 32-bit boundary
+        chunk.push(0);
+        while !chunk.len().is_multiple_of(4) {
+            chunk.push(0);
+        }
+        out.extend_from_slice(&[0x81, RTCP_SDES]);
+        out.extend_from_slice(&(chunk.len() as u16 / 4).to_be_bytes());
+        out.extend_from_slice(&chunk);
+        out
+    }
+
+    fn packet(&mut self, timestamp: u32, marker: bool, payload: &[u8]) -> Vec<u8> {
+        let mut out = Vec::with_capacity(RTP_HEADER_SIZE + payload.len());
+        out.push(0x80);
+        out.push(((marker as u8) << 7) | (self.payload_type & 0x7f));
+        out.extend_from_slice(&self.sequence.to_be_bytes());
+        out.extend_from_slice(&timestamp.to_be_bytes());
+        out.extend_from_slice(&self.ssrc.to_be_bytes());
+        out.extend_from_slice(payload);
+
+        self.sequence = self.sequence.wrapping_add(1);
+        self.packet_count = self.packet_count.wrapping_add(1);
+        self.octet_count = self.octet_count.wrapping_add(payload.len() as u32);
+        out
+    }
+
+    // RFC 6184 single NAL unit packets, FU-A for NAL units over the MTU
+    fn h264_payloads(&self, nalus: &[&[u8]]) -> Vec<(Vec<u8>, bool)> {
+        let max = self.mtu - RTP_HEADER_SIZE;
+        let mut out = Vec::new();
+        for (i, nalu) in nalus.iter().enumerate() {
+            let last_nalu = i + 1 == nalus.len();
+            if nalu.is_empty() {
+                continue;
+            }
+            if nalu.len() <= max {
+                out.push((nalu.to_vec(), last_nalu));
+                continue;
+            }
+
+            let indicator = (nalu[0] & 0xe0) | FU_A;
+            let nal_type = nalu[0] & 0x1f;
+            let chunks: Vec<&[u8]> = nalu[1..].chunks(max - 2).collect();
+            for (j, chunk) in chunks.iter().enumerate() {
+                let start = if j == 0 { 0x80 } else { 0 };
+                let end = if j + 1 == chunks.len() { 0x40 } else { 0 };
+                let mut fragment = vec![indicator, start | end | nal_type];
+                fragment.extend_from_slice(chunk);
+                out.push((fragment, last_nalu && end != 0));
+            }
+        }
+        out
+    }
+
+    // RFC 3640 AAC-hbr, one access unit per packet, fragmented over the MTU
+    fn aac_payloads(&self, payload: &[u8]) -> Vec<(Vec<u8>, bool)> {
+        if payload.len() > AAC_MAX_AU_SIZE {
+            warn!(
+                "aac frame of {} bytes does not fit an au header",
+                payload.len()
+            );
+            return Vec::new();
+        }
+        let max = self.mtu - RTP_HEADER_SIZE - 4;
+        let au_header = ((payload.len() as u16) << 3).to_be_bytes();
+        let chunks: Vec<&[u8]> = payload.chunks(max).collect();
+        chunks
+            .iter()
+            .enumerate()
+            .map(|(i, chunk)| {
+                let mut out = vec![0, 16, au_header[0], au_header[1]];
+                out.extend_from_slice(chunk);
+                (out, i + 1 == chunks.len())
+            })
+            .collect()
+    }
+}
+
+pub fn clock_rate(config: &CodecConfig) -> u32 {
+    match config.codec {
+        CodecType::H264 | CodecType::H265 => 90000,
+        CodecType::OPUS => 48000,
+        CodecType::AAC if config.sample_rate != 0 => config.sample_rate,
+        _ => 90000,
+    }
+}
+
+// 64-bit NTP timestamp of a time since the unix epoch
+pub fn ntp_time(since_epoch: Duration) -> u64 {
+    let seconds = since_epoch.as_secs() + NTP_UNIX_OFFSET;
+    let fraction = ((since_epoch.subsec_nanos() as u64) << 32) / 1_000_000_000;
+    (seconds << 32) | fraction
+}
+
+// the packetizer of a running sender and when it last sent a frame
+struct SendState {
+    packetizer: RtpPacketizer,
+    last_send: Instant,
+}
+
+impl SendState {
+    // the report carries the rtp time of "now", extrapolated from the last frame
+    fn sender_report(&self, cname: &str) -> Vec<u8> {
+        let elapsed = self.last_send.elapsed().as_millis() as u64;
+        let packetizer = &self.packetizer;
+        let timestamp = packetizer
+            .last_timestamp()
+            .wrapping_add((elapsed * packetizer.clock_rate() as u64 / 1000) as u32);
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        packetizer.sender_report(ntp_time(now), timestamp, cname)
+    }
+}
+
+// sends one track of a dispatcher as an RTP session, RTCP goes to the next port up
+pub struct RtpSender {
+    receiver: Arc<Receiver>,
+    media_type: MediaType,
+    socket: Arc<UdpSocket>,
+    rtp_addr: SocketAddr,
+    rtcp_addr: SocketAddr,
+    mtu: usize,
+    report_interval: Duration,
+    send_thread: Option<JoinHandle<()>>,
+    report_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+    // wakes the report thread on the first frame and on stop
+    report_wake: Arc<Condvar>,
+}
+
+impl RtpSender {
+    pub fn new(
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        media_type: MediaType,
+        rtp_addr: SocketAddr,
+    ) -> io::Result<Self> {
+        // one rtp session carries one track with its own codec config
+        if !matches!(media_type, MediaType::VIDEO | MediaType::AUDIO) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("rtp can not send {:?}", media_type),
+            ));
+        }
+        let local: SocketAddr = if rtp_addr.is_ipv4() {
+            "0.0.0.0:0".parse().unwrap()
+        } else {
+            "[::]:0".parse().unwrap()
+        };
+        let mut rtcp_addr = rtp_addr;
+        rtcp_addr.set_port(rtp_addr.port().wrapping_add(1));
+
+        let sender = RtpSender {
+            receiver: Arc::new(Receiver::new()),
+            media_type,
+            socket: Arc::new(UdpSocket::bind(local)?),
+            rtp_addr,
+            rtcp_addr,
+            mtu: DEFAULT_MTU,
+            report_interval: Duration::from_secs(5),
+            send_thread: None,
+            report_thread: None,
+            running: Arc::new(Mutex::new(false)),
+            report_wake: Arc::new(Condvar::new()),
+        };
+
+        dispatcher
+            .lock()
+            .unwrap()
+            .attach_receiver(sender.receiver.clone());
+        sender.receiver.set_dispatcher(dispatcher.clone());
+        Ok(sender)
+    }
+
+    pub fn set_mtu(&mut self, mtu: usize) {
+        self.mtu = mtu;
+    }
+
+    pub fn set_rtcp_addr(&mut self, rtcp_addr: SocketAddr) {
+        self.rtcp_addr = rtcp_addr;
+    }
+
+    pub fn set_report_interval(&mut self, interval: Duration) {
+        self.report_interval = interval;
+    }
+
+    pub fn set_key_mode(&self, enable: bool) {
+        self.receiver.set_key_mode(enable);
+    }
+
+    pub fn start_send(&mut self) {
+        info!("start rtp send to {}", self.rtp_addr);
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let state: Arc<Mutex<Option<SendState>>> = Arc::new(Mutex::new(None));
+        self.start_report(state.clone());
+
+        let receiver = self.receiver.clone();
+        let media_type = self.media_type;
+        let socket = self.socket.clone();
+        let rtp_addr = self.rtp_addr;
+        let mtu = self.mtu;
+        let report_wake = self.report_wake.clone();
+
+        self.send_thread = Some(thread::spawn(move || {
+            while *running.lock().unwrap() {
+                let (ret, out_data) = receiver.request_read(media_type);
+                if !receiver.is_attached() {
+                    info!("rtp receiver detached");
+                    break;
+                }
+                if !ret || !*running.lock().unwrap() {
+                    continue;
+                }
+                let binding = out_data.unwrap();
+                let data = binding.lock().unwrap();
+
+                let mut send_state = state.lock().unwrap();
+                let first_frame = send_state.is_none();
+                if first_frame {
+                    let config = match receiver.get_codec_config(media_type) {
+                        Some(config) => config,
+                        None => {
+                            warn!("no codec config yet, frame dropped");
+                            continue;
+                        }
+                    };
+                    let payload_type = match media_type {
+                        MediaType::AUDIO => AUDIO_PAYLOAD_TYPE,
+                        _ => VIDEO_PAYLOAD_TYPE,
+                    };
+                    let mut packetizer = RtpPacketizer::new(&config, payload_type, random_u32());
+                    packetizer.set_mtu(mtu);
+                    *send_state = Some(SendState {
+                        packetizer,
+                        last_send: Instant::now(),
+                    });
+                }
+                let state = send_state.as_mut().unwrap();
+
+                for packet in state.packetizer.packetize(&data) {
+                    if let Err(e) = socket.send_to(&packet, rtp_addr) {
+                        error!("send rtp packet failed: {}", e);
+                    }
+                }
+                state.last_send = Instant::now();
+                drop(send_state);
+
+                if first_frame {
+                    let _running = running.lock().unwrap();
+                    report_wake.notify_all();
+                }
+            }
+            info!("rtp send done");
+        }));
+    }
+
+    // sender reports go out every report interval from the first frame on, also while
+    // the input stalls
+    fn start_report(&mut self, state: Arc<Mutex<Option<SendState>>>) {
+        let running = self.running.clone();
+        let report_wake = self.report_wake.clone();
+        let socket = self.socket.clone();
+        let rtcp_addr = self.rtcp_addr;
+        let report_interval = self.report_interval;
+
+        self.report_thread = Some(thread::spawn(move || {
+            let cname = format!("rust_proj-{}@localhost", process::id());
+            let mut last_report: Option<Instant> = None;
+            let mut running = running.lock().unwrap();
+            while *running {
+                let wait = match last_report.map(|report| report.elapsed()) {
+                    Some(elapsed) if elapsed < report_interval => {
+                        report_interval.saturating_sub(elapsed)
+                    }
+                    _ => {
+                        // nothing to report before the first frame, which wakes us
+                        let state = state.lock().unwrap();
+                        if let Some(state) = state.as_ref() {
+                            let report = state.sender_report(&cname);
+                            if let Err(e) = socket.send_to(&report, rtcp_addr) {
+                                error!("send rtcp report failed: {}", e);
+                            }
+                            last_report = Some(Instant::now());
+                        }
+                        report_interval
+                    }
+                };
+                running = report_wake.wait_timeout(running, wait).unwrap().0;
+            }
+            debug!("rtcp report done");
+        }));
+    }
+
+    pub fn stop_send(&mut self) {
+        debug!("stop rtp send begin");
+        *self.running.lock().unwrap() = false;
+        self.report_wake.notify_all();
+        self.receiver.notify_read_stop();
+        if let Some(send_thread) = self.send_thread.take() {
+            send_thread.join().expect("can not join the send thread");
+        }
+        if let Some(report_thread) = self.report_thread.take() {
+            report_thread
+                .join()
+                .expect("can not join the report thread");
+        }
+        self.receiver.detach();
+        debug!("stop rtp send end");
+    }
+}
+
+// a random value for ssrcs and timestamp offsets, from the randomly keyed std hasher
+fn random_u32() -> u32 {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.write_u128(now.as_nanos());
+    hasher.finish() as u32
+}
@@ -0,0 +1,225 @@
+use super::{es::AnnexbParser, media_data, Demuxer, Packet};
+use crate::utils::{
+    buffer::MediaType,
+    codec::{split_annexb, AdtsHeader},
+};
+use crate::{error, warn};
+use std::{
+    collections::{HashMap, VecDeque},
+    io::Read,
+};
+
+pub const TS_PACKET_SIZE: usize = 188;
+pub const STREAM_TYPE_AAC: u8 = 0x0f;
+pub const STREAM_TYPE_H264: u8 = 0x1b;
+
+struct PesStream {
+    stream_type: u8,
+    buffer: Vec<u8>,
+}
+
+pub struct TsDemuxer {
+    input: Box<dyn Read + Send>,
+    pmt_pid: Option<u16>,
+    streams: HashMap<u16, PesStream>,
+    packets: VecDeque<Packet>,
+    h264: AnnexbParser,
+    aac_header: Option<AdtsHeader>,
+    eof: bool,
+}
+
+impl TsDemuxer {
+    pub fn new(input: Box<dyn Read + Send>) -> Self {
+        TsDemuxer {
+            input,
+            pmt_pid: None,
+            streams: HashMap::new(),
+            packets: VecDeque::new(),
+            h264: AnnexbParser::new(),
+            aac_header: None,
+            eof: false,
+        }
+    }
+
+    fn read_ts_packet(&mut self) -> bool {
+        let mut packet = [0u8; TS_PACKET_SIZE];
+        if self.input.read_exact(&mut packet).is_err() {
+            return false;
+        }
+        if packet[0] != 0x47 {
+            error!("lost ts sync");
+            return false;
+        }
+
+        let unit_start = packet[1] & 0x40 != 0;
+        let pid = u16::from_be_bytes([packet[1] & 0x1f, packet[2]]);
+        let adaptation = (packet[3] >> 4) & 0x3;
+        let mut offset = 4;
+        if adaptation & 0x2 != 0 {
+            offset += 1 + packet[4] as usize;
+        }
+        if adaptation & 0x1 == 0 || offset >= TS_PACKET_SIZE {
+            return true;
+        }
+        let payload = &packet[offset..];
+
+        if pid == 0 {
+            self.parse_pat(unit_start, payload);
+        } else if Some(pid) == self.pmt_pid {
+            self.parse_pmt(unit_start, payload);
+        } else if self.streams.contains_key(&pid) {
+            if unit_start {
+                self.flush_pes(pid);
+            }
+            self.streams
+                .get_mut(&pid)
+                .unwrap()
+                .buffer
+                .extend_from_slice(payload);
+        }
+        true
+    }
+
+    fn section<'a>(&self, unit_start: bool, payload: &'a [u8]) -> Option<&'a [u8]> {
+        if !unit_start {
+            return None;
+        }
+        let pointer = *payload.first()? as usize;
+        let section = payload.get(1 + pointer..)?;
+        let len = (u16::from_be_bytes([*section.get(1)? & 0x0f, *section.get(2)?])) as usize;
+        // skip the crc
+        section.get(..(3 + len).checked_sub(4)?)
+    }
+
+    fn parse_pat(&mut self, unit_start: bool, payload: &[u8]) {
+        let Some(section) = self.section(unit_start, payload) else {
+            return;
+        };
+        for entry in section.get(8..).unwrap_or_default().chunks_exact(4) {
+            let program = u16::from_be_bytes([entry[0], entry[1]]);
+            if program != 0 {
+                self.pmt_pid = Some(u16::from_be_bytes([entry[2] & 0x1f, entry[3]]));
+                return;
+            }
+        }
+    }
+
+    fn parse_pmt(&mut self, unit_start: bool, payload: &[u8]) {
+        let Some(section) = self.section(unit_start, payload) else {
+            return;
+        };
+        if section.len() < 12 {
+            return;
+        }
+        let info_len = u16::from_be_bytes([section[10] & 0x0f, section[11]]) as usize;
+        let mut i = 12 + info_len;
+        while i + 5 <= section.len() {
+            let stream_type = section[i];
+            let pid = u16::from_be_bytes([section[i + 1] & 0x1f, section[i + 2]]);
+            let es_info_len = u16::from_be_bytes([section[i + 3] & 0x0f, section[i + 4]]) as usize;
+            if stream_type == STREAM_TYPE_H264 || stream_type == STREAM_TYPE_AAC {
+                self.streams.entry(pid).or_insert(PesStream {
+                    stream_type,
+                    buffer: Vec::new(),
+                });
+            } else {
+                warn!("unsupported stream type: {:#x}", stream_type);
+            }
+            i += 5 + es_info_len;
+        }
+    }
+
+    fn flush_pes(&mut self, pid: u16) {
+        let stream = self.streams.get_mut(&pid).unwrap();
+        let pes = std::mem::take(&mut stream.buffer);
+        let stream_type = stream.stream_type;
+        if pes.len() < 9 || pes[0..3] != [0, 0, 1] {
+            return;
+        }
+
+        let flags = pes[7];
+        let header_len = pes[8] as usize;
+        if pes.len() < 9 + header_len {
+            return;
+        }
+        let pts = if flags & 0x80 != 0 && header_len >= 5 {
+            read_timestamp(&pes[9..14])
+        } else {
+            0
+        };
+        let dts = if flags & 0x40 != 0 && header_len >= 10 {
+            read_timestamp(&pes[14..19])
+        } else {
+            pts
+        };
+        let data = &pes[9 + header_len..];
+
+        match stream_type {
+            STREAM_TYPE_H264 => {
+                let (config, payload, key_frame) = self.h264.parse(&split_annexb(data));
+                if let Some(config) = config {
+                    self.packets.push_back(Packet::CONFIG(config));
+                }
+                self.packets.push_back(Packet::DATA(media_data(
+                    MediaType::VIDEO,
+                    pts / 90,
+                    dts / 90,
+                    key_frame,
+                    payload,
+                )));
+            }
+            STREAM_TYPE_AAC => self.parse_adts(pts, data),
+            _ => {}
+        }
+    }
+
+    fn parse_adts(&mut self, pts: u64, data: &[u8]) {
+        let mut offset = 0;
+        let mut frames = 0;
+        while let Some(header) = AdtsHeader::parse(&data[offset..]) {
+            if offset + header.frame_len > data.len() {
+                break;
+            }
+            if self.aac_header != Some(header) {
+                self.aac_header = Some(header);
+                if let Some(config) = header.config() {
+                    self.packets.push_back(Packet::CONFIG(config));
+                }
+            }
+            let sample_rate = header.config().map_or(48000, |config| config.sample_rate) as u64;
+            let frame_pts = pts / 90 + frames * 1024 * 1000 / sample_rate;
+            self.packets.push_back(Packet::DATA(media_data(
+                MediaType::AUDIO,
+                frame_pts,
+                frame_pts,
+                false,
+                data[offset + header.header_len..offset + header.frame_len].to_vec(),
+            )));
+            offset += header.frame_len;
+            frames += 1;
+        }
+    }
+}
+
+fn read_timestamp(data: &[u8]) -> u64 {
+    (((data[0] as u64 >> 1) & 0x07) << 30)
+        | ((data[1] as u64) << 22)
+        | (((data[2] as u64) >> 1) << 15)
+        | ((data[3] as u64) << 7)
+        | ((data[4] as u64) >> 1)
+}
+
+impl Demuxer for TsDemuxer {
+    fn read_packet(&mut self) -> Option<Packet> {
+        while self.packets.is_empty() && !self.eof {
+            if !self.read_ts_packet() {
+                self.eof = true;
+                let pids: Vec<u16> = self.streams.keys().copied().collect();
+                for pid in pids {
+                    self.flush_pes(pid);
+                }
+            }
+        }
+        self.packets.pop_front()
+    }
+}
@@ -0,0 +1,9 @@
+pub mod config;
+pub mod dispatcher;
+pub mod ffi;
+pub mod format;
+#[cfg(feature = "python")]
+pub mod python;
+pub mod server;
+pub mod source;
+pub mod utils;
@@ -1,235 +1,250 @@
-#![allow(dead_code, unused)]
-pub mod dispatcher;
-pub mod utils;
 use std::{
-    borrow::BorrowMut,
-    fmt::{Debug, Error},
-    panic::Location,
+    env,
+    fmt::Debug,
     process,
-    sync::{Arc, Mutex, RwLock},
-    thread::{self, JoinHandle, ThreadId},
-    time::Duration,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
 };
 
-// use dispatcher::DispatcherReceiver;
-
-use stdext::function_name;
-
-use crate::{
-    dispatcher::{
-        dispatcher::Dispatcher,
-        receiver::{self, Receiver},
-    },
-    utils::buffer::{MediaData, MediaType},
-    utils::macros,
+use rust_proj::{
+    config::{Pipeline, PipelineConfig, PipelineReceiver},
+    debug,
+    dispatcher::{dump::DumpFormat, receiver::Receiver},
+    utils::buffer::MediaType,
+    utils::{options::RunOptions, signal, Identity},
+    warn,
 };
-struct BufferController {
-    pub dispatcher: Arc<Mutex<Dispatcher>>,
-    dummy_data: Vec<Arc<Mutex<MediaData>>>,
-    dummy_count: u32,
-    gop_size: u32,
-    write_thread: Option<JoinHandle<()>>,
-    running: Arc<Mutex<bool>>,
-    video_count: u32,
-}
-
-impl BufferController {
-    fn new(dummy_count: u32) -> Self {
-        BufferController {
-            dispatcher: Dispatcher::new(400, 50),
-            dummy_data: Vec::new(),
-            dummy_count,
-            gop_size: 30,
-            write_thread: None,
-            running: Arc::new(Mutex::new(false)),
-            video_count: 0,
-        }
-    }
-
-    fn generate_av_data(&mut self) {
-        for i in 0..self.dummy_count {
-            let mut media_data = MediaData::default();
-            media_data.buff.lock().unwrap().set_capacity(16);
-            media_data
-                .buff
-                .lock()
-                .unwrap()
-                .borrow_mut()
-                .replace(i.to_be_bytes().to_vec());
-
-            media_data.pts = i as u64;
-            if i % 3 == 0 {
-                media_data.media_type = MediaType::VIDEO;
-                self.video_count += 1;
-                if self.video_count >= self.gop_size && self.video_count % self.gop_size == 0 {
-                    media_data.key_frame = true
-                } else {
-                    media_data.key_frame = false
-                }
-            } else {
-                media_data.media_type = MediaType::AUDIO;
-            }
 
-            self.dummy_data.push(Arc::new(Mutex::new(media_data)));
-        }
-        self.dummy_data[0].lock().unwrap().key_frame = true;
-        self.dispatcher.lock().unwrap().start_dispatch();
-    }
-
-    fn start_write(&mut self) {
-        info!("start write");
-        let running = self.running.clone();
-        let dummy = self.dummy_data.clone();
-        let dispatcher = self.dispatcher.clone();
-        *running.lock().unwrap() = true;
-        self.write_thread = Some(thread::spawn(move || {
-            while *running.lock().unwrap() {
-                for data in dummy.iter() {
-                    dispatcher.lock().unwrap().input_data(data.clone());
-                    thread::sleep(Duration::from_millis(25));
-                }
-                *running.lock().unwrap() = false;
-            }
-        }));
-    }
+// how often the main loop checks the sources, the duration and SIGUSR1
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+// how long the readers get to catch up once the sources are done
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone)]
+struct ReadStats {
+    // request_read calls, the ones that returned no frame included
+    requests: u32,
+    empty: u32,
+    audio: u32,
+    video: u32,
+    other: u32,
+    key_frames: u32,
+    first_pts: Option<u64>,
+    last_pts: Option<u64>,
+}
 
-    fn stop_write(&mut self) {
-        debug!("stop write begin");
-        self.dispatcher.lock().unwrap().stop_dispatch();
-        *self.running.lock().unwrap() = false;
-        self.write_thread
-            .take()
-            .unwrap()
-            .join()
-            .expect("can not join the write thread");
-        debug!("stop write end");
+impl ReadStats {
+    fn frames(&self) -> u32 {
+        self.audio + self.video + self.other
     }
 }
 
 struct BufferReceiver {
+    name: String,
     receiver: Arc<Receiver>,
+    media_type: MediaType,
     read_thread: Option<JoinHandle<()>>,
     reading: Arc<Mutex<bool>>,
-    av_count: Arc<Mutex<u32>>,
-    audio_count: Arc<Mutex<u32>>,
-    video_count: Arc<Mutex<u32>>,
-    read_count: Arc<Mutex<u32>>,
+    stats: Arc<Mutex<ReadStats>>,
 }
 
 impl BufferReceiver {
-    fn new(dispatcher: Arc<Mutex<Dispatcher>>) -> BufferReceiver {
-        let receiver = BufferReceiver {
-            receiver: Arc::new(Receiver::new()),
+    fn new(reader: &PipelineReceiver) -> BufferReceiver {
+        BufferReceiver {
+            name: reader.name.clone(),
+            receiver: reader.receiver.clone(),
+            media_type: reader.media_type,
             read_thread: None,
             reading: Arc::new(Mutex::new(false)),
-            av_count: Arc::new(Mutex::new(0)),
-            audio_count: Arc::new(Mutex::new(0)),
-            video_count: Arc::new(Mutex::new(0)),
-            read_count: Arc::new(Mutex::new(0)),
-        };
-
-        dispatcher
-            .lock()
-            .unwrap()
-            .attach_receiver(receiver.receiver.clone());
-
-        receiver.receiver.set_dispatcher(dispatcher.clone());
-        receiver.receiver.set_key_mode(true);
-        receiver
+            stats: Arc::new(Mutex::new(ReadStats::default())),
+        }
     }
 
-    fn start_read(&mut self, media_type: MediaType) {
+    fn start_read(&mut self) {
         debug!("start read");
         let reading = self.reading.clone();
         *reading.lock().unwrap() = true;
         let receiver = self.receiver.clone();
-        let av_count = self.av_count.clone();
-        let audio_count = self.audio_count.clone();
-        let video_count = self.video_count.clone();
-        let read_count = self.read_count.clone();
+        let stats = self.stats.clone();
+        let media_type = self.media_type;
 
         self.read_thread = Some(thread::spawn(move || {
             while *reading.lock().unwrap() {
-                info!("request read in");
                 let (ret, out_data) = receiver.request_read(media_type);
-                info!("request read out");
-                *read_count.lock().unwrap() += 1;
+                let mut stats = stats.lock().unwrap();
+                stats.requests += 1;
                 if !ret {
-                    warn!("request read error");
+                    stats.empty += 1;
                     continue;
                 }
+                let Some(binding) = out_data else {
+                    stats.empty += 1;
+                    continue;
+                };
 
-                {
-                    let binding = out_data.unwrap();
-                    let data = binding.lock().unwrap();
-                    match data.media_type {
-                        MediaType::AUDIO => *audio_count.lock().unwrap() += 1,
-                        MediaType::VIDEO => *video_count.lock().unwrap() += 1,
-                        MediaType::AV => todo!(),
-                    }
-                    if (media_type == MediaType::AV) {
-                        *av_count.lock().unwrap() += 1;
-                    }
-                    fatal!(
-                        "read_type: {:?}, out_type: {:?}, pts: {:?}",
-                        media_type,
-                        data.media_type,
-                        data.pts
-                    );
+                let data = binding.lock().unwrap();
+                match data.media_type {
+                    MediaType::AUDIO => stats.audio += 1,
+                    MediaType::VIDEO => stats.video += 1,
+                    _ => stats.other += 1,
                 }
+                if data.key_frame {
+                    stats.key_frames += 1;
+                }
+                stats.first_pts.get_or_insert(data.pts);
+                stats.last_pts = Some(data.pts);
+                debug!(
+                    "read_type: {:?}, out_type: {:?}, pts: {:?}",
+                    media_type, data.media_type, data.pts
+                );
             }
-
-            match media_type {
-                MediaType::AUDIO => fatal!(
-                    "read_type: {:?}, read done, read count: {}, total count: {}",
-                    media_type,
-                    *audio_count.lock().unwrap(),
-                    *read_count.lock().unwrap()
-                ),
-                MediaType::VIDEO => fatal!(
-                    "read_type: {:?}, read done, read count: {}, total count: {}",
-                    media_type,
-                    *video_count.lock().unwrap(),
-                    *read_count.lock().unwrap()
-                ),
-                MediaType::AV => fatal!(
-                    "read_type: {:?}, read done, read count: {}, total count: {}",
-                    media_type,
-                    *av_count.lock().unwrap(),
-                    *read_count.lock().unwrap()
-                ),
-            }
+            debug!("read_type: {:?}, read done", media_type);
         }));
     }
 
+    fn frames_read(&self) -> u32 {
+        self.stats.lock().unwrap().frames()
+    }
+
     fn stop_read(&mut self) {
         debug!("stop read begin");
         *self.reading.lock().unwrap() = false;
         self.receiver.notify_read_stop();
-        info!("trace");
         self.read_thread
             .take()
             .unwrap()
             .join()
-            .expect("can not join the write thread");
+            .expect("can not join the read thread");
 
         debug!("stop read end");
     }
+
+    fn summary(&self) -> String {
+        let stats = self.stats.lock().unwrap();
+        let pts = match (stats.first_pts, stats.last_pts) {
+            (Some(first), Some(last)) => format!("pts {}..{}", first, last),
+            _ => "no pts".to_string(),
+        };
+        format!(
+            "{}: recv {}, {:?}{}, {} frames (audio {}, video {}, other {}), {} key frames, {}, {} requests, {} empty",
+            self.name,
+            self.receiver.get_id(),
+            self.media_type,
+            if self.receiver.is_key_read() {
+                " key only"
+            } else {
+                ""
+            },
+            stats.frames(),
+            stats.audio,
+            stats.video,
+            stats.other,
+            stats.key_frames,
+            pts,
+            stats.requests,
+            stats.empty
+        )
+    }
+}
+
+// the buffer states on SIGUSR1, for a reader that seems stuck
+fn dump_state(pipeline: &Pipeline) {
+    for name in pipeline.dispatcher_names() {
+        let dispatcher = pipeline.dispatcher(&name).unwrap();
+        let dispatcher = dispatcher.lock().unwrap();
+        println!("dispatcher {}", name);
+        println!("{}", dispatcher.dump_state_as(DumpFormat::JSON));
+        println!("{}", dispatcher.dump_state_as(DumpFormat::ASCII));
+    }
+}
+
+// waits until no reader made progress for a poll interval
+fn drain(receivers: &[BufferReceiver]) {
+    let deadline = Instant::now() + DRAIN_TIMEOUT;
+    let mut counts: Vec<u32> = receivers.iter().map(|r| r.frames_read()).collect();
+    while Instant::now() < deadline {
+        thread::sleep(POLL_INTERVAL);
+        let current: Vec<u32> = receivers.iter().map(|r| r.frames_read()).collect();
+        if current == counts {
+            break;
+        }
+        counts = current;
+    }
 }
 
 fn main() {
-    let mut controller = BufferController::new(100);
-    controller.generate_av_data();
-    let mut receiver = BufferReceiver::new(controller.dispatcher.clone());
-    // thread::sleep(Duration::from_millis(500));
-    controller.start_write();
-
-    receiver.start_read(MediaType::VIDEO);
-    while *controller.running.lock().unwrap() == true {
-        warn!("wait 1s");
-        thread::sleep(Duration::from_secs(1));
+    let args: Vec<String> = env::args().collect();
+    let options = match RunOptions::parse(&args[1..]) {
+        Ok(Some(options)) => options,
+        Ok(None) => {
+            println!("{}", RunOptions::usage(&args[0]));
+            return;
+        }
+        Err(err) => {
+            eprintln!("{}\n{}", err, RunOptions::usage(&args[0]));
+            process::exit(2);
+        }
+    };
+    let config = match options.config.as_ref() {
+        Some(path) => PipelineConfig::load(path),
+        None => Ok(options.pipeline()),
+    };
+    let mut pipeline = match config.and_then(|config| config.build()) {
+        Ok(pipeline) => pipeline,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(2);
+        }
+    };
+    if !signal::watch(libc::SIGUSR1) {
+        warn!("can not watch SIGUSR1, no state dumps");
+    }
+
+    let mut receivers: Vec<BufferReceiver> = pipeline
+        .receivers()
+        .iter()
+        .map(BufferReceiver::new)
+        .collect();
+    let started = Instant::now();
+    pipeline.start();
+    for receiver in receivers.iter_mut() {
+        receiver.start_read();
+    }
+
+    loop {
+        let done = match options.duration {
+            Some(duration) => started.elapsed() >= duration,
+            None => !pipeline.is_ingesting(),
+        };
+        if done {
+            break;
+        }
+        thread::sleep(POLL_INTERVAL);
+        if signal::take(libc::SIGUSR1) {
+            dump_state(&pipeline);
+        }
     }
 
-    controller.stop_write();
-    receiver.stop_read();
+    pipeline.stop_ingest();
+    drain(&receivers);
+    for receiver in receivers.iter_mut() {
+        receiver.stop_read();
+    }
+    pipeline.stop();
+
+    println!("ran {:.2}s", started.elapsed().as_secs_f64());
+    for name in pipeline.dispatcher_names() {
+        let dispatcher = pipeline.dispatcher(&name).unwrap();
+        let dispatcher = dispatcher.lock().unwrap();
+        println!(
+            "dispatcher {}: {} frames buffered, {} dropped",
+            name,
+            dispatcher.buffered_frames(),
+            dispatcher.dropped_frames()
+        );
+    }
+    for receiver in receivers.iter() {
+        println!("{}", receiver.summary());
+    }
 }
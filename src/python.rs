@@ -0,0 +1,447 @@
+// python bindings, built with the python feature into the rust_proj extension module,
+// see python/. media types are the ints of the module's AV, AUDIO, VIDEO, SUBTITLE,
+// METADATA and DATA. calls that can wait on the dispatcher release the GIL
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::{
+    fmp4::{Fmp4Writer, FragmentMode},
+    ipc::{media_type_code, media_type_from_code},
+};
+use crate::source::{
+    file_source::{FileSource, PaceMode},
+    synthetic::SyntheticStream,
+};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::{aac_config, h264_config},
+    Identity,
+};
+use pyo3::exceptions::{PyBufferError, PyIOError, PyValueError};
+use pyo3::ffi;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::{
+    fs::File,
+    os::raw::{c_int, c_void},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+fn from_code(media_type: u8) -> PyResult<MediaType> {
+    media_type_from_code(media_type)
+        .ok_or_else(|| PyValueError::new_err(format!("unknown media type: {}", media_type)))
+}
+
+// a frame with its payload copied out of the dispatcher, bytes(frame) and
+// memoryview(frame) give the payload
+#[pyclass(name = "MediaData", module = "rust_proj", frozen)]
+pub struct PyMediaData {
+    #[pyo3(get)]
+    media_type: u8,
+    #[pyo3(get)]
+    track_id: u32,
+    #[pyo3(get)]
+    pts: u64,
+    #[pyo3(get)]
+    dts: u64,
+    #[pyo3(get)]
+    key_frame: bool,
+    payload: Vec<u8>,
+}
+
+impl PyMediaData {
+    fn from_data(data: &MediaData) -> Self {
+        PyMediaData {
+            media_type: media_type_code(data.media_type),
+            track_id: data.track_id,
+            pts: data.pts,
+            dts: data.dts,
+            key_frame: data.key_frame,
+            payload: data.payload(),
+        }
+    }
+
+    fn to_data(&self) -> PyResult<Arc<Mutex<MediaData>>> {
+        let data = MediaData {
+            key_frame: self.key_frame,
+            pts: self.pts,
+            dts: self.dts,
+            media_type: from_code(self.media_type)?,
+            track_id: self.track_id,
+            ..Default::default()
+        };
+        data.buff.lock().unwrap().replace(self.payload.clone());
+        Ok(Arc::new(Mutex::new(data)))
+    }
+}
+
+#[pymethods]
+impl PyMediaData {
+    // dts defaults to pts
+    #[new]
+    #[pyo3(signature = (media_type, pts, payload = Vec::new(), key_frame = false, dts = None, track_id = 0))]
+    fn new(
+        media_type: u8,
+        pts: u64,
+        payload: Vec<u8>,
+        key_frame: bool,
+        dts: Option<u64>,
+        track_id: u32,
+    ) -> PyResult<Self> {
+        from_code(media_type)?;
+        Ok(PyMediaData {
+            media_type,
+            track_id,
+            pts,
+            dts: dts.unwrap_or(pts),
+            key_frame,
+            payload,
+        })
+    }
+
+    #[getter]
+    fn payload<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new_bound(py, &self.payload)
+    }
+
+    fn __bytes__<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        self.payload(py)
+    }
+
+    fn __len__(&self) -> usize {
+        self.payload.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MediaData(media_type={}, pts={}, dts={}, key_frame={}, track_id={}, size={})",
+            self.media_type,
+            self.pts,
+            self.dts,
+            self.key_frame,
+            self.track_id,
+            self.payload.len()
+        )
+    }
+
+    // a read-only view of the payload, the frame is frozen so it never moves
+    unsafe fn __getbuffer__(
+        slf: Bound<'_, Self>,
+        view: *mut ffi::Py_buffer,
+        flags: c_int,
+    ) -> PyResult<()> {
+        if view.is_null() {
+            return Err(PyBufferError::new_err("view is null"));
+        }
+        let payload = &slf.get().payload;
+        let ret = ffi::PyBuffer_FillInfo(
+            view,
+            slf.as_ptr(),
+            payload.as_ptr() as *mut c_void,
+            payload.len() as ffi::Py_ssize_t,
+            1,
+            flags,
+        );
+        if ret == -1 {
+            return Err(PyErr::fetch(slf.py()));
+        }
+        Ok(())
+    }
+}
+
+#[pyclass(name = "Dispatcher", module = "rust_proj")]
+pub struct PyDispatcher {
+    dispatcher: Arc<Mutex<Dispatcher>>,
+}
+
+#[pymethods]
+impl PyDispatcher {
+    #[new]
+    #[pyo3(signature = (max_capacity = 400, capacity_increment = 50))]
+    fn new(max_capacity: u32, capacity_increment: u32) -> Self {
+        PyDispatcher {
+            dispatcher: Dispatcher::new(max_capacity, capacity_increment),
+        }
+    }
+
+    fn start(&self, py: Python<'_>) {
+        py.allow_threads(|| self.dispatcher.lock().unwrap().start_dispatch());
+    }
+
+    fn stop(&self, py: Python<'_>) {
+        py.allow_threads(|| self.dispatcher.lock().unwrap().stop_dispatch());
+    }
+
+    // one notify pass on the calling thread, for a dispatcher that was not started
+    fn dispatch_once(&self, py: Python<'_>) {
+        py.allow_threads(|| self.dispatcher.lock().unwrap().dispatch_once());
+    }
+
+    fn push(&self, py: Python<'_>, frame: &PyMediaData) -> PyResult<()> {
+        let data = frame.to_data()?;
+        py.allow_threads(|| self.dispatcher.lock().unwrap().input_data(data));
+        Ok(())
+    }
+
+    fn push_all(&self, py: Python<'_>, frames: Vec<PyRef<'_, PyMediaData>>) -> PyResult<()> {
+        let frames = frames
+            .iter()
+            .map(|frame| frame.to_data())
+            .collect::<PyResult<Vec<_>>>()?;
+        py.allow_threads(|| {
+            for data in frames {
+                self.dispatcher.lock().unwrap().input_data(data);
+            }
+        });
+        Ok(())
+    }
+
+    // tracks limits what a mixed read delivers, audio and video by default
+    #[pyo3(signature = (key_only = false, tracks = None))]
+    fn attach(
+        &self,
+        py: Python<'_>,
+        key_only: bool,
+        tracks: Option<Vec<u8>>,
+    ) -> PyResult<PyReceiver> {
+        let receiver = Arc::new(Receiver::new());
+        if let Some(tracks) = tracks {
+            let tracks = tracks
+                .into_iter()
+                .map(from_code)
+                .collect::<PyResult<Vec<_>>>()?;
+            receiver.set_tracks(&tracks);
+        }
+        let attached = py.allow_threads(|| {
+            let mut dispatcher = self.dispatcher.lock().unwrap();
+            let count = dispatcher.receiver_count();
+            dispatcher.attach_receiver(receiver.clone());
+            dispatcher.receiver_count() > count
+        });
+        if !attached {
+            return Err(PyValueError::new_err("no read index left"));
+        }
+        receiver.set_dispatcher(self.dispatcher.clone());
+        receiver.set_key_mode(key_only);
+        Ok(PyReceiver { receiver })
+    }
+
+    fn set_h264_config(&self, sps: Vec<u8>, pps: Vec<u8>) -> PyResult<()> {
+        let config =
+            h264_config(&sps, &pps).ok_or_else(|| PyValueError::new_err("invalid sps or pps"))?;
+        self.dispatcher.lock().unwrap().set_codec_config(config);
+        Ok(())
+    }
+
+    fn set_aac_config(&self, audio_specific_config: Vec<u8>) -> PyResult<()> {
+        let config = aac_config(&audio_specific_config)
+            .ok_or_else(|| PyValueError::new_err("invalid audio specific config"))?;
+        self.dispatcher.lock().unwrap().set_codec_config(config);
+        Ok(())
+    }
+
+    #[getter]
+    fn buffered_frames(&self) -> u32 {
+        self.dispatcher.lock().unwrap().buffered_frames()
+    }
+
+    #[getter]
+    fn receiver_count(&self) -> usize {
+        self.dispatcher.lock().unwrap().receiver_count()
+    }
+
+    // the buffer state as a JSON string, see Dispatcher::dump_state
+    fn dump_state(&self) -> String {
+        self.dispatcher.lock().unwrap().dump_state().to_string()
+    }
+}
+
+#[pyclass(name = "Receiver", module = "rust_proj")]
+pub struct PyReceiver {
+    receiver: Arc<Receiver>,
+}
+
+#[pymethods]
+impl PyReceiver {
+    #[getter]
+    fn id(&self) -> u32 {
+        self.receiver.get_id()
+    }
+
+    #[getter]
+    fn key_only(&self) -> bool {
+        self.receiver.is_key_read()
+    }
+
+    #[getter]
+    fn attached(&self) -> bool {
+        self.receiver.is_attached()
+    }
+
+    // the next frame, None once the timeout in seconds passed or reading stopped.
+    // without a timeout it waits, which needs a started dispatcher
+    #[pyo3(signature = (media_type = 0, timeout = None))]
+    fn read(
+        &self,
+        py: Python<'_>,
+        media_type: u8,
+        timeout: Option<f64>,
+    ) -> PyResult<Option<PyMediaData>> {
+        let media_type = from_code(media_type)?;
+        let timeout = timeout
+            .map(|timeout| Duration::try_from_secs_f64(timeout.max(0.0)))
+            .transpose()
+            .map_err(|err| PyValueError::new_err(err.to_string()))?;
+        let data = py.allow_threads(|| self.receiver.read_frame(media_type, timeout));
+        Ok(data.map(|data| PyMediaData::from_data(&data.lock().unwrap())))
+    }
+
+    // None right away when no data has been signaled
+    #[pyo3(signature = (media_type = 0))]
+    fn try_read(&self, py: Python<'_>, media_type: u8) -> PyResult<Option<PyMediaData>> {
+        let media_type = from_code(media_type)?;
+        let (_, data) = py.allow_threads(|| self.receiver.try_read(media_type));
+        Ok(data.map(|data| PyMediaData::from_data(&data.lock().unwrap())))
+    }
+
+    // every frame until nothing came for timeout seconds
+    #[pyo3(signature = (media_type = 0, timeout = 0.1))]
+    fn read_all(&self, py: Python<'_>, media_type: u8, timeout: f64) -> PyResult<Vec<PyMediaData>> {
+        let mut frames = Vec::new();
+        while let Some(frame) = self.read(py, media_type, Some(timeout))? {
+            frames.push(frame);
+        }
+        Ok(frames)
+    }
+
+    fn select_track(&self, media_type: u8, track_id: u32) -> PyResult<()> {
+        self.receiver.select_track(from_code(media_type)?, track_id);
+        Ok(())
+    }
+
+    // wakes a blocked read, later reads return None
+    fn stop(&self) {
+        self.receiver.notify_read_stop();
+    }
+
+    fn detach(&self, py: Python<'_>) {
+        py.allow_threads(|| self.receiver.detach());
+    }
+}
+
+#[pyclass(name = "SyntheticStream", module = "rust_proj")]
+pub struct PySyntheticStream {
+    stream: SyntheticStream,
+}
+
+#[pymethods]
+impl PySyntheticStream {
+    // gop_size video frames per GOP, every video_interval-th frame is video
+    #[new]
+    #[pyo3(signature = (gop_size = 30, video_interval = 3, frame_interval = 25))]
+    fn new(gop_size: u32, video_interval: u32, frame_interval: u64) -> Self {
+        PySyntheticStream {
+            stream: SyntheticStream::new(gop_size, video_interval, frame_interval),
+        }
+    }
+
+    fn frames(&mut self, count: usize) -> Vec<PyMediaData> {
+        (0..count)
+            .map(|_| PyMediaData::from_data(&self.stream.next_frame()))
+            .collect()
+    }
+}
+
+#[pyclass(name = "FileSource", module = "rust_proj", unsendable)]
+pub struct PyFileSource {
+    source: FileSource,
+}
+
+#[pymethods]
+impl PyFileSource {
+    // fast releases frames as the dispatcher takes them instead of by their dts
+    #[new]
+    #[pyo3(signature = (path, dispatcher, fast = false, looping = false, speed = 1.0))]
+    fn new(
+        path: PathBuf,
+        dispatcher: &PyDispatcher,
+        fast: bool,
+        looping: bool,
+        speed: f64,
+    ) -> PyResult<Self> {
+        let mut source = FileSource::new(&path, dispatcher.dispatcher.clone())
+            .ok_or_else(|| PyValueError::new_err(format!("unknown file format: {:?}", path)))?;
+        if fast {
+            source.set_pace_mode(PaceMode::FAST);
+        }
+        source.set_loop(looping);
+        source.set_speed(speed);
+        Ok(PyFileSource { source })
+    }
+
+    fn start(&mut self) {
+        self.source.start_ingest();
+    }
+
+    fn stop(&mut self, py: Python<'_>) {
+        py.allow_threads(|| self.source.stop_ingest());
+    }
+
+    #[getter]
+    fn running(&self) -> bool {
+        self.source.is_running()
+    }
+}
+
+// writes what a receiver of the dispatcher reads to an fMP4 file, fragment_ms
+// cuts fragments by duration instead of at key frames
+#[pyclass(name = "Fmp4FileSink", module = "rust_proj", unsendable)]
+pub struct PyFmp4FileSink {
+    writer: Fmp4Writer,
+}
+
+#[pymethods]
+impl PyFmp4FileSink {
+    #[new]
+    #[pyo3(signature = (path, dispatcher, fragment_ms = None))]
+    fn new(path: PathBuf, dispatcher: &PyDispatcher, fragment_ms: Option<u64>) -> PyResult<Self> {
+        let file = File::create(&path)
+            .map_err(|err| PyIOError::new_err(format!("can not create {:?}: {}", path, err)))?;
+        let mode = fragment_ms.map_or(FragmentMode::GOP, FragmentMode::DURATION);
+        Ok(PyFmp4FileSink {
+            writer: Fmp4Writer::new(dispatcher.dispatcher.clone(), Box::new(file), mode),
+        })
+    }
+
+    fn start(&mut self) {
+        self.writer.start_write();
+    }
+
+    fn stop(&mut self, py: Python<'_>) {
+        py.allow_threads(|| self.writer.stop_write());
+    }
+}
+
+#[pymodule]
+fn rust_proj(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    for media_type in [
+        MediaType::AV,
+        MediaType::AUDIO,
+        MediaType::VIDEO,
+        MediaType::SUBTITLE,
+        MediaType::METADATA,
+        MediaType::DATA,
+    ] {
+        m.add(
+            format!("{:?}", media_type).as_str(),
+            media_type_code(media_type),
+        )?;
+    }
+    m.add_class::<PyMediaData>()?;
+    m.add_class::<PyDispatcher>()?;
+    m.add_class::<PyReceiver>()?;
+    m.add_class::<PySyntheticStream>()?;
+    m.add_class::<PyFileSource>()?;
+    m.add_class::<PyFmp4FileSink>()?;
+    Ok(())
+}
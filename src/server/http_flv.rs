@@ -0,0 +1,212 @@
+use super::{read_request, write_status};
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::flv::FlvMuxer;
+use crate::utils::{buffer::MediaType, Identity};
+use crate::{debug, error, info, warn};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+type Streams = Arc<Mutex<HashMap<String, Arc<Mutex<Dispatcher>>>>>;
+type Connections = Arc<Mutex<HashMap<u32, (Arc<Receiver>, TcpStream)>>>;
+
+// serves `GET /live/<stream>.flv`, every connection reads through its own receiver
+pub struct HttpFlvServer {
+    listener: Arc<TcpListener>,
+    streams: Streams,
+    connections: Connections,
+    accept_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl HttpFlvServer {
+    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(HttpFlvServer {
+            listener: Arc::new(TcpListener::bind(addr)?),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            accept_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn add_stream(&self, name: &str, dispatcher: Arc<Mutex<Dispatcher>>) {
+        info!("add flv stream: {}", name);
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), dispatcher);
+    }
+
+    // connections already playing the stream keep going until they disconnect
+    pub fn remove_stream(&self, name: &str) {
+        info!("remove flv stream: {}", name);
+        self.streams.lock().unwrap().remove(name);
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn start_serve(&mut self) {
+        info!("start http-flv serve: {:?}", self.listener.local_addr());
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let listener = self.listener.clone();
+        let streams = self.streams.clone();
+        let connections = self.connections.clone();
+
+        self.accept_thread = Some(thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !*running.lock().unwrap() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let streams = streams.clone();
+                let connections = connections.clone();
+                let running = running.clone();
+                thread::spawn(move || serve_connection(stream, streams, connections, running));
+            }
+            info!("http-flv accept done");
+        }));
+    }
+
+    pub fn stop_serve(&mut self) {
+        debug!("stop http-flv serve begin");
+        *self.running.lock().unwrap() = false;
+        // wake up the blocking accept
+        if let Ok(addr) = self.listener.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+        if let Some(accept_thread) = self.accept_thread.take() {
+            accept_thread
+                .join()
+                .expect("can not join the accept thread");
+        }
+        for (receiver, stream) in self.connections.lock().unwrap().values() {
+            receiver.notify_read_stop();
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        debug!("stop http-flv serve end");
+    }
+}
+
+fn stream_name(path: &str) -> Option<&str> {
+    let name = path.strip_prefix("/live/")?.strip_suffix(".flv")?;
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some(name)
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    streams: Streams,
+    connections: Connections,
+    running: Arc<Mutex<bool>>,
+) {
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("bad request: {}", e);
+            let _ = write_status(&mut stream, "400 Bad Request");
+            return;
+        }
+    };
+    if request.method != "GET" {
+        let _ = write_status(&mut stream, "405 Method Not Allowed");
+        return;
+    }
+    let dispatcher =
+        stream_name(&request.path).and_then(|name| streams.lock().unwrap().get(name).cloned());
+    let dispatcher = match dispatcher {
+        Some(dispatcher) => dispatcher,
+        None => {
+            warn!("unknown stream: {}", request.path);
+            let _ = write_status(&mut stream, "404 Not Found");
+            return;
+        }
+    };
+
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    match stream.try_clone() {
+        Ok(clone) => {
+            connections
+                .lock()
+                .unwrap()
+                .insert(receiver.get_id(), (receiver.clone(), clone));
+        }
+        Err(e) => error!("clone connection failed: {}", e),
+    }
+    info!(
+        "flv play: {}, receiver: {}",
+        request.path,
+        receiver.get_id()
+    );
+
+    if let Err(e) = play(&mut stream, &receiver, &running) {
+        debug!("flv connection closed: {}", e);
+    }
+
+    receiver.detach();
+    connections.lock().unwrap().remove(&receiver.get_id());
+    info!("flv play done, receiver: {}", receiver.get_id());
+}
+
+fn play(stream: &mut TcpStream, receiver: &Receiver, running: &Mutex<bool>) -> io::Result<()> {
+    stream.write_all(
+        b"HTTP/1.1 200 OK\r\nContent-Type: video/x-flv\r\nCache-Control: no-cache\r\n\
+          Access-Control-Allow-Origin: *\r\nConnection: close\r\n\r\n",
+    )?;
+
+    let mut muxer = FlvMuxer::new();
+    let has_audio = receiver.get_codec_config(MediaType::AUDIO).is_some();
+    let has_video = receiver.get_codec_config(MediaType::VIDEO).is_some();
+    // before any codec config is known, announce both tracks
+    let (has_audio, has_video) = match (has_audio, has_video) {
+        (false, false) => (true, true),
+        flags => flags,
+    };
+    stream.write_all(&muxer.header(has_audio, has_video))?;
+
+    while *running.lock().unwrap() {
+        // sequence headers go out before the first frame and again whenever a config changes
+        for media_type in [MediaType::VIDEO, MediaType::AUDIO] {
+            if let Some(config) = receiver.get_codec_config(media_type) {
+                if let Some(tag) = muxer.set_codec_config(&config) {
+                    stream.write_all(&tag)?;
+                }
+            }
+        }
+
+        let (ret, out_data) = receiver.request_read(MediaType::AV);
+        if !receiver.is_attached() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "stream ended"));
+        }
+        if !ret || out_data.is_none() {
+            continue;
+        }
+        let binding = out_data.unwrap();
+        let data = binding.lock().unwrap();
+        if let Some(tag) = muxer.push(&data) {
+            stream.write_all(&tag)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,78 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+pub mod http_flv;
+pub mod shm;
+pub mod uds;
+pub mod websocket;
+
+// bound on the request head, a live client never sends a body
+const MAX_REQUEST_SIZE: usize = 8192;
+
+#[derive(Debug, Default, Clone)]
+pub struct HttpRequest {
+    pub method: String,
+    pub path: String,
+    pub headers: Vec<(String, String)>,
+}
+
+impl HttpRequest {
+    // header lookup, names are case-insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// reads a request head byte by byte, so nothing after it is consumed from the stream
+pub fn read_request(input: &mut impl Read) -> io::Result<HttpRequest> {
+    let mut reader = BufReader::with_capacity(1, input);
+    let mut lines = Vec::new();
+    let mut size = 0;
+    loop {
+        let mut line = String::new();
+        let len = reader.read_line(&mut line)?;
+        size += len;
+        if len == 0 || size > MAX_REQUEST_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "incomplete request",
+            ));
+        }
+        let line = line.trim_end().to_string();
+        if line.is_empty() {
+            break;
+        }
+        lines.push(line);
+    }
+
+    let mut request_line = lines.first().map_or("", |line| line.as_str()).split(' ');
+    let method = request_line.next().unwrap_or_default().to_string();
+    let path = request_line.next().unwrap_or_default();
+    let path = path.split('?').next().unwrap_or_default().to_string();
+    if method.is_empty() || path.is_empty() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "invalid request line",
+        ));
+    }
+    let headers = lines[1..]
+        .iter()
+        .filter_map(|line| line.split_once(':'))
+        .map(|(key, value)| (key.trim().to_string(), value.trim().to_string()))
+        .collect();
+    Ok(HttpRequest {
+        method,
+        path,
+        headers,
+    })
+}
+
+pub fn write_status(output: &mut impl Write, status: &str) -> io::Result<()> {
+    write!(
+        output,
+        "HTTP/1.1 {}\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+        status
+    )
+}
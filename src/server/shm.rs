@@ -0,0 +1,619 @@
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::{
+    ipc::{decode, encode_config, media_type_code, media_type_from_code},
+    media_data, Packet,
+};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    codec::CodecConfig,
+};
+use crate::{debug, error, info};
+use std::{
+    fs::{self, File, OpenOptions},
+    io,
+    os::unix::io::AsRawFd,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{
+        atomic::{fence, AtomicU32, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+// A single-writer ring in a file under /dev/shm, mapped by every process that uses it.
+//
+// layout, all integers native-endian since both ends run on the same host:
+//   [0, 4096)          header: magic, version, capacity, slot count, counters and configs
+//   [4096, data)       slot table, one 48-byte entry per frame: seq + 1, byte offset,
+//                      length, media type, key flag, pts, dts, track id
+//   [data, data + cap) payload bytes, frames written back to back and wrapping around
+//
+// slots and configs are seqlocked: a reader copies the entry and the payload, then
+// checks that the slot still holds the same frame and that the writer has not reserved
+// the payload's bytes for a newer frame since.
+
+const MAGIC: u32 = 0x4d44_5247;
+// version 1 had no track id
+const VERSION: u32 = 2;
+const HEADER_SIZE: usize = 4096;
+const SLOT_SIZE: usize = 48;
+const CONFIG_OFFSET: usize = 1024;
+const CONFIG_SLOT_SIZE: usize = 1536;
+const NO_KEY_FRAME: u64 = u64::MAX;
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+// header fields
+const CAPACITY: usize = 8;
+const SLOT_COUNT: usize = 16;
+const WRITE_SEQ: usize = 24;
+const RESERVE_POS: usize = 32;
+const LAST_KEY_SEQ: usize = 40;
+const READERS: usize = 48;
+const CLOSED: usize = 52;
+
+// slot fields
+const SLOT_SEQ: usize = 0;
+const SLOT_OFFSET: usize = 8;
+const SLOT_LEN: usize = 16;
+const SLOT_INFO: usize = 20;
+const SLOT_PTS: usize = 24;
+const SLOT_DTS: usize = 32;
+const SLOT_TRACK: usize = 40;
+
+pub fn shm_path(name: &str) -> PathBuf {
+    let dir = Path::new("/dev/shm");
+    if dir.is_dir() {
+        dir.join(name)
+    } else {
+        std::env::temp_dir().join(name)
+    }
+}
+
+struct Mapping {
+    ptr: *mut u8,
+    len: usize,
+    capacity: u64,
+    slot_count: u64,
+    data_offset: usize,
+}
+
+// the mapping is only accessed through atomics and seqlock-validated copies
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn map(file: &File, len: usize) -> io::Result<*mut u8> {
+        let ptr = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                len,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(ptr as *mut u8)
+    }
+
+    fn layout(capacity: u64, slot_count: u64) -> (usize, usize) {
+        let table = slot_count as usize * SLOT_SIZE;
+        let data_offset = (HEADER_SIZE + table).div_ceil(4096) * 4096;
+        (data_offset, data_offset + capacity as usize)
+    }
+
+    fn create(path: &Path, capacity: u64, slot_count: u64) -> io::Result<Mapping> {
+        if capacity == 0 || slot_count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "empty media ring",
+            ));
+        }
+        let (data_offset, len) = Self::layout(capacity, slot_count);
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(len as u64)?;
+        let mapping = Mapping {
+            ptr: Self::map(&file, len)?,
+            len,
+            capacity,
+            slot_count,
+            data_offset,
+        };
+        mapping.u64_at(CAPACITY).store(capacity, Ordering::Relaxed);
+        mapping
+            .u64_at(SLOT_COUNT)
+            .store(slot_count, Ordering::Relaxed);
+        mapping
+            .u64_at(LAST_KEY_SEQ)
+            .store(NO_KEY_FRAME, Ordering::Relaxed);
+        mapping.u32_at(4).store(VERSION, Ordering::Relaxed);
+        // readers check the magic last
+        mapping.u32_at(0).store(MAGIC, Ordering::Release);
+        Ok(mapping)
+    }
+
+    fn open(path: &Path) -> io::Result<Mapping> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // mapping past the end of a file that is still being created would fault
+        let file_len = file.metadata()?.len() as usize;
+        if file_len < HEADER_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::WouldBlock,
+                "media ring not ready",
+            ));
+        }
+        let header = Self::map(&file, HEADER_SIZE)?;
+        let (magic, version, capacity, slot_count) = unsafe {
+            let magic = (*(header as *const AtomicU32)).load(Ordering::Acquire);
+            let version = (*(header.add(4) as *const AtomicU32)).load(Ordering::Relaxed);
+            let capacity = (*(header.add(CAPACITY) as *const AtomicU64)).load(Ordering::Relaxed);
+            let slot_count =
+                (*(header.add(SLOT_COUNT) as *const AtomicU64)).load(Ordering::Relaxed);
+            libc::munmap(header as *mut libc::c_void, HEADER_SIZE);
+            (magic, version, capacity, slot_count)
+        };
+        if magic != MAGIC || version != VERSION || capacity == 0 || slot_count == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a media ring",
+            ));
+        }
+        let (data_offset, len) = Self::layout(capacity, slot_count);
+        if file_len < len {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated media ring",
+            ));
+        }
+        Ok(Mapping {
+            ptr: Self::map(&file, len)?,
+            len,
+            capacity,
+            slot_count,
+            data_offset,
+        })
+    }
+
+    fn u64_at(&self, offset: usize) -> &AtomicU64 {
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU64) }
+    }
+
+    fn u32_at(&self, offset: usize) -> &AtomicU32 {
+        unsafe { &*(self.ptr.add(offset) as *const AtomicU32) }
+    }
+
+    fn slot(&self, seq: u64) -> usize {
+        HEADER_SIZE + (seq % self.slot_count) as usize * SLOT_SIZE
+    }
+
+    fn config_slot(media_type: MediaType) -> usize {
+        match media_type {
+            MediaType::AUDIO => CONFIG_OFFSET,
+            _ => CONFIG_OFFSET + CONFIG_SLOT_SIZE,
+        }
+    }
+
+    // copies `len` bytes in or out of the data area at a logical position, wrapping around
+    fn copy(&self, pos: u64, len: usize, mut copy: impl FnMut(*mut u8, usize, usize)) {
+        let start = (pos % self.capacity) as usize;
+        let first = len.min(self.capacity as usize - start);
+        let data = unsafe { self.ptr.add(self.data_offset) };
+        copy(unsafe { data.add(start) }, 0, first);
+        if first < len {
+            copy(data, first, len - first);
+        }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        unsafe {
+            libc::munmap(self.ptr as *mut libc::c_void, self.len);
+        }
+    }
+}
+
+// writing end of the ring, the file is removed when it is dropped
+pub struct ShmRing {
+    path: PathBuf,
+    mapping: Mapping,
+    seq: u64,
+    pos: u64,
+}
+
+impl ShmRing {
+    pub fn create(path: &Path, capacity: usize, slot_count: usize) -> io::Result<Self> {
+        info!(
+            "create shm ring: {:?}, capacity: {}, slots: {}",
+            path, capacity, slot_count
+        );
+        Ok(ShmRing {
+            path: path.to_path_buf(),
+            mapping: Mapping::create(path, capacity as u64, slot_count.max(1) as u64)?,
+            seq: 0,
+            pos: 0,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn reader_count(&self) -> u32 {
+        self.mapping.u32_at(READERS).load(Ordering::Acquire)
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.seq
+    }
+
+    pub fn write(&mut self, data: &MediaData) -> bool {
+        let buff = data.buff.lock().unwrap().data();
+        let payload = buff.lock().unwrap();
+        let mapping = &self.mapping;
+        if payload.len() as u64 > mapping.capacity {
+            error!(
+                "frame of {} bytes does not fit the ring, dropped",
+                payload.len()
+            );
+            return false;
+        }
+
+        let (seq, pos) = (self.seq, self.pos);
+        let slot = mapping.slot(seq);
+        // invalidate the slot and reserve the bytes before overwriting them
+        mapping.u64_at(slot + SLOT_SEQ).store(0, Ordering::Relaxed);
+        mapping
+            .u64_at(RESERVE_POS)
+            .store(pos + payload.len() as u64, Ordering::Relaxed);
+        fence(Ordering::Release);
+
+        mapping.copy(pos, payload.len(), |dst, from, len| unsafe {
+            ptr::copy_nonoverlapping(payload.as_ptr().add(from), dst, len);
+        });
+        mapping
+            .u64_at(slot + SLOT_OFFSET)
+            .store(pos, Ordering::Relaxed);
+        mapping
+            .u32_at(slot + SLOT_LEN)
+            .store(payload.len() as u32, Ordering::Relaxed);
+        let info = ((data.key_frame as u32) << 8) | media_type_code(data.media_type) as u32;
+        mapping
+            .u32_at(slot + SLOT_INFO)
+            .store(info, Ordering::Relaxed);
+        mapping
+            .u64_at(slot + SLOT_PTS)
+            .store(data.pts, Ordering::Relaxed);
+        mapping
+            .u64_at(slot + SLOT_DTS)
+            .store(data.dts, Ordering::Relaxed);
+        mapping
+            .u32_at(slot + SLOT_TRACK)
+            .store(data.track_id, Ordering::Relaxed);
+        mapping
+            .u64_at(slot + SLOT_SEQ)
+            .store(seq + 1, Ordering::Release);
+
+        if data.media_type == MediaType::VIDEO && data.key_frame {
+            mapping.u64_at(LAST_KEY_SEQ).store(seq, Ordering::Release);
+        }
+        self.seq += 1;
+        self.pos += payload.len() as u64;
+        mapping.u64_at(WRITE_SEQ).store(self.seq, Ordering::Release);
+        true
+    }
+
+    pub fn set_codec_config(&mut self, config: &CodecConfig) -> bool {
+        let body = encode_config(config);
+        if body.len() > CONFIG_SLOT_SIZE - 8 {
+            error!("codec config of {} bytes does not fit", body.len());
+            return false;
+        }
+        let slot = Mapping::config_slot(config.media_type());
+        let version = self.mapping.u32_at(slot);
+        let current = version.load(Ordering::Relaxed);
+        // odd while the config is being written
+        version.store(current + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        self.mapping
+            .u32_at(slot + 4)
+            .store(body.len() as u32, Ordering::Relaxed);
+        unsafe {
+            ptr::copy_nonoverlapping(body.as_ptr(), self.mapping.ptr.add(slot + 8), body.len());
+        }
+        version.store(current + 2, Ordering::Release);
+        true
+    }
+
+    // readers drain what is left and then see the end of the stream
+    pub fn close(&mut self) {
+        self.mapping.u32_at(CLOSED).store(1, Ordering::Release);
+    }
+}
+
+impl Drop for ShmRing {
+    fn drop(&mut self) {
+        self.close();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+// reading end of the ring with its own cursor. like a Receiver it starts at the last
+// key frame, reads one media type or both, and in key-only mode skips video frames
+// that are not key frames. a reader that falls behind the writer skips ahead to the
+// newest key frame still in the ring.
+pub struct ShmReader {
+    mapping: Mapping,
+    media_type: MediaType,
+    key_only: bool,
+    cursor: u64,
+    waiting_key_frame: bool,
+    lost_frames: u64,
+}
+
+impl ShmReader {
+    pub fn open(path: &Path, media_type: MediaType) -> io::Result<Self> {
+        let mapping = Mapping::open(path)?;
+        mapping.u32_at(READERS).fetch_add(1, Ordering::AcqRel);
+        let mut reader = ShmReader {
+            mapping,
+            media_type,
+            key_only: false,
+            cursor: 0,
+            waiting_key_frame: false,
+            lost_frames: 0,
+        };
+        reader.resync();
+        reader.lost_frames = 0;
+        info!("open shm reader: {:?}, cursor: {}", path, reader.cursor);
+        Ok(reader)
+    }
+
+    pub fn set_key_mode(&mut self, enable: bool) {
+        self.key_only = enable;
+    }
+
+    // frames skipped because the writer overtook this reader
+    pub fn lost_frames(&self) -> u64 {
+        self.lost_frames
+    }
+
+    pub fn is_closed(&self) -> bool {
+        self.mapping.u32_at(CLOSED).load(Ordering::Acquire) != 0
+    }
+
+    pub fn get_codec_config(&self, media_type: MediaType) -> Option<CodecConfig> {
+        let slot = Mapping::config_slot(media_type);
+        let version = self.mapping.u32_at(slot);
+        loop {
+            let before = version.load(Ordering::Acquire);
+            if before == 0 {
+                return None;
+            }
+            if before % 2 == 1 {
+                thread::yield_now();
+                continue;
+            }
+            let len = (self.mapping.u32_at(slot + 4).load(Ordering::Relaxed) as usize)
+                .min(CONFIG_SLOT_SIZE - 8);
+            let mut body = vec![0u8; len];
+            unsafe {
+                ptr::copy_nonoverlapping(self.mapping.ptr.add(slot + 8), body.as_mut_ptr(), len);
+            }
+            fence(Ordering::Acquire);
+            if version.load(Ordering::Relaxed) == before {
+                return match decode(&body) {
+                    Some(Packet::CONFIG(config)) => Some(config),
+                    _ => None,
+                };
+            }
+        }
+    }
+
+    // the next frame for this reader, None when the writer has nothing newer
+    pub fn try_read(&mut self) -> Option<MediaData> {
+        loop {
+            let write_seq = self.mapping.u64_at(WRITE_SEQ).load(Ordering::Acquire);
+            if self.cursor >= write_seq {
+                return None;
+            }
+            if write_seq - self.cursor > self.mapping.slot_count {
+                self.resync();
+                continue;
+            }
+            let data = match self.read_slot(self.cursor) {
+                Some(data) => data,
+                None => {
+                    self.resync();
+                    continue;
+                }
+            };
+            self.cursor += 1;
+
+            if self.media_type != MediaType::AV && data.media_type != self.media_type {
+                continue;
+            }
+            if data.media_type == MediaType::VIDEO {
+                if self.waiting_key_frame && !data.key_frame {
+                    continue;
+                }
+                self.waiting_key_frame = false;
+                if self.key_only && !data.key_frame {
+                    continue;
+                }
+            }
+            return Some(data);
+        }
+    }
+
+    // blocks until a frame arrives, the ring is closed or the timeout passes
+    pub fn read_timeout(&mut self, timeout: Duration) -> Option<MediaData> {
+        let start = Instant::now();
+        loop {
+            if let Some(data) = self.try_read() {
+                return Some(data);
+            }
+            if self.is_closed() || start.elapsed() >= timeout {
+                return None;
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+
+    fn read_slot(&self, seq: u64) -> Option<MediaData> {
+        let mapping = &self.mapping;
+        let slot = mapping.slot(seq);
+        if mapping.u64_at(slot + SLOT_SEQ).load(Ordering::Acquire) != seq + 1 {
+            return None;
+        }
+        let pos = mapping.u64_at(slot + SLOT_OFFSET).load(Ordering::Relaxed);
+        let len = mapping.u32_at(slot + SLOT_LEN).load(Ordering::Relaxed) as usize;
+        let info = mapping.u32_at(slot + SLOT_INFO).load(Ordering::Relaxed);
+        let pts = mapping.u64_at(slot + SLOT_PTS).load(Ordering::Relaxed);
+        let dts = mapping.u64_at(slot + SLOT_DTS).load(Ordering::Relaxed);
+        let track_id = mapping.u32_at(slot + SLOT_TRACK).load(Ordering::Relaxed);
+        if len as u64 > mapping.capacity {
+            return None;
+        }
+        let mut payload = vec![0u8; len];
+        mapping.copy(pos, len, |src, to, len| unsafe {
+            ptr::copy_nonoverlapping(src, payload.as_mut_ptr().add(to), len);
+        });
+
+        fence(Ordering::Acquire);
+        let reserved = mapping.u64_at(RESERVE_POS).load(Ordering::Relaxed);
+        if mapping.u64_at(slot + SLOT_SEQ).load(Ordering::Relaxed) != seq + 1
+            || reserved.saturating_sub(pos) > mapping.capacity
+        {
+            return None;
+        }
+        let mut data = media_data(
+            media_type_from_code(info as u8)?,
+            pts,
+            dts,
+            info >> 8 != 0,
+            payload,
+        );
+        data.track_id = track_id;
+        Some(data)
+    }
+
+    // moves to the newest key frame still readable, or waits for the next one
+    fn resync(&mut self) {
+        let write_seq = self.mapping.u64_at(WRITE_SEQ).load(Ordering::Acquire);
+        let last_key = self.mapping.u64_at(LAST_KEY_SEQ).load(Ordering::Acquire);
+        let cursor = if last_key != NO_KEY_FRAME && self.read_slot(last_key).is_some() {
+            self.waiting_key_frame = false;
+            last_key
+        } else {
+            self.waiting_key_frame = true;
+            write_seq
+        };
+        if cursor > self.cursor {
+            debug!("shm reader skips {} frames", cursor - self.cursor);
+            self.lost_frames += cursor - self.cursor;
+        }
+        self.cursor = cursor;
+    }
+}
+
+impl Drop for ShmReader {
+    fn drop(&mut self) {
+        self.mapping.u32_at(READERS).fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+// feeds a ring from a dispatcher through a receiver
+pub struct ShmWriter {
+    receiver: Arc<Receiver>,
+    ring: Arc<Mutex<ShmRing>>,
+    write_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl ShmWriter {
+    pub fn new(
+        dispatcher: Arc<Mutex<Dispatcher>>,
+        path: &Path,
+        capacity: usize,
+        slot_count: usize,
+    ) -> io::Result<Self> {
+        let writer = ShmWriter {
+            receiver: Arc::new(Receiver::new()),
+            ring: Arc::new(Mutex::new(ShmRing::create(path, capacity, slot_count)?)),
+            write_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        };
+
+        dispatcher
+            .lock()
+            .unwrap()
+            .attach_receiver(writer.receiver.clone());
+        writer.receiver.set_dispatcher(dispatcher.clone());
+        Ok(writer)
+    }
+
+    pub fn reader_count(&self) -> u32 {
+        self.ring.lock().unwrap().reader_count()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.ring.lock().unwrap().frame_count()
+    }
+
+    pub fn start_write(&mut self) {
+        info!("start shm write");
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let receiver = self.receiver.clone();
+        let ring = self.ring.clone();
+
+        self.write_thread = Some(thread::spawn(move || {
+            let mut configs: [Option<CodecConfig>; 2] = [None, None];
+            while *running.lock().unwrap() {
+                let (ret, out_data) = receiver.request_read(MediaType::AV);
+                if !ret || out_data.is_none() || !*running.lock().unwrap() {
+                    continue;
+                }
+
+                let mut ring = ring.lock().unwrap();
+                for (media_type, sent) in [MediaType::VIDEO, MediaType::AUDIO]
+                    .into_iter()
+                    .zip(configs.iter_mut())
+                {
+                    if let Some(config) = receiver.get_codec_config(media_type) {
+                        if sent.as_ref() != Some(&config) {
+                            ring.set_codec_config(&config);
+                            *sent = Some(config);
+                        }
+                    }
+                }
+                let binding = out_data.unwrap();
+                let data = binding.lock().unwrap();
+                ring.write(&data);
+            }
+            ring.lock().unwrap().close();
+            info!("shm write done");
+        }));
+    }
+
+    pub fn stop_write(&mut self) {
+        debug!("stop shm write begin");
+        *self.running.lock().unwrap() = false;
+        self.receiver.notify_read_stop();
+        if let Some(write_thread) = self.write_thread.take() {
+            write_thread.join().expect("can not join the write thread");
+        }
+        debug!("stop shm write end");
+    }
+}
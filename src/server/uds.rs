@@ -0,0 +1,271 @@
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::{
+    ipc::{
+        encode_config, media_type_code, media_type_from_code, read_message, write_data,
+        write_message, FORMAT_VERSION,
+    },
+    Packet,
+};
+use crate::utils::{buffer::MediaType, codec::CodecConfig, Identity};
+use crate::{debug, error, info, warn};
+use std::{
+    collections::HashMap,
+    fs,
+    io::{self, Read, Write},
+    net::Shutdown,
+    os::unix::net::{UnixListener, UnixStream},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+type Connections = Arc<Mutex<HashMap<u32, (Arc<Receiver>, UnixStream)>>>;
+
+// serves a dispatcher's frames over a unix domain socket with the ipc framing.
+// a client opens with three bytes, the ipc format version, the media type code and
+// the key-only flag, and then only reads: codec configs come before the first frame
+// of a track and again whenever they change.
+pub struct UdsServer {
+    path: PathBuf,
+    listener: Arc<UnixListener>,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    connections: Connections,
+    accept_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl UdsServer {
+    pub fn new(path: &Path, dispatcher: Arc<Mutex<Dispatcher>>) -> io::Result<Self> {
+        // a socket file left behind by a previous run would fail the bind
+        if path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(UdsServer {
+            path: path.to_path_buf(),
+            listener: Arc::new(UnixListener::bind(path)?),
+            dispatcher,
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            accept_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn start_serve(&mut self) {
+        info!("start uds serve: {:?}", self.path);
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let listener = self.listener.clone();
+        let dispatcher = self.dispatcher.clone();
+        let connections = self.connections.clone();
+
+        self.accept_thread = Some(thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !*running.lock().unwrap() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let dispatcher = dispatcher.clone();
+                let connections = connections.clone();
+                let running = running.clone();
+                thread::spawn(move || serve_connection(stream, dispatcher, connections, running));
+            }
+            info!("uds accept done");
+        }));
+    }
+
+    pub fn stop_serve(&mut self) {
+        debug!("stop uds serve begin");
+        *self.running.lock().unwrap() = false;
+        // wake up the blocking accept
+        let _ = UnixStream::connect(&self.path);
+        if let Some(accept_thread) = self.accept_thread.take() {
+            accept_thread
+                .join()
+                .expect("can not join the accept thread");
+        }
+        for (receiver, stream) in self.connections.lock().unwrap().values() {
+            receiver.notify_read_stop();
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+        let _ = fs::remove_file(&self.path);
+        debug!("stop uds serve end");
+    }
+}
+
+fn serve_connection(
+    mut stream: UnixStream,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    connections: Connections,
+    running: Arc<Mutex<bool>>,
+) {
+    let mut request = [0u8; 3];
+    if let Err(e) = stream.read_exact(&mut request) {
+        debug!("uds client gone before subscribing: {}", e);
+        return;
+    }
+    if request[0] != FORMAT_VERSION {
+        warn!("unsupported ipc format version: {}", request[0]);
+        return;
+    }
+    let media_type = match media_type_from_code(request[1]) {
+        Some(media_type) => media_type,
+        None => {
+            warn!("invalid media type: {}", request[1]);
+            return;
+        }
+    };
+    let key_only = request[2] != 0;
+
+    let receiver = Arc::new(Receiver::new());
+    // the other tracks are only delivered to receivers subscribed to them
+    if !matches!(
+        media_type,
+        MediaType::AV | MediaType::AUDIO | MediaType::VIDEO
+    ) {
+        receiver.set_tracks(&[media_type]);
+    }
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver.set_key_mode(key_only);
+    match stream.try_clone() {
+        Ok(clone) => {
+            connections
+                .lock()
+                .unwrap()
+                .insert(receiver.get_id(), (receiver.clone(), clone));
+        }
+        Err(e) => error!("clone connection failed: {}", e),
+    }
+    info!(
+        "uds subscribe: {:?}, key only: {}, receiver: {}",
+        media_type,
+        key_only,
+        receiver.get_id()
+    );
+
+    if let Err(e) = serve(&mut stream, &receiver, media_type, &running) {
+        debug!("uds connection closed: {}", e);
+    }
+
+    receiver.detach();
+    connections.lock().unwrap().remove(&receiver.get_id());
+    info!("uds serve done, receiver: {}", receiver.get_id());
+}
+
+fn serve(
+    stream: &mut UnixStream,
+    receiver: &Receiver,
+    media_type: MediaType,
+    running: &Mutex<bool>,
+) -> io::Result<()> {
+    let tracks = match media_type {
+        MediaType::AV => vec![MediaType::VIDEO, MediaType::AUDIO],
+        media_type => vec![media_type],
+    };
+    let mut sent_configs: Vec<Option<CodecConfig>> = vec![None; tracks.len()];
+
+    while *running.lock().unwrap() {
+        let (ret, out_data) = receiver.request_read(media_type);
+        if !receiver.is_attached() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "stream ended"));
+        }
+        if !ret || out_data.is_none() {
+            continue;
+        }
+
+        for (track, sent) in tracks.iter().zip(sent_configs.iter_mut()) {
+            if let Some(config) = receiver.get_codec_config(*track) {
+                if sent.as_ref() != Some(&config) {
+                    write_message(stream, &encode_config(&config))?;
+                    *sent = Some(config);
+                }
+            }
+        }
+        let binding = out_data.unwrap();
+        let data = binding.lock().unwrap();
+        write_data(stream, &data)?;
+    }
+    Ok(())
+}
+
+// reads a UdsServer's frames, either one by one or re-injected into a local dispatcher
+pub struct UdsClient {
+    stream: UnixStream,
+    inject_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl UdsClient {
+    pub fn connect(path: &Path, media_type: MediaType, key_only: bool) -> io::Result<Self> {
+        let mut stream = UnixStream::connect(path)?;
+        stream.write_all(&[FORMAT_VERSION, media_type_code(media_type), key_only as u8])?;
+        Ok(UdsClient {
+            stream,
+            inject_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn read_packet(&mut self) -> io::Result<Packet> {
+        read_message(&mut self.stream)
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    // configs go to set_codec_config and frames to input_data until the server goes away
+    pub fn start_inject(&mut self, dispatcher: Arc<Mutex<Dispatcher>>) -> io::Result<()> {
+        info!("start uds inject");
+        let mut stream = self.stream.try_clone()?;
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+
+        self.inject_thread = Some(thread::spawn(move || {
+            while *running.lock().unwrap() {
+                match read_message(&mut stream) {
+                    Ok(Packet::CONFIG(config)) => {
+                        dispatcher.lock().unwrap().set_codec_config(config)
+                    }
+                    Ok(Packet::DATA(data)) => dispatcher
+                        .lock()
+                        .unwrap()
+                        .input_data(Arc::new(Mutex::new(data))),
+                    Err(e) => {
+                        debug!("uds read failed: {}", e);
+                        break;
+                    }
+                }
+            }
+            *running.lock().unwrap() = false;
+            info!("uds inject done");
+        }));
+        Ok(())
+    }
+
+    pub fn stop_inject(&mut self) {
+        debug!("stop uds inject begin");
+        *self.running.lock().unwrap() = false;
+        let _ = self.stream.shutdown(Shutdown::Both);
+        if let Some(inject_thread) = self.inject_thread.take() {
+            inject_thread
+                .join()
+                .expect("can not join the inject thread");
+        }
+        debug!("stop uds inject end");
+    }
+}
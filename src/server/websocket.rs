@@ -0,0 +1,551 @@
+use super::{read_request, write_status, HttpRequest};
+use crate::dispatcher::{dispatcher::Dispatcher, receiver::Receiver};
+use crate::format::{
+    flv::FlvMuxer,
+    fmp4::{Fmp4Muxer, FragmentMode},
+};
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    Identity,
+};
+use crate::{debug, error, info, warn};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde_json::{json, Value};
+use sha1::{Digest, Sha1};
+use std::{
+    collections::HashMap,
+    io::{self, Read, Write},
+    net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+};
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xa;
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+// bound on client messages, only control messages are expected
+const MAX_MESSAGE_SIZE: usize = 65536;
+
+type Streams = Arc<Mutex<HashMap<String, Arc<Mutex<Dispatcher>>>>>;
+type Connections = Arc<Mutex<HashMap<u32, Arc<Connection>>>>;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum WsFormat {
+    FLV,
+    FMP4,
+}
+
+struct Connection {
+    receiver: Arc<Receiver>,
+    output: Mutex<TcpStream>,
+    // shut down without the output lock, which a blocked write may hold
+    socket: TcpStream,
+    paused: AtomicBool,
+    // set on pause, video restarts at a key frame after it
+    resync: AtomicBool,
+    closed: AtomicBool,
+}
+
+impl Connection {
+    fn send(&self, opcode: u8, payload: &[u8]) -> io::Result<()> {
+        write_frame(&mut *self.output.lock().unwrap(), opcode, payload, None)
+    }
+
+    fn send_json(&self, value: &Value) -> io::Result<()> {
+        self.send(OPCODE_TEXT, value.to_string().as_bytes())
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::Release);
+        self.receiver.notify_read_stop();
+        let _ = self.socket.shutdown(Shutdown::Both);
+    }
+}
+
+// serves `/ws/<stream>.flv` and `/ws/<stream>.mp4`: binary messages carry FLV tags or
+// fMP4 segments, text messages carry the JSON control channel
+pub struct WebSocketServer {
+    listener: Arc<TcpListener>,
+    streams: Streams,
+    connections: Connections,
+    accept_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl WebSocketServer {
+    pub fn new(addr: impl ToSocketAddrs) -> io::Result<Self> {
+        Ok(WebSocketServer {
+            listener: Arc::new(TcpListener::bind(addr)?),
+            streams: Arc::new(Mutex::new(HashMap::new())),
+            connections: Arc::new(Mutex::new(HashMap::new())),
+            accept_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn add_stream(&self, name: &str, dispatcher: Arc<Mutex<Dispatcher>>) {
+        info!("add websocket stream: {}", name);
+        self.streams
+            .lock()
+            .unwrap()
+            .insert(name.to_string(), dispatcher);
+    }
+
+    pub fn remove_stream(&self, name: &str) {
+        info!("remove websocket stream: {}", name);
+        self.streams.lock().unwrap().remove(name);
+    }
+
+    pub fn connection_count(&self) -> usize {
+        self.connections.lock().unwrap().len()
+    }
+
+    pub fn start_serve(&mut self) {
+        info!("start websocket serve: {:?}", self.listener.local_addr());
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let listener = self.listener.clone();
+        let streams = self.streams.clone();
+        let connections = self.connections.clone();
+
+        self.accept_thread = Some(thread::spawn(move || {
+            for stream in listener.incoming() {
+                if !*running.lock().unwrap() {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        error!("accept failed: {}", e);
+                        continue;
+                    }
+                };
+                let streams = streams.clone();
+                let connections = connections.clone();
+                let running = running.clone();
+                thread::spawn(move || serve_connection(stream, streams, connections, running));
+            }
+            info!("websocket accept done");
+        }));
+    }
+
+    pub fn stop_serve(&mut self) {
+        debug!("stop websocket serve begin");
+        *self.running.lock().unwrap() = false;
+        // wake up the blocking accept
+        if let Ok(addr) = self.listener.local_addr() {
+            let _ = TcpStream::connect(addr);
+        }
+        if let Some(accept_thread) = self.accept_thread.take() {
+            accept_thread
+                .join()
+                .expect("can not join the accept thread");
+        }
+        for connection in self.connections.lock().unwrap().values() {
+            connection.close();
+        }
+        debug!("stop websocket serve end");
+    }
+}
+
+// Sec-WebSocket-Accept value for a client key
+pub fn accept_key(key: &str) -> String {
+    let digest = Sha1::digest(format!("{}{}", key, ACCEPT_GUID).as_bytes());
+    STANDARD.encode(digest)
+}
+
+// writes one unfragmented frame, clients must pass a mask
+pub fn write_frame(
+    output: &mut impl Write,
+    opcode: u8,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> io::Result<()> {
+    let mut header = vec![0x80 | opcode];
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+    match payload.len() {
+        len if len < 126 => header.push(mask_bit | len as u8),
+        len if len <= u16::MAX as usize => {
+            header.push(mask_bit | 126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(mask_bit | 127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    match mask {
+        Some(mask) => {
+            header.extend_from_slice(&mask);
+            let masked: Vec<u8> = payload
+                .iter()
+                .enumerate()
+                .map(|(i, byte)| byte ^ mask[i % 4])
+                .collect();
+            header.extend_from_slice(&masked);
+            output.write_all(&header)
+        }
+        None => {
+            output.write_all(&header)?;
+            output.write_all(payload)
+        }
+    }
+}
+
+// joins continuation frames into messages; control frames may come between the
+// fragments of a message and are returned as they come, keeping the partial message
+pub struct MessageReader {
+    max_size: usize,
+    opcode: Option<u8>,
+    message: Vec<u8>,
+}
+
+impl MessageReader {
+    pub fn new(max_size: usize) -> Self {
+        MessageReader {
+            max_size,
+            opcode: None,
+            message: Vec::new(),
+        }
+    }
+
+    pub fn read(&mut self, input: &mut impl Read) -> io::Result<(u8, Vec<u8>)> {
+        loop {
+            let mut head = [0u8; 2];
+            input.read_exact(&mut head)?;
+            let fin = head[0] & 0x80 != 0;
+            let opcode = head[0] & 0x0f;
+            let len = match head[1] & 0x7f {
+                126 => {
+                    let mut len = [0u8; 2];
+                    input.read_exact(&mut len)?;
+                    u16::from_be_bytes(len) as u64
+                }
+                127 => {
+                    let mut len = [0u8; 8];
+                    input.read_exact(&mut len)?;
+                    u64::from_be_bytes(len)
+                }
+                len => len as u64,
+            };
+            // control frames are not part of the message and do not count against it
+            let buffered = if opcode >= OPCODE_CLOSE {
+                0
+            } else {
+                self.message.len()
+            };
+            let len = match usize::try_from(len)
+                .ok()
+                .and_then(|len| len.checked_add(buffered).map(|total| (len, total)))
+            {
+                Some((len, total)) if total <= self.max_size => len,
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "message too large",
+                    ))
+                }
+            };
+            let mut mask = [0u8; 4];
+            if head[1] & 0x80 != 0 {
+                input.read_exact(&mut mask)?;
+            }
+            let mut payload = vec![0u8; len];
+            input.read_exact(&mut payload)?;
+            for (i, byte) in payload.iter_mut().enumerate() {
+                *byte ^= mask[i % 4];
+            }
+
+            if opcode >= OPCODE_CLOSE {
+                return Ok((opcode, payload));
+            }
+            if opcode != OPCODE_CONTINUATION {
+                self.opcode = Some(opcode);
+            }
+            self.message.extend_from_slice(&payload);
+            if fin {
+                let opcode = self.opcode.take().unwrap_or(OPCODE_BINARY);
+                return Ok((opcode, std::mem::take(&mut self.message)));
+            }
+        }
+    }
+}
+
+// reads one message with a reader of its own, a message that a control frame
+// interrupts is lost with it; keep a MessageReader per connection for those
+pub fn read_message(input: &mut impl Read, max_size: usize) -> io::Result<(u8, Vec<u8>)> {
+    MessageReader::new(max_size).read(input)
+}
+
+fn stream_target(path: &str) -> Option<(&str, WsFormat)> {
+    let name = path.strip_prefix("/ws/")?;
+    let (name, format) = if let Some(name) = name.strip_suffix(".flv") {
+        (name, WsFormat::FLV)
+    } else {
+        (name.strip_suffix(".mp4")?, WsFormat::FMP4)
+    };
+    if name.is_empty() || name.contains('/') {
+        return None;
+    }
+    Some((name, format))
+}
+
+fn handshake(stream: &mut TcpStream, request: &HttpRequest) -> io::Result<bool> {
+    let upgrade = request
+        .header("upgrade")
+        .is_some_and(|value| value.eq_ignore_ascii_case("websocket"));
+    let key = match request.header("sec-websocket-key") {
+        Some(key) if upgrade && request.method == "GET" => key,
+        _ => {
+            write_status(stream, "400 Bad Request")?;
+            return Ok(false);
+        }
+    };
+    write!(
+        stream,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key)
+    )?;
+    Ok(true)
+}
+
+fn serve_connection(
+    mut stream: TcpStream,
+    streams: Streams,
+    connections: Connections,
+    running: Arc<Mutex<bool>>,
+) {
+    let request = match read_request(&mut stream) {
+        Ok(request) => request,
+        Err(e) => {
+            warn!("bad request: {}", e);
+            let _ = write_status(&mut stream, "400 Bad Request");
+            return;
+        }
+    };
+    let target = stream_target(&request.path).and_then(|(name, format)| {
+        let dispatcher = streams.lock().unwrap().get(name).cloned()?;
+        Some((dispatcher, format))
+    });
+    let (dispatcher, format) = match target {
+        Some(target) => target,
+        None => {
+            warn!("unknown stream: {}", request.path);
+            let _ = write_status(&mut stream, "404 Not Found");
+            return;
+        }
+    };
+    match handshake(&mut stream, &request) {
+        Ok(true) => {}
+        Ok(false) => return,
+        Err(e) => {
+            warn!("websocket handshake failed: {}", e);
+            return;
+        }
+    }
+    let (input, socket) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(input), Ok(socket)) => (input, socket),
+        (Err(e), _) | (_, Err(e)) => {
+            error!("clone connection failed: {}", e);
+            return;
+        }
+    };
+
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    let connection = Arc::new(Connection {
+        receiver: receiver.clone(),
+        output: Mutex::new(stream),
+        socket,
+        paused: AtomicBool::new(false),
+        resync: AtomicBool::new(false),
+        closed: AtomicBool::new(false),
+    });
+    connections
+        .lock()
+        .unwrap()
+        .insert(receiver.get_id(), connection.clone());
+    info!(
+        "websocket play: {}, receiver: {}",
+        request.path,
+        receiver.get_id()
+    );
+
+    let control = connection.clone();
+    let control_thread = thread::spawn(move || read_control(input, &control));
+
+    if let Err(e) = play(&connection, format, &running) {
+        debug!("websocket connection closed: {}", e);
+    }
+
+    connection.close();
+    control_thread
+        .join()
+        .expect("can not join the control thread");
+    receiver.detach();
+    connections.lock().unwrap().remove(&receiver.get_id());
+    info!("websocket play done, receiver: {}", receiver.get_id());
+}
+
+// control messages: {"cmd": "key_mode", "enable": bool}, {"cmd": "pause"}, {"cmd": "resume"}
+fn read_control(mut input: TcpStream, connection: &Connection) {
+    let mut reader = MessageReader::new(MAX_MESSAGE_SIZE);
+    while !connection.closed.load(Ordering::Acquire) {
+        let (opcode, payload) = match reader.read(&mut input) {
+            Ok(message) => message,
+            Err(e) => {
+                debug!("websocket read failed: {}", e);
+                break;
+            }
+        };
+        let ret = match opcode {
+            OPCODE_TEXT => handle_control(connection, &payload),
+            OPCODE_PING => connection.send(OPCODE_PONG, &payload),
+            OPCODE_CLOSE => {
+                let _ = connection.send(OPCODE_CLOSE, &payload);
+                break;
+            }
+            _ => Ok(()),
+        };
+        if ret.is_err() {
+            break;
+        }
+    }
+    connection.close();
+}
+
+fn handle_control(connection: &Connection, payload: &[u8]) -> io::Result<()> {
+    let message: Value = match serde_json::from_slice(payload) {
+        Ok(message) => message,
+        Err(e) => {
+            warn!("invalid control message: {}", e);
+            return connection.send_json(&json!({"event": "error", "message": e.to_string()}));
+        }
+    };
+    let cmd = message["cmd"].as_str().unwrap_or_default();
+    info!(
+        "websocket control: {}, receiver: {}",
+        cmd,
+        connection.receiver.get_id()
+    );
+    match cmd {
+        "key_mode" => {
+            let enable = message["enable"].as_bool().unwrap_or(true);
+            connection.receiver.set_key_mode(enable);
+        }
+        "pause" => {
+            connection.paused.store(true, Ordering::Release);
+            connection.resync.store(true, Ordering::Release);
+        }
+        "resume" => connection.paused.store(false, Ordering::Release),
+        _ => {
+            return connection
+                .send_json(&json!({"event": "error", "message": format!("unknown cmd: {}", cmd)}))
+        }
+    }
+    connection.send_json(&json!({"event": "ack", "cmd": cmd}))
+}
+
+enum Packager {
+    FlvTags(Option<FlvMuxer>),
+    Fmp4Fragments(Option<Fmp4Muxer>),
+}
+
+impl Packager {
+    // binary messages for one frame, the first ones carry the stream headers
+    fn package(&mut self, receiver: &Receiver, data: &MediaData) -> Vec<Vec<u8>> {
+        let mut messages = Vec::new();
+        match self {
+            Packager::FlvTags(muxer) => {
+                let muxer = muxer.get_or_insert_with(|| {
+                    let muxer = FlvMuxer::new();
+                    let has_audio = receiver.get_codec_config(MediaType::AUDIO).is_some();
+                    let has_video = receiver.get_codec_config(MediaType::VIDEO).is_some();
+                    // before any codec config is known, announce both tracks
+                    messages.push(muxer.header(has_audio || !has_video, has_video || !has_audio));
+                    muxer
+                });
+                for media_type in [MediaType::VIDEO, MediaType::AUDIO] {
+                    if let Some(config) = receiver.get_codec_config(media_type) {
+                        messages.extend(muxer.set_codec_config(&config));
+                    }
+                }
+                messages.extend(muxer.push(data));
+            }
+            Packager::Fmp4Fragments(muxer) => {
+                if muxer.is_none() {
+                    let mut new_muxer = Fmp4Muxer::new(FragmentMode::GOP);
+                    for media_type in [MediaType::VIDEO, MediaType::AUDIO] {
+                        if let Some(config) = receiver.get_codec_config(media_type) {
+                            new_muxer.add_track(config);
+                        }
+                    }
+                    if !new_muxer.has_track(MediaType::AUDIO)
+                        && !new_muxer.has_track(MediaType::VIDEO)
+                    {
+                        warn!("no codec config yet, frame dropped");
+                        return messages;
+                    }
+                    messages.push(new_muxer.init_segment());
+                    *muxer = Some(new_muxer);
+                }
+                messages.extend(muxer.as_mut().unwrap().push(data));
+            }
+        }
+        messages
+    }
+}
+
+fn play(connection: &Connection, format: WsFormat, running: &Mutex<bool>) -> io::Result<()> {
+    let receiver = &connection.receiver;
+    let mut packager = match format {
+        WsFormat::FLV => Packager::FlvTags(None),
+        WsFormat::FMP4 => Packager::Fmp4Fragments(None),
+    };
+    let mut waiting_key_frame = false;
+
+    while *running.lock().unwrap() && !connection.closed.load(Ordering::Acquire) {
+        let (ret, out_data) = receiver.request_read(MediaType::AV);
+        if !receiver.is_attached() {
+            return Err(io::Error::new(io::ErrorKind::NotConnected, "stream ended"));
+        }
+        if !ret || out_data.is_none() {
+            continue;
+        }
+        let binding = out_data.unwrap();
+        let data = binding.lock().unwrap();
+
+        if connection.resync.swap(false, Ordering::AcqRel) {
+            waiting_key_frame = receiver.get_codec_config(MediaType::VIDEO).is_some();
+        }
+        // paused connections keep reading so the dispatcher is not held back
+        if connection.paused.load(Ordering::Acquire) {
+            continue;
+        }
+        if waiting_key_frame {
+            if data.media_type != MediaType::VIDEO || !data.key_frame {
+                continue;
+            }
+            waiting_key_frame = false;
+        }
+
+        for message in packager.package(receiver, &data) {
+            connection.send(OPCODE_BINARY, &message)?;
+        }
+    }
+    Ok(())
+}
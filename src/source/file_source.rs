@@ -0,0 +1,204 @@
+use super::pacer::Pacer;
+use crate::dispatcher::dispatcher::Dispatcher;
+use crate::format::{
+    es::{AdtsDemuxer, H264Demuxer},
+    flv::FlvDemuxer,
+    ts::TsDemuxer,
+    Demuxer, Packet,
+};
+use crate::utils::clock::{SharedClock, SystemClock};
+use crate::{debug, error, info};
+use std::{
+    fs::File,
+    io::BufReader,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+// gap inserted between the last frame of a pass and the first frame of the next loop
+const LOOP_GAP_MS: u64 = 40;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FileFormat {
+    FLV,
+    TS,
+    H264,
+    AAC,
+}
+
+impl FileFormat {
+    pub fn from_path(path: &Path) -> Option<FileFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "flv" => Some(FileFormat::FLV),
+            "ts" | "m2ts" => Some(FileFormat::TS),
+            "h264" | "264" => Some(FileFormat::H264),
+            "aac" => Some(FileFormat::AAC),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaceMode {
+    // release frames according to their dts
+    REALTIME,
+    // release frames as fast as the dispatcher takes them
+    FAST,
+}
+
+pub struct FileSource {
+    path: PathBuf,
+    format: FileFormat,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    pace_mode: PaceMode,
+    speed: f64,
+    looping: bool,
+    fps: u32,
+    clock: SharedClock,
+    read_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl FileSource {
+    pub fn new(path: &Path, dispatcher: Arc<Mutex<Dispatcher>>) -> Option<Self> {
+        let format = FileFormat::from_path(path);
+        if format.is_none() {
+            error!("unknown file format: {:?}", path);
+            return None;
+        }
+        Some(FileSource {
+            path: path.to_path_buf(),
+            format: format.unwrap(),
+            dispatcher,
+            pace_mode: PaceMode::REALTIME,
+            speed: 1.0,
+            looping: false,
+            fps: 25,
+            clock: SystemClock::shared(),
+            read_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        })
+    }
+
+    pub fn set_pace_mode(&mut self, pace_mode: PaceMode) {
+        self.pace_mode = pace_mode;
+    }
+
+    // playback speed factor for REALTIME pacing
+    pub fn set_speed(&mut self, speed: f64) {
+        self.speed = speed;
+    }
+
+    pub fn set_loop(&mut self, looping: bool) {
+        self.looping = looping;
+    }
+
+    // frame rate used to time raw H.264 streams, which carry no timestamps
+    pub fn set_fps(&mut self, fps: u32) {
+        self.fps = fps;
+    }
+
+    // the clock REALTIME pacing sleeps on
+    pub fn set_clock(&mut self, clock: SharedClock) {
+        self.clock = clock;
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    pub fn start_ingest(&mut self) {
+        info!("start ingest: {:?}", self.path);
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let path = self.path.clone();
+        let format = self.format;
+        let fps = self.fps;
+        let pace_mode = self.pace_mode;
+        let mut pacer = Pacer::with_clock(self.clock.clone());
+        pacer.set_speed(self.speed);
+        let looping = self.looping;
+        let dispatcher = self.dispatcher.clone();
+
+        self.read_thread = Some(thread::spawn(move || {
+            let mut last_dts = 0;
+            let mut offset = 0i64;
+            let mut first_pass = true;
+
+            while *running.lock().unwrap() {
+                let demuxer = open_demuxer(&path, format, fps);
+                if demuxer.is_none() {
+                    break;
+                }
+                let mut demuxer = demuxer.unwrap();
+                let mut pass_start = None;
+
+                while *running.lock().unwrap() {
+                    let packet = demuxer.read_packet();
+                    match packet {
+                        None => break,
+                        Some(Packet::CONFIG(config)) => {
+                            dispatcher.lock().unwrap().set_codec_config(config)
+                        }
+                        Some(Packet::DATA(mut data)) => {
+                            if pass_start.is_none() {
+                                pass_start = Some(data.dts);
+                                if !first_pass {
+                                    offset = (last_dts + LOOP_GAP_MS) as i64 - data.dts as i64;
+                                }
+                            }
+                            data.pts = (data.pts as i64 + offset).max(0) as u64;
+                            data.dts = (data.dts as i64 + offset).max(0) as u64;
+                            last_dts = last_dts.max(data.dts);
+
+                            let data = Arc::new(Mutex::new(data));
+                            if pace_mode == PaceMode::REALTIME {
+                                pacer.release(&dispatcher, data);
+                            } else {
+                                dispatcher.lock().unwrap().input_data(data);
+                            }
+                        }
+                    }
+                }
+
+                if !looping || pass_start.is_none() {
+                    break;
+                }
+                debug!("loop ingest, last dts: {}", last_dts);
+                first_pass = false;
+            }
+
+            *running.lock().unwrap() = false;
+            info!("ingest done");
+        }));
+    }
+
+    pub fn stop_ingest(&mut self) {
+        debug!("stop ingest begin");
+        *self.running.lock().unwrap() = false;
+        if let Some(read_thread) = self.read_thread.take() {
+            read_thread.join().expect("can not join the read thread");
+        }
+        debug!("stop ingest end");
+    }
+}
+
+pub fn open_demuxer(path: &Path, format: FileFormat, fps: u32) -> Option<Box<dyn Demuxer>> {
+    let file = match File::open(path) {
+        Ok(file) => file,
+        Err(e) => {
+            error!("open {:?} failed: {}", path, e);
+            return None;
+        }
+    };
+    let input = Box::new(BufReader::new(file));
+    let demuxer: Box<dyn Demuxer> = match format {
+        FileFormat::FLV => Box::new(FlvDemuxer::new(input)),
+        FileFormat::TS => Box::new(TsDemuxer::new(input)),
+        FileFormat::H264 => Box::new(H264Demuxer::new(input, fps)),
+        FileFormat::AAC => Box::new(AdtsDemuxer::new(input)),
+    };
+    Some(demuxer)
+}
@@ -0,0 +1,4 @@
+pub mod file_source;
+pub mod pacer;
+pub mod switcher;
+pub mod synthetic;
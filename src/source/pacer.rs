@@ -0,0 +1,132 @@
+use crate::dispatcher::dispatcher::Dispatcher;
+use crate::utils::{
+    buffer::MediaData,
+    clock::{SharedClock, SystemClock},
+};
+use crate::{debug, warn};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// releases frames according to their dts against a monotonic clock
+pub struct Pacer {
+    clock: SharedClock,
+    speed: f64,
+    jitter: Duration,
+    max_lag: Duration,
+    max_gap: Duration,
+    max_rewind: Duration,
+    // clock time and dts of the frame the schedule is anchored to
+    anchor: Option<(Duration, u64)>,
+    last_dts: u64,
+}
+
+impl Default for Pacer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pacer {
+    pub fn new() -> Self {
+        Self::with_clock(SystemClock::shared())
+    }
+
+    pub fn with_clock(clock: SharedClock) -> Self {
+        Pacer {
+            clock,
+            speed: 1.0,
+            jitter: Duration::from_millis(5),
+            max_lag: Duration::from_millis(500),
+            max_gap: Duration::from_secs(5),
+            max_rewind: Duration::from_millis(500),
+            anchor: None,
+            last_dts: 0,
+        }
+    }
+
+    // 2.0 plays twice as fast, 0.5 at half speed
+    pub fn set_speed(&mut self, speed: f64) {
+        if speed <= 0.0 {
+            warn!("invalid speed: {}", speed);
+            return;
+        }
+        // keep the current position when the speed changes mid-stream
+        if self.anchor.is_some() {
+            self.anchor = Some((self.deadline(self.last_dts), self.last_dts));
+        }
+        self.speed = speed;
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // frames due within this tolerance are released without sleeping
+    pub fn set_jitter_tolerance(&mut self, jitter: Duration) {
+        self.jitter = jitter;
+    }
+
+    // frames later than this are not caught up, the schedule restarts from them instead
+    pub fn set_max_lag(&mut self, max_lag: Duration) {
+        self.max_lag = max_lag;
+    }
+
+    // dts jumps larger than this restart the schedule instead of sleeping through them
+    pub fn set_max_gap(&mut self, max_gap: Duration) {
+        self.max_gap = max_gap;
+    }
+
+    // dts going back by more than this restarts the schedule, smaller steps back are
+    // released right away
+    pub fn set_max_rewind(&mut self, max_rewind: Duration) {
+        self.max_rewind = max_rewind;
+    }
+
+    pub fn reset(&mut self) {
+        self.anchor = None;
+    }
+
+    // blocks until the frame with this dts is due
+    pub fn wait(&mut self, dts: u64) {
+        let now = self.clock.now();
+        if self.anchor.is_none()
+            || Duration::from_millis(self.last_dts.saturating_sub(dts)) > self.max_rewind
+            || Duration::from_millis(dts.saturating_sub(self.last_dts)) > self.max_gap
+        {
+            if self.anchor.is_some() {
+                debug!("dts jump {} -> {}, restart pacing", self.last_dts, dts);
+            }
+            self.anchor = Some((now, dts));
+            self.last_dts = dts;
+            return;
+        }
+        if dts < self.last_dts {
+            return;
+        }
+        self.last_dts = dts;
+
+        let deadline = self.deadline(dts);
+        if deadline > now + self.jitter {
+            self.clock.sleep(deadline - now);
+        } else if now > deadline + self.max_lag {
+            warn!("pacing behind by {:?}, restart pacing", now - deadline);
+            self.anchor = Some((now, dts));
+        }
+        // otherwise release right away, catching up when behind
+    }
+
+    // waits for the frame and hands it to the dispatcher
+    pub fn release(&mut self, dispatcher: &Arc<Mutex<Dispatcher>>, data: Arc<Mutex<MediaData>>) {
+        let dts = data.lock().unwrap().dts;
+        self.wait(dts);
+        dispatcher.lock().unwrap().input_data(data);
+    }
+
+    fn deadline(&self, dts: u64) -> Duration {
+        let (anchor_time, anchor_dts) = self.anchor.unwrap();
+        let elapsed = dts.saturating_sub(anchor_dts) as f64 / self.speed;
+        anchor_time + Duration::from_secs_f64(elapsed / 1000.0)
+    }
+}
@@ -0,0 +1,214 @@
+use crate::dispatcher::dispatcher::Dispatcher;
+use crate::utils::{
+    buffer::{MediaData, MediaType},
+    clock::{SharedClock, SystemClock},
+    codec::CodecConfig,
+};
+use crate::{info, warn};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+struct Source {
+    name: String,
+    priority: u32,
+    last_pts: Option<u64>,
+    // clock time the pts last moved forward
+    last_progress: Option<Duration>,
+    // clock time the source started progressing again after a stall
+    healthy_since: Option<Duration>,
+    audio_config: Option<CodecConfig>,
+    video_config: Option<CodecConfig>,
+}
+
+impl Source {
+    fn is_stalled(&self, now: Duration, stall_timeout: Duration) -> bool {
+        match self.last_progress {
+            Some(last_progress) => now.saturating_sub(last_progress) >= stall_timeout,
+            None => true,
+        }
+    }
+}
+
+// picks one of several named inputs for a dispatcher. a lower priority value is
+// preferred; a source whose pts stops moving for the stall timeout loses its turn,
+// and it gets it back once it has progressed again for the recover time. switches
+// happen at a key frame of the new source, which is marked as a discontinuity.
+pub struct Switcher {
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    clock: SharedClock,
+    stall_timeout: Duration,
+    recover_time: Duration,
+    sources: Vec<Source>,
+    active: Option<usize>,
+    switch_count: u64,
+}
+
+impl Switcher {
+    pub fn new(dispatcher: Arc<Mutex<Dispatcher>>) -> Self {
+        Self::with_clock(dispatcher, SystemClock::shared())
+    }
+
+    pub fn with_clock(dispatcher: Arc<Mutex<Dispatcher>>, clock: SharedClock) -> Self {
+        Switcher {
+            dispatcher,
+            clock,
+            stall_timeout: Duration::from_secs(2),
+            recover_time: Duration::from_secs(5),
+            sources: Vec::new(),
+            active: None,
+            switch_count: 0,
+        }
+    }
+
+    pub fn set_stall_timeout(&mut self, stall_timeout: Duration) {
+        self.stall_timeout = stall_timeout;
+    }
+
+    pub fn set_recover_time(&mut self, recover_time: Duration) {
+        self.recover_time = recover_time;
+    }
+
+    pub fn add_source(&mut self, name: &str, priority: u32) {
+        if self.find(name).is_some() {
+            warn!("source {} already added", name);
+            return;
+        }
+        info!("add source: {}, priority: {}", name, priority);
+        let active = self.active.map(|index| self.sources[index].name.clone());
+        self.sources.push(Source {
+            name: name.to_string(),
+            priority,
+            last_pts: None,
+            last_progress: None,
+            healthy_since: None,
+            audio_config: None,
+            video_config: None,
+        });
+        self.sources.sort_by_key(|source| source.priority);
+        self.active = active.and_then(|name| self.find(&name));
+    }
+
+    pub fn remove_source(&mut self, name: &str) {
+        let index = match self.find(name) {
+            Some(index) => index,
+            None => return,
+        };
+        info!("remove source: {}", name);
+        let active = self.active.map(|index| self.sources[index].name.clone());
+        self.sources.remove(index);
+        self.active = active.and_then(|name| self.find(&name));
+    }
+
+    pub fn active_source(&self) -> Option<&str> {
+        self.active.map(|index| self.sources[index].name.as_str())
+    }
+
+    pub fn switch_count(&self) -> u64 {
+        self.switch_count
+    }
+
+    fn find(&self, name: &str) -> Option<usize> {
+        self.sources.iter().position(|source| source.name == name)
+    }
+
+    pub fn set_codec_config(&mut self, name: &str, config: CodecConfig) {
+        let index = match self.find(name) {
+            Some(index) => index,
+            None => {
+                warn!("unknown source: {}", name);
+                return;
+            }
+        };
+        let source = &mut self.sources[index];
+        match config.media_type() {
+            MediaType::AUDIO => source.audio_config = Some(config.clone()),
+            MediaType::VIDEO => source.video_config = Some(config.clone()),
+            _ => return,
+        }
+        if self.active == Some(index) {
+            self.dispatcher.lock().unwrap().set_codec_config(config);
+        }
+    }
+
+    // frames of every source come through here, only the active one's reach the dispatcher
+    pub fn input_data(&mut self, name: &str, data: Arc<Mutex<MediaData>>) {
+        let index = match self.find(name) {
+            Some(index) => index,
+            None => {
+                warn!("unknown source: {}", name);
+                return;
+            }
+        };
+        let now = self.clock.now();
+        let (pts, key_frame) = {
+            let data = data.lock().unwrap();
+            (data.pts, data.key_frame)
+        };
+
+        let stall_timeout = self.stall_timeout;
+        let source = &mut self.sources[index];
+        if source.last_pts.is_none_or(|last_pts| pts > last_pts) {
+            if source.is_stalled(now, stall_timeout) {
+                source.healthy_since = Some(now);
+            }
+            source.last_progress = Some(now);
+            source.last_pts = Some(pts);
+        }
+
+        if self.active != Some(index) {
+            if !key_frame || self.target(now) != Some(index) {
+                return;
+            }
+            // the first source picked starts the timeline, later ones break it
+            if self.active.is_some() {
+                data.lock().unwrap().discontinuity = true;
+                self.switch_count += 1;
+            }
+            self.switch_to(index);
+        }
+        self.dispatcher.lock().unwrap().input_data(data);
+    }
+
+    // the preferred source that is fit to take over from the active one
+    fn target(&self, now: Duration) -> Option<usize> {
+        let active_ok = self
+            .active
+            .is_some_and(|index| !self.sources[index].is_stalled(now, self.stall_timeout));
+        self.sources
+            .iter()
+            .enumerate()
+            .find(|(index, source)| {
+                if Some(*index) == self.active {
+                    return active_ok;
+                }
+                // the first source to show up takes over right away, a recovering
+                // one has to prove itself against a healthy active source
+                let recovered = !active_ok
+                    || source
+                        .healthy_since
+                        .is_some_and(|since| now.saturating_sub(since) >= self.recover_time);
+                !source.is_stalled(now, self.stall_timeout) && recovered
+            })
+            .map(|(index, _)| index)
+    }
+
+    fn switch_to(&mut self, index: usize) {
+        let source = &self.sources[index];
+        info!(
+            "switch source: {:?} -> {}",
+            self.active_source(),
+            source.name
+        );
+        let mut dispatcher = self.dispatcher.lock().unwrap();
+        for config in [&source.video_config, &source.audio_config]
+            .into_iter()
+            .flatten()
+        {
+            dispatcher.set_codec_config(config.clone());
+        }
+        drop(dispatcher);
+        self.active = Some(index);
+    }
+}
@@ -0,0 +1,138 @@
+use super::{file_source::PaceMode, pacer::Pacer};
+use crate::dispatcher::dispatcher::Dispatcher;
+use crate::format::media_data;
+use crate::utils::buffer::{MediaData, MediaType};
+use crate::{debug, info};
+use std::{
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+// generates interleaved audio/video frames whose payload carries the frame index
+#[derive(Clone, Debug)]
+pub struct SyntheticStream {
+    // video frames per GOP
+    pub gop_size: u32,
+    // every n-th frame is a video frame, 1 for video only
+    pub video_interval: u32,
+    // milliseconds between two frames
+    pub frame_interval: u64,
+    pub payload_size: usize,
+    index: u64,
+    video_count: u32,
+}
+
+impl SyntheticStream {
+    pub fn new(gop_size: u32, video_interval: u32, frame_interval: u64) -> Self {
+        SyntheticStream {
+            gop_size: gop_size.max(1),
+            video_interval: video_interval.max(1),
+            frame_interval,
+            payload_size: 4,
+            index: 0,
+            video_count: 0,
+        }
+    }
+
+    pub fn next_frame(&mut self) -> MediaData {
+        let index = self.index;
+        self.index += 1;
+
+        let mut payload = (index as u32).to_be_bytes().to_vec();
+        payload.resize(self.payload_size, 0);
+        let pts = index * self.frame_interval;
+
+        if index.is_multiple_of(self.video_interval as u64) {
+            let key_frame = self.video_count.is_multiple_of(self.gop_size);
+            self.video_count += 1;
+            media_data(MediaType::VIDEO, pts, pts, key_frame, payload)
+        } else {
+            media_data(MediaType::AUDIO, pts, pts, false, payload)
+        }
+    }
+
+    pub fn frames(&mut self, count: usize) -> Vec<Arc<Mutex<MediaData>>> {
+        (0..count)
+            .map(|_| Arc::new(Mutex::new(self.next_frame())))
+            .collect()
+    }
+}
+
+// pushes a synthetic stream into a dispatcher from its own thread
+pub struct SyntheticSource {
+    stream: SyntheticStream,
+    dispatcher: Arc<Mutex<Dispatcher>>,
+    // 0 for no limit
+    frame_count: u32,
+    pace_mode: PaceMode,
+    written: Arc<Mutex<u32>>,
+    write_thread: Option<JoinHandle<()>>,
+    running: Arc<Mutex<bool>>,
+}
+
+impl SyntheticSource {
+    pub fn new(stream: SyntheticStream, dispatcher: Arc<Mutex<Dispatcher>>) -> Self {
+        SyntheticSource {
+            stream,
+            dispatcher,
+            frame_count: 0,
+            pace_mode: PaceMode::REALTIME,
+            written: Arc::new(Mutex::new(0)),
+            write_thread: None,
+            running: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    pub fn set_frame_count(&mut self, frame_count: u32) {
+        self.frame_count = frame_count;
+    }
+
+    pub fn set_pace_mode(&mut self, pace_mode: PaceMode) {
+        self.pace_mode = pace_mode;
+    }
+
+    pub fn is_running(&self) -> bool {
+        *self.running.lock().unwrap()
+    }
+
+    pub fn written(&self) -> u32 {
+        *self.written.lock().unwrap()
+    }
+
+    pub fn start_ingest(&mut self) {
+        info!("start synthetic ingest");
+        let running = self.running.clone();
+        *running.lock().unwrap() = true;
+        let written = self.written.clone();
+        let mut stream = self.stream.clone();
+        let frame_count = self.frame_count;
+        let pace_mode = self.pace_mode;
+        let dispatcher = self.dispatcher.clone();
+
+        self.write_thread = Some(thread::spawn(move || {
+            let mut pacer = Pacer::new();
+            while *running.lock().unwrap()
+                && (frame_count == 0 || *written.lock().unwrap() < frame_count)
+            {
+                let data = Arc::new(Mutex::new(stream.next_frame()));
+                if pace_mode == PaceMode::REALTIME {
+                    pacer.release(&dispatcher, data);
+                } else {
+                    dispatcher.lock().unwrap().input_data(data);
+                }
+                *written.lock().unwrap() += 1;
+            }
+            *running.lock().unwrap() = false;
+            info!("synthetic ingest done");
+        }));
+    }
+
+    pub fn stop_ingest(&mut self) {
+        debug!("stop synthetic ingest begin");
+        *self.running.lock().unwrap() = false;
+        if let Some(write_thread) = self.write_thread.take() {
+            write_thread.join().expect("can not join the write thread");
+        }
+        debug!("stop synthetic ingest end");
+    }
+}
@@ -1,21 +1,80 @@
 use std::sync::{Arc, Mutex};
 
-#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub enum MediaType {
     #[default]
     AV,
     AUDIO,
     VIDEO,
+    // WebVTT cues
+    SUBTITLE,
+    // ID3 or SCTE-35 timed metadata
+    METADATA,
+    // anything else carried along with the stream, telemetry for example
+    DATA,
 }
 
-#[derive(Default, Debug)]
+pub const TRACK_COUNT: usize = 5;
+
+impl MediaType {
+    // the single tracks, a read of AV stands for all of them
+    pub const TRACKS: [MediaType; TRACK_COUNT] = [
+        MediaType::AUDIO,
+        MediaType::VIDEO,
+        MediaType::SUBTITLE,
+        MediaType::METADATA,
+        MediaType::DATA,
+    ];
+
+    // position in per-track arrays, AV comes after the single tracks
+    pub fn slot(self) -> usize {
+        match self {
+            MediaType::AUDIO => 0,
+            MediaType::VIDEO => 1,
+            MediaType::SUBTITLE => 2,
+            MediaType::METADATA => 3,
+            MediaType::DATA => 4,
+            MediaType::AV => TRACK_COUNT,
+        }
+    }
+
+    // the track's bit in a track mask, AV sets all of them
+    pub fn track_bit(self) -> u32 {
+        match self {
+            MediaType::AV => (1 << TRACK_COUNT) - 1,
+            media_type => 1 << media_type.slot(),
+        }
+    }
+
+    // "av", "audio", "video", "subtitle", "metadata" or "data", in any case
+    pub fn from_name(name: &str) -> Option<MediaType> {
+        [MediaType::AV]
+            .into_iter()
+            .chain(MediaType::TRACKS)
+            .find(|media_type| format!("{:?}", media_type).eq_ignore_ascii_case(name))
+    }
+}
+
+// clones share the payload buffer
+#[derive(Default, Debug, Clone)]
 pub struct MediaData {
     pub key_frame: bool,
     pub pts: u64,
+    pub dts: u64,
     pub media_type: MediaType,
+    // tells apart the renditions of one media type, 0 for a single rendition
+    pub track_id: u32,
+    // the first frame after a break in the timeline, readers reset their state on it
+    pub discontinuity: bool,
     pub buff: Arc<Mutex<Buffer>>,
 }
 
+impl MediaData {
+    pub fn payload(&self) -> Vec<u8> {
+        self.buff.lock().unwrap().data().lock().unwrap().clone()
+    }
+}
+
 #[derive(Debug, Default)]
 pub struct Buffer {
     size: usize,
@@ -0,0 +1,359 @@
+use super::buffer::MediaType;
+
+#[derive(Default, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CodecType {
+    #[default]
+    UNKNOWN,
+    H264,
+    H265,
+    AAC,
+    OPUS,
+}
+
+impl CodecType {
+    pub fn media_type(&self) -> MediaType {
+        match self {
+            CodecType::H264 | CodecType::H265 => MediaType::VIDEO,
+            CodecType::AAC | CodecType::OPUS => MediaType::AUDIO,
+            CodecType::UNKNOWN => MediaType::AV,
+        }
+    }
+}
+
+// video payloads are carried as 4-byte length-prefixed NAL units (AVCC/HVCC),
+// audio payloads as raw frames without ADTS/LATM framing. `extra_data` holds
+// the decoder configuration record (avcC/hvcC body, AudioSpecificConfig or
+// the OpusHead body).
+#[derive(Default, Clone, Debug, PartialEq, Eq)]
+pub struct CodecConfig {
+    pub codec: CodecType,
+    pub width: u32,
+    pub height: u32,
+    pub sample_rate: u32,
+    pub channels: u32,
+    pub extra_data: Vec<u8>,
+}
+
+impl CodecConfig {
+    pub fn media_type(&self) -> MediaType {
+        self.codec.media_type()
+    }
+}
+
+pub const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+pub const H264_NAL_SLICE: u8 = 1;
+pub const H264_NAL_IDR: u8 = 5;
+pub const H264_NAL_SEI: u8 = 6;
+pub const H264_NAL_SPS: u8 = 7;
+pub const H264_NAL_PPS: u8 = 8;
+pub const H264_NAL_AUD: u8 = 9;
+
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        BitReader { data, pos: 0 }
+    }
+
+    pub fn read_bit(&mut self) -> Option<u32> {
+        let byte = *self.data.get(self.pos / 8)?;
+        let bit = (byte >> (7 - self.pos % 8)) & 0x1;
+        self.pos += 1;
+        Some(bit as u32)
+    }
+
+    pub fn read_bits(&mut self, count: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()?;
+        }
+        Some(value)
+    }
+
+    pub fn read_ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read_bit()? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some((1u32 << zeros) - 1 + self.read_bits(zeros)?)
+    }
+
+    pub fn read_se(&mut self) -> Option<i32> {
+        let value = self.read_ue()?;
+        if value & 0x1 == 1 {
+            Some(value.div_ceil(2) as i32)
+        } else {
+            Some(-((value / 2) as i32))
+        }
+    }
+}
+
+// split an Annex-B byte stream into NAL units, start codes removed
+pub fn split_annexb(data: &[u8]) -> Vec<&[u8]> {
+    let mut nalus = Vec::new();
+    let mut start = None;
+    let mut i = 0;
+    while i + 3 <= data.len() {
+        if data[i] == 0 && data[i + 1] == 0 && data[i + 2] == 1 {
+            if let Some(begin) = start {
+                let mut end = i;
+                while end > begin && data[end - 1] == 0 {
+                    end -= 1;
+                }
+                nalus.push(&data[begin..end]);
+            }
+            i += 3;
+            start = Some(i);
+        } else {
+            i += 1;
+        }
+    }
+    if let Some(begin) = start {
+        if begin < data.len() {
+            nalus.push(&data[begin..]);
+        }
+    }
+    nalus
+}
+
+// split 4-byte length-prefixed NAL units
+pub fn split_avcc(data: &[u8]) -> Vec<&[u8]> {
+    let mut nalus = Vec::new();
+    let mut i = 0;
+    while i + 4 <= data.len() {
+        let len = u32::from_be_bytes([data[i], data[i + 1], data[i + 2], data[i + 3]]) as usize;
+        i += 4;
+        if i + len > data.len() {
+            break;
+        }
+        nalus.push(&data[i..i + len]);
+        i += len;
+    }
+    nalus
+}
+
+pub fn nalus_to_avcc(nalus: &[&[u8]]) -> Vec<u8> {
+    let mut out = Vec::new();
+    for nalu in nalus {
+        out.extend_from_slice(&(nalu.len() as u32).to_be_bytes());
+        out.extend_from_slice(nalu);
+    }
+    out
+}
+
+fn remove_emulation_prevention(nalu: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(nalu.len());
+    let mut zeros = 0;
+    for &byte in nalu {
+        if zeros >= 2 && byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if byte == 0 { zeros + 1 } else { 0 };
+        out.push(byte);
+    }
+    out
+}
+
+// returns the coded width and height of an H.264 SPS NAL unit
+pub fn parse_h264_sps(sps: &[u8]) -> Option<(u32, u32)> {
+    let rbsp = remove_emulation_prevention(sps.get(1..)?);
+    let mut reader = BitReader::new(&rbsp);
+    let profile_idc = reader.read_bits(8)?;
+    reader.read_bits(16)?;
+    reader.read_ue()?;
+
+    let mut chroma_format_idc = 1;
+    if [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135].contains(&profile_idc) {
+        chroma_format_idc = reader.read_ue()?;
+        if chroma_format_idc == 3 {
+            reader.read_bit()?;
+        }
+        reader.read_ue()?;
+        reader.read_ue()?;
+        reader.read_bit()?;
+        if reader.read_bit()? == 1 {
+            let count = if chroma_format_idc == 3 { 12 } else { 8 };
+            for i in 0..count {
+                if reader.read_bit()? == 0 {
+                    continue;
+                }
+                let size = if i < 6 { 16 } else { 64 };
+                let mut last_scale = 8;
+                let mut next_scale = 8;
+                for _ in 0..size {
+                    if next_scale != 0 {
+                        let delta = reader.read_se()?;
+                        next_scale = (last_scale + delta + 256) % 256;
+                    }
+                    if next_scale != 0 {
+                        last_scale = next_scale;
+                    }
+                }
+            }
+        }
+    }
+
+    reader.read_ue()?;
+    let pic_order_cnt_type = reader.read_ue()?;
+    if pic_order_cnt_type == 0 {
+        reader.read_ue()?;
+    } else if pic_order_cnt_type == 1 {
+        reader.read_bit()?;
+        reader.read_se()?;
+        reader.read_se()?;
+        for _ in 0..reader.read_ue()? {
+            reader.read_se()?;
+        }
+    }
+    reader.read_ue()?;
+    reader.read_bit()?;
+    let width_in_mbs = reader.read_ue()? + 1;
+    let height_in_map_units = reader.read_ue()? + 1;
+    let frame_mbs_only = reader.read_bit()?;
+    if frame_mbs_only == 0 {
+        reader.read_bit()?;
+    }
+    reader.read_bit()?;
+
+    let mut width = width_in_mbs * 16;
+    let mut height = (2 - frame_mbs_only) * height_in_map_units * 16;
+    if reader.read_bit()? == 1 {
+        let (crop_x, crop_y) = match chroma_format_idc {
+            1 => (2, 2 * (2 - frame_mbs_only)),
+            2 => (2, 2 - frame_mbs_only),
+            _ => (1, 2 - frame_mbs_only),
+        };
+        let left = reader.read_ue()?;
+        let right = reader.read_ue()?;
+        let top = reader.read_ue()?;
+        let bottom = reader.read_ue()?;
+        width = width.checked_sub((left + right) * crop_x)?;
+        height = height.checked_sub((top + bottom) * crop_y)?;
+    }
+    Some((width, height))
+}
+
+// build an H.264 codec config (avcC body) from a SPS and PPS
+pub fn h264_config(sps: &[u8], pps: &[u8]) -> Option<CodecConfig> {
+    if sps.len() < 4 {
+        return None;
+    }
+    let (width, height) = parse_h264_sps(sps)?;
+    let mut extra_data = vec![1, sps[1], sps[2], sps[3], 0xff, 0xe1];
+    extra_data.extend_from_slice(&(sps.len() as u16).to_be_bytes());
+    extra_data.extend_from_slice(sps);
+    extra_data.push(1);
+    extra_data.extend_from_slice(&(pps.len() as u16).to_be_bytes());
+    extra_data.extend_from_slice(pps);
+    Some(CodecConfig {
+        codec: CodecType::H264,
+        width,
+        height,
+        extra_data,
+        ..Default::default()
+    })
+}
+
+// build an H.264 codec config from an avcC body
+pub fn h264_config_from_avcc(avcc: &[u8]) -> Option<CodecConfig> {
+    if avcc.len() < 8 || avcc[5] & 0x1f == 0 {
+        return None;
+    }
+    let sps_len = u16::from_be_bytes([avcc[6], avcc[7]]) as usize;
+    let (width, height) = parse_h264_sps(avcc.get(8..8 + sps_len)?)?;
+    Some(CodecConfig {
+        codec: CodecType::H264,
+        width,
+        height,
+        extra_data: avcc.to_vec(),
+        ..Default::default()
+    })
+}
+
+// the SPS and PPS NAL units of an avcC body, in that order
+pub fn avcc_parameter_sets(avcc: &[u8]) -> Vec<&[u8]> {
+    let mut nalus = Vec::new();
+    let mut i = 5;
+    for count_mask in [0x1f, 0xff] {
+        let Some(&count) = avcc.get(i) else {
+            break;
+        };
+        i += 1;
+        for _ in 0..count & count_mask {
+            let Some(len) = avcc.get(i..i + 2) else {
+                return nalus;
+            };
+            let len = u16::from_be_bytes([len[0], len[1]]) as usize;
+            let Some(nalu) = avcc.get(i + 2..i + 2 + len) else {
+                return nalus;
+            };
+            nalus.push(nalu);
+            i += 2 + len;
+        }
+    }
+    nalus
+}
+
+// build an AAC codec config from an AudioSpecificConfig
+pub fn aac_config(asc: &[u8]) -> Option<CodecConfig> {
+    let mut reader = BitReader::new(asc);
+    reader.read_bits(5)?;
+    let sample_rate = *AAC_SAMPLE_RATES.get(reader.read_bits(4)? as usize)?;
+    let channels = reader.read_bits(4)?;
+    Some(CodecConfig {
+        codec: CodecType::AAC,
+        sample_rate,
+        channels,
+        extra_data: asc.to_vec(),
+        ..Default::default()
+    })
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AdtsHeader {
+    pub object_type: u8,
+    pub frequency_index: u8,
+    pub channels: u8,
+    pub header_len: usize,
+    pub frame_len: usize,
+}
+
+impl AdtsHeader {
+    pub fn parse(data: &[u8]) -> Option<AdtsHeader> {
+        if data.len() < 7 || data[0] != 0xff || data[1] & 0xf0 != 0xf0 {
+            return None;
+        }
+        let header_len = if data[1] & 0x1 == 1 { 7 } else { 9 };
+        let frame_len = ((data[3] as usize & 0x3) << 11)
+            | ((data[4] as usize) << 3)
+            | ((data[5] as usize) >> 5);
+        if frame_len < header_len {
+            return None;
+        }
+        Some(AdtsHeader {
+            object_type: (data[2] >> 6) + 1,
+            frequency_index: (data[2] >> 2) & 0xf,
+            channels: ((data[2] & 0x1) << 2) | (data[3] >> 6),
+            header_len,
+            frame_len,
+        })
+    }
+
+    pub fn config(&self) -> Option<CodecConfig> {
+        let asc = [
+            (self.object_type << 3) | (self.frequency_index >> 1),
+            ((self.frequency_index & 0x1) << 7) | (self.channels << 3),
+        ];
+        aac_config(&asc)
+    }
+}
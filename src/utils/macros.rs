@@ -1,66 +1,158 @@
+use std::sync::{
+    atomic::{AtomicBool, AtomicU8, Ordering},
+    RwLock,
+};
+
 #[macro_export]
 macro_rules! debug {
     ($($arg:tt)*) => {{
-        let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
+        if $crate::utils::macros::enabled($crate::utils::macros::LogLevel::DEBUG, file!()) {
+            let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
 
-        let mut f = collect[collect.len() - 1];
-        if collect[collect.len() - 1].contains("closure") {
-            f = collect[collect.len() - 2];
-        };
-        print!("\x1b[34m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+            let mut f = collect[collect.len() - 1];
+            if collect[collect.len() - 1].contains("closure") {
+                f = collect[collect.len() - 2];
+            };
+            print!("\x1b[34m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
+                std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+        }
     }};
 }
 #[macro_export]
 macro_rules! info {
     ($($arg:tt)*) => {{
-        let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
+        if $crate::utils::macros::enabled($crate::utils::macros::LogLevel::INFO, file!()) {
+            let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
 
-        let mut f = collect[collect.len() - 1];
-        if collect[collect.len() - 1].contains("closure") {
-            f = collect[collect.len() - 2];
-        };
-        print!("\x1b[92m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+            let mut f = collect[collect.len() - 1];
+            if collect[collect.len() - 1].contains("closure") {
+                f = collect[collect.len() - 2];
+            };
+            print!("\x1b[92m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
+                std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+        }
     }};
 }
 #[macro_export]
 macro_rules! warn {
     ($($arg:tt)*) => {{
-        let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
+        if $crate::utils::macros::enabled($crate::utils::macros::LogLevel::WARN, file!()) {
+            let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
 
-        let mut f = collect[collect.len() - 1];
-        if collect[collect.len() - 1].contains("closure") {
-            f = collect[collect.len() - 2];
-        };
-        print!("\x1b[93m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+            let mut f = collect[collect.len() - 1];
+            if collect[collect.len() - 1].contains("closure") {
+                f = collect[collect.len() - 2];
+            };
+            print!("\x1b[93m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
+                std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+        }
     }};
 }
 #[macro_export]
 macro_rules! error {
     ($($arg:tt)*) => {{
-        let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
+        if $crate::utils::macros::enabled($crate::utils::macros::LogLevel::ERROR, file!()) {
+            let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
 
-        let mut f = collect[collect.len() - 1];
-        if collect[collect.len() - 1].contains("closure") {
-            f = collect[collect.len() - 2];
-        };
-        print!("\x1b[91m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+            let mut f = collect[collect.len() - 1];
+            if collect[collect.len() - 1].contains("closure") {
+                f = collect[collect.len() - 2];
+            };
+            print!("\x1b[91m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
+                std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+        }
     }};
 }
 
 #[macro_export]
 macro_rules! fatal {
     ($($arg:tt)*) => {{
-        let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
+        if $crate::utils::macros::enabled($crate::utils::macros::LogLevel::FATAL, file!()) {
+            let collect = stdext::function_name!().split_terminator("::").collect::<Vec<&str>>();
 
-        let mut f = collect[collect.len() - 1];
-        if collect[collect.len() - 1].contains("closure") {
-            f = collect[collect.len() - 2];
-        };
-        print!("\x1b[38;5;226m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
-            std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+            let mut f = collect[collect.len() - 1];
+            if collect[collect.len() - 1].contains("closure") {
+                f = collect[collect.len() - 2];
+            };
+            print!("\x1b[38;5;226m{} D {:?} {:?}: [{}()] [{}:{}] {:?}\n", chrono::prelude::Local::now().format("%Y-%m-%d %H:%M:%S.%6f"),
+                std::process::id(), std::thread::current().id(), f, file!(), line!(), format_args!($($arg)*));
+        }
     }};
 }
+
+// the level the macros print from, by default everything
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    DEBUG,
+    INFO,
+    WARN,
+    ERROR,
+    FATAL,
+    OFF,
+}
+
+impl LogLevel {
+    const ALL: [LogLevel; 6] = [
+        LogLevel::DEBUG,
+        LogLevel::INFO,
+        LogLevel::WARN,
+        LogLevel::ERROR,
+        LogLevel::FATAL,
+        LogLevel::OFF,
+    ];
+
+    // "debug", "info", "warn", "error", "fatal" or "off", in any case
+    pub fn from_name(name: &str) -> Option<LogLevel> {
+        LogLevel::ALL
+            .into_iter()
+            .find(|level| format!("{:?}", level).eq_ignore_ascii_case(name))
+    }
+}
+
+static LOG_LEVEL: AtomicU8 = AtomicU8::new(LogLevel::DEBUG as u8);
+// per module overrides, the longest matching module wins
+static MODULE_LEVELS: RwLock<Vec<(String, LogLevel)>> = RwLock::new(Vec::new());
+static HAS_MODULE_LEVELS: AtomicBool = AtomicBool::new(false);
+
+pub fn set_log_level(level: LogLevel) {
+    LOG_LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn log_level() -> LogLevel {
+    LogLevel::ALL[LOG_LEVEL.load(Ordering::Relaxed) as usize]
+}
+
+// module is a path below src like "dispatcher" or "dispatcher::receiver"
+pub fn set_module_level(module: &str, level: LogLevel) {
+    let path = format!("src/{}", module.replace("::", "/"));
+    let mut levels = MODULE_LEVELS.write().unwrap();
+    levels.retain(|(other, _)| *other != path);
+    levels.push((path, level));
+    HAS_MODULE_LEVELS.store(true, Ordering::Relaxed);
+}
+
+pub fn clear_module_levels() {
+    MODULE_LEVELS.write().unwrap().clear();
+    HAS_MODULE_LEVELS.store(false, Ordering::Relaxed);
+}
+
+// whether a macro at level in the file, as given by file!(), prints
+pub fn enabled(level: LogLevel, file: &str) -> bool {
+    let mut threshold = log_level();
+    if HAS_MODULE_LEVELS.load(Ordering::Relaxed) {
+        let matches = |path: &str| {
+            file.strip_prefix(path)
+                .is_some_and(|rest| rest.starts_with('/') || rest.starts_with('.'))
+        };
+        if let Some((_, module_level)) = MODULE_LEVELS
+            .read()
+            .unwrap()
+            .iter()
+            .filter(|(path, _)| matches(path))
+            .max_by_key(|(path, _)| path.len())
+        {
+            threshold = *module_level;
+        }
+    }
+    level >= threshold
+}
@@ -1,5 +1,10 @@
 pub mod buffer;
+pub mod clock;
+pub mod codec;
 pub mod macros;
+pub mod options;
+pub mod signal;
+pub mod sync;
 pub mod timeout_timer;
 
 pub trait Identity {
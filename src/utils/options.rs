@@ -0,0 +1,221 @@
+// command line options of the main binary, a synthetic stream pushed into one
+// dispatcher and read back by a set of readers
+use crate::config::{
+    DispatcherConfig, PipelineConfig, ReceiverConfig, SourceConfig, SyntheticConfig,
+};
+use crate::dispatcher::dispatcher::MAX_RECEIVERS;
+use crate::utils::buffer::MediaType;
+use std::{path::PathBuf, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReaderOptions {
+    pub media_type: MediaType,
+    pub key_only: bool,
+}
+
+impl ReaderOptions {
+    // "video", "av:key", ...
+    pub fn parse(spec: &str) -> Result<ReaderOptions, String> {
+        let (name, mode) = match spec.split_once(':') {
+            Some((name, mode)) => (name, Some(mode)),
+            None => (spec, None),
+        };
+        let media_type = MediaType::from_name(name)
+            .ok_or_else(|| format!("unknown media type in reader {:?}", spec))?;
+        let key_only = match mode {
+            None => false,
+            Some("key") => true,
+            Some(mode) => return Err(format!("unknown read mode {:?}, expected key", mode)),
+        };
+        Ok(ReaderOptions {
+            media_type,
+            key_only,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunOptions {
+    // 0 keeps generating until the run duration is over
+    pub frames: u32,
+    pub fps: u32,
+    // audio frames between two video frames, 0 for video only
+    pub audio_per_video: u32,
+    pub gop_size: u32,
+    pub payload_size: usize,
+    pub readers: Vec<ReaderOptions>,
+    pub max_capacity: u32,
+    pub capacity_increment: u32,
+    // none runs until all frames are written
+    pub duration: Option<Duration>,
+    // a pipeline config file that replaces the stream and reader options
+    pub config: Option<PathBuf>,
+}
+
+impl Default for RunOptions {
+    fn default() -> Self {
+        RunOptions {
+            frames: 100,
+            fps: 40,
+            audio_per_video: 2,
+            gop_size: 30,
+            payload_size: 4,
+            readers: vec![ReaderOptions {
+                media_type: MediaType::VIDEO,
+                key_only: true,
+            }],
+            max_capacity: 400,
+            capacity_increment: 50,
+            duration: None,
+            config: None,
+        }
+    }
+}
+
+fn value<T: std::str::FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {:?}", option, value))
+}
+
+impl RunOptions {
+    pub fn usage(program: &str) -> String {
+        let defaults = RunOptions::default();
+        format!(
+            "usage: {} [options]\n\
+             \x20 --frames N              frames to write, 0 until --duration is over ({})\n\
+             \x20 --fps N                 frames per second ({})\n\
+             \x20 --audio-per-video N     audio frames between two video frames ({})\n\
+             \x20 --gop N                 video frames per GOP ({})\n\
+             \x20 --payload BYTES         payload size of a frame ({})\n\
+             \x20 --reader TYPE[:key]     add a reader of av, audio, video, subtitle, metadata\n\
+             \x20                         or data, key only with :key (one video:key reader)\n\
+             \x20 --max-capacity N        dispatcher buffer capacity ({})\n\
+             \x20 --capacity-increment N  dispatcher capacity increment ({})\n\
+             \x20 --duration SECONDS      run this long, by default until all frames are written\n\
+             \x20 --config FILE           build the pipeline from a TOML or YAML file instead\n\
+             \x20                         of the stream and reader options\n\
+             \x20 --help                  print this help",
+            program,
+            defaults.frames,
+            defaults.fps,
+            defaults.audio_per_video,
+            defaults.gop_size,
+            defaults.payload_size,
+            defaults.max_capacity,
+            defaults.capacity_increment
+        )
+    }
+
+    // the arguments after the program name, "--option value" or "--option=value".
+    // Ok(None) when help was asked for
+    pub fn parse(args: &[String]) -> Result<Option<RunOptions>, String> {
+        let mut options = RunOptions::default();
+        let mut readers = Vec::new();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            if arg == "-h" || arg == "--help" {
+                return Ok(None);
+            }
+            let (option, inline) = match arg.split_once('=') {
+                Some((option, inline)) => (option, Some(inline.to_string())),
+                None => (arg.as_str(), None),
+            };
+            if !option.starts_with("--") {
+                return Err(format!("unexpected argument {:?}", arg));
+            }
+            let argument = match inline.or_else(|| args.next().cloned()) {
+                Some(argument) => argument,
+                None => return Err(format!("missing value for {}", option)),
+            };
+            match option {
+                "--frames" => options.frames = value(option, &argument)?,
+                "--fps" => options.fps = value(option, &argument)?,
+                "--audio-per-video" => options.audio_per_video = value(option, &argument)?,
+                "--gop" => options.gop_size = value(option, &argument)?,
+                "--payload" => options.payload_size = value(option, &argument)?,
+                "--reader" => readers.push(ReaderOptions::parse(&argument)?),
+                "--max-capacity" => options.max_capacity = value(option, &argument)?,
+                "--capacity-increment" => options.capacity_increment = value(option, &argument)?,
+                "--duration" => {
+                    let seconds: f64 = value(option, &argument)?;
+                    options.duration =
+                        Some(Duration::try_from_secs_f64(seconds).map_err(|_| {
+                            format!("invalid value for --duration: {:?}", argument)
+                        })?);
+                }
+                "--config" => options.config = Some(PathBuf::from(argument)),
+                _ => return Err(format!("unknown option {}", option)),
+            }
+        }
+        if !readers.is_empty() {
+            options.readers = readers;
+        }
+        options.validate()?;
+        Ok(Some(options))
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if self.fps == 0 {
+            return Err("--fps must be at least 1".to_string());
+        }
+        if self.gop_size == 0 {
+            return Err("--gop must be at least 1".to_string());
+        }
+        if self.max_capacity == 0 {
+            return Err("--max-capacity must be at least 1".to_string());
+        }
+        if self.frames == 0 && self.duration.is_none() && self.config.is_none() {
+            return Err("--frames 0 needs a --duration".to_string());
+        }
+        if self.readers.len() > MAX_RECEIVERS {
+            return Err(format!("at most {} readers", MAX_RECEIVERS));
+        }
+        Ok(())
+    }
+
+    // milliseconds between two frames
+    pub fn frame_interval(&self) -> u64 {
+        (1000 / self.fps as u64).max(1)
+    }
+
+    // every n-th frame of the synthetic stream is a video frame
+    pub fn video_interval(&self) -> u32 {
+        self.audio_per_video.saturating_add(1)
+    }
+
+    // one dispatcher "main" fed by the synthetic stream, the readers as "reader0",
+    // "reader1"...
+    pub fn pipeline(&self) -> PipelineConfig {
+        PipelineConfig {
+            dispatchers: vec![DispatcherConfig {
+                name: "main".to_string(),
+                max_capacity: self.max_capacity,
+                capacity_increment: self.capacity_increment,
+                ..Default::default()
+            }],
+            sources: vec![SourceConfig::SYNTHETIC(SyntheticConfig {
+                dispatcher: "main".to_string(),
+                frames: self.frames,
+                fps: self.fps,
+                audio_per_video: self.audio_per_video,
+                gop: self.gop_size,
+                payload: self.payload_size,
+                ..Default::default()
+            })],
+            receivers: self
+                .readers
+                .iter()
+                .enumerate()
+                .map(|(index, reader)| ReceiverConfig {
+                    name: format!("reader{}", index),
+                    dispatcher: "main".to_string(),
+                    media_type: Some(format!("{:?}", reader.media_type).to_lowercase()),
+                    key_only: reader.key_only,
+                    ..Default::default()
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+}
@@ -0,0 +1,29 @@
+// process signals turned into flags the program polls, a handler may do little
+// more than store an atomic
+use std::sync::atomic::{AtomicBool, Ordering};
+
+const MAX_SIGNAL: usize = 64;
+
+static PENDING: [AtomicBool; MAX_SIGNAL] = [const { AtomicBool::new(false) }; MAX_SIGNAL];
+
+extern "C" fn on_signal(signal: libc::c_int) {
+    if let Some(pending) = PENDING.get(signal as usize) {
+        pending.store(true, Ordering::Relaxed);
+    }
+}
+
+// replaces the default action of the signal, false if it can not be caught
+pub fn watch(signal: libc::c_int) -> bool {
+    if signal <= 0 || signal as usize >= MAX_SIGNAL {
+        return false;
+    }
+    let handler = on_signal as extern "C" fn(libc::c_int) as libc::sighandler_t;
+    unsafe { libc::signal(signal, handler) != libc::SIG_ERR }
+}
+
+// whether the signal arrived since the last call
+pub fn take(signal: libc::c_int) -> bool {
+    PENDING
+        .get(signal as usize)
+        .is_some_and(|pending| pending.swap(false, Ordering::Relaxed))
+}
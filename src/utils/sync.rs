@@ -0,0 +1,118 @@
+// synchronization primitives, swapped for loom's models when built with `--cfg loom`
+#[cfg(loom)]
+pub use loom::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+#[cfg(not(loom))]
+pub use std::{
+    sync::{
+        atomic::{AtomicBool, AtomicU32, Ordering},
+        Condvar, Mutex, MutexGuard,
+    },
+    thread,
+};
+
+// a wakeup that is remembered until the waiter consumes it, so a notify issued
+// while the waiter is busy is not lost
+#[derive(Debug, Default)]
+pub struct Signal {
+    pending: Mutex<bool>,
+    condvar: Condvar,
+}
+
+impl Signal {
+    pub fn new() -> Self {
+        Signal {
+            pending: Mutex::new(false),
+            condvar: Condvar::new(),
+        }
+    }
+
+    pub fn notify(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        *pending = true;
+        self.condvar.notify_all();
+    }
+
+    // blocks until notified since the last wait returned
+    pub fn wait(&self) {
+        let mut pending = self.pending.lock().unwrap();
+        while !*pending {
+            pending = self.condvar.wait(pending).unwrap();
+        }
+        *pending = false;
+    }
+}
+
+// runs pass once per wakeup until running is cleared; the stopper clears running
+// before it notifies, so the wakeup it issues ends the loop
+pub fn run_signaled(running: &AtomicBool, signal: &Signal, mut pass: impl FnMut()) {
+    while running.load(Ordering::Acquire) {
+        pass();
+        signal.wait();
+    }
+}
+
+// one mask per read index with a bit per track slot: recv marks the tracks a
+// receiver is waiting on, data the tracks that have data for it. both are set before
+// the data signal is notified, and the notify pass runs after its wait returned, so
+// the signal's mutex orders them and relaxed accesses are enough
+#[derive(Debug)]
+pub struct ReadyMasks<const N: usize> {
+    recv: [AtomicU32; N],
+    data: [AtomicU32; N],
+}
+
+impl<const N: usize> Default for ReadyMasks<N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const N: usize> ReadyMasks<N> {
+    pub fn new() -> Self {
+        ReadyMasks {
+            recv: std::array::from_fn(|_| AtomicU32::new(0)),
+            data: std::array::from_fn(|_| AtomicU32::new(0)),
+        }
+    }
+
+    pub fn set_recv(&self, index: usize, bits: u32, ready: bool) {
+        Self::set(&self.recv[index], bits, ready);
+    }
+
+    pub fn set_data(&self, index: usize, bits: u32, ready: bool) {
+        Self::set(&self.data[index], bits, ready);
+    }
+
+    pub fn recv(&self, index: usize) -> u32 {
+        self.recv[index].load(Ordering::Relaxed)
+    }
+
+    pub fn data(&self, index: usize) -> u32 {
+        self.data[index].load(Ordering::Relaxed)
+    }
+
+    // the tracks to wake the receiver at index for
+    pub fn ready(&self, index: usize) -> u32 {
+        self.data(index) & self.recv(index)
+    }
+
+    pub fn clear_data(&self) {
+        for data in self.data.iter() {
+            data.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn set(mask: &AtomicU32, bits: u32, ready: bool) {
+        if ready {
+            mask.fetch_or(bits, Ordering::Relaxed);
+        } else {
+            mask.fetch_and(!bits, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use rust_proj::{
+    dispatcher::{
+        capture::{self, SessionCapture, TraceEvent},
+        dispatcher::Dispatcher,
+        scenario::Scenario,
+    },
+    source::synthetic::SyntheticStream,
+    utils::buffer::MediaType,
+};
+use std::{fs, path::PathBuf, process};
+
+fn trace_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rust_proj-{}-trace-{}", process::id(), name))
+}
+
+// a video, an audio and a key-only reader, one of them leaving half way
+fn capture_session(name: &str) -> (PathBuf, usize) {
+    let path = trace_path(name);
+    let mut scenario = Scenario::new(SyntheticStream::new(4, 3, 10));
+    scenario
+        .dispatcher()
+        .lock()
+        .unwrap()
+        .set_capture(SessionCapture::new(&path));
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .attach("audio", MediaType::AUDIO)
+        .attach_key_only("key", MediaType::VIDEO);
+    for _ in 0..6 {
+        scenario
+            .input(5)
+            .read("video", 2)
+            .read_all("audio")
+            .read_all("key");
+    }
+    scenario.detach("audio").input(8).read_all("video");
+    let reads = ["video", "audio", "key"]
+        .iter()
+        .map(|name| scenario.frames(name).len())
+        .sum();
+    // flushes the trace
+    scenario.dispatcher().lock().unwrap().set_capture(None);
+    (path, reads)
+}
+
+#[test]
+fn replay_of_a_capture_gets_the_same_frames() {
+    let (path, reads) = capture_session("same");
+    let records = capture::read_trace(&path).unwrap();
+    let captured = records
+        .iter()
+        .filter(|record| matches!(record.event, TraceEvent::READ(_, _, Some(_))))
+        .count();
+    assert_eq!(captured, reads);
+    // the trace is timed by the scenario's clock, which never moved
+    assert!(records.iter().all(|record| record.time == 0));
+
+    let report = capture::replay(&records, &Dispatcher::new(400, 50));
+    assert_eq!(report.events, records.len());
+    assert_eq!(report.reads.len(), 3);
+    assert!(report.is_clean(), "{:?}", report.divergences);
+    let _ = fs::remove_file(&path);
+}
+
+#[test]
+fn replay_reports_reads_that_differ() {
+    let (path, _) = capture_session("differ");
+    let mut records = capture::read_trace(&path).unwrap();
+    let (index, recv_id) = records
+        .iter()
+        .enumerate()
+        .find_map(|(index, record)| match record.event {
+            TraceEvent::READ(receiver, _, Some(_)) if receiver.key_only => {
+                Some((index, receiver.recv_id))
+            }
+            _ => None,
+        })
+        .unwrap();
+    if let TraceEvent::READ(_, _, Some(frame)) = &mut records[index].event {
+        frame.pts += 1;
+    }
+
+    let report = capture::replay(&records, &Dispatcher::new(400, 50));
+    assert_eq!(report.divergences.len(), 1);
+    let divergence = report.divergences[0];
+    assert_eq!(divergence.event, index);
+    assert_eq!(divergence.recv_id, recv_id);
+    assert_eq!(
+        divergence.expected.unwrap().pts,
+        divergence.actual.unwrap().pts + 1
+    );
+    let _ = fs::remove_file(&path);
+}
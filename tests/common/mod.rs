@@ -0,0 +1,117 @@
+// helpers shared by the integration tests, not every test uses all of them
+#![allow(dead_code)]
+
+use rust_proj::{
+    dispatcher::{dispatcher::Dispatcher, receiver::Receiver},
+    utils::{
+        buffer::{MediaData, MediaType},
+        codec::{aac_config, h264_config, nalus_to_avcc},
+    },
+};
+use std::{
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// baseline profile, 640x480
+pub const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1e, 0x95, 0xa8, 0x28, 0x0f, 0x64];
+pub const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+
+// an IDR or non-IDR slice for video, a raw AAC frame for every other track
+pub fn payload(media_type: MediaType, pts: u64, key_frame: bool) -> Vec<u8> {
+    match media_type {
+        MediaType::VIDEO => nalus_to_avcc(&[&[if key_frame { 0x65 } else { 0x41 }, pts as u8]]),
+        _ => vec![0x21, pts as u8],
+    }
+}
+
+pub fn frame(media_type: MediaType, pts: u64, key_frame: bool) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type,
+        ..Default::default()
+    };
+    data.buff
+        .lock()
+        .unwrap()
+        .replace(payload(media_type, pts, key_frame));
+    Arc::new(Mutex::new(data))
+}
+
+pub fn dispatcher() -> Arc<Mutex<Dispatcher>> {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher.lock().unwrap().start_dispatch();
+    dispatcher
+}
+
+// a started dispatcher with H.264 and AAC configs
+pub fn av_dispatcher() -> Arc<Mutex<Dispatcher>> {
+    let dispatcher = dispatcher();
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(h264_config(SPS, PPS).unwrap());
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(aac_config(&[0x12, 0x10]).unwrap());
+    dispatcher
+}
+
+pub fn attach(dispatcher: &Arc<Mutex<Dispatcher>>) -> Arc<Receiver> {
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver
+}
+
+pub fn attach_tracks(dispatcher: &Arc<Mutex<Dispatcher>>, tracks: &[MediaType]) -> Arc<Receiver> {
+    let receiver = Arc::new(Receiver::new());
+    receiver.set_tracks(tracks);
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver
+}
+
+pub fn input(dispatcher: &Arc<Mutex<Dispatcher>>, frames: &[(MediaType, u64, bool)]) {
+    for &(media_type, pts, key_frame) in frames {
+        dispatcher
+            .lock()
+            .unwrap()
+            .input_data(frame(media_type, pts, key_frame));
+    }
+}
+
+// the next count frames of the receiver, as f maps them
+pub fn read_with<T>(
+    receiver: &Receiver,
+    media_type: MediaType,
+    count: usize,
+    f: impl Fn(&MediaData) -> T,
+) -> Vec<T> {
+    let mut read = Vec::new();
+    while read.len() < count {
+        if let (true, Some(data)) = receiver.request_read(media_type) {
+            read.push(f(&data.lock().unwrap()));
+        }
+    }
+    read
+}
+
+// (media type, pts) of the next count frames
+pub fn read(receiver: &Receiver, media_type: MediaType, count: usize) -> Vec<(MediaType, u64)> {
+    read_with(receiver, media_type, count, |data| {
+        (data.media_type, data.pts)
+    })
+}
+
+pub fn wait_for(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
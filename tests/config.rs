@@ -0,0 +1,162 @@
+use rust_proj::{
+    config::{ConfigError, PipelineConfig},
+    dispatcher::dispatcher::OverflowPolicy,
+    utils::{
+        buffer::MediaType,
+        macros::{self, LogLevel},
+    },
+};
+use std::time::Duration;
+
+const PIPELINE: &str = r#"
+[logging]
+level = "warn"
+modules = { "dispatcher::receiver" = "off" }
+
+[[dispatchers]]
+name = "main"
+max_capacity = 100
+overflow = "drop_oldest"
+
+[[sources]]
+type = "synthetic"
+dispatcher = "main"
+frames = 24
+gop = 4
+audio_per_video = 1
+pace = "fast"
+
+[[receivers]]
+name = "all"
+dispatcher = "main"
+
+[[receivers]]
+name = "keys"
+dispatcher = "main"
+media_type = "video"
+key_only = true
+
+[[receivers]]
+name = "late"
+dispatcher = "main"
+media_type = "video"
+start = 100
+"#;
+
+#[test]
+fn a_toml_pipeline_is_built_and_runs() {
+    let config = PipelineConfig::from_toml(PIPELINE).unwrap();
+    let mut pipeline = config.build().unwrap();
+    assert_eq!(macros::log_level(), LogLevel::WARN);
+    assert!(!macros::enabled(
+        LogLevel::FATAL,
+        "src/dispatcher/receiver.rs"
+    ));
+    assert!(macros::enabled(
+        LogLevel::WARN,
+        "src/dispatcher/dispatcher.rs"
+    ));
+    assert!(!macros::enabled(
+        LogLevel::INFO,
+        "src/dispatcher/dispatcher.rs"
+    ));
+    macros::set_log_level(LogLevel::DEBUG);
+    macros::clear_module_levels();
+
+    let dispatcher = pipeline.dispatcher("main").unwrap();
+    assert_eq!(
+        dispatcher.lock().unwrap().overflow_policy(),
+        OverflowPolicy::EVICT
+    );
+    assert_eq!(dispatcher.lock().unwrap().receiver_count(), 3);
+
+    pipeline.start();
+    let mut read = Vec::new();
+    for receiver in pipeline.receivers() {
+        let mut frames = Vec::new();
+        while let Some(data) = receiver
+            .receiver
+            .read_frame(receiver.media_type, Some(Duration::from_millis(200)))
+        {
+            let data = data.lock().unwrap();
+            frames.push((data.media_type, data.pts, data.key_frame));
+        }
+        read.push((receiver.name.clone(), frames));
+    }
+    pipeline.stop();
+
+    assert_eq!(read[0].0, "all");
+    assert_eq!(read[0].1.len(), 24);
+    assert_eq!(read[1].0, "keys");
+    let keys: Vec<_> = read[1].1.iter().map(|(_, pts, key)| (*pts, *key)).collect();
+    assert_eq!(keys, vec![(0, true), (320, true), (640, true)]);
+    // video every 80 ms from the first key frame after pts 100
+    assert_eq!(read[2].0, "late");
+    let late: Vec<_> = read[2].1.iter().map(|(_, pts, key)| (*pts, *key)).collect();
+    assert_eq!(late[0], (320, true));
+    assert_eq!(late.len(), 8);
+    assert!(read[2]
+        .1
+        .iter()
+        .all(|(media_type, _, _)| *media_type == MediaType::VIDEO));
+}
+
+#[test]
+fn invalid_configs_list_every_problem() {
+    let yaml = r#"
+logging:
+  level: loud
+dispatchers:
+  - name: main
+    overflow: drop_everything
+  - name: main
+    max_capacity: 0
+sources:
+  - type: file
+    dispatcher: main
+    path: /nonexistent/input.flv
+  - type: synthetic
+    dispatcher: backup
+    pace: slow
+receivers:
+  - name: preview
+    dispatcher: main
+    media_type: vidoe
+  - name: preview
+    dispatcher: main
+    start: oldest
+sinks:
+  - type: fmp4
+  - type: fmp4
+    dispatcher: main
+    path: captions.mp4
+    media_type: subtitle
+"#;
+    let problems = match PipelineConfig::from_yaml(yaml) {
+        Err(ConfigError::INVALID(problems)) => problems,
+        other => panic!("unexpected result: {:?}", other.err()),
+    };
+    assert_eq!(
+        problems,
+        vec![
+            "logging: unknown level \"loud\", expected debug, info, warn, error, fatal or off",
+            "dispatchers[0] \"main\": unknown overflow \"drop_everything\", expected grow, drop_oldest or drop_new",
+            "dispatchers[1] \"main\": duplicate name",
+            "dispatchers[1] \"main\": max_capacity must be at least 1",
+            "sources[0]: no such file \"/nonexistent/input.flv\"",
+            "sources[1]: unknown dispatcher \"backup\"",
+            "sources[1]: unknown pace \"slow\", expected realtime or fast",
+            "receivers[0] \"preview\": unknown media_type \"vidoe\"",
+            "receivers[1] \"preview\": duplicate name",
+            "receivers[1] \"preview\": unknown start \"oldest\", expected \"live\" or a pts",
+            "sinks[0]: missing dispatcher",
+            "sinks[0]: missing path",
+            "sinks[1]: media_type \"subtitle\" can not be written to fmp4, expected av, audio or video",
+        ]
+    );
+
+    let error = PipelineConfig::from_toml("[[dispatchers]]\nname = \"main\"\ncapacity = 10\n")
+        .unwrap_err()
+        .to_string();
+    assert!(error.contains("unknown field `capacity`"), "{}", error);
+}
@@ -0,0 +1,153 @@
+use rust_proj::{
+    format::{
+        es::{AdtsDemuxer, H264Demuxer},
+        Demuxer, Packet,
+    },
+    source::file_source::{open_demuxer, FileFormat},
+    utils::{
+        buffer::MediaType,
+        codec::{CodecConfig, CodecType},
+    },
+};
+use std::{fs::File, io::Read, path::PathBuf};
+
+// the fixtures are written by tests/fixtures/make_fixtures.py
+fn fixture(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(name)
+}
+
+// hands out a few bytes per read, so frames straddle reads
+struct Trickle(File);
+
+impl Read for Trickle {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let len = buf.len().min(3);
+        self.0.read(&mut buf[..len])
+    }
+}
+
+struct Frame {
+    media_type: MediaType,
+    pts: u64,
+    key_frame: bool,
+    payload: Vec<u8>,
+}
+
+fn read_all(mut demuxer: Box<dyn Demuxer>) -> (Vec<CodecConfig>, Vec<Frame>) {
+    let mut configs = Vec::new();
+    let mut frames = Vec::new();
+    while let Some(packet) = demuxer.read_packet() {
+        match packet {
+            Packet::CONFIG(config) => configs.push(config),
+            Packet::DATA(data) => frames.push(Frame {
+                media_type: data.media_type,
+                pts: data.pts,
+                key_frame: data.key_frame,
+                payload: data.buff.lock().unwrap().data().lock().unwrap().clone(),
+            }),
+        }
+    }
+    (configs, frames)
+}
+
+fn open(name: &str) -> Box<dyn Demuxer> {
+    let path = fixture(name);
+    open_demuxer(&path, FileFormat::from_path(&path).unwrap(), 25).unwrap()
+}
+
+fn video(frames: &[Frame]) -> Vec<(u64, bool)> {
+    frames
+        .iter()
+        .filter(|frame| frame.media_type == MediaType::VIDEO)
+        .map(|frame| (frame.pts, frame.key_frame))
+        .collect()
+}
+
+fn audio_payloads(frames: &[Frame]) -> Vec<Vec<u8>> {
+    frames
+        .iter()
+        .filter(|frame| frame.media_type == MediaType::AUDIO)
+        .map(|frame| frame.payload.clone())
+        .collect()
+}
+
+fn audio_payload(index: u8) -> Vec<u8> {
+    let mut payload = vec![0x21, index];
+    payload.resize(10, 0);
+    payload
+}
+
+fn assert_configs(configs: &[CodecConfig]) {
+    let video = configs
+        .iter()
+        .find(|config| config.codec == CodecType::H264)
+        .unwrap();
+    assert_eq!((video.width, video.height), (640, 480));
+    let audio = configs
+        .iter()
+        .find(|config| config.codec == CodecType::AAC)
+        .unwrap();
+    assert_eq!((audio.sample_rate, audio.channels), (48000, 2));
+}
+
+fn expected_video() -> Vec<(u64, bool)> {
+    (0..8).map(|index| (index * 40, index % 4 == 0)).collect()
+}
+
+#[test]
+fn annexb_stream_read_in_small_pieces_splits_into_access_units() {
+    let input = Box::new(Trickle(File::open(fixture("sample.h264")).unwrap()));
+    let (configs, frames) = read_all(Box::new(H264Demuxer::new(input, 25)));
+    assert_eq!(configs.len(), 1);
+    assert_eq!((configs[0].width, configs[0].height), (640, 480));
+    assert_eq!(video(&frames), expected_video());
+    // sps and pps stay in the key frames, each nal unit length prefixed
+    assert_eq!(&frames[0].payload[..4], &[0, 0, 0, 9]);
+    assert_eq!(frames[0].payload.len(), 4 + 9 + 4 + 4 + 4 + 204);
+    assert_eq!(frames[1].payload, [0, 0, 0, 4, 0x41, 0x9a, 0x02, 1]);
+}
+
+#[test]
+fn adts_stream_skips_junk_and_unsupported_headers() {
+    let input = Box::new(Trickle(File::open(fixture("sample.aac")).unwrap()));
+    let (configs, frames) = read_all(Box::new(AdtsDemuxer::new(input)));
+    assert_eq!(configs.len(), 1);
+    assert_eq!((configs[0].sample_rate, configs[0].channels), (48000, 2));
+    let pts: Vec<u64> = frames.iter().map(|frame| frame.pts).collect();
+    assert_eq!(pts, [0, 21, 42, 64, 85]);
+    assert_eq!(
+        audio_payloads(&frames),
+        (0..5).map(audio_payload).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn flv_file_demuxes_configs_and_frames() {
+    let (configs, frames) = read_all(open("sample.flv"));
+    assert_eq!(configs.len(), 2);
+    assert_configs(&configs);
+    assert_eq!(video(&frames), expected_video());
+    assert_eq!(
+        audio_payloads(&frames),
+        (0..8).map(audio_payload).collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn ts_file_demuxes_configs_and_frames() {
+    let (configs, frames) = read_all(open("sample.ts"));
+    assert_configs(&configs);
+    assert_eq!(video(&frames), expected_video());
+    let audio: Vec<u64> = frames
+        .iter()
+        .filter(|frame| frame.media_type == MediaType::AUDIO)
+        .map(|frame| frame.pts)
+        .collect();
+    assert_eq!(audio, (0..8).map(|index| index * 40 + 10).collect::<Vec<_>>());
+    assert_eq!(
+        audio_payloads(&frames),
+        (0..8).map(audio_payload).collect::<Vec<_>>()
+    );
+}
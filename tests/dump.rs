@@ -0,0 +1,73 @@
+use rust_proj::{
+    dispatcher::{dump::DumpFormat, scenario::Scenario},
+    source::synthetic::SyntheticStream,
+    utils::buffer::MediaType,
+};
+
+// key frames at 0 and 120, video every third frame
+fn scenario() -> Scenario {
+    let mut scenario = Scenario::new(SyntheticStream::new(4, 3, 10));
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .attach("audio", MediaType::AUDIO)
+        .input(14)
+        .read("video", 2)
+        .read("audio", 1);
+    scenario
+}
+
+#[test]
+fn state_shows_reserve_flags_and_cursors() {
+    let scenario = scenario();
+    let state = scenario.dispatcher().lock().unwrap().dump_state();
+
+    assert_eq!(state["key_index"], serde_json::json!([0, 12]));
+    assert_eq!(state["read_flag"], 0b11);
+    let samples = state["samples"].as_array().unwrap();
+    assert_eq!(samples.len(), 14);
+    assert_eq!(samples[12]["pts"], 120);
+    assert_eq!(samples[12]["key_frame"], true);
+
+    let receivers = state["receivers"].as_array().unwrap();
+    assert_eq!(receivers.len(), 2);
+    let video = &receivers[0];
+    let read_index = video["read_index"].as_u64().unwrap();
+    let read: Vec<u64> = samples
+        .iter()
+        .filter(|sample| sample["reserve_flag"].as_u64().unwrap() & (1 << read_index) != 0)
+        .map(|sample| sample["pts"].as_u64().unwrap())
+        .collect();
+    assert_eq!(read, [0, 30]);
+    // the next video frame is at 60
+    assert_eq!(video["indices"]["video"], 6);
+    assert_eq!(video["indices"]["subtitle"], serde_json::Value::Null);
+    assert!(video["data_ref"].is_u64());
+    assert!(video["recv_ref"].is_u64());
+}
+
+#[test]
+fn timeline_and_graph_render_the_buffer() {
+    let scenario = scenario();
+    let dispatcher = scenario.dispatcher();
+    let dispatcher = dispatcher.lock().unwrap();
+
+    let timeline = dispatcher.dump_state_as(DumpFormat::ASCII);
+    let rows: Vec<&str> = timeline
+        .lines()
+        .skip_while(|line| !line.trim_start().starts_with("index"))
+        .skip(1)
+        .collect();
+    assert_eq!(rows.len(), 14);
+    let columns: Vec<&str> = rows[0].split_whitespace().collect();
+    // the audio receiver keeps the video cursor it got on attach
+    assert_eq!(columns, ["0", "video", "0", "0", "K*", "x", ".v"]);
+    let columns: Vec<&str> = rows[6].split_whitespace().collect();
+    assert_eq!(columns, ["6", "video", "0", "60", ".v", "."]);
+
+    let graph = dispatcher.dump_state_as(DumpFormat::DOT);
+    assert!(graph.starts_with("digraph dispatcher {"));
+    assert!(graph.contains("s12 [label=\"12|video 0|pts 120|"));
+    assert!(graph.contains("s0 -> s1;"));
+    assert!(graph.contains("-> s6 [label=\"video\""));
+    serde_json::from_str::<serde_json::Value>(&dispatcher.dump_state_as(DumpFormat::JSON)).unwrap();
+}
@@ -0,0 +1,183 @@
+use rust_proj::{
+    dispatcher::{
+        dispatcher::Dispatcher,
+        dvr::{DvrPlayback, DvrStore},
+        receiver::Receiver,
+    },
+    utils::buffer::{MediaData, MediaType},
+};
+use std::{
+    fs,
+    path::PathBuf,
+    process,
+    sync::{Arc, Mutex},
+};
+
+fn dvr_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("rust_proj-{}-dvr-{}", process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+// video every 40ms with a key frame every 120ms, audio in between
+fn frames(until: u64) -> Vec<(MediaType, u64, bool)> {
+    (0..until)
+        .step_by(20)
+        .map(|pts| match pts % 40 {
+            0 => (MediaType::VIDEO, pts, pts % 120 == 0),
+            _ => (MediaType::AUDIO, pts, false),
+        })
+        .collect()
+}
+
+fn frame((media_type, pts, key_frame): (MediaType, u64, bool)) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type,
+        ..Default::default()
+    };
+    data.buff
+        .lock()
+        .unwrap()
+        .replace(pts.to_be_bytes().to_vec());
+    Arc::new(Mutex::new(data))
+}
+
+// feeds the frames while a live receiver reading media_type keeps up, so GOPs leave the
+// buffer
+fn ingest(
+    dispatcher: &Arc<Mutex<Dispatcher>>,
+    frames: &[(MediaType, u64, bool)],
+    media_type: MediaType,
+) -> Arc<Receiver> {
+    let live = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(live.clone());
+    live.set_dispatcher(dispatcher.clone());
+    for frame_info in frames {
+        dispatcher.lock().unwrap().input_data(frame(*frame_info));
+        loop {
+            dispatcher.lock().unwrap().dispatch_once();
+            if live.try_read(media_type).1.is_none() {
+                break;
+            }
+        }
+    }
+    live
+}
+
+#[test]
+fn playback_reads_from_disk_into_the_live_buffer() {
+    let dir = dvr_dir("playback");
+    let store = Arc::new(Mutex::new(DvrStore::new(&dir, 200, 3_600_000).unwrap()));
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_dvr_store(Some(store.clone()));
+    let input = frames(1200);
+    let live = ingest(&dispatcher, &input, MediaType::AV);
+
+    let (first, last) = store.lock().unwrap().range().unwrap();
+    assert_eq!(first, 0);
+    assert!((960..1200).contains(&last));
+    assert!(store.lock().unwrap().segment_count() > 1);
+    assert!(dir.join("00000000.seg").exists());
+    assert!(dir.join("00000000.idx").exists());
+
+    dispatcher.lock().unwrap().start_dispatch();
+    let receiver = Arc::new(Receiver::new());
+    let mut playback = DvrPlayback::new(dispatcher.clone(), store.clone(), receiver, 250);
+    assert!(!playback.is_live());
+
+    // from the key frame before 250, every frame once, in order
+    let expected: Vec<(MediaType, u64)> = input
+        .iter()
+        .filter(|(_, pts, _)| *pts >= 240)
+        .map(|(media_type, pts, _)| (*media_type, *pts))
+        .collect();
+    let mut read = Vec::new();
+    while read.len() < expected.len() {
+        if let (true, Some(data)) = playback.request_read(MediaType::AV) {
+            let data = data.lock().unwrap();
+            read.push((data.media_type, data.pts));
+        }
+    }
+    assert_eq!(read, expected);
+    assert!(playback.is_live());
+
+    live.detach();
+    playback.receiver().detach();
+    dispatcher.lock().unwrap().stop_dispatch();
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn segments_past_the_retention_are_removed() {
+    let dir = dvr_dir("retention");
+    let store = Arc::new(Mutex::new(DvrStore::new(&dir, 120, 300).unwrap()));
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_dvr_store(Some(store.clone()));
+    ingest(&dispatcher, &frames(1200), MediaType::AV);
+
+    let (first, last) = store.lock().unwrap().range().unwrap();
+    assert!(first > 0);
+    assert!(last - first <= 300 + 120);
+    assert!(!dir.join("00000000.seg").exists());
+    // a pts before the store starts at its oldest key frame
+    let store = store.lock().unwrap();
+    assert_eq!(store.seek(0), store.seek(first));
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn a_store_reopened_on_its_dir_loads_the_segments() {
+    let dir = dvr_dir("reopen");
+    let store = Arc::new(Mutex::new(DvrStore::new(&dir, 200, 3_600_000).unwrap()));
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_dvr_store(Some(store.clone()));
+    let live = ingest(&dispatcher, &frames(1200), MediaType::AV);
+    live.detach();
+    dispatcher.lock().unwrap().set_dvr_store(None);
+    let (range, count, position) = {
+        let store = store.lock().unwrap();
+        (store.range(), store.segment_count(), store.seek(250))
+    };
+    // the writers are flushed once the store is dropped
+    drop(store);
+
+    let reopened = DvrStore::new(&dir, 200, 3_600_000).unwrap();
+    assert_eq!(reopened.range(), range);
+    assert_eq!(reopened.segment_count(), count);
+    assert_eq!(reopened.seek(250), position);
+    let _ = fs::remove_dir_all(&dir);
+}
+
+#[test]
+fn a_video_reader_does_not_hold_back_the_audio() {
+    let dir = dvr_dir("video-reader");
+    let store = Arc::new(Mutex::new(DvrStore::new(&dir, 200, 3_600_000).unwrap()));
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_dvr_store(Some(store.clone()));
+    let live = ingest(&dispatcher, &frames(1200), MediaType::VIDEO);
+
+    // the audio it never reads goes with the GOPs it did
+    assert!(dispatcher.lock().unwrap().buffered_frames() <= 6);
+    let (first, last) = store.lock().unwrap().range().unwrap();
+    assert_eq!(first, 0);
+    assert!((960..1200).contains(&last));
+    assert!(store.lock().unwrap().segment_count() > 1);
+
+    live.detach();
+    let _ = fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,169 @@
+use rust_proj::ffi::*;
+use std::{ptr, thread, time::Duration};
+
+unsafe fn push(dispatcher: *mut RpDispatcher, media_type: u32, pts: u64, key_frame: bool) {
+    let payload = [pts as u8; 8];
+    assert_eq!(
+        rp_dispatcher_push(
+            dispatcher,
+            media_type,
+            pts,
+            pts,
+            key_frame,
+            payload.as_ptr(),
+            payload.len()
+        ),
+        RP_OK
+    );
+}
+
+#[test]
+fn frames_are_copied_into_caller_buffers() {
+    unsafe {
+        let dispatcher = rp_dispatcher_new(400, 50);
+        assert_eq!(rp_dispatcher_start(dispatcher), RP_OK);
+        let receiver = rp_receiver_attach(dispatcher, 0, false);
+        assert!(!receiver.is_null());
+        push(dispatcher, RP_MEDIA_VIDEO, 0, true);
+        push(dispatcher, RP_MEDIA_AUDIO, 20, false);
+        push(dispatcher, RP_MEDIA_VIDEO, 40, false);
+
+        let mut buffer = [0u8; 8];
+        let mut info = RpFrameInfo::default();
+        let mut read = Vec::new();
+        for _ in 0..3 {
+            let ret = rp_receiver_read_timeout(
+                receiver,
+                RP_MEDIA_AV,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut info,
+                1000,
+            );
+            assert_eq!(ret, RP_OK);
+            assert_eq!(info.size, 8);
+            assert_eq!(buffer, [info.pts as u8; 8]);
+            read.push((info.media_type, info.pts, info.key_frame));
+        }
+        assert_eq!(
+            read,
+            [
+                (RP_MEDIA_VIDEO, 0, true),
+                (RP_MEDIA_AUDIO, 20, false),
+                (RP_MEDIA_VIDEO, 40, false)
+            ]
+        );
+
+        // too small, then read with a buffer that fits
+        push(dispatcher, RP_MEDIA_AUDIO, 60, false);
+        let ret = rp_receiver_read_timeout(
+            receiver,
+            RP_MEDIA_AV,
+            buffer.as_mut_ptr(),
+            2,
+            &mut info,
+            1000,
+        );
+        assert_eq!(ret, RP_ERR_BUFFER_TOO_SMALL);
+        assert_eq!((info.pts, info.size), (60, 8));
+        let ret = rp_receiver_read(
+            receiver,
+            RP_MEDIA_AV,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            ptr::null_mut(),
+        );
+        assert_eq!(ret, RP_OK);
+        assert_eq!(buffer, [60; 8]);
+
+        let ret = rp_receiver_read_timeout(
+            receiver,
+            RP_MEDIA_AV,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut info,
+            20,
+        );
+        assert_eq!(ret, RP_ERR_TIMEOUT);
+
+        rp_receiver_detach(receiver);
+        rp_dispatcher_free(dispatcher);
+    }
+}
+
+#[test]
+fn stop_wakes_a_blocked_read() {
+    unsafe {
+        let dispatcher = rp_dispatcher_new(400, 50);
+        rp_dispatcher_start(dispatcher);
+        let receiver = rp_receiver_attach(dispatcher, 0, false);
+        let address = receiver as usize;
+        let reader = thread::spawn(move || {
+            let mut buffer = [0u8; 8];
+            rp_receiver_read(
+                address as *mut RpReceiver,
+                RP_MEDIA_VIDEO,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                ptr::null_mut(),
+            )
+        });
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(rp_receiver_stop(receiver), RP_OK);
+        assert_eq!(reader.join().unwrap(), RP_ERR_STOPPED);
+
+        assert_eq!(rp_receiver_stop(ptr::null_mut()), RP_ERR_INVALID);
+        assert!(rp_receiver_attach(ptr::null_mut(), 0, false).is_null());
+        assert_eq!(
+            rp_receiver_read(receiver, 42, ptr::null_mut(), 0, ptr::null_mut()),
+            RP_ERR_INVALID
+        );
+        rp_receiver_detach(receiver);
+        rp_dispatcher_free(dispatcher);
+    }
+}
+
+#[test]
+fn a_track_mask_picks_the_tracks_and_held_frames_stay_with_their_type() {
+    unsafe {
+        let dispatcher = rp_dispatcher_new(400, 50);
+        rp_dispatcher_start(dispatcher);
+        assert!(rp_receiver_attach(dispatcher, 1 << (RP_MEDIA_DATA + 1), false).is_null());
+        let receiver = rp_receiver_attach(
+            dispatcher,
+            1 << RP_MEDIA_VIDEO | 1 << RP_MEDIA_METADATA,
+            false,
+        );
+        assert!(!receiver.is_null());
+        push(dispatcher, RP_MEDIA_VIDEO, 0, true);
+        push(dispatcher, RP_MEDIA_AUDIO, 20, false);
+        push(dispatcher, RP_MEDIA_METADATA, 30, false);
+        push(dispatcher, RP_MEDIA_VIDEO, 40, false);
+
+        let mut buffer = [0u8; 8];
+        let mut info = RpFrameInfo::default();
+        let mut read = |media_type, capacity| {
+            let ret = rp_receiver_read_timeout(
+                receiver,
+                media_type,
+                buffer.as_mut_ptr(),
+                capacity,
+                &mut info,
+                100,
+            );
+            (ret, info.media_type, info.pts)
+        };
+        assert_eq!(
+            read(RP_MEDIA_VIDEO, 2),
+            (RP_ERR_BUFFER_TOO_SMALL, RP_MEDIA_VIDEO, 0)
+        );
+        // the held video frame is not handed to a read of another type
+        assert_eq!(read(RP_MEDIA_METADATA, 8), (RP_OK, RP_MEDIA_METADATA, 30));
+        assert_eq!(read(RP_MEDIA_VIDEO, 8), (RP_OK, RP_MEDIA_VIDEO, 0));
+        assert_eq!(read(RP_MEDIA_VIDEO, 8), (RP_OK, RP_MEDIA_VIDEO, 40));
+        assert_eq!(read(RP_MEDIA_AUDIO, 8).0, RP_ERR_TIMEOUT);
+
+        rp_receiver_detach(receiver);
+        rp_dispatcher_free(dispatcher);
+    }
+}
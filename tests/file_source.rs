@@ -0,0 +1,82 @@
+mod common;
+
+use common::{attach, dispatcher, read, wait_for};
+use rust_proj::{
+    dispatcher::dispatcher::Dispatcher,
+    source::file_source::{FileSource, PaceMode},
+    utils::{
+        buffer::MediaType,
+        clock::{Clock, VirtualClock},
+    },
+};
+use std::{
+    path::PathBuf,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+// eight frames at 25 fps, key frames at 0 and 160, see tests/fixtures/make_fixtures.py
+fn h264_source(dispatcher: &Arc<Mutex<Dispatcher>>, clock: &Arc<VirtualClock>) -> FileSource {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/sample.h264");
+    let mut source = FileSource::new(&path, dispatcher.clone()).unwrap();
+    source.set_clock(clock.clone());
+    source
+}
+
+// a key frame coming in while a reader is between two reads moves it past the frames it
+// has not read yet, so the frames are read once the ingest is done and all buffered
+fn run(speed: f64, pace_mode: PaceMode) -> (Vec<(MediaType, u64)>, Duration) {
+    let dispatcher = dispatcher();
+    let receiver = attach(&dispatcher);
+    let clock = VirtualClock::shared();
+    let mut source = h264_source(&dispatcher, &clock);
+    source.set_pace_mode(pace_mode);
+    source.set_speed(speed);
+    source.start_ingest();
+    wait_for(|| !source.is_running());
+    source.stop_ingest();
+    let frames = read(&receiver, MediaType::VIDEO, 8);
+    dispatcher.lock().unwrap().stop_dispatch();
+    (frames, clock.now())
+}
+
+#[test]
+fn realtime_pacing_follows_the_dts_at_the_speed() {
+    let expected: Vec<_> = (0..8).map(|index| (MediaType::VIDEO, index * 40)).collect();
+    let (frames, elapsed) = run(1.0, PaceMode::REALTIME);
+    assert_eq!(frames, expected);
+    assert_eq!(elapsed, Duration::from_millis(280));
+
+    let (frames, elapsed) = run(2.0, PaceMode::REALTIME);
+    assert_eq!(frames, expected);
+    assert_eq!(elapsed, Duration::from_millis(140));
+
+    // fast ingest never waits on the clock
+    let (frames, elapsed) = run(1.0, PaceMode::FAST);
+    assert_eq!(frames, expected);
+    assert_eq!(elapsed, Duration::ZERO);
+}
+
+#[test]
+fn a_looped_file_restarts_after_the_last_frame() {
+    let dispatcher = dispatcher();
+    let receiver = attach(&dispatcher);
+    let clock = VirtualClock::shared();
+    let mut source = h264_source(&dispatcher, &clock);
+    source.set_loop(true);
+    source.start_ingest();
+
+    // three passes, the unread frames stay buffered
+    wait_for(|| clock.now() >= Duration::from_millis(23 * 40));
+    assert!(source.is_running());
+    source.stop_ingest();
+    assert!(!source.is_running());
+
+    // every pass goes on 40ms after the last frame of the one before
+    let frames = read(&receiver, MediaType::VIDEO, 24);
+    let expected: Vec<_> = (0..24)
+        .map(|index| (MediaType::VIDEO, index * 40))
+        .collect();
+    assert_eq!(frames, expected);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
@@ -0,0 +1,147 @@
+#!/usr/bin/env python3
+# regenerates the demuxer fixtures next to this script, see tests/demux.rs
+import os
+import struct
+
+HERE = os.path.dirname(os.path.abspath(__file__))
+
+# baseline profile, 640x480
+SPS = bytes([0x67, 0x42, 0x00, 0x1E, 0x95, 0xA8, 0x28, 0x0F, 0x64])
+PPS = bytes([0x68, 0xCE, 0x38, 0x80])
+START = b"\x00\x00\x00\x01"
+# AAC LC, 48 kHz, stereo
+ASC = bytes([0x11, 0x90])
+
+
+# eight frames, key frames at 0 and 4; the key frames are long enough to cross
+# ts packet boundaries
+def video_frames():
+    frames = []
+    for index in range(8):
+        if index % 4 == 0:
+            frames.append((True, [SPS, PPS, bytes([0x65, 0x88, 0x84, index]) + b"\x5A" * 200]))
+        else:
+            frames.append((False, [bytes([0x41, 0x9A, 0x02, index])]))
+    return frames
+
+
+def adts(payload, frequency_index=3, channels=2):
+    frame_len = 7 + len(payload)
+    return bytes([
+        0xFF,
+        0xF1,
+        (1 << 6) | (frequency_index << 2) | (channels >> 2),
+        ((channels & 3) << 6) | (frame_len >> 11),
+        (frame_len >> 3) & 0xFF,
+        ((frame_len & 7) << 5) | 0x1F,
+        0xFC,
+    ]) + payload
+
+
+def audio_payload(index):
+    return bytes([0x21, index]) + bytes(8)
+
+
+def h264():
+    out = b""
+    for _, nalus in video_frames():
+        for nalu in nalus:
+            out += START + nalu
+    return out
+
+
+# five good frames with junk and an unsupported sample rate between the second and third
+def aac():
+    out = adts(audio_payload(0)) + adts(audio_payload(1))
+    out += b"\x00\x12\xFF\x00"
+    out += adts(audio_payload(99), frequency_index=15)
+    for index in range(2, 5):
+        out += adts(audio_payload(index))
+    return out
+
+
+def flv_tag(tag_type, timestamp, body):
+    header = bytes([tag_type]) + struct.pack(">I", len(body))[1:]
+    header += struct.pack(">I", timestamp)[1:] + bytes([timestamp >> 24 & 0xFF]) + bytes(3)
+    return header + body + struct.pack(">I", 11 + len(body))
+
+
+def flv():
+    out = b"FLV\x01\x05\x00\x00\x00\x09" + bytes(4)
+    avcc = bytes([1, SPS[1], SPS[2], SPS[3], 0xFF, 0xE1]) + struct.pack(">H", len(SPS)) + SPS
+    avcc += bytes([1]) + struct.pack(">H", len(PPS)) + PPS
+    out += flv_tag(9, 0, bytes([0x17, 0, 0, 0, 0]) + avcc)
+    out += flv_tag(8, 0, bytes([0xAF, 0]) + ASC)
+    for index, (key_frame, nalus) in enumerate(video_frames()):
+        # the sps and pps travel in the sequence header
+        nalus = [nalu for nalu in nalus if nalu[0] & 0x1F not in (7, 8)]
+        body = b"".join(struct.pack(">I", len(nalu)) + nalu for nalu in nalus)
+        out += flv_tag(9, index * 40, bytes([0x17 if key_frame else 0x27, 1, 0, 0, 0]) + body)
+        out += flv_tag(8, index * 40 + 10, bytes([0xAF, 1]) + audio_payload(index))
+    return out
+
+
+def crc32_mpeg(data):
+    crc = 0xFFFFFFFF
+    for byte in data:
+        crc ^= byte << 24
+        for _ in range(8):
+            crc = (crc << 1) ^ 0x04C11DB7 if crc & 0x80000000 else crc << 1
+            crc &= 0xFFFFFFFF
+    return crc
+
+
+def ts_packets(pid, payload, counters):
+    out = b""
+    first = True
+    while payload:
+        chunk = payload[:184]
+        payload = payload[184:]
+        header = bytes([0x47, (0x40 if first else 0) | pid >> 8, pid & 0xFF])
+        counter = counters.get(pid, 0)
+        counters[pid] = (counter + 1) & 0xF
+        stuffing = 184 - len(chunk)
+        if stuffing == 0:
+            out += header + bytes([0x10 | counter]) + chunk
+        else:
+            adaptation = bytes([stuffing - 1])
+            if stuffing > 1:
+                adaptation += b"\x00" + b"\xFF" * (stuffing - 2)
+            out += header + bytes([0x30 | counter]) + adaptation + chunk
+        first = False
+    return out
+
+
+def section(table):
+    return b"\x00" + table + struct.pack(">I", crc32_mpeg(table))
+
+
+def pes(stream_id, pts_ms, data):
+    pts = pts_ms * 90
+    stamp = bytes([
+        0x21 | (pts >> 29 & 0x0E),
+        pts >> 22 & 0xFF,
+        0x01 | (pts >> 14 & 0xFE),
+        pts >> 7 & 0xFF,
+        0x01 | (pts << 1 & 0xFE),
+    ])
+    return b"\x00\x00\x01" + bytes([stream_id]) + b"\x00\x00\x80\x80\x05" + stamp + data
+
+
+def ts():
+    counters = {}
+    pat = bytes([0x00, 0xB0, 0x0D, 0x00, 0x01, 0xC1, 0x00, 0x00, 0x00, 0x01, 0xE1, 0x00])
+    pmt = bytes([0x02, 0xB0, 0x17, 0x00, 0x01, 0xC1, 0x00, 0x00, 0xE1, 0x01, 0xF0, 0x00])
+    pmt += bytes([0x1B, 0xE1, 0x01, 0xF0, 0x00, 0x0F, 0xE1, 0x02, 0xF0, 0x00])
+    out = ts_packets(0, section(pat), counters)
+    out += ts_packets(0x100, section(pmt), counters)
+    for index, (_, nalus) in enumerate(video_frames()):
+        data = b"".join(START + nalu for nalu in nalus)
+        out += ts_packets(0x101, pes(0xE0, index * 40, data), counters)
+        out += ts_packets(0x102, pes(0xC0, index * 40 + 10, adts(audio_payload(index))), counters)
+    return out
+
+
+for name, data in [("sample.h264", h264()), ("sample.aac", aac()), ("sample.flv", flv()), ("sample.ts", ts())]:
+    with open(os.path.join(HERE, name), "wb") as file:
+        file.write(data)
@@ -0,0 +1,296 @@
+mod common;
+
+use rust_proj::{
+    format::fmp4::{Fmp4Muxer, Fmp4Writer, FragmentMode},
+    utils::{
+        buffer::{MediaData, MediaType},
+        codec::{aac_config, h264_config, CodecConfig, CodecType},
+    },
+};
+use std::{
+    io::{self, Write},
+    sync::{Arc, Mutex},
+};
+
+// a sink the test keeps a handle to
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Output {
+    fn top_level(&self) -> Vec<[u8; 4]> {
+        boxes(&self.0.lock().unwrap())
+            .iter()
+            .map(|(name, _)| *name)
+            .collect()
+    }
+
+    fn fragment_count(&self) -> usize {
+        self.top_level()
+            .iter()
+            .filter(|name| *name == b"moof")
+            .count()
+    }
+
+    // the earliest decode time of each fragment
+    fn fragment_starts(&self) -> Vec<u64> {
+        let data = self.0.lock().unwrap();
+        children(&data, b"moof")
+            .into_iter()
+            .map(|moof| {
+                children(moof, b"traf")
+                    .into_iter()
+                    .map(|traf| {
+                        let tfdt = children(traf, b"tfdt")[0];
+                        u64::from_be_bytes(tfdt[4..12].try_into().unwrap())
+                    })
+                    .min()
+                    .unwrap()
+            })
+            .collect()
+    }
+}
+
+fn frame(media_type: MediaType, pts: u64, key_frame: bool, payload: &[u8]) -> MediaData {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type,
+        ..Default::default()
+    };
+    data.buff.lock().unwrap().replace(payload.to_vec());
+    data
+}
+
+fn muxer() -> Fmp4Muxer {
+    let mut muxer = Fmp4Muxer::new(FragmentMode::GOP);
+    assert!(muxer.add_track(CodecConfig {
+        codec: CodecType::H264,
+        width: 640,
+        height: 360,
+        extra_data: vec![1, 0x42, 0, 0x1e, 0xff, 0xe0, 0],
+        ..Default::default()
+    }));
+    assert!(muxer.add_track(CodecConfig {
+        codec: CodecType::AAC,
+        sample_rate: 48000,
+        channels: 2,
+        extra_data: vec![0x11, 0x90],
+        ..Default::default()
+    }));
+    muxer
+}
+
+// the boxes laid out back to back in data, as (fourcc, body)
+fn boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut out = Vec::new();
+    let mut pos = 0;
+    while pos < data.len() {
+        let size = u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap()) as usize;
+        assert!(
+            size >= 8 && pos + size <= data.len(),
+            "bad box size {}",
+            size
+        );
+        out.push((
+            data[pos + 4..pos + 8].try_into().unwrap(),
+            &data[pos + 8..pos + size],
+        ));
+        pos += size;
+    }
+    out
+}
+
+fn children<'a>(data: &'a [u8], fourcc: &[u8; 4]) -> Vec<&'a [u8]> {
+    boxes(data)
+        .into_iter()
+        .filter(|(name, _)| name == fourcc)
+        .map(|(_, body)| body)
+        .collect()
+}
+
+fn u32_at(data: &[u8], pos: usize) -> u32 {
+    u32::from_be_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+#[test]
+fn init_segment_has_spec_sized_track_headers() {
+    let init = muxer().init_segment();
+    let top: Vec<[u8; 4]> = boxes(&init).iter().map(|(name, _)| *name).collect();
+    assert_eq!(top, [*b"ftyp", *b"moov"]);
+
+    let moov = children(&init, b"moov")[0];
+    let traks = children(moov, b"trak");
+    assert_eq!(traks.len(), 2);
+    for (index, trak) in traks.iter().enumerate() {
+        // version 0: 4 bytes of version and flags, then an 80 byte body
+        let tkhd = children(trak, b"tkhd")[0];
+        assert_eq!(tkhd.len(), 84);
+        assert_eq!(u32_at(tkhd, 12), index as u32 + 1, "track id");
+        let body = &tkhd[4..];
+        assert_eq!(&body[28..32], &[0u8; 4], "layer and alternate group");
+        let volume = u16::from_be_bytes([body[32], body[33]]);
+        assert_eq!(u32_at(body, 36), 0x00010000, "first matrix value");
+        if index == 0 {
+            assert_eq!(volume, 0);
+            assert_eq!(u32_at(body, 72), 640 << 16);
+            assert_eq!(u32_at(body, 76), 360 << 16);
+        } else {
+            assert_eq!(volume, 0x0100);
+        }
+    }
+    assert_eq!(children(moov, b"mvex")[0].len(), 2 * 32);
+}
+
+#[test]
+fn fragments_carry_the_samples_of_a_gop() {
+    let mut muxer = muxer();
+    assert!(muxer
+        .push(&frame(MediaType::VIDEO, 0, true, &[1, 2, 3]))
+        .is_none());
+    assert!(muxer
+        .push(&frame(MediaType::AUDIO, 10, false, &[4, 5]))
+        .is_none());
+    assert!(muxer
+        .push(&frame(MediaType::VIDEO, 40, false, &[6]))
+        .is_none());
+    let fragment = muxer
+        .push(&frame(MediaType::VIDEO, 80, true, &[7, 8]))
+        .expect("the key frame at 80 cuts the first fragment");
+
+    let top = boxes(&fragment);
+    assert_eq!(top.len(), 2);
+    assert_eq!(&top[0].0, b"moof");
+    assert_eq!(&top[1].0, b"mdat");
+    assert_eq!(top[1].1, &[1, 2, 3, 6, 4, 5]);
+
+    let moof = top[0].1;
+    assert_eq!(u32_at(children(moof, b"mfhd")[0], 4), 1, "sequence number");
+    let trafs = children(moof, b"traf");
+    assert_eq!(trafs.len(), 2);
+
+    let moof_size = moof.len() as u32 + 8;
+    // video: two samples, the key frame is a sync sample
+    let trun = children(trafs[0], b"trun")[0];
+    assert_eq!(u32_at(trun, 0), 0x01000f01, "version and flags");
+    assert_eq!(u32_at(trun, 4), 2, "sample count");
+    assert_eq!(u32_at(trun, 8), moof_size + 8, "data offset into mdat");
+    assert_eq!(
+        [u32_at(trun, 12), u32_at(trun, 16), u32_at(trun, 20)],
+        [40, 3, 0x02000000]
+    );
+    assert_eq!(
+        [u32_at(trun, 28), u32_at(trun, 32), u32_at(trun, 36)],
+        [40, 1, 0x01010000]
+    );
+    let tfdt = children(trafs[0], b"tfdt")[0];
+    assert_eq!(&tfdt[4..], &0u64.to_be_bytes());
+
+    // audio follows the video samples in mdat
+    let trun = children(trafs[1], b"trun")[0];
+    assert_eq!(u32_at(trun, 4), 1);
+    assert_eq!(u32_at(trun, 8), moof_size + 8 + 4);
+    assert_eq!(u32_at(trun, 16), 2);
+}
+
+#[test]
+fn writer_cuts_fragments_by_duration_and_detaches_on_stop() {
+    let dispatcher = common::av_dispatcher();
+    let output = Output::default();
+    let mut writer = Fmp4Writer::new(
+        dispatcher.clone(),
+        Box::new(output.clone()),
+        FragmentMode::DURATION(100),
+    );
+    // video every 40ms with a key frame every 160ms, audio in between, buffered before
+    // the writer starts as a key frame coming in between two of its reads would move it
+    // past the frames it has not read yet
+    let frames: Vec<_> = (0..400)
+        .step_by(20)
+        .map(|pts| match pts % 40 {
+            0 => (MediaType::VIDEO, pts, pts % 160 == 0),
+            _ => (MediaType::AUDIO, pts, false),
+        })
+        .collect();
+    common::input(&dispatcher, &frames);
+    writer.start_write();
+
+    // the frame at 300 cut the third fragment, stop flushes the rest
+    common::wait_for(|| output.fragment_count() == 3);
+    writer.stop_write();
+    assert_eq!(dispatcher.lock().unwrap().receiver_count(), 0);
+
+    let mut expected = vec![*b"ftyp", *b"moov"];
+    for _ in 0..4 {
+        expected.extend([*b"moof", *b"mdat"]);
+    }
+    assert_eq!(output.top_level(), expected);
+    assert_eq!(output.fragment_starts(), [0, 100, 200, 300]);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn writer_waits_for_a_late_video_config() {
+    let dispatcher = common::dispatcher();
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(aac_config(&[0x12, 0x10]).unwrap());
+    let output = Output::default();
+    let mut writer = Fmp4Writer::new(
+        dispatcher.clone(),
+        Box::new(output.clone()),
+        FragmentMode::GOP,
+    );
+    writer.start_write();
+
+    // the first frames are read before there is a video config
+    common::input(
+        &dispatcher,
+        &[(MediaType::VIDEO, 0, true), (MediaType::AUDIO, 20, false)],
+    );
+    common::wait_for(|| {
+        let state = dispatcher.lock().unwrap().dump_state();
+        let samples = state["samples"].as_array().unwrap().clone();
+        samples
+            .iter()
+            .all(|sample| sample["reserve_flag"].as_u64() != Some(0))
+    });
+    assert!(output.0.lock().unwrap().is_empty());
+
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(h264_config(common::SPS, common::PPS).unwrap());
+    common::input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 40, true),
+            (MediaType::AUDIO, 60, false),
+            (MediaType::VIDEO, 80, false),
+            (MediaType::VIDEO, 120, true),
+        ],
+    );
+    common::wait_for(|| output.fragment_count() == 1);
+    writer.stop_write();
+
+    let data = output.0.lock().unwrap().clone();
+    let moov = children(&data, b"moov")[0];
+    assert_eq!(children(moov, b"trak").len(), 2);
+    // the first fragment has the video and audio from the key frame at 40 on
+    let moof = children(&data, b"moof")[0];
+    assert_eq!(children(moof, b"traf").len(), 2);
+    assert_eq!(output.fragment_starts()[0], 40);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
@@ -0,0 +1,113 @@
+mod common;
+
+use common::{av_dispatcher, frame, input, wait_for};
+use rust_proj::{
+    format::{flv::FlvDemuxer, Demuxer, Packet},
+    server::http_flv::HttpFlvServer,
+    utils::buffer::MediaType,
+};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+fn get(server: &HttpFlvServer, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    stream
+}
+
+// the response head, leaving the body unread
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    String::from_utf8(head).unwrap()
+}
+
+#[test]
+fn live_flv_starts_with_sequence_headers() {
+    let dispatcher = av_dispatcher();
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 1000, true),
+            (MediaType::AUDIO, 1010, false),
+            (MediaType::VIDEO, 1040, false),
+        ],
+    );
+
+    let mut server = HttpFlvServer::new("127.0.0.1:0").unwrap();
+    server.add_stream("test", dispatcher.clone());
+    server.start_serve();
+
+    let mut stream = get(&server, "/live/test.flv");
+    let head = read_head(&mut stream);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: video/x-flv\r\n"));
+
+    let mut demuxer = FlvDemuxer::new(Box::new(stream.try_clone().unwrap()));
+    let mut packets = Vec::new();
+    while packets.len() < 5 {
+        packets.push(demuxer.read_packet().expect("flv tag"));
+    }
+    match &packets[0] {
+        Packet::CONFIG(config) => assert_eq!((config.width, config.height), (640, 480)),
+        packet => panic!("expected the video sequence header, got {:?}", packet),
+    }
+    match &packets[1] {
+        Packet::CONFIG(config) => assert_eq!(config.sample_rate, 44100),
+        packet => panic!("expected the audio sequence header, got {:?}", packet),
+    }
+    // timestamps are rebased to the first frame sent on this connection
+    let frames: Vec<(MediaType, u64, bool)> = packets[2..]
+        .iter()
+        .map(|packet| match packet {
+            Packet::DATA(data) => (data.media_type, data.pts, data.key_frame),
+            packet => panic!("expected a frame, got {:?}", packet),
+        })
+        .collect();
+    assert_eq!(
+        frames,
+        [
+            (MediaType::VIDEO, 0, true),
+            (MediaType::AUDIO, 10, false),
+            (MediaType::VIDEO, 40, false)
+        ]
+    );
+    assert_eq!(server.connection_count(), 1);
+
+    // the receiver is detached once a write fails on the closed connection
+    drop(demuxer);
+    drop(stream);
+    wait_for(|| {
+        dispatcher
+            .lock()
+            .unwrap()
+            .input_data(frame(MediaType::AUDIO, 2000, false));
+        server.connection_count() == 0
+    });
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn unknown_stream_is_not_found() {
+    let mut server = HttpFlvServer::new("127.0.0.1:0").unwrap();
+    server.start_serve();
+
+    let mut stream = get(&server, "/live/missing.flv");
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 404"));
+
+    server.stop_serve();
+}
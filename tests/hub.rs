@@ -0,0 +1,91 @@
+mod common;
+
+use common::{frame, read};
+use rust_proj::{
+    dispatcher::{hub::StreamHub, receiver::Receiver},
+    utils::{buffer::MediaType, clock::VirtualClock},
+};
+use std::{sync::Arc, thread, time::Duration};
+
+#[test]
+fn subscriber_waits_for_publisher() {
+    let clock = VirtualClock::shared();
+    let hub = StreamHub::with_clock(400, 50, clock.clone());
+    let receiver = Arc::new(Receiver::new());
+    hub.subscribe("live", receiver.clone());
+    assert_eq!(hub.streams()[0].receivers, 1);
+    assert!(!hub.streams()[0].publishing);
+
+    let publisher = hub.publish("live").unwrap();
+    assert!(hub.publish("live").is_none(), "second publisher accepted");
+    for (media_type, pts, key_frame) in [
+        (MediaType::VIDEO, 0, true),
+        (MediaType::AUDIO, 10, false),
+        (MediaType::VIDEO, 40, false),
+    ] {
+        publisher.input_data(frame(media_type, pts, key_frame));
+    }
+
+    assert_eq!(
+        read(&receiver, MediaType::AV, 3),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 10),
+            (MediaType::VIDEO, 40)
+        ]
+    );
+
+    let stats = &hub.streams()[0];
+    assert_eq!(stats.name, "live");
+    assert_eq!(stats.input_frames, 3);
+    assert!(stats.publishing);
+    assert_eq!(stats.idle_for, None);
+}
+
+#[test]
+fn idle_stream_is_torn_down_after_grace() {
+    let clock = VirtualClock::shared();
+    let mut hub = StreamHub::with_clock(400, 50, clock.clone());
+    hub.set_grace_period(Duration::from_secs(5));
+    let receiver = Arc::new(Receiver::new());
+    hub.subscribe("live", receiver.clone());
+    let publisher = hub.publish("live").unwrap();
+    let dispatcher = publisher.dispatcher();
+    drop(publisher);
+
+    // publishing again within the grace period keeps the stream
+    clock.advance(Duration::from_secs(3));
+    assert_eq!(hub.streams()[0].idle_for, Some(Duration::from_secs(3)));
+    assert!(hub.reap().is_empty());
+    let publisher = hub.publish("live").unwrap();
+    assert!(Arc::ptr_eq(&publisher.dispatcher(), &dispatcher));
+    drop(publisher);
+
+    clock.advance(Duration::from_secs(4));
+    assert!(hub.reap().is_empty());
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(hub.reap(), ["live"]);
+    assert!(hub.streams().is_empty());
+    assert!(!receiver.is_attached());
+    assert!(hub.dispatcher("live").is_none());
+}
+
+#[test]
+fn teardown_ends_a_blocked_read() {
+    let clock = VirtualClock::shared();
+    let mut hub = StreamHub::with_clock(400, 50, clock.clone());
+    hub.set_grace_period(Duration::from_secs(1));
+    let receiver = Arc::new(Receiver::new());
+    hub.subscribe("live", receiver.clone());
+    drop(hub.publish("live").unwrap());
+
+    let reader = {
+        let receiver = receiver.clone();
+        thread::spawn(move || receiver.request_read(MediaType::AV))
+    };
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(hub.reap(), ["live"]);
+    let (ret, data) = reader.join().unwrap();
+    assert!(!ret && data.is_none());
+    assert!(!receiver.is_attached());
+}
@@ -0,0 +1,116 @@
+mod common;
+
+use common::{attach, attach_tracks, dispatcher, frame, input, read};
+use rust_proj::{
+    dispatcher::{dispatcher::Dispatcher, interleaver::Interleaver},
+    utils::{
+        buffer::{MediaData, MediaType},
+        clock::{Clock, VirtualClock},
+    },
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+#[test]
+fn skewed_tracks_are_read_in_dts_order() {
+    let dispatcher = dispatcher();
+    let receiver = attach(&dispatcher);
+    receiver.set_interleave(8, Duration::from_millis(50));
+
+    // the audio arrives late
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 0, true),
+            (MediaType::VIDEO, 40, false),
+            (MediaType::VIDEO, 80, false),
+            (MediaType::AUDIO, 10, false),
+            (MediaType::AUDIO, 30, false),
+            (MediaType::AUDIO, 50, false),
+            (MediaType::AUDIO, 70, false),
+        ],
+    );
+
+    let read: Vec<u64> = read(&receiver, MediaType::AV, 7)
+        .into_iter()
+        .map(|(_, dts)| dts)
+        .collect();
+    assert_eq!(read, [0, 10, 30, 40, 50, 70, 80]);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn quiet_track_holds_back_until_window_or_max_wait() {
+    let start = Duration::ZERO;
+    let mut interleaver = Interleaver::new(3, Duration::from_millis(100));
+    interleaver.push(frame(MediaType::VIDEO, 40, false), start);
+    interleaver.push(frame(MediaType::VIDEO, 0, true), start);
+    assert!(interleaver.pop(start, false).is_none());
+
+    // the oldest frame has waited long enough
+    let later = start + Duration::from_millis(100);
+    let dts = |data: Arc<Mutex<MediaData>>| data.lock().unwrap().dts;
+    assert_eq!(interleaver.pop(later, false).map(dts), Some(0));
+    assert_eq!(interleaver.pop(later, false).map(dts), Some(40));
+
+    // a full window
+    for dts in [80, 120, 160] {
+        interleaver.push(frame(MediaType::VIDEO, dts, false), later);
+    }
+    assert_eq!(interleaver.pop(later, false).map(dts), Some(80));
+    assert!(interleaver.pop(later, false).is_none());
+    assert_eq!(interleaver.pop(later, true).map(dts), Some(120));
+    assert_eq!(interleaver.len(), 1);
+}
+
+#[test]
+fn max_wait_runs_on_the_receiver_clock() {
+    let clock = VirtualClock::shared();
+    let dispatcher = Dispatcher::with_clock(400, 50, clock.clone());
+    dispatcher.lock().unwrap().start_dispatch();
+    let receiver = attach(&dispatcher);
+    receiver.set_interleave(8, Duration::from_millis(100));
+
+    // no audio comes, the key frame goes out once it waited max_wait
+    input(&dispatcher, &[(MediaType::VIDEO, 0, true)]);
+    assert_eq!(read(&receiver, MediaType::AV, 1), [(MediaType::VIDEO, 0)]);
+    assert_eq!(clock.now(), Duration::from_millis(100));
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn subscribed_extra_tracks_are_interleaved() {
+    let dispatcher = dispatcher();
+    let receiver = attach_tracks(
+        &dispatcher,
+        &[MediaType::AUDIO, MediaType::VIDEO, MediaType::SUBTITLE],
+    );
+    receiver.set_interleave(8, Duration::from_secs(5));
+
+    // the subtitles arrive after the video and audio they belong between
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 0, true),
+            (MediaType::AUDIO, 10, false),
+            (MediaType::VIDEO, 40, false),
+            (MediaType::AUDIO, 50, false),
+            (MediaType::SUBTITLE, 20, false),
+            (MediaType::SUBTITLE, 60, false),
+            (MediaType::VIDEO, 80, false),
+        ],
+    );
+    assert_eq!(
+        read(&receiver, MediaType::AV, 5),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 10),
+            (MediaType::SUBTITLE, 20),
+            (MediaType::VIDEO, 40),
+            (MediaType::AUDIO, 50),
+        ]
+    );
+    dispatcher.lock().unwrap().stop_dispatch();
+}
@@ -0,0 +1,137 @@
+// model checks of the receiver/dispatcher wakeup protocol, run with
+// RUSTFLAGS="--cfg loom" cargo test --release --test loom_handshake
+#![cfg(loom)]
+
+use loom::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+};
+use rust_proj::{
+    dispatcher::receiver::Receiver,
+    utils::{
+        buffer::MediaType,
+        sync::{self, ReadyMasks, Signal},
+    },
+};
+
+#[test]
+fn data_signal_is_not_lost() {
+    loom::model(|| {
+        let signal = Arc::new(Signal::new());
+        let notifier = signal.clone();
+        let input = thread::spawn(move || notifier.notify());
+
+        signal.wait();
+        input.join().unwrap();
+    });
+}
+
+// the dispatch thread loop: a stop issued at any point must let it exit
+#[test]
+fn stop_ends_notify_loop() {
+    loom::model(|| {
+        let running = Arc::new(AtomicBool::new(true));
+        let signal = Arc::new(Signal::new());
+
+        let notify_thread = {
+            let running = running.clone();
+            let signal = signal.clone();
+            thread::spawn(move || sync::run_signaled(&running, &signal, || {}))
+        };
+
+        running.store(false, Ordering::Release);
+        signal.notify();
+        notify_thread.join().unwrap();
+    });
+}
+
+// a read request and the input it waits for, each setting its mask and notifying
+// from its own thread: a notify pass of the loop sees both bits
+#[test]
+fn notify_pass_sees_request_and_data() {
+    loom::model(|| {
+        let refs = Arc::new(ReadyMasks::<1>::new());
+        let signal = Arc::new(Signal::new());
+        let video = MediaType::VIDEO.track_bit();
+
+        let request = {
+            let refs = refs.clone();
+            let signal = signal.clone();
+            thread::spawn(move || {
+                refs.set_recv(0, video, true);
+                signal.notify();
+            })
+        };
+        let input = {
+            let refs = refs.clone();
+            let signal = signal.clone();
+            thread::spawn(move || {
+                refs.set_data(0, video, true);
+                signal.notify();
+            })
+        };
+
+        // the model's own thread runs the loop and stops it, like stop_dispatch, from
+        // the pass that sees both bits, so it only returns if no wakeup was lost
+        let running = AtomicBool::new(true);
+        sync::run_signaled(&running, &signal, || {
+            if refs.ready(0) == video {
+                running.store(false, Ordering::Release);
+                signal.notify();
+            }
+        });
+        request.join().unwrap();
+        input.join().unwrap();
+    });
+}
+
+#[test]
+fn receiver_wakes_on_data() {
+    loom::model(|| {
+        let receiver = Arc::new(Receiver::new());
+        let notifier = receiver.clone();
+        let dispatch = thread::spawn(move || notifier.on_video_data());
+
+        receiver.wait_data(MediaType::VIDEO);
+        dispatch.join().unwrap();
+    });
+}
+
+#[test]
+fn receiver_wakes_on_stop() {
+    loom::model(|| {
+        let receiver = Arc::new(Receiver::new());
+        let reader = {
+            let receiver = receiver.clone();
+            thread::spawn(move || receiver.wait_data(MediaType::AV))
+        };
+
+        receiver.notify_read_stop();
+        reader.join().unwrap();
+    });
+}
+
+// data signaled while the previous read is still being served must wake the next read
+#[test]
+fn data_signaled_during_read_stays_pending() {
+    loom::model(|| {
+        let receiver = Arc::new(Receiver::new());
+        let first = {
+            let receiver = receiver.clone();
+            thread::spawn(move || receiver.on_audio_data())
+        };
+        receiver.wait_data(MediaType::AUDIO);
+
+        let second = {
+            let receiver = receiver.clone();
+            thread::spawn(move || receiver.on_audio_data())
+        };
+        receiver.wait_data(MediaType::AUDIO);
+
+        first.join().unwrap();
+        second.join().unwrap();
+    });
+}
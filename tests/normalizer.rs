@@ -0,0 +1,129 @@
+mod common;
+
+use rust_proj::{
+    dispatcher::normalizer::{
+        JumpAction, TimestampCorrections, TimestampNormalizer, MPEG_ROLLOVER,
+    },
+    utils::buffer::MediaType,
+};
+
+// (dts, pts, discontinuity) after normalizing video frames given as (pts, dts)
+fn normalize(normalizer: &mut TimestampNormalizer, frames: &[(u64, u64)]) -> Vec<(u64, u64, bool)> {
+    frames
+        .iter()
+        .map(|&(pts, dts)| {
+            let data = common::frame(MediaType::VIDEO, pts, true);
+            let mut data = data.lock().unwrap();
+            data.dts = dts;
+            normalizer.normalize(&mut data);
+            (data.dts, data.pts, data.discontinuity)
+        })
+        .collect()
+}
+
+#[test]
+fn jumps_are_rebased() {
+    let mut normalizer = TimestampNormalizer::new();
+    let out = normalize(
+        &mut normalizer,
+        &[
+            (5080, 5000),
+            (5040, 5040),
+            // encoder restart
+            (100, 100),
+            (140, 140),
+            // a small step back is held
+            (130, 130),
+            // a gap
+            (100000, 100000),
+            (MPEG_ROLLOVER - 40, MPEG_ROLLOVER - 40),
+            // 33-bit rollover
+            (0, 0),
+        ],
+    );
+    assert_eq!(
+        out,
+        [
+            (0, 80, false),
+            (40, 40, false),
+            (80, 80, false),
+            (120, 120, false),
+            (120, 120, false),
+            (160, 160, false),
+            (200, 200, false),
+            (240, 240, false),
+        ]
+    );
+    assert_eq!(
+        normalizer.corrections(),
+        TimestampCorrections {
+            rollovers: 1,
+            backward_jumps: 1,
+            forward_gaps: 2,
+            clamped: 1,
+        }
+    );
+}
+
+#[test]
+fn jumps_are_marked_as_discontinuities() {
+    let mut normalizer = TimestampNormalizer::with_threshold(500, JumpAction::DISCONTINUITY);
+    let out = normalize(
+        &mut normalizer,
+        &[
+            (1000, 1000),
+            (1040, 1040),
+            (5000, 5000),
+            (200, 200),
+            (240, 240),
+        ],
+    );
+    assert_eq!(
+        out,
+        [
+            (0, 0, false),
+            (40, 40, false),
+            (4000, 4000, true),
+            (4040, 4040, true),
+            (4080, 4080, false),
+        ]
+    );
+}
+
+#[test]
+fn dispatcher_normalizes_accepted_frames() {
+    let dispatcher = common::dispatcher();
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_timestamp_normalizer(Some(TimestampNormalizer::new()));
+    let receiver = common::attach(&dispatcher);
+
+    let frames: Vec<_> = [
+        (MediaType::VIDEO, 90000),
+        (MediaType::AUDIO, 90010),
+        (MediaType::VIDEO, 90040),
+    ]
+    .into_iter()
+    .map(|(media_type, pts)| common::frame(media_type, pts, media_type == MediaType::VIDEO))
+    .collect();
+    for data in frames.iter() {
+        dispatcher.lock().unwrap().input_data(data.clone());
+    }
+    assert_eq!(
+        common::read(&receiver, MediaType::AV, 3),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 10),
+            (MediaType::VIDEO, 40),
+        ]
+    );
+    // the frames are shared with the producer, which still sees its own timestamps
+    let input: Vec<u64> = frames.iter().map(|data| data.lock().unwrap().pts).collect();
+    assert_eq!(input, [90000, 90010, 90040]);
+    assert_eq!(
+        dispatcher.lock().unwrap().timestamp_corrections(),
+        Some(TimestampCorrections::default())
+    );
+    dispatcher.lock().unwrap().stop_dispatch();
+}
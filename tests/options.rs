@@ -0,0 +1,87 @@
+use rust_proj::utils::{
+    buffer::MediaType,
+    options::{ReaderOptions, RunOptions},
+};
+use std::time::Duration;
+
+fn parse(args: &[&str]) -> Result<Option<RunOptions>, String> {
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    RunOptions::parse(&args)
+}
+
+#[test]
+fn options_override_the_defaults() {
+    assert_eq!(parse(&[]).unwrap().unwrap(), RunOptions::default());
+    assert_eq!(parse(&["--help"]).unwrap(), None);
+
+    let options = parse(&[
+        "--frames",
+        "0",
+        "--fps=50",
+        "--audio-per-video",
+        "3",
+        "--gop",
+        "10",
+        "--payload",
+        "512",
+        "--reader",
+        "av",
+        "--reader",
+        "VIDEO:key",
+        "--max-capacity",
+        "100",
+        "--capacity-increment",
+        "20",
+        "--duration",
+        "2.5",
+    ])
+    .unwrap()
+    .unwrap();
+    assert_eq!(options.frames, 0);
+    assert_eq!(options.frame_interval(), 20);
+    assert_eq!(options.video_interval(), 4);
+    assert_eq!(options.gop_size, 10);
+    assert_eq!(options.payload_size, 512);
+    assert_eq!(
+        options.readers,
+        vec![
+            ReaderOptions {
+                media_type: MediaType::AV,
+                key_only: false
+            },
+            ReaderOptions {
+                media_type: MediaType::VIDEO,
+                key_only: true
+            },
+        ]
+    );
+    assert_eq!(
+        (options.max_capacity, options.capacity_increment),
+        (100, 20)
+    );
+    assert_eq!(options.duration, Some(Duration::from_millis(2500)));
+}
+
+#[test]
+fn invalid_options_are_reported() {
+    let error = |args: &[&str]| parse(args).unwrap_err();
+    assert_eq!(
+        error(&["--fps", "fast"]),
+        "invalid value for --fps: \"fast\""
+    );
+    assert_eq!(error(&["--fps", "0"]), "--fps must be at least 1");
+    assert_eq!(error(&["--gop"]), "missing value for --gop");
+    assert_eq!(error(&["--frames", "0"]), "--frames 0 needs a --duration");
+    assert_eq!(
+        error(&["--reader", "foo"]),
+        "unknown media type in reader \"foo\""
+    );
+    assert_eq!(
+        error(&["--reader", "video:all"]),
+        "unknown read mode \"all\", expected key"
+    );
+    assert_eq!(error(&["--verbose", "1"]), "unknown option --verbose");
+    assert_eq!(error(&["17"]), "unexpected argument \"17\"");
+    let readers: Vec<&str> = (0..17).flat_map(|_| ["--reader", "av"]).collect();
+    assert_eq!(error(&readers), "at most 16 readers");
+}
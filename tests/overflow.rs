@@ -0,0 +1,92 @@
+use rust_proj::{
+    dispatcher::{
+        dispatcher::{Dispatcher, OverflowPolicy},
+        receiver::Receiver,
+    },
+    source::synthetic::SyntheticStream,
+    utils::buffer::MediaType,
+};
+use std::sync::{Arc, Mutex};
+
+fn dispatcher(max_capacity: u32, policy: OverflowPolicy) -> Arc<Mutex<Dispatcher>> {
+    let dispatcher = Dispatcher::new(max_capacity, 10);
+    dispatcher.lock().unwrap().set_overflow_policy(policy);
+    dispatcher
+}
+
+fn attach(dispatcher: &Arc<Mutex<Dispatcher>>) -> Arc<Receiver> {
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver
+}
+
+// video only, a key frame every 4 frames, pts 0, 10, 20...
+fn push(dispatcher: &Arc<Mutex<Dispatcher>>, count: usize) {
+    let mut stream = SyntheticStream::new(4, 1, 10);
+    for data in stream.frames(count) {
+        dispatcher.lock().unwrap().input_data(data);
+    }
+}
+
+fn read_all(dispatcher: &Arc<Mutex<Dispatcher>>, receiver: &Receiver) -> Vec<(u64, bool)> {
+    let mut frames = Vec::new();
+    loop {
+        // the first read only registers the receiver
+        let mut data = None;
+        for _ in 0..2 {
+            dispatcher.lock().unwrap().dispatch_once();
+            if let (true, Some(read)) = receiver.try_read(MediaType::VIDEO) {
+                data = Some(read);
+                break;
+            }
+        }
+        match data {
+            Some(data) => {
+                let data = data.lock().unwrap();
+                frames.push((data.pts, data.key_frame));
+            }
+            None => return frames,
+        }
+    }
+}
+
+#[test]
+fn evict_drops_the_oldest_gops_of_a_lagging_receiver() {
+    let dispatcher = dispatcher(10, OverflowPolicy::EVICT);
+    let receiver = attach(&dispatcher);
+    push(&dispatcher, 20);
+
+    // the key frames at pts 120 and 160 each evicted a GOP, the capacity is only
+    // checked at key frames so the 3 frames after pts 160 are over it
+    let buffered = dispatcher.lock().unwrap().buffered_frames();
+    assert_eq!(buffered, 12);
+    assert_eq!(dispatcher.lock().unwrap().dropped_frames(), 8);
+
+    let frames = read_all(&dispatcher, &receiver);
+    assert_eq!(frames.first(), Some(&(80, true)));
+    let pts: Vec<u64> = frames.iter().map(|(pts, _)| *pts).collect();
+    assert_eq!(pts, (8..20).map(|index| index * 10).collect::<Vec<u64>>());
+}
+
+#[test]
+fn drop_keeps_the_buffer_and_resumes_at_a_key_frame() {
+    let dispatcher = dispatcher(6, OverflowPolicy::DROP);
+    let receiver = attach(&dispatcher);
+    push(&dispatcher, 12);
+
+    assert_eq!(dispatcher.lock().unwrap().dropped_frames(), 5);
+    let frames = read_all(&dispatcher, &receiver);
+    assert_eq!(
+        frames,
+        vec![
+            (0, true),
+            (10, false),
+            (20, false),
+            (30, false),
+            (40, true),
+            (50, false),
+            (80, true)
+        ]
+    );
+}
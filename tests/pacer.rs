@@ -0,0 +1,74 @@
+use rust_proj::{
+    source::pacer::Pacer,
+    utils::clock::{Clock, VirtualClock},
+};
+use std::time::Duration;
+
+#[test]
+fn pacer_follows_virtual_clock() {
+    let clock = VirtualClock::shared();
+    let mut pacer = Pacer::with_clock(clock.clone());
+    for dts in (0..=1000).step_by(40) {
+        pacer.wait(dts);
+    }
+    assert_eq!(clock.now(), Duration::from_millis(1000));
+
+    pacer.set_speed(2.0);
+    for dts in (1040..=2000).step_by(40) {
+        pacer.wait(dts);
+    }
+    assert_eq!(clock.now(), Duration::from_millis(1500));
+}
+
+#[test]
+fn gaps_and_rewinds_restart_pacing() {
+    let clock = VirtualClock::shared();
+    let mut pacer = Pacer::with_clock(clock.clone());
+    for dts in (0..=400).step_by(40) {
+        pacer.wait(dts);
+    }
+    assert_eq!(clock.now(), Duration::from_millis(400));
+
+    // a jump past the max gap is not slept through
+    pacer.wait(60_000);
+    assert_eq!(clock.now(), Duration::from_millis(400));
+    pacer.wait(60_040);
+    assert_eq!(clock.now(), Duration::from_millis(440));
+
+    // a small step back is released right away and keeps the schedule
+    pacer.wait(60_000);
+    assert_eq!(clock.now(), Duration::from_millis(440));
+    pacer.wait(60_080);
+    assert_eq!(clock.now(), Duration::from_millis(480));
+
+    // going back past the max rewind, e.g. a looped file, starts over from there
+    pacer.wait(0);
+    assert_eq!(clock.now(), Duration::from_millis(480));
+    pacer.wait(40);
+    assert_eq!(clock.now(), Duration::from_millis(520));
+}
+
+#[test]
+fn late_frames_catch_up_until_the_max_lag() {
+    let clock = VirtualClock::shared();
+    let mut pacer = Pacer::with_clock(clock.clone());
+    pacer.set_speed(0.5);
+    pacer.wait(0);
+    pacer.wait(40);
+    assert_eq!(clock.now(), Duration::from_millis(80));
+
+    // a stall shorter than the max lag is caught up without sleeping
+    clock.advance(Duration::from_millis(200));
+    pacer.wait(80);
+    pacer.wait(120);
+    assert_eq!(clock.now(), Duration::from_millis(280));
+    pacer.wait(200);
+    assert_eq!(clock.now(), Duration::from_millis(400));
+
+    // a longer one restarts the schedule from the late frame
+    clock.advance(Duration::from_secs(1));
+    pacer.wait(240);
+    assert_eq!(clock.now(), Duration::from_millis(1400));
+    pacer.wait(280);
+    assert_eq!(clock.now(), Duration::from_millis(1480));
+}
@@ -0,0 +1,133 @@
+mod common;
+
+use common::{attach, dispatcher, frame, input, read_with, wait_for};
+use rust_proj::{
+    dispatcher::{hub::StreamHub, receiver::Receiver, relay::Relay},
+    utils::{
+        buffer::MediaType,
+        clock::VirtualClock,
+        codec::{aac_config, CodecType},
+    },
+};
+use std::{sync::Arc, thread, time::Duration};
+
+// (media type, pts, discontinuity) of the next count frames
+fn read(receiver: &Receiver, media_type: MediaType, count: usize) -> Vec<(MediaType, u64, bool)> {
+    read_with(receiver, media_type, count, |data| {
+        (data.media_type, data.pts, data.discontinuity)
+    })
+}
+
+const FRAMES: &[(MediaType, u64, bool)] = &[
+    (MediaType::VIDEO, 0, true),
+    (MediaType::AUDIO, 10, false),
+    (MediaType::VIDEO, 40, false),
+    (MediaType::AUDIO, 50, false),
+    (MediaType::VIDEO, 80, true),
+];
+
+#[test]
+fn audio_only_relay_starts_without_key_frames() {
+    let upstream = dispatcher();
+    upstream
+        .lock()
+        .unwrap()
+        .set_codec_config(aac_config(&[0x12, 0x10]).unwrap());
+    let downstream = dispatcher();
+    let receiver = attach(&downstream);
+    let mut relay = Relay::new(attach(&upstream), downstream.clone(), MediaType::AUDIO);
+    relay.start_relay();
+
+    input(&upstream, FRAMES);
+    assert_eq!(
+        read(&receiver, MediaType::AUDIO, 2),
+        [(MediaType::AUDIO, 10, false), (MediaType::AUDIO, 50, false)]
+    );
+    assert_eq!(
+        receiver
+            .get_codec_config(MediaType::AUDIO)
+            .map(|config| config.codec),
+        Some(CodecType::AAC)
+    );
+
+    relay.stop_relay();
+    downstream.lock().unwrap().stop_dispatch();
+    upstream.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn key_only_relay_propagates_discontinuity_and_end() {
+    let clock = VirtualClock::shared();
+    let mut hub = StreamHub::with_clock(400, 50, clock.clone());
+    hub.set_grace_period(Duration::from_secs(1));
+    let upstream = Arc::new(Receiver::new());
+    hub.subscribe("live", upstream.clone());
+    let publisher = hub.publish("live").unwrap();
+
+    let downstream = dispatcher();
+    let receiver = attach(&downstream);
+    let mut relay = Relay::new(upstream, downstream.clone(), MediaType::AV);
+    relay.set_key_mode(true);
+    relay.start_relay();
+
+    for &(media_type, pts, key_frame) in FRAMES {
+        publisher.input_data(frame(media_type, pts, key_frame));
+    }
+    assert_eq!(
+        read(&receiver, MediaType::AV, 2),
+        [(MediaType::VIDEO, 0, false), (MediaType::VIDEO, 80, false)]
+    );
+
+    // a new timeline starts over in the downstream
+    let restart = frame(MediaType::VIDEO, 0, true);
+    restart.lock().unwrap().discontinuity = true;
+    publisher.input_data(restart);
+    assert_eq!(
+        read(&receiver, MediaType::AV, 1),
+        [(MediaType::VIDEO, 0, true)]
+    );
+    assert_eq!(relay.frame_count(), 3);
+
+    // tearing down the upstream leaves the downstream waiting for a key frame
+    drop(publisher);
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(hub.reap(), ["live"]);
+    wait_for(|| !relay.is_running());
+    input(
+        &downstream,
+        &[(MediaType::AUDIO, 10, false), (MediaType::VIDEO, 40, true)],
+    );
+    assert_eq!(
+        read(&receiver, MediaType::AV, 1),
+        [(MediaType::VIDEO, 40, false)]
+    );
+
+    relay.stop_relay();
+    downstream.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn detaching_upstream_during_a_read_ends_the_relay() {
+    let upstream = dispatcher();
+    let downstream = dispatcher();
+    let receiver = attach(&downstream);
+    let relay_receiver = attach(&upstream);
+    let mut relay = Relay::new(relay_receiver.clone(), downstream.clone(), MediaType::AV);
+    relay.start_relay();
+
+    input(&upstream, &FRAMES[..2]);
+    assert_eq!(
+        read(&receiver, MediaType::AV, 2),
+        [(MediaType::VIDEO, 0, false), (MediaType::AUDIO, 10, false)]
+    );
+
+    // the relay is blocked in a read of the drained upstream
+    thread::sleep(Duration::from_millis(10));
+    relay_receiver.detach();
+    wait_for(|| !relay.is_running());
+    assert_eq!(relay.frame_count(), 2);
+
+    relay.stop_relay();
+    downstream.lock().unwrap().stop_dispatch();
+    upstream.lock().unwrap().stop_dispatch();
+}
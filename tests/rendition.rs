@@ -0,0 +1,128 @@
+use rust_proj::{
+    dispatcher::{dispatcher::Dispatcher, receiver::Receiver},
+    utils::buffer::{MediaData, MediaType},
+};
+use std::sync::{Arc, Mutex};
+
+fn frame(media_type: MediaType, track_id: u32, pts: u64, key_frame: bool) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type,
+        track_id,
+        ..Default::default()
+    };
+    data.buff.lock().unwrap().replace(vec![track_id as u8]);
+    Arc::new(Mutex::new(data))
+}
+
+// two aligned video renditions and two audio languages, a key frame every 120ms.
+// at the 120 and 240 boundaries rendition 1 puts its key frame in first
+fn publish(dispatcher: &Arc<Mutex<Dispatcher>>) {
+    for pts in (0..=400).step_by(40) {
+        let key_frame = pts % 120 == 0;
+        let order = if pts == 120 || pts == 240 {
+            [1, 0]
+        } else {
+            [0, 1]
+        };
+        let mut dispatcher = dispatcher.lock().unwrap();
+        for track_id in order {
+            dispatcher.input_data(frame(MediaType::VIDEO, track_id, pts, key_frame));
+        }
+        for track_id in [0, 1] {
+            dispatcher.input_data(frame(MediaType::AUDIO, track_id, pts + 20, false));
+        }
+    }
+}
+
+fn read_until(receiver: &Receiver, video_pts: u64) -> Vec<(MediaType, u32, u64)> {
+    let mut read = Vec::new();
+    loop {
+        if let (true, Some(data)) = receiver.request_read(MediaType::AV) {
+            let data = data.lock().unwrap();
+            read.push((data.media_type, data.track_id, data.pts));
+            if data.media_type == MediaType::VIDEO && data.pts == video_pts {
+                return read;
+            }
+        }
+    }
+}
+
+fn video(read: &[(MediaType, u32, u64)]) -> Vec<(u32, u64)> {
+    read.iter()
+        .filter(|(media_type, _, _)| *media_type == MediaType::VIDEO)
+        .map(|(_, track_id, pts)| (*track_id, *pts))
+        .collect()
+}
+
+#[test]
+fn receiver_switches_rendition_at_aligned_key_frames() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher.lock().unwrap().start_dispatch();
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver.select_track(MediaType::VIDEO, 0);
+    receiver.select_track(MediaType::AUDIO, 0);
+    publish(&dispatcher);
+    assert_eq!(dispatcher.lock().unwrap().video_tracks(), [0, 1]);
+    assert!(dispatcher.lock().unwrap().is_gop_aligned(0, 1));
+
+    let mut read = read_until(&receiver, 40);
+    // the new rendition's key frame comes first at 120
+    receiver.select_track(MediaType::VIDEO, 1);
+    receiver.select_track(MediaType::AUDIO, 1);
+    read.extend(read_until(&receiver, 160));
+    assert_eq!(receiver.selected_track(MediaType::VIDEO), Some(1));
+    // and second at 240, the old rendition stops at its own key frame
+    receiver.select_track(MediaType::VIDEO, 0);
+    read.extend(read_until(&receiver, 280));
+
+    assert_eq!(
+        video(&read),
+        [
+            (0, 0),
+            (0, 40),
+            (0, 80),
+            (1, 120),
+            (1, 160),
+            (1, 200),
+            (0, 240),
+            (0, 280)
+        ]
+    );
+    let audio: Vec<(u32, u64)> = read
+        .iter()
+        .filter(|(media_type, _, _)| *media_type == MediaType::AUDIO)
+        .map(|(_, track_id, pts)| (*track_id, *pts))
+        .collect();
+    assert_eq!(
+        audio,
+        [
+            (0, 20),
+            (1, 60),
+            (1, 100),
+            (1, 140),
+            (1, 180),
+            (1, 220),
+            (1, 260)
+        ]
+    );
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn late_receiver_starts_at_its_rendition_key_frame() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher.lock().unwrap().start_dispatch();
+    publish(&dispatcher);
+
+    let receiver = Arc::new(Receiver::new());
+    receiver.select_track(MediaType::VIDEO, 0);
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    assert_eq!(video(&read_until(&receiver, 360)), [(0, 360)]);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
@@ -0,0 +1,225 @@
+use rust_proj::{
+    dispatcher::dispatcher::Dispatcher,
+    format::rtp::{RtpPacketizer, RtpSender},
+    utils::{
+        buffer::{MediaData, MediaType},
+        codec::{aac_config, h264_config, nalus_to_avcc, CodecConfig, CodecType},
+    },
+};
+use std::{
+    io,
+    net::UdpSocket,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1e, 0x95, 0xa8, 0x28, 0x0f, 0x64];
+const PPS: &[u8] = &[0x68, 0xce, 0x3c, 0x80];
+
+fn video_frame(pts: u64, key_frame: bool, nalus: &[&[u8]]) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type: MediaType::VIDEO,
+        ..Default::default()
+    };
+    data.buff.lock().unwrap().replace(nalus_to_avcc(nalus));
+    Arc::new(Mutex::new(data))
+}
+
+fn recv(socket: &UdpSocket) -> Vec<u8> {
+    let mut buf = [0u8; 2048];
+    let len = socket.recv(&mut buf).expect("no packet on loopback");
+    buf[..len].to_vec()
+}
+
+#[test]
+fn h264_frames_are_fragmented_over_loopback() {
+    let rtp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rtcp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rtp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+    rtcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher.lock().unwrap().set_codec_config(CodecConfig {
+        codec: CodecType::H264,
+        ..Default::default()
+    });
+    dispatcher.lock().unwrap().start_dispatch();
+
+    let mut sender = RtpSender::new(
+        dispatcher.clone(),
+        MediaType::VIDEO,
+        rtp.local_addr().unwrap(),
+    )
+    .unwrap();
+    sender.set_rtcp_addr(rtcp.local_addr().unwrap());
+    sender.set_mtu(200);
+    sender.start_send();
+
+    let sps: &[u8] = &[0x67, 1, 2, 3];
+    let idr: Vec<u8> = [0x65]
+        .into_iter()
+        .chain((0..1000).map(|i| i as u8))
+        .collect();
+    let slice: &[u8] = &[0x41, 9, 9];
+    dispatcher
+        .lock()
+        .unwrap()
+        .input_data(video_frame(0, true, &[sps, &idr]));
+    dispatcher
+        .lock()
+        .unwrap()
+        .input_data(video_frame(40, false, &[slice]));
+
+    // single NAL unit packet for the SPS, no marker before the end of the access unit
+    let packet = recv(&rtp);
+    assert_eq!(packet[0], 0x80);
+    assert_eq!(packet[1], 96);
+    assert_eq!(&packet[12..], sps);
+    let ssrc = &packet[8..12].to_vec();
+    let timestamp = u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]);
+    let mut sequence = u16::from_be_bytes([packet[2], packet[3]]);
+
+    // FU-A fragments of the IDR, reassembled back to the original NAL unit
+    let mut nalu = Vec::new();
+    loop {
+        let packet = recv(&rtp);
+        assert!(packet.len() <= 200);
+        sequence = sequence.wrapping_add(1);
+        assert_eq!(u16::from_be_bytes([packet[2], packet[3]]), sequence);
+        assert_eq!(&packet[4..8], &timestamp.to_be_bytes());
+        assert_eq!(&packet[8..12], ssrc);
+
+        let (indicator, header) = (packet[12], packet[13]);
+        assert_eq!(indicator & 0x1f, 28);
+        if nalu.is_empty() {
+            assert_ne!(header & 0x80, 0);
+            nalu.push((indicator & 0xe0) | (header & 0x1f));
+        }
+        nalu.extend_from_slice(&packet[14..]);
+        if header & 0x40 != 0 {
+            assert_eq!(packet[1] & 0x80, 0x80);
+            break;
+        }
+        assert_eq!(packet[1] & 0x80, 0);
+    }
+    assert_eq!(nalu, idr);
+
+    // 40 ms at the 90 kHz video clock
+    let packet = recv(&rtp);
+    assert_eq!(&packet[4..8], &timestamp.wrapping_add(3600).to_be_bytes());
+    assert_eq!(packet[1], 0x80 | 96);
+    assert_eq!(&packet[12..], slice);
+
+    // the first frame triggers a sender report with a CNAME
+    let report = recv(&rtcp);
+    assert_eq!(report[0], 0x80);
+    assert_eq!(report[1], 200);
+    assert_eq!(&report[4..8], ssrc);
+    assert_eq!(report[29], 202);
+
+    sender.stop_send();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn parameter_sets_go_ahead_of_key_frames() {
+    let mut packetizer = RtpPacketizer::new(&h264_config(SPS, PPS).unwrap(), 96, 1);
+    let idr: &[u8] = &[0x65, 1, 2, 3];
+    let slice: &[u8] = &[0x41, 4, 5];
+
+    let frame = video_frame(0, true, &[idr]);
+    let packets = packetizer.packetize(&frame.lock().unwrap());
+    let payloads: Vec<&[u8]> = packets.iter().map(|packet| &packet[12..]).collect();
+    assert_eq!(payloads, vec![SPS, PPS, idr]);
+    assert_eq!(
+        &packets[0][4..8],
+        &packetizer.timestamp_offset().to_be_bytes()
+    );
+
+    // not repeated for other frames, or for key frames that carry their own
+    let frame = video_frame(40, false, &[slice]);
+    assert_eq!(packetizer.packetize(&frame.lock().unwrap()).len(), 1);
+    let frame = video_frame(80, true, &[SPS, PPS, idr]);
+    assert_eq!(packetizer.packetize(&frame.lock().unwrap()).len(), 3);
+}
+
+#[test]
+fn reports_continue_while_the_input_stalls() {
+    let rtp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    let rtcp = UdpSocket::bind("127.0.0.1:0").unwrap();
+    rtcp.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(h264_config(SPS, PPS).unwrap());
+    dispatcher.lock().unwrap().start_dispatch();
+
+    let mut sender = RtpSender::new(
+        dispatcher.clone(),
+        MediaType::VIDEO,
+        rtp.local_addr().unwrap(),
+    )
+    .unwrap();
+    sender.set_rtcp_addr(rtcp.local_addr().unwrap());
+    sender.set_report_interval(Duration::from_millis(50));
+    sender.start_send();
+
+    dispatcher
+        .lock()
+        .unwrap()
+        .input_data(video_frame(0, true, &[&[0x65, 1, 2, 3]]));
+
+    // the rtp time moves on with the wall clock between reports
+    let timestamps: Vec<u32> = (0..3)
+        .map(|_| {
+            let report = recv(&rtcp);
+            assert_eq!(report[1], 200);
+            u32::from_be_bytes([report[16], report[17], report[18], report[19]])
+        })
+        .collect();
+    assert_ne!(timestamps[0], timestamps[2]);
+
+    sender.stop_send();
+    assert_eq!(dispatcher.lock().unwrap().receiver_count(), 0);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn aac_frames_over_the_au_size_field_are_dropped() {
+    let mut packetizer = RtpPacketizer::new(&aac_config(&[0x12, 0x10]).unwrap(), 97, 1);
+    let frame = |len: usize| {
+        let data = MediaData {
+            pts: 0,
+            media_type: MediaType::AUDIO,
+            ..Default::default()
+        };
+        data.buff.lock().unwrap().replace(vec![0x21; len]);
+        data
+    };
+
+    // the size goes in 13 bits, each fragment repeats the au header
+    let packets = packetizer.packetize(&frame(8191));
+    assert_eq!(packets.len(), 6);
+    assert_eq!(&packets[0][12..16], &[0, 16, 0xff, 0xf8]);
+    assert!(packetizer.packetize(&frame(8192)).is_empty());
+}
+
+#[test]
+fn mixed_and_data_tracks_are_rejected() {
+    let dispatcher = Dispatcher::new(400, 50);
+    for media_type in [MediaType::AV, MediaType::DATA] {
+        let err = RtpSender::new(
+            dispatcher.clone(),
+            media_type,
+            "127.0.0.1:5004".parse().unwrap(),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+}
@@ -0,0 +1,167 @@
+use rust_proj::{
+    dispatcher::{receiver::Receiver, scenario::Scenario},
+    source::synthetic::SyntheticStream,
+    utils::{buffer::MediaType, clock::Clock},
+};
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+// GOP of 4 video frames, every third frame is video, 10 ms apart:
+// key frames at pts 0, 120, 240, ...
+fn stream() -> SyntheticStream {
+    SyntheticStream::new(4, 3, 10)
+}
+
+#[test]
+fn receiver_reads_its_track() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .input(9)
+        .read_all("video");
+
+    scenario.assert_pts("video", &[0, 30, 60]);
+}
+
+#[test]
+fn receivers_read_their_tracks() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .attach("audio", MediaType::AUDIO)
+        .attach("mixed", MediaType::AV)
+        .input(9)
+        .read_all("video")
+        .read_all("audio")
+        .read_all("mixed");
+
+    scenario
+        .assert_pts("video", &[0, 30, 60])
+        .assert_pts("audio", &[10, 20, 40, 50, 70, 80])
+        .assert_pts("mixed", &[0, 10, 20, 30, 40, 50, 60, 70, 80]);
+}
+
+#[test]
+fn late_receiver_starts_at_last_key_frame() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .input(50)
+        .attach("video", MediaType::VIDEO)
+        .attach("mixed", MediaType::AV)
+        .input(6)
+        .read_all("video")
+        .read_all("mixed");
+
+    scenario
+        .assert_pts("video", &[480, 510, 540])
+        .assert_pts("mixed", &[480, 490, 500, 510, 520, 530, 540, 550]);
+}
+
+#[test]
+fn key_only_receiver_reads_each_key_frame_once() {
+    let mut scenario = Scenario::new(stream());
+    scenario.attach_key_only("key", MediaType::VIDEO);
+    for _ in 0..36 {
+        scenario.input(1).read_all("key");
+    }
+
+    scenario.assert_pts("key", &[0, 120, 240]);
+    assert!(scenario.frames("key").iter().all(|frame| frame.key_frame));
+}
+
+#[test]
+fn lagging_receiver_resumes_at_the_oldest_kept_gop() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .attach("mixed", MediaType::AV)
+        .input(13)
+        .read_all("video");
+    // "mixed" keeps up while "video" stalls inside the GOP at 120, which is erased
+    // once the key frame at 360 comes in
+    for _ in 0..30 {
+        scenario.input(1).read_all("mixed");
+    }
+    scenario.read_all("video");
+
+    scenario
+        .assert_pts(
+            "video",
+            &[0, 30, 60, 90, 120, 240, 270, 300, 330, 360, 390, 420],
+        )
+        .assert_pts(
+            "mixed",
+            &(0..=42).map(|frame| frame * 10).collect::<Vec<u64>>(),
+        );
+}
+
+#[test]
+fn detached_receiver_stops_reading() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .attach("first", MediaType::VIDEO)
+        .input(6)
+        .read_all("first")
+        .detach("first")
+        .input(6)
+        .read_all("first")
+        .attach("second", MediaType::VIDEO)
+        .input(12)
+        .read_all("second");
+
+    scenario
+        .assert_pts("first", &[0, 30])
+        .assert_pts("second", &[0, 30, 60, 90, 120, 150, 180, 210]);
+}
+
+#[test]
+fn read_gops_are_erased_when_every_key_frame_was_read() {
+    let mut scenario = Scenario::new(stream());
+    scenario.attach("mixed", MediaType::AV);
+    for _ in 0..61 {
+        scenario.input(1).read_all("mixed");
+    }
+
+    // the GOPs before the key frame at 600 were read completely and are gone
+    let buffered = scenario.dispatcher().lock().unwrap().buffered_frames();
+    assert_eq!(buffered, 1);
+    assert_eq!(scenario.pts("mixed").len(), 61);
+}
+
+#[test]
+fn receivers_start_at_the_start_pts() {
+    let mut scenario = Scenario::new(stream());
+    scenario
+        .attach("video", MediaType::VIDEO)
+        .attach("mixed", MediaType::AV)
+        .start_at("video", 130)
+        .start_at("mixed", 130);
+    for _ in 0..30 {
+        scenario.input(1).read_all("video").read_all("mixed");
+    }
+
+    // video waits for the key frame at 240, audio starts right at 130
+    scenario.assert_pts("video", &[240, 270]).assert_pts(
+        "mixed",
+        &[
+            130, 140, 160, 170, 190, 200, 220, 230, 240, 250, 260, 270, 280, 290,
+        ],
+    );
+}
+
+#[test]
+fn read_timeout_runs_on_the_dispatcher_clock() {
+    let scenario = Scenario::new(stream());
+    let dispatcher = scenario.dispatcher();
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher);
+
+    let start = Instant::now();
+    let data = receiver.read_frame(MediaType::VIDEO, Some(Duration::from_secs(60)));
+    assert!(data.is_none());
+    assert_eq!(scenario.clock().now(), Duration::from_secs(60));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
@@ -0,0 +1,192 @@
+use rust_proj::{
+    dispatcher::dispatcher::Dispatcher,
+    server::shm::{shm_path, ShmReader, ShmRing, ShmWriter},
+    utils::{
+        buffer::{MediaData, MediaType},
+        codec::{aac_config, h264_config, nalus_to_avcc},
+    },
+};
+use std::{
+    env, process,
+    process::Command,
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+// baseline profile, 640x480
+const SPS: &[u8] = &[0x67, 0x42, 0x00, 0x1e, 0x95, 0xa8, 0x28, 0x0f, 0x64];
+const PPS: &[u8] = &[0x68, 0xce, 0x38, 0x80];
+// set in the environment of the reader process
+const RING_ENV: &str = "RUST_PROJ_SHM_RING";
+
+fn payload(media_type: MediaType, pts: u64, key_frame: bool) -> Vec<u8> {
+    match media_type {
+        MediaType::VIDEO => nalus_to_avcc(&[&[if key_frame { 0x65 } else { 0x41 }, pts as u8]]),
+        _ => vec![0x21, pts as u8],
+    }
+}
+
+fn frame(media_type: MediaType, pts: u64, key_frame: bool) -> MediaData {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type,
+        ..Default::default()
+    };
+    data.buff
+        .lock()
+        .unwrap()
+        .replace(payload(media_type, pts, key_frame));
+    data
+}
+
+fn wait_for(mut condition: impl FnMut() -> bool) {
+    let start = Instant::now();
+    while !condition() {
+        assert!(start.elapsed() < Duration::from_secs(5), "timed out");
+        thread::sleep(Duration::from_millis(1));
+    }
+}
+
+const BEFORE_READER: &[(MediaType, u64, bool)] = &[
+    (MediaType::VIDEO, 0, true),
+    (MediaType::AUDIO, 10, false),
+    (MediaType::VIDEO, 40, false),
+    (MediaType::VIDEO, 80, true),
+    (MediaType::AUDIO, 90, false),
+];
+const AFTER_READER: &[(MediaType, u64, bool)] = &[
+    (MediaType::VIDEO, 120, false),
+    (MediaType::AUDIO, 130, false),
+    (MediaType::VIDEO, 160, true),
+];
+
+// runs in the spawned process, the parent checks its exit status
+#[test]
+fn shm_reader_process() {
+    let path = match env::var(RING_ENV) {
+        Ok(path) => path,
+        Err(_) => return,
+    };
+    let mut reader = ShmReader::open(path.as_ref(), MediaType::AV).unwrap();
+    assert_eq!(
+        reader.get_codec_config(MediaType::VIDEO),
+        h264_config(SPS, PPS)
+    );
+
+    let mut read = Vec::new();
+    while let Some(data) = reader.read_timeout(Duration::from_secs(5)) {
+        assert_eq!(
+            data.payload(),
+            payload(data.media_type, data.pts, data.key_frame)
+        );
+        read.push((data.media_type, data.pts, data.key_frame));
+    }
+    assert!(reader.is_closed());
+    // starts at the last key frame written before it opened the ring
+    let expected: Vec<_> = BEFORE_READER[3..]
+        .iter()
+        .chain(AFTER_READER)
+        .copied()
+        .collect();
+    assert_eq!(read, expected);
+}
+
+#[test]
+fn reader_process_follows_dispatcher() {
+    let path = shm_path(&format!("rust_proj-{}-ring", process::id()));
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(h264_config(SPS, PPS).unwrap());
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_codec_config(aac_config(&[0x12, 0x10]).unwrap());
+    dispatcher.lock().unwrap().start_dispatch();
+    let mut writer = ShmWriter::new(dispatcher.clone(), &path, 1 << 20, 64).unwrap();
+
+    let input = |frames: &[(MediaType, u64, bool)]| {
+        for &(media_type, pts, key_frame) in frames {
+            dispatcher
+                .lock()
+                .unwrap()
+                .input_data(Arc::new(Mutex::new(frame(media_type, pts, key_frame))));
+        }
+    };
+    // buffered before the writer starts, a key frame coming in while it is between two
+    // reads would move it past the frames it has not read yet
+    input(BEFORE_READER);
+    writer.start_write();
+    wait_for(|| writer.frame_count() == BEFORE_READER.len() as u64);
+
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(["shm_reader_process", "--exact", "--nocapture"])
+        .env(RING_ENV, &path)
+        .spawn()
+        .unwrap();
+    wait_for(|| writer.reader_count() == 1);
+    input(AFTER_READER);
+    wait_for(|| writer.frame_count() == (BEFORE_READER.len() + AFTER_READER.len()) as u64);
+
+    writer.stop_write();
+    assert!(child.wait().unwrap().success(), "reader process failed");
+    dispatcher.lock().unwrap().stop_dispatch();
+    drop(writer);
+    assert!(!path.exists());
+}
+
+#[test]
+fn lagging_reader_skips_to_last_key_frame() {
+    let path = shm_path(&format!("rust_proj-{}-lag", process::id()));
+    let mut ring = ShmRing::create(&path, 4096, 4).unwrap();
+    let mut reader = ShmReader::open(&path, MediaType::VIDEO).unwrap();
+    let mut key_reader = ShmReader::open(&path, MediaType::VIDEO).unwrap();
+    key_reader.set_key_mode(true);
+    assert_eq!(ring.reader_count(), 2);
+
+    for (pts, key_frame) in [
+        (0, true),
+        (40, false),
+        (80, false),
+        (120, true),
+        (160, false),
+        (200, false),
+    ] {
+        ring.write(&frame(MediaType::VIDEO, pts, key_frame));
+    }
+
+    // only four frames fit, the reader resumes at the key frame at 120
+    let pts: Vec<u64> = std::iter::from_fn(|| reader.try_read())
+        .map(|data| data.pts)
+        .collect();
+    assert_eq!(pts, [120, 160, 200]);
+    assert_eq!(reader.lost_frames(), 3);
+    let pts: Vec<u64> = std::iter::from_fn(|| key_reader.try_read())
+        .map(|data| data.pts)
+        .collect();
+    assert_eq!(pts, [120]);
+
+    drop(reader);
+    assert_eq!(ring.reader_count(), 1);
+}
+
+#[test]
+fn track_ids_cross_the_ring() {
+    let path = shm_path(&format!("rust_proj-{}-tracks", process::id()));
+    let mut ring = ShmRing::create(&path, 4096, 4).unwrap();
+    let mut reader = ShmReader::open(&path, MediaType::VIDEO).unwrap();
+
+    for (pts, track_id) in [(0, 1), (40, 2)] {
+        let mut data = frame(MediaType::VIDEO, pts, true);
+        data.track_id = track_id;
+        ring.write(&data);
+    }
+    let read: Vec<(u64, u32)> = std::iter::from_fn(|| reader.try_read())
+        .map(|data| (data.pts, data.track_id))
+        .collect();
+    assert_eq!(read, [(0, 1), (40, 2)]);
+}
@@ -0,0 +1,134 @@
+use rust_proj::{
+    dispatcher::{dispatcher::Dispatcher, normalizer::TimestampNormalizer, receiver::Receiver},
+    utils::buffer::{MediaData, MediaType},
+};
+use std::sync::{Arc, Mutex};
+
+const PAYLOAD_SIZE: usize = 1000;
+
+fn frame(pts: u64, key_frame: bool) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type: MediaType::VIDEO,
+        ..Default::default()
+    };
+    data.buff
+        .lock()
+        .unwrap()
+        .replace(vec![pts as u8; PAYLOAD_SIZE]);
+    Arc::new(Mutex::new(data))
+}
+
+fn attach(dispatcher: &Arc<Mutex<Dispatcher>>) -> Arc<Receiver> {
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+    receiver
+}
+
+fn read(dispatcher: &Arc<Mutex<Dispatcher>>, receiver: &Receiver) -> Option<Arc<Mutex<MediaData>>> {
+    // the first read only registers the receiver
+    for _ in 0..2 {
+        dispatcher.lock().unwrap().dispatch_once();
+        if let (true, Some(data)) = receiver.try_read(MediaType::VIDEO) {
+            return Some(data);
+        }
+    }
+    None
+}
+
+#[test]
+fn payloads_over_the_budget_are_spilled_and_read_back() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_memory_budget(Some(3 * PAYLOAD_SIZE));
+    let receiver = attach(&dispatcher);
+    for pts in 0..10 {
+        dispatcher.lock().unwrap().input_data(frame(pts, pts == 0));
+    }
+    assert_eq!(
+        dispatcher.lock().unwrap().resident_bytes(),
+        3 * PAYLOAD_SIZE
+    );
+    assert_eq!(dispatcher.lock().unwrap().spilled_frames(), 7);
+
+    for pts in 0..10 {
+        let data = read(&dispatcher, &receiver).unwrap();
+        let data = data.lock().unwrap();
+        assert_eq!(data.pts, pts);
+        assert_eq!(data.payload(), vec![pts as u8; PAYLOAD_SIZE]);
+    }
+    assert_eq!(dispatcher.lock().unwrap().spilled_frames(), 0);
+}
+
+#[test]
+fn frames_held_by_a_reader_stay_in_memory() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_memory_budget(Some(PAYLOAD_SIZE));
+    let reader = attach(&dispatcher);
+    let _lagging = attach(&dispatcher);
+    dispatcher.lock().unwrap().input_data(frame(0, true));
+    dispatcher.lock().unwrap().input_data(frame(1, false));
+    let held = read(&dispatcher, &reader).unwrap();
+
+    for pts in 2..6 {
+        dispatcher.lock().unwrap().input_data(frame(pts, false));
+    }
+    assert_eq!(held.lock().unwrap().payload(), vec![0; PAYLOAD_SIZE]);
+    // all but the held frame and the newest one
+    assert_eq!(dispatcher.lock().unwrap().spilled_frames(), 4);
+    drop(held);
+
+    for pts in 1..6 {
+        let data = read(&dispatcher, &reader).unwrap();
+        assert_eq!(
+            data.lock().unwrap().payload(),
+            vec![pts as u8; PAYLOAD_SIZE]
+        );
+    }
+}
+
+#[test]
+fn payloads_the_producer_still_holds_stay_in_memory() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_memory_budget(Some(PAYLOAD_SIZE));
+    // the buffer keeps a normalized copy sharing the producer's payload
+    dispatcher
+        .lock()
+        .unwrap()
+        .set_timestamp_normalizer(Some(TimestampNormalizer::new()));
+    let reader = attach(&dispatcher);
+    let frames: Vec<_> = (0..4).map(|pts| frame(pts, pts == 0)).collect();
+    for data in frames.iter() {
+        dispatcher.lock().unwrap().input_data(data.clone());
+    }
+    assert_eq!(dispatcher.lock().unwrap().spilled_frames(), 0);
+    for (pts, data) in frames.iter().enumerate() {
+        assert_eq!(
+            data.lock().unwrap().payload(),
+            vec![pts as u8; PAYLOAD_SIZE]
+        );
+    }
+
+    // spilled once the producer lets go
+    drop(frames);
+    dispatcher.lock().unwrap().input_data(frame(4, false));
+    assert_eq!(dispatcher.lock().unwrap().spilled_frames(), 4);
+    for pts in 0..5 {
+        let data = read(&dispatcher, &reader).unwrap();
+        assert_eq!(
+            data.lock().unwrap().payload(),
+            vec![pts as u8; PAYLOAD_SIZE]
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use rust_proj::{
+    dispatcher::{dispatcher::Dispatcher, receiver::Receiver},
+    source::switcher::Switcher,
+    utils::{
+        buffer::{MediaData, MediaType},
+        clock::{Clock, VirtualClock},
+    },
+};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+fn frame(pts: u64, key_frame: bool) -> Arc<Mutex<MediaData>> {
+    let data = MediaData {
+        key_frame,
+        pts,
+        dts: pts,
+        media_type: MediaType::VIDEO,
+        ..Default::default()
+    };
+    data.buff.lock().unwrap().replace(vec![pts as u8]);
+    Arc::new(Mutex::new(data))
+}
+
+#[test]
+fn stalled_primary_fails_over_and_back() {
+    let dispatcher = Dispatcher::new(400, 50);
+    dispatcher.lock().unwrap().start_dispatch();
+    let receiver = Arc::new(Receiver::new());
+    dispatcher.lock().unwrap().attach_receiver(receiver.clone());
+    receiver.set_dispatcher(dispatcher.clone());
+
+    let clock = VirtualClock::shared();
+    let mut switcher = Switcher::with_clock(dispatcher.clone(), clock.clone());
+    switcher.set_stall_timeout(Duration::from_secs(2));
+    switcher.set_recover_time(Duration::from_secs(5));
+    switcher.add_source("backup", 1);
+    switcher.add_source("primary", 0);
+
+    // the clock moves to `at` seconds before the frame goes in
+    let input = |switcher: &mut Switcher, at: u64, name: &str, pts: u64, key_frame: bool| {
+        clock.advance(Duration::from_secs(at).saturating_sub(clock.now()));
+        switcher.input_data(name, frame(pts, key_frame));
+    };
+    input(&mut switcher, 0, "primary", 1000, true);
+    input(&mut switcher, 0, "backup", 5000, true);
+    input(&mut switcher, 1, "primary", 2000, false);
+    input(&mut switcher, 1, "backup", 6000, false);
+    assert_eq!(switcher.active_source(), Some("primary"));
+
+    // the primary goes quiet, the backup takes over at its next key frame
+    input(&mut switcher, 3, "backup", 8000, false);
+    assert_eq!(switcher.active_source(), Some("primary"));
+    input(&mut switcher, 4, "backup", 9000, true);
+    assert_eq!(switcher.active_source(), Some("backup"));
+
+    // the primary is back, but has to stay healthy for the recover time
+    input(&mut switcher, 5, "primary", 10000, true);
+    input(&mut switcher, 5, "backup", 10000, false);
+    assert_eq!(switcher.active_source(), Some("backup"));
+    input(&mut switcher, 10, "primary", 15000, true);
+    assert_eq!(switcher.active_source(), Some("primary"));
+    assert_eq!(switcher.switch_count(), 2);
+
+    let mut read = Vec::new();
+    while read.len() < 5 {
+        if let (true, Some(data)) = receiver.request_read(MediaType::VIDEO) {
+            let data = data.lock().unwrap();
+            read.push((data.pts, data.discontinuity));
+        }
+    }
+    assert_eq!(
+        read,
+        [
+            (1000, false),
+            (2000, false),
+            (9000, true),
+            (10000, false),
+            (15000, true)
+        ]
+    );
+    dispatcher.lock().unwrap().stop_dispatch();
+}
@@ -0,0 +1,128 @@
+mod common;
+
+use common::{attach, attach_tracks, dispatcher, frame, input, read};
+use rust_proj::{dispatcher::dispatcher::Dispatcher, utils::buffer::MediaType};
+use std::{thread, time::Duration};
+
+#[test]
+fn subscribed_tracks_are_delivered_in_mixed_reads() {
+    let dispatcher = dispatcher();
+    let all = attach_tracks(
+        &dispatcher,
+        &[MediaType::AUDIO, MediaType::VIDEO, MediaType::SUBTITLE],
+    );
+    let plain = attach(&dispatcher);
+    let subtitles = attach_tracks(&dispatcher, &[MediaType::SUBTITLE]);
+
+    // the subtitle before the first key frame is dropped with everything else
+    input(
+        &dispatcher,
+        &[
+            (MediaType::SUBTITLE, 0, false),
+            (MediaType::VIDEO, 0, true),
+            (MediaType::AUDIO, 10, false),
+            (MediaType::SUBTITLE, 20, false),
+            (MediaType::VIDEO, 40, false),
+            (MediaType::METADATA, 50, false),
+            (MediaType::SUBTITLE, 60, false),
+            (MediaType::VIDEO, 80, false),
+        ],
+    );
+
+    assert_eq!(
+        read(&all, MediaType::AV, 6),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 10),
+            (MediaType::SUBTITLE, 20),
+            (MediaType::VIDEO, 40),
+            (MediaType::SUBTITLE, 60),
+            (MediaType::VIDEO, 80),
+        ]
+    );
+    assert_eq!(
+        read(&plain, MediaType::AV, 4),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 10),
+            (MediaType::VIDEO, 40),
+            (MediaType::VIDEO, 80),
+        ]
+    );
+    assert_eq!(
+        read(&subtitles, MediaType::SUBTITLE, 2),
+        [(MediaType::SUBTITLE, 20), (MediaType::SUBTITLE, 60)]
+    );
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn other_tracks_are_buffered_without_config() {
+    let dispatcher = Dispatcher::new(400, 50);
+    assert!(dispatcher
+        .lock()
+        .unwrap()
+        .get_codec_config(MediaType::SUBTITLE)
+        .is_none());
+    dispatcher
+        .lock()
+        .unwrap()
+        .input_data(frame(MediaType::VIDEO, 0, true));
+    dispatcher
+        .lock()
+        .unwrap()
+        .input_data(frame(MediaType::DATA, 10, false));
+    assert_eq!(dispatcher.lock().unwrap().buffered_frames(), 2);
+}
+
+#[test]
+fn extra_tracks_alone_skip_audio_and_video() {
+    let dispatcher = dispatcher();
+    let extra = attach_tracks(&dispatcher, &[MediaType::METADATA, MediaType::DATA]);
+    let plain = attach(&dispatcher);
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 0, true),
+            (MediaType::METADATA, 10, false),
+            (MediaType::AUDIO, 20, false),
+            (MediaType::DATA, 30, false),
+            (MediaType::VIDEO, 40, false),
+        ],
+    );
+
+    assert_eq!(
+        read(&extra, MediaType::AV, 2),
+        [(MediaType::METADATA, 10), (MediaType::DATA, 30)]
+    );
+    // a receiver that did not subscribe never sees the extra tracks
+    assert_eq!(
+        read(&plain, MediaType::AV, 3),
+        [
+            (MediaType::VIDEO, 0),
+            (MediaType::AUDIO, 20),
+            (MediaType::VIDEO, 40)
+        ]
+    );
+    assert!(plain.try_read(MediaType::DATA).1.is_none());
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn detach_ends_a_blocked_subtitle_read() {
+    let dispatcher = dispatcher();
+    let subtitles = attach_tracks(&dispatcher, &[MediaType::SUBTITLE]);
+    input(&dispatcher, &[(MediaType::VIDEO, 0, true)]);
+
+    let reader = {
+        let subtitles = subtitles.clone();
+        thread::spawn(move || subtitles.request_read(MediaType::SUBTITLE))
+    };
+    // no subtitle comes, the read is still blocked
+    thread::sleep(Duration::from_millis(10));
+    subtitles.detach();
+    let (ret, data) = reader.join().unwrap();
+    assert!(!ret && data.is_none());
+    assert_eq!(dispatcher.lock().unwrap().receiver_count(), 0);
+    dispatcher.lock().unwrap().stop_dispatch();
+}
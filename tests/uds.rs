@@ -0,0 +1,114 @@
+mod common;
+
+use common::{attach, av_dispatcher, frame, input, payload, read_with, wait_for, PPS, SPS};
+use rust_proj::{
+    format::Packet,
+    server::uds::{UdsClient, UdsServer},
+    utils::{
+        buffer::MediaType,
+        codec::{h264_config, CodecType},
+    },
+};
+use std::{path::PathBuf, process};
+
+fn socket_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("rust_proj-{}-{}.sock", process::id(), name))
+}
+
+const FRAMES: &[(MediaType, u64, bool)] = &[
+    (MediaType::VIDEO, 0, true),
+    (MediaType::AUDIO, 10, false),
+    (MediaType::VIDEO, 40, false),
+    (MediaType::AUDIO, 50, false),
+];
+
+#[test]
+fn client_reads_selected_media_type() {
+    let dispatcher = av_dispatcher();
+    let mut server = UdsServer::new(&socket_path("select"), dispatcher.clone()).unwrap();
+    server.start_serve();
+
+    let mut client = UdsClient::connect(server.path(), MediaType::VIDEO, false).unwrap();
+    input(&dispatcher, FRAMES);
+
+    match client.read_packet().unwrap() {
+        Packet::CONFIG(config) => assert_eq!(config, h264_config(SPS, PPS).unwrap()),
+        packet => panic!("expected the video config, got {:?}", packet),
+    }
+    for (pts, key_frame) in [(0, true), (40, false)] {
+        match client.read_packet().unwrap() {
+            Packet::DATA(data) => {
+                assert_eq!(data.media_type, MediaType::VIDEO);
+                assert_eq!((data.pts, data.dts, data.key_frame), (pts, pts, key_frame));
+                assert_eq!(data.payload(), payload(MediaType::VIDEO, pts, key_frame));
+            }
+            packet => panic!("expected a video frame, got {:?}", packet),
+        }
+    }
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn client_injects_into_local_dispatcher() {
+    let dispatcher = av_dispatcher();
+    let mut server = UdsServer::new(&socket_path("inject"), dispatcher.clone()).unwrap();
+    server.start_serve();
+
+    let local = common::dispatcher();
+    let receiver = attach(&local);
+
+    let mut client = UdsClient::connect(server.path(), MediaType::AV, false).unwrap();
+    client.start_inject(local.clone()).unwrap();
+    input(&dispatcher, FRAMES);
+
+    let read = read_with(&receiver, MediaType::AV, FRAMES.len(), |data| {
+        (data.media_type, data.pts, data.key_frame)
+    });
+    assert_eq!(read, FRAMES);
+    assert_eq!(
+        local
+            .lock()
+            .unwrap()
+            .get_codec_config(MediaType::AUDIO)
+            .map(|config| config.codec),
+        Some(CodecType::AAC)
+    );
+
+    // the inject thread ends with the server
+    server.stop_serve();
+    wait_for(|| !client.is_running());
+    client.stop_inject();
+    local.lock().unwrap().stop_dispatch();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn client_reads_subscribed_extra_track_with_track_ids() {
+    let dispatcher = av_dispatcher();
+    let mut server = UdsServer::new(&socket_path("subtitle"), dispatcher.clone()).unwrap();
+    server.start_serve();
+
+    let mut client = UdsClient::connect(server.path(), MediaType::SUBTITLE, false).unwrap();
+    wait_for(|| server.connection_count() == 1);
+    input(&dispatcher, &[(MediaType::VIDEO, 0, true)]);
+    for (pts, track_id) in [(20, 0), (60, 2)] {
+        let subtitle = frame(MediaType::SUBTITLE, pts, false);
+        subtitle.lock().unwrap().track_id = track_id;
+        dispatcher.lock().unwrap().input_data(subtitle);
+    }
+
+    for (pts, track_id) in [(20, 0), (60, 2)] {
+        match client.read_packet().unwrap() {
+            Packet::DATA(data) => {
+                assert_eq!(data.media_type, MediaType::SUBTITLE);
+                assert_eq!((data.pts, data.track_id), (pts, track_id));
+            }
+            packet => panic!("expected a subtitle, got {:?}", packet),
+        }
+    }
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
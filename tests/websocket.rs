@@ -0,0 +1,221 @@
+mod common;
+
+use common::{av_dispatcher, input};
+use rust_proj::{
+    server::websocket::{
+        read_message, write_frame, MessageReader, WebSocketServer, OPCODE_BINARY, OPCODE_CLOSE,
+        OPCODE_CONTINUATION, OPCODE_PING, OPCODE_PONG, OPCODE_TEXT,
+    },
+    utils::buffer::MediaType,
+};
+use serde_json::{json, Value};
+use std::{
+    io::{self, Cursor, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+const MASK: [u8; 4] = [0x12, 0x34, 0x56, 0x78];
+
+// connects and completes the opening handshake, with the sample key of RFC 6455
+fn connect(server: &WebSocketServer, path: &str) -> TcpStream {
+    let mut stream = TcpStream::connect(server.local_addr().unwrap()).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(
+        stream,
+        "GET {} HTTP/1.1\r\nHost: localhost\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n",
+        path
+    )
+    .unwrap();
+
+    let mut head = Vec::new();
+    let mut byte = [0u8];
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+    let head = String::from_utf8(head).unwrap();
+    assert!(head.starts_with("HTTP/1.1 101 "));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+    stream
+}
+
+fn binary(stream: &mut TcpStream) -> Vec<u8> {
+    let (opcode, payload) = read_message(stream, usize::MAX).unwrap();
+    assert_eq!(opcode, OPCODE_BINARY);
+    payload
+}
+
+// sends a control message and skips media until its reply
+fn control(stream: &mut TcpStream, message: Value) -> Value {
+    write_frame(
+        stream,
+        OPCODE_TEXT,
+        message.to_string().as_bytes(),
+        Some(MASK),
+    )
+    .unwrap();
+    loop {
+        let (opcode, payload) = read_message(stream, usize::MAX).unwrap();
+        if opcode == OPCODE_TEXT {
+            return serde_json::from_slice(&payload).unwrap();
+        }
+    }
+}
+
+// one masked frame, fin clear for all but the last fragment of a message
+fn fragment(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![(fin as u8) << 7 | opcode, 0x80 | payload.len() as u8];
+    frame.extend_from_slice(&MASK);
+    frame.extend(
+        payload
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| byte ^ MASK[i % 4]),
+    );
+    frame
+}
+
+// (tag type, timestamp, key frame) of an FLV tag message
+fn flv_tag(tag: &[u8]) -> (u8, u32, bool) {
+    let timestamp = u32::from_be_bytes([tag[7], tag[4], tag[5], tag[6]]);
+    (tag[0], timestamp, tag[11] >> 4 == 1)
+}
+
+#[test]
+fn flv_control_channel_switches_modes() {
+    let dispatcher = av_dispatcher();
+    let mut server = WebSocketServer::new("127.0.0.1:0").unwrap();
+    server.add_stream("test", dispatcher.clone());
+    server.start_serve();
+    input(&dispatcher, &[(MediaType::VIDEO, 0, true)]);
+
+    let mut stream = connect(&server, "/ws/test.flv");
+    assert!(binary(&mut stream).starts_with(b"FLV"));
+    // sequence headers, then the key frame
+    assert_eq!(binary(&mut stream)[0], 9);
+    assert_eq!(binary(&mut stream)[0], 8);
+    assert_eq!(flv_tag(&binary(&mut stream)), (9, 0, true));
+
+    // nothing is sent while paused, and video resumes at a key frame
+    assert_eq!(
+        control(&mut stream, json!({"cmd": "pause"})),
+        json!({"event": "ack", "cmd": "pause"})
+    );
+    input(
+        &dispatcher,
+        &[(MediaType::VIDEO, 40, false), (MediaType::AUDIO, 50, false)],
+    );
+    assert_eq!(
+        control(&mut stream, json!({"cmd": "resume"})),
+        json!({"event": "ack", "cmd": "resume"})
+    );
+    input(
+        &dispatcher,
+        &[(MediaType::VIDEO, 80, false), (MediaType::VIDEO, 120, true)],
+    );
+    assert_eq!(flv_tag(&binary(&mut stream)), (9, 120, true));
+
+    // key-only mode skips the rest of the GOP
+    assert_eq!(
+        control(&mut stream, json!({"cmd": "key_mode", "enable": true}))["event"],
+        "ack"
+    );
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 160, false),
+            (MediaType::VIDEO, 200, true),
+        ],
+    );
+    let video = loop {
+        let tag = flv_tag(&binary(&mut stream));
+        if tag.0 == 9 {
+            break tag;
+        }
+    };
+    assert_eq!(video, (9, 200, true));
+
+    assert_eq!(
+        control(&mut stream, json!({"cmd": "rewind"}))["event"],
+        "error"
+    );
+    write_frame(&mut stream, OPCODE_CLOSE, &[], Some(MASK)).unwrap();
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn fmp4_starts_with_init_segment() {
+    let dispatcher = av_dispatcher();
+    let mut server = WebSocketServer::new("127.0.0.1:0").unwrap();
+    server.add_stream("test", dispatcher.clone());
+    server.start_serve();
+
+    let mut stream = connect(&server, "/ws/test.mp4");
+    input(
+        &dispatcher,
+        &[
+            (MediaType::VIDEO, 0, true),
+            (MediaType::VIDEO, 40, false),
+            (MediaType::VIDEO, 80, true),
+        ],
+    );
+    let init = binary(&mut stream);
+    assert_eq!(&init[4..8], b"ftyp");
+    // the first GOP is cut when the second key frame arrives
+    let fragment = binary(&mut stream);
+    assert_eq!(&fragment[4..8], b"moof");
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn ping_between_fragments_keeps_the_message() {
+    let dispatcher = av_dispatcher();
+    let mut server = WebSocketServer::new("127.0.0.1:0").unwrap();
+    server.add_stream("test", dispatcher.clone());
+    server.start_serve();
+
+    let mut stream = connect(&server, "/ws/test.flv");
+    let message = json!({"cmd": "pause"}).to_string();
+    let (head, tail) = message.as_bytes().split_at(5);
+    let mut frames = fragment(false, OPCODE_TEXT, head);
+    frames.extend(fragment(true, OPCODE_PING, b"ping"));
+    frames.extend(fragment(true, OPCODE_CONTINUATION, tail));
+    stream.write_all(&frames).unwrap();
+
+    let mut replies = Vec::new();
+    while replies.len() < 2 {
+        match read_message(&mut stream, usize::MAX).unwrap() {
+            (OPCODE_BINARY, _) => {}
+            reply => replies.push(reply),
+        }
+    }
+    assert_eq!(replies[0], (OPCODE_PONG, b"ping".to_vec()));
+    assert_eq!(
+        serde_json::from_slice::<Value>(&replies[1].1).unwrap(),
+        json!({"event": "ack", "cmd": "pause"})
+    );
+
+    server.stop_serve();
+    dispatcher.lock().unwrap().stop_dispatch();
+}
+
+#[test]
+fn oversized_continuation_is_rejected() {
+    let mut frames = fragment(false, OPCODE_BINARY, &[1, 2, 3]);
+    // a 64-bit length that wraps when added to the buffered fragment
+    frames.extend_from_slice(&[OPCODE_CONTINUATION | 0x80, 0x80 | 127]);
+    frames.extend_from_slice(&u64::MAX.to_be_bytes());
+    frames.extend_from_slice(&MASK);
+
+    let mut reader = MessageReader::new(65536);
+    let err = reader.read(&mut Cursor::new(frames)).unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+}